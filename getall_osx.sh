@@ -2,5 +2,5 @@
 
 echo "Scanning all directories / twitter users under out and downloading the new stuff"
 cd out
-find . -type d -mindepth 1 -maxdepth 1 -execdir ../target/release/twitter-media-downloader -c 5 -r -o ./ -u "$(basename {})"  \;
+find . -type d -mindepth 1 -maxdepth 1 -execdir ../target/release/twitter-media-downloader download -c 5 -r -o ./ -u "$(basename {})"  \;
 cd ../
\ No newline at end of file
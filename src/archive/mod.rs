@@ -0,0 +1,167 @@
+//! module to write the downloads of a run into an archive file per user instead of loose files, see `--archive`.
+//!
+//! The archive of a run is `<user dir>/<username>-<date>-<time>.zip` (or `.tar.gz`), created with the first file of
+//! the run. Every file is added as soon as it is downloaded and post-processed, with its metadata sidecar, and then
+//! removed from the user directory. The manifests of the user directory are added when the archive is finished, at
+//! the end of the run.
+//!
+//! The [state database](crate::state) keeps the records of the archived files, so later runs skip them like files
+//! on disk.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tracing::{error, info};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::manifest::{JSON_MANIFEST_FILENAME, SHA256_MANIFEST_FILENAME};
+use crate::sidecar;
+
+/// Format of the archive of `--archive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// zip, the media stored as they are
+    Zip,
+    /// gzipped tar
+    TarGz,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            _ => Err(format!("unknown archive format '{}'. Expected zip or tar.gz", s)),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+enum Writer {
+    Zip(ZipWriter<File>),
+    TarGz(tar::Builder<GzEncoder<File>>),
+}
+
+impl Writer {
+    fn add(&mut self, path: &Path, name: &str) -> Result<(), io::Error> {
+        match self {
+            Writer::Zip(zip) => {
+                let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+                zip.start_file(name, options).map_err(io::Error::from)?;
+                io::copy(&mut File::open(path)?, zip)?;
+                Ok(())
+            }
+            Writer::TarGz(tar) => tar.append_path_with_name(path, name),
+        }
+    }
+
+    fn finish(self) -> Result<(), io::Error> {
+        match self {
+            Writer::Zip(mut zip) => zip.finish().map_err(io::Error::from)?.flush(),
+            Writer::TarGz(tar) => tar.into_inner()?.finish()?.flush(),
+        }
+    }
+}
+
+/// The archive of the run of one user. Finished when dropped.
+pub struct RunArchive {
+    username: String,
+    user_output_dir: PathBuf,
+    format: ArchiveFormat,
+    /// path of the archive, set with its first file
+    path: Mutex<Option<PathBuf>>,
+    writer: Mutex<Option<Writer>>,
+}
+
+impl RunArchive {
+    pub fn new(user_output_dir: &Path, username: &str, format: ArchiveFormat) -> Self {
+        RunArchive {
+            username: username.into(),
+            user_output_dir: user_output_dir.into(),
+            format,
+            path: Mutex::new(None),
+            writer: Mutex::new(None),
+        }
+    }
+
+    fn create(&self) -> Result<Writer, io::Error> {
+        let stamp = OffsetDateTime::now_utc().format(format_description!("[year][month][day]-[hour][minute][second]"))
+            .map_err(io::Error::other)?;
+        let path = self.user_output_dir.join(format!("{}-{}.{}", self.username, stamp, self.format.extension()));
+        let file = File::create(&path)?;
+        info!("username: {}, archive: {}. Writing the downloads into the archive", self.username, path.display());
+        *self.path.lock().unwrap_or_else(|e| e.into_inner()) = Some(path);
+        Ok(match self.format {
+            ArchiveFormat::Zip => Writer::Zip(ZipWriter::new(file)),
+            ArchiveFormat::TarGz => Writer::TarGz(tar::Builder::new(GzEncoder::new(file, Compression::default()))),
+        })
+    }
+
+    /// Moves the downloaded file `path`, `name` within the user directory, its sidecar and text file into the archive.
+    pub fn add(&self, path: &Path, name: &Path) -> Result<(), io::Error> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if writer.is_none() {
+            *writer = Some(self.create()?);
+        }
+        let writer = writer.as_mut().expect("archive created above");
+
+        let name = name.to_string_lossy().replace('\\', "/");
+        writer.add(path, &name)?;
+        for sidecar in [sidecar::sidecar_path(path), sidecar::text_path(path)] {
+            if !sidecar.is_file() {
+                continue;
+            }
+            let sidecar_name = sidecar.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+            let sidecar_name = match name.rsplit_once('/') {
+                Some((dir, _)) => format!("{}/{}", dir, sidecar_name),
+                None => sidecar_name,
+            };
+            writer.add(&sidecar, &sidecar_name)?;
+            fs::remove_file(&sidecar)?;
+        }
+        fs::remove_file(path)
+    }
+
+    /// Adds the manifests of the user directory and writes the end of the archive. Does nothing without a file.
+    fn finish(&self) -> Result<(), io::Error> {
+        let mut writer = match self.writer.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        for manifest in [SHA256_MANIFEST_FILENAME, JSON_MANIFEST_FILENAME] {
+            let path = self.user_output_dir.join(manifest);
+            if path.is_file() {
+                writer.add(&path, manifest)?;
+            }
+        }
+        writer.finish()
+    }
+}
+
+impl Drop for RunArchive {
+    fn drop(&mut self) {
+        let path = self.path.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+        match self.finish() {
+            Ok(()) if path.as_os_str().is_empty() => (),
+            Ok(()) => info!("username: {}, archive: {}. Archive written", self.username, path.display()),
+            Err(e) => error!("username: {}, archive: {}. Cannot finish the archive: {}", self.username, path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! module to capture the HTTP exchanges of a run for bug reports, see `--debug-http`.
+//!
+//! Every exchange is written as one JSON file `<seq>-<name>.json` to the capture directory, with the request as it
+//! was built and the response as it was received. Credentials are redacted. `twitter_v2` does not expose the raw
+//! exchange, so Twitter API calls are captured as their query parameters and their parsed response.
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use reqwest::header::HeaderMap;
+use serde_json::{json, Map, Value};
+use tracing::warn;
+
+/// Headers whose values are replaced by [REDACTED](REDACTED).
+const SECRET_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+pub const REDACTED: &str = "[REDACTED]";
+
+struct Capture {
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+/// Starts capturing to `dir`, creating it if needed.
+pub fn install(dir: &Path) -> Result<(), io::Error> {
+    DirBuilder::new().recursive(true).create(dir)?;
+    let _ = CAPTURE.set(Capture { dir: dir.into(), seq: AtomicU64::new(0) });
+    Ok(())
+}
+
+/// Returns true if capturing is installed. Callers skip building the capture otherwise.
+pub fn is_enabled() -> bool {
+    CAPTURE.get().is_some()
+}
+
+/// Writes the exchange `name` with its `request` and `response`.
+///
+/// Failures are logged, never returned: a broken capture must not fail a run.
+pub fn record(name: &str, request: Value, response: Value) {
+    let capture = match CAPTURE.get() {
+        Some(capture) => capture,
+        None => return,
+    };
+    let seq = capture.seq.fetch_add(1, Ordering::SeqCst);
+    let path = capture.dir.join(format!("{:06}-{}.json", seq, name));
+    let exchange = json!({ "request": request, "response": response });
+
+    let written = serde_json::to_vec_pretty(&exchange)
+        .map_err(io::Error::from)
+        .and_then(|contents| fs::write(&path, contents));
+    if let Err(e) = written {
+        warn!("Cannot write the HTTP capture {}: {}", path.display(), e);
+    }
+}
+
+/// Returns `headers` as a JSON object with the credentials redacted.
+pub fn headers_json(headers: &HeaderMap) -> Value {
+    let mut map = Map::new();
+    for (name, value) in headers.iter() {
+        let value = if SECRET_HEADERS.contains(&name.as_str()) {
+            REDACTED.into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        map.insert(name.as_str().into(), Value::String(value));
+    }
+    Value::Object(map)
+}
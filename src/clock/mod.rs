@@ -0,0 +1,100 @@
+//! module to abstract time: the current time and sleeping.
+//!
+//! Everything that waits or schedules (retry backoff, rate limit waits, time budgets, notification roll-ups) asks
+//! this module instead of the OS, so it can be run against a [MockClock](MockClock) in tests: sleeps then return at
+//! once and only move the mock time forward.
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::{self, BoxFuture, FutureExt};
+
+/// Source of the current time and of sleeps.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Returns a future completing after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real clock: the system time and tokio sleeps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// A virtual clock for tests. Sleeps complete immediately, advance the time by their duration and are recorded.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+    sleeps: Mutex<Vec<Duration>>,
+}
+
+impl MockClock {
+    /// A clock standing at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        MockClock { now: Mutex::new(now), sleeps: Mutex::new(Vec::new()) }
+    }
+
+    /// A clock standing at `unix_secs` seconds after the unix epoch.
+    pub fn at_unix(unix_secs: u64) -> Self {
+        MockClock::new(UNIX_EPOCH + Duration::from_secs(unix_secs))
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+
+    /// Returns the durations of the sleeps so far, in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.sleeps.lock().unwrap_or_else(|e| e.into_inner()).push(duration);
+        self.advance(duration);
+        future::ready(()).boxed()
+    }
+}
+
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+/// Replaces the clock of the process, the [SystemClock](SystemClock) by default.
+pub fn set(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap_or_else(|e| e.into_inner()) = Some(clock);
+}
+
+/// Returns the clock of the process.
+pub fn get() -> Arc<dyn Clock> {
+    CLOCK.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_else(|| Arc::new(SystemClock))
+}
+
+/// Returns the current time of the [clock](get).
+pub fn now() -> SystemTime {
+    get().now()
+}
+
+/// Returns the current time of the [clock](get) in seconds since the unix epoch.
+pub fn unix_now() -> u64 {
+    now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Sleeps for `duration` on the [clock](get).
+pub async fn sleep(duration: Duration) {
+    let sleep = get().sleep(duration);
+    sleep.await
+}
@@ -1,13 +1,758 @@
 //! module to hold common structs for `twitter-media-downloader`
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug)]
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+use crate::archive::ArchiveFormat;
+use crate::compat::{Compat, GALLERY_DL_CATEGORY, GALLERY_DL_TEMPLATE};
+use crate::convert::{ConvertFormat, DEFAULT_QUALITY};
+use crate::dates::{DatePolicy, Timezone};
+use crate::dedup::Dedup;
+use crate::exec::ExecHook;
+use crate::manifest::ChecksumFormat;
+use crate::similar::NearDupes;
+use crate::twitter::auth::Credentials;
+use crate::twitter::filename::{FilenameTemplate, Layout};
+use crate::twitter::filter::TweetFilter;
+use crate::twitter::hls::HlsMode;
+use crate::twitter::nitter::FallbackBackend;
+use crate::twitter::order::Order;
+use crate::twitter::retry::{RetryPolicy, DEFAULT_STALL_TIMEOUT};
+use crate::twitter::search::SourceKind;
+use crate::volumes::Volume;
+
+/// Settings of a download run of one user. Built and validated with [Config::builder](Config::builder).
+#[derive(Debug, Clone)]
 pub struct Config {
-    pub bearer_token: String,
-    pub username: String,
-    pub count: u8,
-    pub reset_marker: bool,
-    pub download_all: bool,
-    pub output_dir: PathBuf,
+    pub(crate) credentials: Credentials,
+    pub(crate) username: String,
+    pub(crate) count: u8,
+    pub(crate) reset_marker: bool,
+    pub(crate) download_all: bool,
+    /// most pages of Tweets a run walks
+    pub(crate) max_pages: Option<u32>,
+    /// where the Tweets come from when the API is rate limited or rejects the token
+    pub(crate) fallback_backend: Option<FallbackBackend>,
+    pub(crate) output_dir: PathBuf,
+    pub(crate) concurrency: usize,
+    pub(crate) volumes: Vec<Volume>,
+    pub(crate) retry: RetryPolicy,
+    /// time a media download may go without receiving any bytes
+    pub(crate) stall_timeout: Duration,
+    /// largest media file to download in bytes
+    pub(crate) max_file_size: Option<u64>,
+    /// free bytes the output filesystem has to keep, the run stops below
+    pub(crate) min_free_space: Option<u64>,
+    /// stamp downloaded files with their date, see [dates::file_date](crate::dates::file_date)
+    pub(crate) set_mtime: bool,
+    pub(crate) date_policy: DatePolicy,
+    /// order the Tweets of a page are processed in
+    pub(crate) order: Order,
+    /// where the Tweets come from
+    pub(crate) source: SourceKind,
+    /// full-archive search query the Tweets have to match
+    pub(crate) query: Option<String>,
+    /// only Tweets created at or after, full-archive search only
+    pub(crate) since: Option<OffsetDateTime>,
+    /// only Tweets created before, full-archive search only
+    pub(crate) until: Option<OffsetDateTime>,
+    /// Tweets to download the media of
+    pub(crate) filter: TweetFilter,
+    /// time zone dates are grouped and named in
+    pub(crate) timezone: Timezone,
+    pub(crate) filename_template: FilenameTemplate,
+    pub(crate) layout: Layout,
+    /// downloader whose output is mirrored, see [compat](crate::compat)
+    pub(crate) compat: Option<Compat>,
+    pub(crate) organize_by_source: bool,
+    pub(crate) save_links: bool,
+    pub(crate) save_tweets: bool,
+    /// write the Tweet's text, date and permalink as `<file>.txt` next to every media file
+    pub(crate) save_text: bool,
+    /// download the media of the rest of the self-threads of the Tweets as well
+    pub(crate) include_thread: bool,
+    /// download the media of the Tweets quoted by the Tweets as well
+    pub(crate) include_quoted: bool,
+    /// download videos and animated GIFs as MP4, see [hls](crate::twitter::hls)
+    pub(crate) videos: bool,
+    /// what to do with videos offered as HLS playlist only
+    pub(crate) hls: HlsMode,
+    /// save the preview image of videos and animated GIFs that are not downloaded themselves
+    pub(crate) video_previews: bool,
+    /// embed the Tweet's text, author, date and url into the files, see [embed](crate::embed)
+    pub(crate) embed_metadata: bool,
+    /// format downloaded photos are converted to, see [convert](crate::convert)
+    pub(crate) convert: Option<ConvertFormat>,
+    /// JPEG quality of converted photos
+    pub(crate) quality: u8,
+    /// what to do with downloads of content downloaded before, see [dedup](crate::dedup)
+    pub(crate) dedup: Dedup,
+    /// what to do with images that look like earlier downloads, see [similar](crate::similar)
+    pub(crate) near_dupes: NearDupes,
+    /// checksum formats kept per user directory besides the manifest, see [manifest](crate::manifest)
+    pub(crate) checksums: Vec<ChecksumFormat>,
+    pub(crate) sync_new: bool,
+    /// wait for the [lock](crate::lock) of the user directory instead of failing
+    pub(crate) wait_for_lock: bool,
+    /// command run after every downloaded file, see [exec](crate::exec)
+    pub(crate) exec: Option<ExecHook>,
+    /// write the downloads into an archive per run instead of loose files, see [archive](crate::archive)
+    pub(crate) archive: Option<ArchiveFormat>,
+    /// id of the run, see [new_run_id](new_run_id)
+    pub(crate) run_id: String,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
+}
+
+/// Smallest and largest number of Tweets per page the Twitter API accepts.
+pub const COUNT_RANGE: (u8, u8) = (5, 100);
+
+/// Why a [ConfigBuilder](ConfigBuilder) cannot build a [Config](Config).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("{0} is required")]
+    Missing(&'static str),
+    #[error("{0} cannot be combined with {1}")]
+    Conflict(&'static str, &'static str),
+    #[error("{field} must be between {min} and {max}, got {value}")]
+    OutOfRange { field: &'static str, value: u64, min: u64, max: u64 },
 }
 
+/// Builder of a [Config](Config).
+///
+/// ```
+/// # use twitter_media_downloader::Config;
+/// let config = Config::builder()
+///     .bearer_token("AAAA...")
+///     .username("NASAHubble")
+///     .output_dir("out")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            config: Config {
+                credentials: Credentials::default(),
+                username: String::new(),
+                count: COUNT_RANGE.1,
+                reset_marker: false,
+                download_all: false,
+                max_pages: None,
+                fallback_backend: None,
+                output_dir: PathBuf::from("."),
+                concurrency: 4,
+                volumes: Vec::new(),
+                retry: RetryPolicy::default(),
+                stall_timeout: DEFAULT_STALL_TIMEOUT,
+                max_file_size: None,
+                min_free_space: None,
+                set_mtime: true,
+                date_policy: DatePolicy::Tweet,
+                order: Order::Newest,
+                source: SourceKind::Timeline,
+                query: None,
+                since: None,
+                until: None,
+                filter: TweetFilter::default(),
+                timezone: Timezone::default(),
+                filename_template: FilenameTemplate::default(),
+                layout: Layout::Flat,
+                compat: None,
+                organize_by_source: false,
+                save_links: false,
+                save_tweets: false,
+                save_text: false,
+                include_thread: false,
+                include_quoted: false,
+                videos: false,
+                hls: HlsMode::Native,
+                video_previews: false,
+                embed_metadata: false,
+                convert: None,
+                quality: DEFAULT_QUALITY,
+                dedup: Dedup::Off,
+                near_dupes: NearDupes::Off,
+                checksums: Vec::new(),
+                sync_new: false,
+                wait_for_lock: false,
+                exec: None,
+                archive: None,
+                run_id: String::new(),
+            },
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// App-only authentication with `bearer_token`, the default.
+    pub fn bearer_token(mut self, bearer_token: impl Into<String>) -> Self {
+        self.config.credentials = Credentials::Bearer(bearer_token.into());
+        self
+    }
+
+    /// Authentication in user context, e.g. [Credentials::OAuth1](Credentials::OAuth1).
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.config.credentials = credentials;
+        self
+    }
+
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.config.username = username.into();
+        self
+    }
+
+    /// Number of Tweets per page, see [COUNT_RANGE](COUNT_RANGE).
+    pub fn count(mut self, count: u8) -> Self {
+        self.config.count = count;
+        self
+    }
+
+    /// Get the Tweets from `fallback_backend` once the API is rate limited or rejects the token, see
+    /// [nitter](crate::twitter::nitter). No fallback by default.
+    pub fn fallback_backend(mut self, fallback_backend: Option<FallbackBackend>) -> Self {
+        self.config.fallback_backend = fallback_backend;
+        self
+    }
+
+    /// Walk at most `max_pages` pages of Tweets. Without `download_all` a run walks one page, with `max_pages` it goes
+    /// on up to this many.
+    pub fn max_pages(mut self, max_pages: Option<u32>) -> Self {
+        self.config.max_pages = max_pages;
+        self
+    }
+
+    /// Start over from the latest Tweet.
+    pub fn reset_marker(mut self, reset_marker: bool) -> Self {
+        self.config.reset_marker = reset_marker;
+        self
+    }
+
+    /// Walk every page instead of stopping at the first one or the first known file.
+    pub fn download_all(mut self, download_all: bool) -> Self {
+        self.config.download_all = download_all;
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.config.output_dir = output_dir.into();
+        self
+    }
+
+    /// Number of media files downloaded in parallel.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = concurrency;
+        self
+    }
+
+    pub fn volumes(mut self, volumes: Vec<Volume>) -> Self {
+        self.config.volumes = volumes;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    /// Time a media download may go without receiving any bytes before it is aborted and retried.
+    pub fn stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.config.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Skip media files larger than `max_file_size` bytes, reporting them in [skipped](crate::skipped). No limit by default.
+    pub fn max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.config.max_file_size = max_file_size;
+        self
+    }
+
+    /// Stop the run before a page once the output filesystem has less than `min_free_space` bytes free, with the
+    /// checkpoint of the pages before written. No minimum by default.
+    pub fn min_free_space(mut self, min_free_space: Option<u64>) -> Self {
+        self.config.min_free_space = min_free_space;
+        self
+    }
+
+    /// Set the modification time of downloaded files to their date according to the date policy. On by default.
+    pub fn set_mtime(mut self, set_mtime: bool) -> Self {
+        self.config.set_mtime = set_mtime;
+        self
+    }
+
+    pub fn date_policy(mut self, date_policy: DatePolicy) -> Self {
+        self.config.date_policy = date_policy;
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.config.order = order;
+        self
+    }
+
+    /// Where the Tweets come from: the timeline of the user, or the [full-archive search](crate::twitter::search) for
+    /// tokens with access to it.
+    pub fn source(mut self, source: SourceKind) -> Self {
+        self.config.source = source;
+        self
+    }
+
+    /// Query the Tweets of the full-archive search have to match as well, e.g. `#astrophotography has:images`.
+    pub fn query(mut self, query: Option<String>) -> Self {
+        self.config.query = query;
+        self
+    }
+
+    /// Only search Tweets created at or after `since`, with the full-archive search.
+    pub fn since(mut self, since: Option<OffsetDateTime>) -> Self {
+        self.config.since = since;
+        self
+    }
+
+    /// Only search Tweets created before `until`, with the full-archive search.
+    pub fn until(mut self, until: Option<OffsetDateTime>) -> Self {
+        self.config.until = until;
+        self
+    }
+
+    /// Only download the media of the Tweets `filter` accepts.
+    pub fn filter(mut self, filter: TweetFilter) -> Self {
+        self.config.filter = filter;
+        self
+    }
+
+    pub fn timezone(mut self, timezone: Timezone) -> Self {
+        self.config.timezone = timezone;
+        self
+    }
+
+    /// Template of the local file names, see [FilenameTemplate](FilenameTemplate).
+    pub fn filename_template(mut self, filename_template: FilenameTemplate) -> Self {
+        self.config.filename_template = filename_template;
+        self
+    }
+
+    /// Directories of the files under the user directory, see [Layout](Layout).
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.config.layout = layout;
+        self
+    }
+
+    /// Mirror the output of another downloader, see [compat](crate::compat). Replaces the output directory by its
+    /// `twitter` directory, the [filename_template](ConfigBuilder::filename_template) and the [layout](ConfigBuilder::layout).
+    pub fn compat(mut self, compat: Option<Compat>) -> Self {
+        self.config.compat = compat;
+        self
+    }
+
+    pub fn organize_by_source(mut self, organize_by_source: bool) -> Self {
+        self.config.organize_by_source = organize_by_source;
+        self
+    }
+
+    pub fn save_links(mut self, save_links: bool) -> Self {
+        self.config.save_links = save_links;
+        self
+    }
+
+    /// Archive every processed Tweet to the user's [Tweets](crate::tweets) file.
+    pub fn save_tweets(mut self, save_tweets: bool) -> Self {
+        self.config.save_tweets = save_tweets;
+        self
+    }
+
+    /// Write the text, date and permalink of the Tweet as `<file>.txt` next to every media file, see [sidecar](crate::sidecar).
+    pub fn save_text(mut self, save_text: bool) -> Self {
+        self.config.save_text = save_text;
+        self
+    }
+
+    /// Download the media of the rest of a self-thread, the Tweets of the user in the conversation of a Tweet. Only
+    /// threads of the last 7 days can be searched.
+    pub fn include_thread(mut self, include_thread: bool) -> Self {
+        self.config.include_thread = include_thread;
+        self
+    }
+
+    /// Download the media of the Tweets quoted by the Tweets as well, named with and attributed to the quoted author.
+    pub fn include_quoted(mut self, include_quoted: bool) -> Self {
+        self.config.include_quoted = include_quoted;
+        self
+    }
+
+    /// Download videos and animated GIFs, the MP4 variant of the highest bit rate. Not downloaded by default.
+    pub fn videos(mut self, videos: bool) -> Self {
+        self.config.videos = videos;
+        self
+    }
+
+    /// What to do with videos offered as HLS playlist only, see [HlsMode](HlsMode). Stitched natively by default.
+    pub fn hls(mut self, hls: HlsMode) -> Self {
+        self.config.hls = hls;
+        self
+    }
+
+    /// Save the preview image of videos and animated GIFs that are not downloaded, marked as `"preview": true` in its
+    /// [sidecar](crate::sidecar).
+    pub fn video_previews(mut self, video_previews: bool) -> Self {
+        self.config.video_previews = video_previews;
+        self
+    }
+
+    /// Embed the Tweet's text, author, date and url into JPEGs, or an XMP sidecar for other formats.
+    pub fn embed_metadata(mut self, embed_metadata: bool) -> Self {
+        self.config.embed_metadata = embed_metadata;
+        self
+    }
+
+    /// Convert downloaded photos to `convert`, see [convert](crate::convert). Not converted by default.
+    pub fn convert(mut self, convert: Option<ConvertFormat>) -> Self {
+        self.config.convert = convert;
+        self
+    }
+
+    /// JPEG quality of converted photos, from 1 to 100. WebP is lossless.
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.config.quality = quality;
+        self
+    }
+
+    /// Deduplicate downloads by their content. Cannot be combined with [embed_metadata](ConfigBuilder::embed_metadata),
+    /// which makes every file unique.
+    pub fn dedup(mut self, dedup: Dedup) -> Self {
+        self.config.dedup = dedup;
+        self
+    }
+
+    /// Detect images that look like earlier downloads, e.g. at another resolution.
+    pub fn near_dupes(mut self, near_dupes: NearDupes) -> Self {
+        self.config.near_dupes = near_dupes;
+        self
+    }
+
+    /// Keep checksum files of these formats in every user directory, rewritten at the end of each run.
+    pub fn checksums(mut self, checksums: Vec<ChecksumFormat>) -> Self {
+        self.config.checksums = checksums;
+        self
+    }
+
+    /// Only walk the Tweets newer than the newest one seen, leaving the checkpoint untouched.
+    pub fn sync_new(mut self, sync_new: bool) -> Self {
+        self.config.sync_new = sync_new;
+        self
+    }
+
+    /// Wait for another run downloading the same user to finish instead of failing.
+    pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.config.wait_for_lock = wait_for_lock;
+        self
+    }
+
+    /// Run this command after every downloaded file, see [exec](crate::exec).
+    pub fn exec(mut self, exec: Option<ExecHook>) -> Self {
+        self.config.exec = exec;
+        self
+    }
+
+    /// Write the downloads of every run into an archive file of this format instead of loose files.
+    pub fn archive(mut self, archive: Option<ArchiveFormat>) -> Self {
+        self.config.archive = archive;
+        self
+    }
+
+    /// Id of the run. A [new_run_id](new_run_id) if not set.
+    pub fn run_id(mut self, run_id: impl Into<String>) -> Self {
+        self.config.run_id = run_id.into();
+        self
+    }
+
+    /// Validates the settings and returns the [Config](Config).
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut config = self.config;
+
+        if let Some(missing) = config.credentials.missing() {
+            return Err(ConfigError::Missing(missing));
+        }
+        if config.username.is_empty() {
+            return Err(ConfigError::Missing("username"));
+        }
+        if config.reset_marker && config.sync_new {
+            return Err(ConfigError::Conflict("reset_marker", "sync_new"));
+        }
+        if config.dedup != Dedup::Off && config.embed_metadata {
+            return Err(ConfigError::Conflict("dedup", "embed_metadata"));
+        }
+        if config.source == SourceKind::Timeline {
+            if config.query.is_some() {
+                return Err(ConfigError::Conflict("query", "the timeline source"));
+            }
+            if config.since.is_some() || config.until.is_some() {
+                return Err(ConfigError::Conflict("since and until", "the timeline source"));
+            }
+        }
+        if config.count < COUNT_RANGE.0 || config.count > COUNT_RANGE.1 {
+            return Err(ConfigError::OutOfRange { field: "count", value: config.count.into(), min: COUNT_RANGE.0.into(), max: COUNT_RANGE.1.into() });
+        }
+        if !(1..=100).contains(&config.quality) {
+            return Err(ConfigError::OutOfRange { field: "quality", value: config.quality.into(), min: 1, max: 100 });
+        }
+        if config.max_pages == Some(0) {
+            return Err(ConfigError::OutOfRange { field: "max_pages", value: 0, min: 1, max: u32::MAX.into() });
+        }
+        if config.stall_timeout.is_zero() {
+            return Err(ConfigError::OutOfRange { field: "stall_timeout", value: 0, min: 1, max: u64::MAX });
+        }
+        if config.concurrency == 0 {
+            return Err(ConfigError::OutOfRange { field: "concurrency", value: 0, min: 1, max: usize::MAX as u64 });
+        }
+        if config.compat == Some(Compat::GalleryDl) {
+            config.output_dir = config.output_dir.join(GALLERY_DL_CATEGORY);
+            config.filename_template = GALLERY_DL_TEMPLATE.parse().expect("the gallery-dl template is valid");
+            config.layout = Layout::Flat;
+        }
+        if config.run_id.is_empty() {
+            config.run_id = new_run_id();
+        }
+        Ok(config)
+    }
+}
+
+/// Returns a new run id, e.g. `6531f2a0-3f9c1e`: the start time in hex seconds and a random suffix.
+///
+/// Every run of the program gets one. It is logged, recorded in the state database and sent with
+/// notifications so events of one run can be correlated.
+pub fn new_run_id() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("{:x}-{:06x}", now, rand::thread_rng().gen::<u32>() & 0xff_ffff)
+}
+
+/// Parses a size like `500`, `10KB`, `2MiB` or `1.5G` into bytes.
+///
+/// Units are case insensitive. `K`, `M`, `G`, `T` with or without `B` are decimal, `KiB`, `MiB`, `GiB`, `TiB` are binary.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("invalid size unit in '{}'", s)),
+    };
+
+    let size = number * multiplier as f64;
+    if size >= u64::MAX as f64 {
+        return Err(format!("size '{}' is too large", s));
+    }
+    Ok(size as u64)
+}
+
+/// Parses a date like `2015-06-30`, midnight UTC, or a date and time like `2015-06-30T12:00:00+02:00` (RFC 3339).
+pub fn parse_date(s: &str) -> Result<OffsetDateTime, String> {
+    let s = s.trim();
+    if let Ok(date) = Date::parse(s, format_description!("[year]-[month]-[day]")) {
+        return Ok(date.midnight().assume_utc());
+    }
+    OffsetDateTime::parse(s, &Rfc3339).map_err(|_| format!("invalid date '{}'. Expected YYYY-MM-DD or an RFC 3339 date and time", s))
+}
+
+/// Parses a duration like `90`, `90s`, `20m`, `1h30m` or `2d`. A plain number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+        let multiplier = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("invalid duration unit '{}' in '{}'. Expected s, m, h or d", c, s)),
+        };
+        total = value.checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| format!("duration '{}' is too long", s))?;
+        number.clear();
+    }
+    if !number.is_empty() || s.is_empty() {
+        return Err(format!("invalid duration '{}'", s));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Writes `contents` to `path` atomically.
+///
+/// The contents are written and synced to a temporary file next to `path`, which is then renamed over `path`.
+/// A crash leaves either the old or the new contents, never a partial file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".tmp{}", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Adds the files under `dir` to `files` as `/` separated paths relative to `root`.
+pub fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+            files.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the hex encoded SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String, io::Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        let cases = [
+            ("0", 0),
+            ("1024", 1024),
+            ("512b", 512),
+            ("2k", 2_000),
+            ("10 MB", 10_000_000),
+            ("1.5KiB", 1536),
+            ("1GiB", 1 << 30),
+            (" 2TiB ", 2 << 40),
+            ("3tb", 3_000_000_000_000),
+        ];
+        for (input, bytes) in cases {
+            assert_eq!(parse_size(input), Ok(bytes), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        let cases = [
+            ("", "invalid size"),
+            ("KiB", "invalid size"),
+            ("1.2.3MB", "invalid size"),
+            ("-1KiB", "invalid size"),
+            ("5XB", "invalid size unit"),
+            ("20000000TiB", "too large"),
+        ];
+        for (input, error) in cases {
+            assert!(parse_size(input).unwrap_err().contains(error), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("2015-06-30"), Ok(datetime!(2015-06-30 0:00 UTC)));
+        assert_eq!(parse_date(" 2015-06-30T12:00:00+02:00 "), Ok(datetime!(2015-06-30 12:00 +2)));
+        assert_eq!(parse_date("2015-06-30T12:00:00Z"), Ok(datetime!(2015-06-30 12:00 UTC)));
+        for input in ["", "2015-6-30", "2015-02-30", "30.06.2015", "2015-06-30 12:00", "yesterday"] {
+            assert!(parse_date(input).unwrap_err().contains("invalid date"), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("90", 90),
+            (" 45s ", 45),
+            ("20m", 20 * 60),
+            ("1h30m", 90 * 60),
+            ("1H", 60 * 60),
+            ("2d", 2 * 24 * 60 * 60),
+            ("1d2h3m4s", 93_784),
+        ];
+        for (input, seconds) in cases {
+            assert_eq!(parse_duration(input), Ok(Duration::from_secs(seconds)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        let cases = [
+            ("", "invalid duration"),
+            ("m", "invalid duration"),
+            ("5m3", "invalid duration"),
+            ("-5m", "invalid duration"),
+            ("5x", "invalid duration unit 'x'"),
+            ("1h 30m", "invalid duration '1h 30m'"),
+            ("18446744073709551615d", "too long"),
+            ("18446744073709551615s1s", "too long"),
+        ];
+        for (input, error) in cases {
+            assert!(parse_duration(input).unwrap_err().contains(error), "{}", input);
+        }
+    }
+}
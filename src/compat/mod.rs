@@ -0,0 +1,79 @@
+//! module to lay out the downloads like other downloaders do, see `--compat`.
+//!
+//! With [Compat::GalleryDl](Compat::GalleryDl) the output drops into an archive of gallery-dl's twitter extractor: user
+//! directories under `twitter/`, files named `{tweet_id}_{num}.{extension}` and sidecars `<file>.json` with the keys of
+//! gallery-dl's metadata. Values unknown to the API of this crate, like the display name of the author, are left out
+//! or filled with their closest match.
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+use time::macros::format_description;
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::{Media, Tweet};
+
+use crate::twitter::media_count;
+
+/// Downloader whose output is mirrored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// gallery-dl's twitter extractor
+    GalleryDl,
+}
+
+impl FromStr for Compat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gallery-dl" => Ok(Compat::GalleryDl),
+            _ => Err(format!("unknown compat mode '{}'. Expected gallery-dl", s)),
+        }
+    }
+}
+
+/// Directory of gallery-dl's twitter extractor under its base directory, holding the user directories.
+pub const GALLERY_DL_CATEGORY: &str = "twitter";
+
+/// File names of gallery-dl's twitter extractor, `{tweet_id}_{num}.{extension}`.
+pub const GALLERY_DL_TEMPLATE: &str = "{tweet_id}_{index}.{ext}";
+
+/// Returns the metadata of gallery-dl for `media` attached to `tweet` of `username` at `media_index` of its attachments.
+///
+/// `author` is the username of the author of `tweet`, `username` unless it was quoted or reposted.
+pub fn gallery_dl_metadata(username: &str, author: &str, tweet: &Tweet, media: &Media, media_index: usize) -> Value {
+    let referenced = |kind: ReferencedTweetKind| tweet.referenced_tweets.iter().flatten()
+        .find(|referenced| referenced.kind == kind)
+        .map_or(0, |referenced| referenced.id.as_u64());
+    let original = media.url.as_ref().and_then(|url| url.path().rsplit('/').next().map(String::from)).unwrap_or_default();
+    let (filename, extension) = original.rsplit_once('.').map_or((original.as_str(), ""), |(stem, ext)| (stem, ext));
+    let author_id = tweet.author_id.as_ref().map_or(0, |id| id.as_u64());
+    let metrics = tweet.public_metrics.as_ref();
+
+    json!({
+        "tweet_id": tweet.id.as_u64(),
+        "retweet_id": 0,
+        "quote_id": referenced(ReferencedTweetKind::Quoted),
+        "reply_id": referenced(ReferencedTweetKind::RepliedTo),
+        "conversation_id": tweet.conversation_id.as_ref().unwrap_or(&tweet.id).as_u64(),
+        "date": tweet.created_at.and_then(|d| d.format(format_description!("[year]-[month]-[day] [hour]:[minute]:[second]")).ok()),
+        "author": { "id": author_id, "name": author, "nick": author },
+        "user": { "name": username, "nick": username },
+        "sensitive": tweet.possibly_sensitive.unwrap_or(false),
+        "favorite_count": metrics.map_or(0, |m| m.like_count),
+        "quote_count": metrics.map_or(0, |m| m.quote_count.unwrap_or(0)),
+        "reply_count": metrics.map_or(0, |m| m.reply_count),
+        "retweet_count": metrics.map_or(0, |m| m.retweet_count),
+        "hashtags": tweet.entities.as_ref().and_then(|e| e.hashtags.as_ref())
+            .map(|hashtags| hashtags.iter().map(|h| h.tag.clone()).collect::<Vec<_>>())
+            .unwrap_or_default(),
+        "content": tweet.text,
+        "count": media_count(tweet),
+        "category": GALLERY_DL_CATEGORY,
+        "subcategory": "timeline",
+        "num": media_index + 1,
+        "filename": filename,
+        "extension": extension,
+        "width": media.width,
+        "height": media.height,
+    })
+}
@@ -0,0 +1,77 @@
+//! module to convert downloaded photos to another format, see `--convert`.
+//!
+//! Photos are decoded and encoded again as lossless WebP or as JPEG of a given quality, e.g. to shrink an archive of
+//! PNG screenshots. The converted file replaces the download, named like it with the extension of the new format.
+//! The [sidecar](crate::sidecar) keeps the original extension.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ImageError, ImageFormat};
+
+use crate::common::write_atomic;
+
+/// Format photos are converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    /// lossless WebP
+    Webp,
+    /// JPEG of the configured quality
+    Jpeg,
+}
+
+impl ConvertFormat {
+    /// Extension of the converted files.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConvertFormat::Webp => "webp",
+            ConvertFormat::Jpeg => "jpg",
+        }
+    }
+
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            ConvertFormat::Webp => ImageFormat::WebP,
+            ConvertFormat::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+}
+
+impl FromStr for ConvertFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webp" => Ok(ConvertFormat::Webp),
+            "jpeg" | "jpg" => Ok(ConvertFormat::Jpeg),
+            _ => Err(format!("unknown format '{}'. Expected webp or jpeg", s)),
+        }
+    }
+}
+
+/// Default JPEG quality of converted photos.
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// Converts the photo `file` to `format`, JPEGs with `quality` from 1 to 100, and deletes `file`.
+///
+/// Returns the path of the converted file, or None if `file` is of `format` already.
+pub fn convert(file: &Path, format: ConvertFormat, quality: u8) -> Result<Option<PathBuf>, ImageError> {
+    let current = ImageFormat::from_path(file).ok();
+    if current == Some(format.image_format()) {
+        return Ok(None);
+    }
+    let image = image::open(file)?;
+    let converted = file.with_extension(format.extension());
+
+    let mut buf = Vec::new();
+    match format {
+        ConvertFormat::Webp => image.write_with_encoder(WebPEncoder::new_lossless(&mut buf))?,
+        // JPEG has no alpha channel
+        ConvertFormat::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))?,
+    }
+    write_atomic(&converted, &buf)?;
+    fs::remove_file(file)?;
+    Ok(Some(converted))
+}
@@ -0,0 +1,114 @@
+//! module to pick the date a downloaded media file is stamped with and the time zone dates are shown in.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use filetime::FileTime;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use time_tz::{timezones, OffsetDateTimeExt, TimeZone, Tz};
+
+/// Which date wins when a file carries its own camera date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePolicy {
+    /// Always the tweet's date.
+    Tweet,
+    /// The EXIF `DateTimeOriginal` of the image if present, the tweet's date otherwise. For photographer accounts.
+    Camera,
+}
+
+impl FromStr for DatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tweet" => Ok(DatePolicy::Tweet),
+            "camera" => Ok(DatePolicy::Camera),
+            _ => Err(format!("unknown date policy '{}'. Expected tweet or camera", s)),
+        }
+    }
+}
+
+/// Reads the original camera date (EXIF `DateTimeOriginal`) of the image at `path`.
+///
+/// Dates without an offset are taken as UTC. Returns None if the file has no EXIF or no such date.
+pub fn camera_date(path: &Path) -> Option<OffsetDateTime> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    let ascii = match &field.value {
+        exif::Value::Ascii(values) => values.first()?,
+        _ => return None,
+    };
+    let dt = exif::DateTime::from_ascii(ascii).ok()?;
+
+    let date = Date::from_calendar_date(dt.year as i32, Month::try_from(dt.month).ok()?, dt.day).ok()?;
+    let time = Time::from_hms(dt.hour, dt.minute, dt.second).ok()?;
+    let offset = match dt.offset {
+        Some(minutes) => UtcOffset::from_whole_seconds(minutes as i32 * 60).ok()?,
+        None => UtcOffset::UTC,
+    };
+    Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+}
+
+/// Returns the date to stamp the file at `path` with according to `policy`.
+pub fn file_date(policy: DatePolicy, tweet_date: Option<OffsetDateTime>, path: &Path) -> Option<OffsetDateTime> {
+    match policy {
+        DatePolicy::Tweet => tweet_date,
+        DatePolicy::Camera => camera_date(path).or(tweet_date),
+    }
+}
+
+/// Sets the modification time of the file at `path` to `date`.
+pub fn set_mtime(path: &Path, date: OffsetDateTime) -> Result<(), io::Error> {
+    let mtime = FileTime::from_unix_time(date.unix_timestamp(), date.nanosecond());
+    filetime::set_file_mtime(path, mtime)
+}
+
+/// Twitter's epoch of Tweet ids in milliseconds, 2010-11-04T01:42:54.657Z.
+const TWITTER_EPOCH_MS: i128 = 1_288_834_974_657;
+
+/// Returns the creation date encoded in the Tweet id `tweet_id`, a Snowflake id.
+pub fn tweet_id_date(tweet_id: u64) -> Option<OffsetDateTime> {
+    let millis = (tweet_id >> 22) as i128 + TWITTER_EPOCH_MS;
+    OffsetDateTime::from_unix_timestamp_nanos(millis * 1_000_000).ok()
+}
+
+/// Time zone for grouping and naming by date, e.g. `Europe/Berlin`. Tweet dates are UTC.
+#[derive(Clone, Copy)]
+pub struct Timezone(&'static Tz);
+
+impl Timezone {
+    /// Returns `date` in this time zone.
+    pub fn local(&self, date: OffsetDateTime) -> OffsetDateTime {
+        date.to_timezone(self.0)
+    }
+
+    /// Returns the IANA name, e.g. `Europe/Berlin`.
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone(timezones::db::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        timezones::get_by_name(s)
+            .map(Timezone)
+            .ok_or_else(|| format!("unknown time zone '{}'. Expected an IANA name like Europe/Berlin", s))
+    }
+}
+
+impl fmt::Debug for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timezone({})", self.name())
+    }
+}
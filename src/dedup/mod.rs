@@ -0,0 +1,101 @@
+//! module to deduplicate downloaded media by their content.
+//!
+//! The same image is often attached to several Tweets, or to the Tweets of several archived accounts. Every downloaded
+//! file is looked up by its SHA-256 in the [state database](crate::state), which keeps an index of the hashes. A file
+//! with the content of an earlier download is deleted again and, depending on the [Dedup](Dedup) mode, hardlinked to it.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::state::StateStore;
+
+/// What to do with a downloaded file that has the content of an earlier download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dedup {
+    /// keep every file
+    Off,
+    /// delete the file, the media is recorded as a duplicate of the earlier download, owning no file
+    Skip,
+    /// replace the file with a hardlink to the earlier download
+    Hardlink,
+}
+
+impl FromStr for Dedup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Dedup::Off),
+            "skip" => Ok(Dedup::Skip),
+            "hardlink" => Ok(Dedup::Hardlink),
+            _ => Err(format!("unknown dedup mode '{}'. Expected skip, hardlink or off", s)),
+        }
+    }
+}
+
+/// Where a deduplicated file ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Duplicate {
+    /// the file was deleted, the content is at the path
+    Skipped(PathBuf),
+    /// the file is a hardlink to the path
+    Hardlinked(PathBuf),
+}
+
+/// Duplicates found by a run and the bytes they would have taken.
+#[derive(Debug, Default)]
+pub struct DedupStats {
+    files: AtomicU64,
+    saved_bytes: AtomicU64,
+}
+
+impl DedupStats {
+    pub fn add(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.saved_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn files(&self) -> u64 {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    pub fn saved_bytes(&self) -> u64 {
+        self.saved_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Deduplicates the downloaded `file` of `media_key` with content `sha256` according to `mode`.
+///
+/// Returns None if there is no earlier download with the same content, or `mode` is [Dedup::Off](Dedup::Off).
+/// A hardlink that cannot be created, e.g. across volumes, keeps the file and returns None.
+pub fn deduplicate(mode: Dedup, state: &StateStore, media_key: &str, file: &Path, sha256: &str) -> Result<Option<Duplicate>, io::Error> {
+    if mode == Dedup::Off {
+        return Ok(None);
+    }
+    let candidates = state.paths_by_sha256(sha256, media_key).map_err(io::Error::other)?;
+    let original = match candidates.into_iter().find(|p| p != file && p.is_file()) {
+        Some(original) => original,
+        None => return Ok(None),
+    };
+
+    match mode {
+        Dedup::Off => Ok(None),
+        Dedup::Skip => {
+            fs::remove_file(file)?;
+            Ok(Some(Duplicate::Skipped(original)))
+        }
+        Dedup::Hardlink => {
+            // link next to the file first, so the file is only replaced once the link exists
+            let mut link = file.as_os_str().to_owned();
+            link.push(".link");
+            let link = PathBuf::from(link);
+            if fs::hard_link(&original, &link).is_err() {
+                return Ok(None);
+            }
+            fs::rename(&link, file)?;
+            Ok(Some(Duplicate::Hardlinked(original)))
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! module to embed the provenance of a media file into the file itself.
+//!
+//! JPEGs get the Tweet text as EXIF `ImageDescription`, the author as `Artist`, the Tweet date as `DateTimeOriginal`
+//! and the Tweet url as `UserComment`. The other EXIF fields of the file are kept, as is an existing
+//! `DateTimeOriginal`, the camera date. Formats without EXIF support get an XMP sidecar `<filename>.xmp` instead.
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use exif::experimental::Writer;
+use exif::{Field, In, Tag, Value};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::common::write_atomic;
+
+/// Provenance of a media file.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub text: String,
+    pub author: String,
+    pub date: Option<OffsetDateTime>,
+    /// url of the Tweet
+    pub url: String,
+}
+
+/// EXIF fields of the file that are not carried over, the writer lays them out itself or they point into the old layout.
+const SKIPPED_TAGS: [Tag; 4] = [Tag::ExifIFDPointer, Tag::GPSInfoIFDPointer, Tag::InteropIFDPointer, Tag::MakerNote];
+
+/// Largest payload of a JPEG segment.
+const MAX_SEGMENT_LEN: usize = 0xFFFF - 2;
+
+/// Returns the path of the XMP sidecar of `media_file`: `media_file` with `.xmp` appended.
+pub fn xmp_path(media_file: &Path) -> PathBuf {
+    let mut path = media_file.as_os_str().to_owned();
+    path.push(".xmp");
+    PathBuf::from(path)
+}
+
+/// Embeds `provenance` into the JPEG at `path`, or writes it as the XMP sidecar of any other file.
+pub fn embed(path: &Path, provenance: &Provenance) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let is_jpeg = path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("jpg") || e.eq_ignore_ascii_case("jpeg"));
+    if is_jpeg {
+        embed_exif(path, provenance)
+    } else {
+        write_atomic(&xmp_path(path), xmp(provenance).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Rewrites the JPEG at `path` with a new EXIF segment holding `provenance`.
+fn embed_exif(path: &Path, provenance: &Provenance) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let data = fs::read(path)?;
+    if data.len() < 4 || data[0..2] != [0xFF, 0xD8] {
+        return Err(format!("{} is not a JPEG", path.display()).into());
+    }
+
+    let existing = exif::Reader::new().read_from_container(&mut Cursor::new(&data)).ok();
+    let tiff = exif_data(existing.as_ref(), provenance)?;
+    if tiff.len() + 6 > MAX_SEGMENT_LEN {
+        return Err(format!("EXIF data of {} bytes does not fit into a JPEG segment", tiff.len()).into());
+    }
+
+    // the segments before the image data, without the old EXIF segment
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0xDA || (0xD0..=0xD7).contains(&marker) {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = (pos + 2 + len).min(data.len());
+        let segment = &data[pos..end];
+        let is_exif = marker == 0xE1 && segment.len() >= 10 && &segment[4..10] == b"Exif\0\0";
+        if !is_exif {
+            segments.push(segment);
+        }
+        pos = end;
+    }
+
+    let app1_len = (2 + 6 + tiff.len()) as u16;
+    let mut app1 = vec![0xFF, 0xE1];
+    app1.extend_from_slice(&app1_len.to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(&tiff);
+
+    let mut out = Vec::with_capacity(data.len() + app1.len());
+    out.extend_from_slice(&data[0..2]);
+    // JFIF wants its APP0 right after the start of image
+    let jfif_first = segments.first().is_some_and(|s| s[1] == 0xE0);
+    if jfif_first {
+        out.extend_from_slice(segments[0]);
+    }
+    out.extend_from_slice(&app1);
+    for segment in segments.iter().skip(if jfif_first { 1 } else { 0 }) {
+        out.extend_from_slice(segment);
+    }
+    out.extend_from_slice(&data[pos..]);
+
+    write_atomic(path, &out)?;
+    Ok(())
+}
+
+/// Returns the TIFF encoded EXIF data of `existing` with the fields of `provenance`.
+fn exif_data(existing: Option<&exif::Exif>, provenance: &Provenance) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let ascii = |tag: Tag, ifd_num: In, value: &str| Field { tag, ifd_num, value: Value::Ascii(vec![value.as_bytes().to_vec()]) };
+
+    let mut ours = vec![
+        ascii(Tag::ImageDescription, In::PRIMARY, &provenance.text),
+        ascii(Tag::Artist, In::PRIMARY, &provenance.author),
+    ];
+    let mut comment = b"ASCII\0\0\0".to_vec();
+    comment.extend_from_slice(provenance.url.as_bytes());
+    ours.push(Field { tag: Tag::UserComment, ifd_num: In::PRIMARY, value: Value::Undefined(comment, 0) });
+
+    let has_camera_date = existing.is_some_and(|e| e.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some());
+    if !has_camera_date {
+        if let Some(date) = provenance.date {
+            let date = date.format(format_description!("[year]:[month]:[day] [hour]:[minute]:[second]"))?;
+            ours.push(ascii(Tag::DateTimeOriginal, In::PRIMARY, &date));
+        }
+    }
+
+    let mut fields: Vec<Field> = Vec::new();
+    if let Some(existing) = existing {
+        for field in existing.fields() {
+            let replaced = ours.iter().any(|f| f.tag == field.tag);
+            if field.ifd_num == In::PRIMARY && !replaced && !SKIPPED_TAGS.contains(&field.tag) {
+                fields.push(field.clone());
+            }
+        }
+    }
+    fields.extend(ours);
+
+    let mut writer = Writer::new();
+    for field in fields.iter() {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, false)?;
+    Ok(tiff.into_inner())
+}
+
+/// Returns the XMP packet of `provenance`.
+fn xmp(provenance: &Provenance) -> String {
+    let date = provenance.date
+        .and_then(|d| d.format(&Rfc3339).ok())
+        .map(|d| format!("   <exif:DateTimeOriginal>{}</exif:DateTimeOriginal>\n", d))
+        .unwrap_or_default();
+    format!("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+             <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:exif=\"http://ns.adobe.com/exif/1.0/\">\n   \
+             <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n   \
+             <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n   \
+             <dc:source>{}</dc:source>\n\
+             {}  \
+             </rdf:Description>\n \
+             </rdf:RDF>\n\
+             </x:xmpmeta>\n\
+             <?xpacket end=\"w\"?>\n",
+            escape_xml(&provenance.text), escape_xml(&provenance.author), escape_xml(&provenance.url), date)
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,101 @@
+//! module to run a command of `--exec` after every downloaded file, for post-processing outside of the crate.
+//!
+//! The command is run by the shell, `sh -c` (`cmd /C` on Windows), with these placeholders replaced by the shell
+//! quoted values of the file:
+//!
+//! - `{path}`: local path of the file
+//! - `{username}`: user the file was downloaded for
+//! - `{tweet_id}`: id of the Tweet
+//! - `{media_key}`: media key of the file
+//! - `{type}`: photo, video or animated_gif
+//!
+//! The same values are passed as the environment variables `TMD_PATH`, `TMD_USERNAME`, `TMD_TWEET_ID`,
+//! `TMD_MEDIA_KEY` and `TMD_MEDIA_TYPE`. A failing command is logged, the download counts all the same.
+use std::path::Path;
+use std::process::Command;
+
+use tracing::{info, warn};
+use twitter_v2::data::MediaType;
+
+/// A downloaded file passed to the [ExecHook](ExecHook).
+#[derive(Debug, Clone)]
+pub struct ExecFile<'a> {
+    pub path: &'a Path,
+    pub username: &'a str,
+    pub tweet_id: &'a str,
+    pub media_key: &'a str,
+    pub media_type: &'a MediaType,
+}
+
+/// Command to run after every downloaded file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecHook {
+    command: String,
+}
+
+impl ExecHook {
+    pub fn new(command: impl Into<String>) -> Self {
+        ExecHook { command: command.into() }
+    }
+
+    /// Runs the command for `file` and waits for it.
+    pub async fn run(&self, file: &ExecFile<'_>) {
+        let values = [
+            ("path", "TMD_PATH", file.path.to_string_lossy().into_owned()),
+            ("username", "TMD_USERNAME", file.username.to_string()),
+            ("tweet_id", "TMD_TWEET_ID", file.tweet_id.to_string()),
+            ("media_key", "TMD_MEDIA_KEY", file.media_key.to_string()),
+            ("type", "TMD_MEDIA_TYPE", media_type_name(file.media_type).to_string()),
+        ];
+        let mut script = self.command.clone();
+        for (placeholder, _, value) in values.iter() {
+            script = script.replace(&format!("{{{}}}", placeholder), &quote(value));
+        }
+        let mut command = shell(&script);
+        for (_, variable, value) in values.iter() {
+            command.env(variable, value);
+        }
+
+        let (username, media_key) = (file.username.to_string(), file.media_key.to_string());
+        match tokio::task::spawn_blocking(move || command.status()).await {
+            Ok(Ok(status)) if status.success() => info!("username: {}, media_key: {}. Exec command done", username, media_key),
+            Ok(Ok(status)) => warn!("username: {}, media_key: {}. Exec command failed with {}", username, media_key, status),
+            Ok(Err(e)) => warn!("username: {}, media_key: {}. Cannot run the exec command: {}", username, media_key, e),
+            Err(e) => warn!("username: {}, media_key: {}. Exec command task failed: {}", username, media_key, e),
+        }
+    }
+}
+
+fn media_type_name(media_type: &MediaType) -> &'static str {
+    match media_type {
+        MediaType::Photo => "photo",
+        MediaType::Video => "video",
+        MediaType::AnimatedGif => "animated_gif",
+    }
+}
+
+#[cfg(not(windows))]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+#[cfg(windows)]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(script);
+    command
+}
+
+/// Quotes `value` as a single argument of the shell.
+#[cfg(not(windows))]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quotes `value` as a single argument of the shell.
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
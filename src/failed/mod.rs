@@ -0,0 +1,112 @@
+//! module to retry the failed downloads recorded in the [state database](crate::state), see `retry`.
+//!
+//! Every download that failed, e.g. on a CDN hiccup, is kept with its Tweet, media key, url, error and number of
+//! attempts. `retry` downloads only those again, without walking the timelines. A url rejected as expired is
+//! refreshed through the API if a token is given.
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use reqwest::{Client, Url};
+use tracing::{error, info};
+
+use crate::common::sha256_file;
+use crate::http;
+use crate::manifest;
+use crate::state::{FailedMedia, MediaRecord, StateStore};
+use crate::twitter;
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::retry::{RetryPolicy, DEFAULT_STALL_TIMEOUT};
+
+/// Outcome of [retry_failed](retry_failed).
+#[derive(Debug, Default)]
+pub struct RetryReport {
+    /// failed downloads attempted again
+    pub retried: usize,
+    pub recovered: usize,
+    /// left out for reaching the maximum attempts
+    pub given_up: usize,
+}
+
+/// Downloads the failed downloads of `usernames` (every user if empty) under `output_dir` again, leaving out those
+/// that failed `max_attempts` times. `credentials` refresh expired urls, if set.
+pub async fn retry_failed(output_dir: &Path, usernames: &[String], credentials: Option<&Credentials>, retry: &RetryPolicy, max_attempts: Option<u32>, run_id: &str) -> Result<RetryReport, Box<dyn Error + Send + Sync>> {
+    let state = StateStore::open(output_dir)?;
+    let mut failed = Vec::new();
+    if usernames.is_empty() {
+        failed = state.failed_media(None)?;
+    } else {
+        for username in usernames {
+            failed.extend(state.failed_media(Some(username))?);
+        }
+    }
+    info!("Retrying {} failed downloads", failed.len());
+
+    let client = http::media_client()?;
+    let mut report = RetryReport::default();
+    let mut recovered_users = BTreeSet::new();
+    for media in failed {
+        if max_attempts.is_some_and(|max| media.attempts >= max) {
+            report.given_up += 1;
+            continue;
+        }
+        report.retried += 1;
+        let local_path = media.local_path.clone().unwrap_or_else(|| default_path(output_dir, &media));
+        match download(&client, retry, credentials, &state, &media, &local_path, run_id).await {
+            Ok(()) => {
+                info!("username: {}, media_key: {}, local: {}. Recovered", media.username, media.media_key, local_path.display());
+                report.recovered += 1;
+                recovered_users.insert(media.username.clone());
+            }
+            Err(e) => {
+                error!("username: {}, media_key: {}, attempts: {}. Failed again: {}", media.username, media.media_key, media.attempts + 1, e);
+                state.record_failed(run_id, &media.username, &media.media_key, &media.tweet_id, &media.url, Some(&local_path), &e.to_string())?;
+            }
+        }
+    }
+    for username in recovered_users {
+        manifest::refresh(&state, output_dir, &username)?;
+    }
+    Ok(report)
+}
+
+/// Returns the path of a failure recorded without one: in the user directory, named by the default template.
+fn default_path(output_dir: &Path, media: &FailedMedia) -> PathBuf {
+    let original = media.url.rsplit('/').next().unwrap_or_default();
+    output_dir.join(&media.username).join(format!("{}_{}_{}", media.media_key, media.username, original))
+}
+
+/// Downloads `media` to `local_path` and records it.
+async fn download(client: &Client, retry: &RetryPolicy, credentials: Option<&Credentials>, state: &StateStore, media: &FailedMedia, local_path: &Path, run_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut url = Url::parse(&media.url)?;
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let size = match twitter::fetch_file(client, retry, DEFAULT_STALL_TIMEOUT, None, &media.username, &media.media_key, url.clone(), local_path).await {
+        Ok(size) => size,
+        Err(e) if e.is_expired_link() => match credentials {
+            Some(credentials) => {
+                url = twitter::refresh_media_url(credentials, &media.tweet_id, &media.media_key).await?;
+                twitter::fetch_file(client, retry, DEFAULT_STALL_TIMEOUT, None, &media.username, &media.media_key, url.clone(), local_path).await?
+            }
+            None => return Err(DownloadError::Other(format!("{}. Pass a token to refresh the url", e)).into()),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    let record = MediaRecord {
+        username: media.username.clone(),
+        media_key: media.media_key.clone(),
+        tweet_id: media.tweet_id.clone(),
+        url: url.to_string(),
+        local_path: local_path.into(),
+        size,
+        sha256: sha256_file(local_path).ok(),
+        run_id: run_id.into(),
+        duplicate_of: None,
+    };
+    state.record_downloaded(&record)?;
+    Ok(())
+}
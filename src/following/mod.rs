@@ -0,0 +1,68 @@
+//! module to list the accounts a user follows, to download all of them with `download --crawl-following`.
+//!
+//! The accounts are walked page by page in the order of the API, most recently followed first. Accounts with fewer
+//! than a minimum of followers are left out, and the walk stops at a maximum of accounts. Rate limits are waited out.
+use tracing::info;
+use twitter_v2::query::UserField;
+
+use crate::shutdown;
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::get_twitter_id;
+use crate::twitter::ratelimit::{self, RateLimit};
+
+/// Largest page of followed accounts the API answers.
+const PAGE_SIZE: usize = 1000;
+
+/// Returns the usernames of the accounts `username` follows with at least `min_followers` followers, at most
+/// `max_accounts` of them.
+pub async fn following(credentials: &Credentials, username: &str, min_followers: u64, max_accounts: Option<usize>) -> Result<Vec<String>, DownloadError> {
+    let api = credentials.api();
+    let id = get_twitter_id(&api, username).await?;
+
+    let mut usernames = Vec::new();
+    let mut seen = 0;
+    let mut pagination_token: Option<String> = None;
+    loop {
+        if shutdown::is_requested() {
+            break;
+        }
+        let mut request = api.get_user_following(id);
+        request
+            .max_results(PAGE_SIZE)
+            .user_fields([UserField::PublicMetrics]);
+        if let Some(token) = &pagination_token {
+            request.pagination_token(token);
+        }
+
+        let response = match request.send().await.map_err(DownloadError::from) {
+            Ok(response) => response,
+            Err(e) if e.is_rate_limited() => {
+                if !ratelimit::wait(username, &RateLimit::default()).await {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        for user in response.data().into_iter().flatten() {
+            seen += 1;
+            let followers = user.public_metrics.as_ref().map_or(0, |m| m.followers_count as u64);
+            if followers < min_followers {
+                continue;
+            }
+            usernames.push(user.username.clone());
+            if max_accounts.is_some_and(|max| usernames.len() >= max) {
+                info!("username: {}. Stopping at {} followed accounts", username, usernames.len());
+                return Ok(usernames);
+            }
+        }
+        pagination_token = response.meta().and_then(|meta| meta.next_token.clone());
+        if pagination_token.is_none() {
+            break;
+        }
+    }
+    info!("username: {}. Follows {} accounts, {} with at least {} followers", username, seen, usernames.len(), min_followers);
+    Ok(usernames)
+}
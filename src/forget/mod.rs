@@ -0,0 +1,210 @@
+//! module to permanently remove media from the archive, e.g. for takedown requests.
+//!
+//! A forgotten media file is deleted from disk and its media key is put on the do-not-redownload list of the
+//! [state database](crate::state). Downloads skip media keys on that list.
+//!
+//! With a retention the files are [quarantined](crate::trash) instead of deleted, and can be restored until it expires.
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::embed;
+use crate::sidecar;
+use crate::state::StateStore;
+use crate::trash;
+
+/// Forgets the media given by `target`: a media key (e.g. `3_1234567890`) or the path of a downloaded file.
+///
+/// The metadata [sidecars](crate::sidecar) and [XMP sidecars](crate::embed::xmp_path) of the files are deleted as well.
+/// With `quarantine` all of them are moved to the [trash](crate::trash) for that long instead.
+///
+/// Returns the media key and the number of media files deleted or quarantined.
+pub fn forget(output_dir: &Path, target: &str, quarantine: Option<Duration>) -> Result<(String, usize), Box<dyn Error + Send + Sync>> {
+    trash::purge_expired(output_dir)?;
+    let state = StateStore::open(output_dir)?;
+    let media_key = resolve_media_key(&state, target)?;
+
+    // skipped duplicates own no path, and a path recorded for another media, by older versions, belongs to it
+    let mut paths = Vec::new();
+    for path in state.local_paths(&media_key)? {
+        let others = state.other_media_keys_at(&path, &media_key)?;
+        if others.is_empty() {
+            paths.push(path);
+        } else {
+            warn!("media_key: {}, local: {}. Kept, the file is recorded for {} as well", media_key, path.display(), others.join(", "));
+        }
+    }
+    for path in paths.iter() {
+        let released = state.release_duplicates(path)?;
+        if released > 0 {
+            info!("media_key: {}, local: {}. {} duplicates of the file will be downloaded again", media_key, path.display(), released);
+        }
+    }
+
+    if let Some(retention) = quarantine {
+        let media_files = paths.iter().filter(|p| p.is_file()).count();
+        let with_sidecars: Vec<_> = paths.iter()
+            .flat_map(|p| [p.clone(), sidecar::sidecar_path(p), sidecar::text_path(p), embed::xmp_path(p)])
+            .collect();
+        trash::quarantine(output_dir, &media_key, &with_sidecars, retention)?;
+        state.forget(&media_key)?;
+        info!("media_key: {}. Will not be downloaded again unless restored", media_key);
+        return Ok((media_key, media_files));
+    }
+
+    let mut deleted = 0;
+    for path in paths {
+        if path.exists() {
+            fs::remove_file(&path)?;
+            info!("media_key: {}, local: {}. Deleted", media_key, path.display());
+            deleted += 1;
+        }
+        for sidecar in [sidecar::sidecar_path(&path), sidecar::text_path(&path), embed::xmp_path(&path)] {
+            if sidecar.exists() {
+                fs::remove_file(&sidecar)?;
+            }
+        }
+    }
+    if deleted == 0 {
+        warn!("media_key: {}. No downloaded file found, only adding it to the do-not-redownload list", media_key);
+    }
+
+    state.forget(&media_key)?;
+    info!("media_key: {}. Will never be downloaded again", media_key);
+    Ok((media_key, deleted))
+}
+
+/// Returns the media key for `target`.
+///
+/// A path is looked up in the state database, falling back to the `{media_key}_{username}_{name}` filename pattern.
+fn resolve_media_key(state: &StateStore, target: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let path = Path::new(target);
+    if path.is_file() {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        for candidate in [path, canonical.as_path()] {
+            if let Some(media_key) = state.media_key_by_path(candidate)? {
+                return Ok(media_key);
+            }
+        }
+        let filename = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+        return media_key_from_filename(filename)
+            .ok_or_else(|| format!("Cannot find the media key of {}", target).into());
+    }
+
+    if is_media_key(target) {
+        Ok(target.into())
+    } else {
+        Err(format!("{} is neither a downloaded file nor a media key", target).into())
+    }
+}
+
+/// Extracts the media key from a `{media_key}_{username}_{name}` filename. Media keys look like `3_1234567890`.
+pub fn media_key_from_filename(filename: &str) -> Option<String> {
+    let mut parts = filename.splitn(3, '_');
+    let media_key = format!("{}_{}", parts.next()?, parts.next()?);
+    if is_media_key(&media_key) { Some(media_key) } else { None }
+}
+
+fn is_media_key(s: &str) -> bool {
+    match s.split_once('_') {
+        Some((kind, id)) => !kind.is_empty() && !id.is_empty()
+            && kind.chars().all(|c| c.is_ascii_digit())
+            && id.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::state::MediaRecord;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-forget-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Records and writes a download of `media_key` at `<dir>/alice/<file_name>` with its sidecar.
+    fn download(dir: &Path, media_key: &str, file_name: &str) -> PathBuf {
+        let path = dir.join("alice").join(file_name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "image").unwrap();
+        fs::write(sidecar::sidecar_path(&path), "{}").unwrap();
+        StateStore::open(dir).unwrap().record_downloaded(&MediaRecord {
+            username: "alice".into(),
+            media_key: media_key.into(),
+            tweet_id: "20".into(),
+            url: format!("https://pbs.twimg.com/media/{}", file_name),
+            local_path: path.clone(),
+            size: 5,
+            sha256: None,
+            run_id: "run".into(),
+            duplicate_of: None,
+        }).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_media_keys() {
+        assert!(is_media_key("3_1234567890"));
+        assert!(!is_media_key("3_"));
+        assert!(!is_media_key("_1234567890"));
+        assert!(!is_media_key("3_12a"));
+        assert!(!is_media_key("31234567890"));
+
+        assert_eq!(media_key_from_filename("3_1234567890_alice_photo.jpg").as_deref(), Some("3_1234567890"));
+        assert_eq!(media_key_from_filename("alice_3_1234567890.jpg"), None);
+        assert_eq!(media_key_from_filename("photo.jpg"), None);
+    }
+
+    #[test]
+    fn deletes_the_files_of_a_media_key() {
+        let dir = test_dir("delete");
+        let path = download(&dir, "3_1", "a.jpg");
+
+        assert_eq!(forget(&dir, "3_1", None).unwrap(), ("3_1".to_string(), 1));
+        assert!(!path.exists() && !sidecar::sidecar_path(&path).exists());
+        assert!(StateStore::open(&dir).unwrap().is_forgotten("3_1").unwrap());
+
+        // unknown media keys are put on the list all the same
+        assert_eq!(forget(&dir, "3_2", None).unwrap(), ("3_2".to_string(), 0));
+        assert!(forget(&dir, "not a media key", None).is_err());
+    }
+
+    #[test]
+    fn resolves_the_media_key_of_a_file() {
+        let dir = test_dir("path");
+        let recorded = download(&dir, "3_1", "a.jpg");
+        let unrecorded = dir.join("7_2_alice_b.mp4");
+        fs::write(&unrecorded, "video").unwrap();
+        let unknown = dir.join("b.mp4");
+        fs::write(&unknown, "video").unwrap();
+
+        let state = StateStore::open(&dir).unwrap();
+        assert_eq!(resolve_media_key(&state, &recorded.to_string_lossy()).unwrap(), "3_1");
+        assert_eq!(resolve_media_key(&state, &unrecorded.to_string_lossy()).unwrap(), "7_2");
+        assert!(resolve_media_key(&state, &unknown.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn quarantines_the_files_of_a_media_key() {
+        let dir = test_dir("quarantine");
+        let path = download(&dir, "3_1", "a.jpg");
+
+        assert_eq!(forget(&dir, "3_1", Some(trash::DEFAULT_RETENTION)).unwrap(), ("3_1".to_string(), 1));
+        assert!(!path.exists() && !sidecar::sidecar_path(&path).exists());
+        let entries = trash::entries(&dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].files.len(), 2);
+
+        assert_eq!(trash::restore(&dir, "3_1").unwrap(), 2);
+        assert!(path.exists());
+    }
+}
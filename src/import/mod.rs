@@ -0,0 +1,346 @@
+//! module to import the media of an official Twitter data export, the zip of "Download an archive of your data".
+//!
+//! The Tweets are read from `data/tweets.js` (`data/tweet.js` in older exports, split into `-part1.js`, ... when
+//! large), which reach back further than the 3200 Tweets of the API. Their photos are downloaded like those of
+//! `download`: named by the filename template and layout, deduplicated and recorded in the
+//! [state database](crate::state), so later runs skip them. A photo whose url is gone is taken from the
+//! `data/tweets_media/` directory of the export if it is there.
+//!
+//! Like `download`, replies and Retweets are left out.
+use std::error::Error;
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use reqwest::{Client, Url};
+use serde_json::Value;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+use twitter_v2::data::MediaType;
+use zip::ZipArchive;
+
+use crate::common::sha256_file;
+use crate::dates::{self, Timezone};
+use crate::dedup::{self, Dedup, Duplicate};
+use crate::http;
+use crate::lock;
+use crate::manifest;
+use crate::state::{MediaRecord, StateStore};
+use crate::twitter;
+use crate::twitter::filename::{FilenameTemplate, FilenameValues, Layout};
+use crate::twitter::retry::{RetryPolicy, DEFAULT_STALL_TIMEOUT};
+
+/// Date format of the Tweets of an export, e.g. `Wed Oct 10 20:19:24 +0000 2018`.
+const CREATED_AT_FORMAT: &[FormatItem<'static>] = format_description!(
+    "[weekday repr:short] [month repr:short] [day] [hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute] [year]"
+);
+
+/// Entries of the export holding the Tweets.
+const TWEETS_PATTERN: &str = r"^data/tweets?(-part\d+)?\.js$";
+
+/// Directories of the export holding the media files, named `<tweet id>-<remote file name>`.
+const MEDIA_DIRS: [&str; 2] = ["data/tweets_media", "data/tweet_media"];
+
+/// Settings of an import, those of `download` of the same name.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// handle of the archive, read from `data/account.js` if None
+    pub username: Option<String>,
+    pub filename_template: FilenameTemplate,
+    pub layout: Layout,
+    pub timezone: Timezone,
+    pub dedup: Dedup,
+    pub retry: RetryPolicy,
+    pub run_id: String,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub username: String,
+    /// Tweets in the export, replies and Retweets included
+    pub tweets: usize,
+    pub downloaded: u32,
+    /// photos downloaded before
+    pub skipped: u32,
+    pub failed: u32,
+}
+
+/// A photo of a Tweet of the export.
+#[derive(Debug)]
+struct ExportedPhoto {
+    tweet_id: String,
+    media_key: String,
+    url: Url,
+    index: usize,
+    count: usize,
+    date: Option<OffsetDateTime>,
+}
+
+/// Reads the `window.YTD.<name>.part0 = [...]` file `name` of the export as its JSON array.
+fn read_ytd(zip: &mut ZipArchive<File>, name: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+    let mut contents = String::new();
+    zip.by_name(name)?.read_to_string(&mut contents)?;
+    let json = match contents.find('=') {
+        Some(i) if !contents.trim_start().starts_with('[') => &contents[i + 1..],
+        _ => &contents[..],
+    };
+    match serde_json::from_str(json.trim())? {
+        Value::Array(items) => Ok(items),
+        _ => Err(format!("{} is not a list", name).into()),
+    }
+}
+
+/// Returns the handle of the account of the export.
+fn account_username(zip: &mut ZipArchive<File>) -> Result<String, Box<dyn Error + Send + Sync>> {
+    read_ytd(zip, "data/account.js")?.iter()
+        .find_map(|item| item.pointer("/account/username").and_then(Value::as_str).map(String::from))
+        .ok_or_else(|| "data/account.js holds no username, pass -u".into())
+}
+
+/// Returns the photos of the exported Tweet `item`, none for replies and Retweets.
+fn exported_photos(item: &Value) -> Vec<ExportedPhoto> {
+    let tweet = item.get("tweet").unwrap_or(item);
+    let text = tweet.get("full_text").or_else(|| tweet.get("text")).and_then(Value::as_str).unwrap_or_default();
+    if text.starts_with("RT @") || tweet.get("in_reply_to_status_id_str").is_some_and(|id| !id.is_null()) {
+        return Vec::new();
+    }
+    let tweet_id = match tweet.get("id_str").and_then(Value::as_str) {
+        Some(id) => id.to_string(),
+        None => return Vec::new(),
+    };
+    let date = tweet.get("created_at").and_then(Value::as_str)
+        .and_then(|d| OffsetDateTime::parse(d, CREATED_AT_FORMAT).ok())
+        .or_else(|| tweet_id.parse().ok().and_then(dates::tweet_id_date));
+
+    let media = tweet.pointer("/extended_entities/media").or_else(|| tweet.pointer("/entities/media"))
+        .and_then(Value::as_array).cloned().unwrap_or_default();
+    let count = media.len();
+    media.iter().enumerate()
+        .filter(|(_, m)| m.get("type").and_then(Value::as_str) == Some("photo"))
+        .filter_map(|(index, m)| Some(ExportedPhoto {
+            tweet_id: tweet_id.clone(),
+            // the media key of the API, type 3 being photos
+            media_key: format!("3_{}", m.get("id_str").and_then(Value::as_str)?),
+            url: Url::parse(m.get("media_url_https").and_then(Value::as_str)?).ok()?,
+            index,
+            count,
+            date,
+        }))
+        .collect()
+}
+
+/// Imports the photos of the export at `path` into the archive under `output_dir`.
+pub async fn import_archive(path: &Path, output_dir: &Path, options: &ImportOptions) -> Result<ImportReport, Box<dyn Error + Send + Sync>> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let username = match &options.username {
+        Some(username) => username.clone(),
+        None => account_username(&mut zip)?,
+    };
+
+    let tweets_pattern = Regex::new(TWEETS_PATTERN).unwrap();
+    let mut names: Vec<String> = zip.file_names().filter(|name| tweets_pattern.is_match(name)).map(String::from).collect();
+    names.sort();
+    if names.is_empty() {
+        return Err(format!("{} holds no data/tweets.js. Is it a Twitter data export?", path.display()).into());
+    }
+    let mut items = Vec::new();
+    for name in names.iter() {
+        items.extend(read_ytd(&mut zip, name)?);
+    }
+    let photos: Vec<ExportedPhoto> = items.iter().flat_map(exported_photos).collect();
+    info!("username: {}, export: {}. {} Tweets with {} photos", username, path.display(), items.len(), photos.len());
+
+    let user_output_dir = output_dir.join(&username);
+    DirBuilder::new().recursive(true).create(&user_output_dir)?;
+    let _lock = lock::acquire(&user_output_dir, &username, false).await?;
+    let state = StateStore::open(output_dir)?;
+    let client = http::media_client()?;
+    let mut report = ImportReport { username: username.clone(), tweets: items.len(), ..Default::default() };
+    for photo in photos.iter() {
+        if state.downloaded_path(&username, &photo.media_key)?.is_some() || state.is_forgotten(&photo.media_key)? {
+            report.skipped += 1;
+            continue;
+        }
+        match import_photo(&client, &state, &mut zip, &user_output_dir, &username, photo, options).await {
+            Ok(true) => report.downloaded += 1,
+            Ok(false) => report.skipped += 1,
+            Err(e) => {
+                error!("username: {}, media_key: {}. Cannot import: {}", username, photo.media_key, e);
+                if let Err(db_err) = state.record_failed(&options.run_id, &username, &photo.media_key, &photo.tweet_id, photo.url.as_str(), None, &e.to_string()) {
+                    error!("username: {}, media_key: {}. Cannot record the failure: {}", username, photo.media_key, db_err);
+                }
+                report.failed += 1;
+            }
+        }
+    }
+    manifest::refresh(&state, output_dir, &username)?;
+    Ok(report)
+}
+
+/// Downloads `photo`, or extracts it from the export, and records it. Returns false if the file was there already.
+async fn import_photo(client: &Client, state: &StateStore, zip: &mut ZipArchive<File>, user_output_dir: &Path, username: &str, photo: &ExportedPhoto, options: &ImportOptions) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let original = photo.url.path().rsplit('/').next().unwrap_or_default().to_string();
+    let filename = options.filename_template.render(&FilenameValues {
+        username,
+        tweet_id: &photo.tweet_id,
+        media_key: &photo.media_key,
+        index: photo.index,
+        count: photo.count,
+        date: photo.date.map(|d| options.timezone.local(d)),
+        original: &original,
+        thread: &photo.tweet_id,
+    });
+    let output_file = user_output_dir.join(options.layout.subdir(photo.date.map(|d| options.timezone.local(d)), &MediaType::Photo)).join(filename);
+    let mut record = MediaRecord {
+        username: username.into(),
+        media_key: photo.media_key.clone(),
+        tweet_id: photo.tweet_id.clone(),
+        url: photo.url.to_string(),
+        local_path: output_file.clone(),
+        size: 0,
+        sha256: None,
+        run_id: options.run_id.clone(),
+        duplicate_of: None,
+    };
+    if output_file.exists() {
+        record.size = fs::metadata(&output_file)?.len();
+        state.record_downloaded(&record)?;
+        return Ok(false);
+    }
+    if let Some(dir) = output_file.parent() {
+        DirBuilder::new().recursive(true).create(dir)?;
+    }
+
+    match twitter::fetch_file(client, &options.retry, DEFAULT_STALL_TIMEOUT, None, username, &photo.media_key, photo.url.clone(), &output_file).await {
+        Ok(_) => (),
+        Err(e) => {
+            if !extract_media(zip, &photo.tweet_id, &original, &output_file)? {
+                return Err(e.into());
+            }
+            warn!("username: {}, media_key: {}. Cannot download ({}), took the file of the export instead", username, photo.media_key, e);
+        }
+    }
+
+    record.size = fs::metadata(&output_file)?.len();
+    record.sha256 = sha256_file(&output_file).ok();
+    let duplicate = match &record.sha256 {
+        Some(sha256) => dedup::deduplicate(options.dedup, state, &photo.media_key, &output_file, sha256)?,
+        None => None,
+    };
+    if let Some(Duplicate::Skipped(original)) = &duplicate {
+        record.duplicate_of = Some(original.clone());
+    }
+    state.record_downloaded(&record)?;
+    info!("username: {}, media_key: {}, local: {}. Imported", username, photo.media_key, output_file.display());
+    Ok(true)
+}
+
+/// Extracts the media file `<tweet_id>-<original>` of the export to `output_file`. Returns false if it is not there.
+fn extract_media(zip: &mut ZipArchive<File>, tweet_id: &str, original: &str, output_file: &PathBuf) -> Result<bool, io::Error> {
+    for dir in MEDIA_DIRS {
+        let name = format!("{}/{}-{}", dir, tweet_id, original);
+        if let Ok(mut entry) = zip.by_name(&name) {
+            io::copy(&mut entry, &mut File::create(output_file)?)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use serde_json::json;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-import-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes an export with the `entries` (name, contents) to `<dir>/export.zip` and opens it.
+    fn export(dir: &Path, entries: &[(&str, &str)]) -> ZipArchive<File> {
+        let path = dir.join("export.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, contents) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        ZipArchive::new(File::open(&path).unwrap()).unwrap()
+    }
+
+    fn photo(id: &str) -> Value {
+        json!({ "type": "photo", "id_str": id, "media_url_https": format!("https://pbs.twimg.com/media/{}.jpg", id) })
+    }
+
+    #[test]
+    fn reads_the_photos_of_tweets() {
+        let tweet = json!({ "tweet": {
+            "id_str": "1600000000000000000",
+            "full_text": "photos",
+            "created_at": "Wed Oct 10 20:19:24 +0000 2018",
+            "extended_entities": { "media": [photo("1"), { "type": "video", "id_str": "2" }, photo("3")] },
+        } });
+        let photos = exported_photos(&tweet);
+        assert_eq!(photos.iter().map(|p| (p.media_key.as_str(), p.index, p.count)).collect::<Vec<_>>(), [("3_1", 0, 3), ("3_3", 2, 3)]);
+        assert_eq!(photos[0].tweet_id, "1600000000000000000");
+        assert_eq!(photos[0].url.as_str(), "https://pbs.twimg.com/media/1.jpg");
+        assert_eq!(photos[0].date, Some(time::macros::datetime!(2018-10-10 20:19:24 UTC)));
+
+        // without a date the Tweet id gives it
+        let tweet = json!({ "id_str": "1600000000000000000", "text": "photo", "entities": { "media": [photo("1")] } });
+        assert_eq!(exported_photos(&tweet)[0].date, dates::tweet_id_date(1600000000000000000));
+    }
+
+    #[test]
+    fn leaves_out_replies_and_retweets() {
+        let reply = json!({ "tweet": { "id_str": "1", "full_text": "@bob photo", "in_reply_to_status_id_str": "2", "entities": { "media": [photo("1")] } } });
+        let retweet = json!({ "tweet": { "id_str": "1", "full_text": "RT @bob: photo", "entities": { "media": [photo("1")] } } });
+        let not_a_reply = json!({ "tweet": { "id_str": "1", "full_text": "photo", "in_reply_to_status_id_str": null, "entities": { "media": [photo("1")] } } });
+        assert!(exported_photos(&reply).is_empty());
+        assert!(exported_photos(&retweet).is_empty());
+        assert_eq!(exported_photos(&not_a_reply).len(), 1);
+    }
+
+    #[test]
+    fn reads_the_files_of_the_export() {
+        let dir = test_dir("export");
+        let mut zip = export(&dir, &[
+            ("data/account.js", r#"window.YTD.account.part0 = [ { "account": { "username": "alice" } } ]"#),
+            ("data/tweets.js", r#"[ { "tweet": { "id_str": "1" } } ]"#),
+            ("data/like.js", r#"window.YTD.like.part0 = { "like": {} }"#),
+            ("data/tweets_media/1-a.jpg", "image"),
+        ]);
+
+        assert_eq!(account_username(&mut zip).unwrap(), "alice");
+        assert_eq!(read_ytd(&mut zip, "data/tweets.js").unwrap(), [json!({ "tweet": { "id_str": "1" } })]);
+        assert!(read_ytd(&mut zip, "data/like.js").is_err());
+        assert!(read_ytd(&mut zip, "data/missing.js").is_err());
+
+        let output_file = dir.join("a.jpg");
+        assert!(extract_media(&mut zip, "1", "a.jpg", &output_file).unwrap());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "image");
+        assert!(!extract_media(&mut zip, "2", "a.jpg", &dir.join("b.jpg")).unwrap());
+    }
+
+    #[test]
+    fn matches_the_tweets_files() {
+        let tweets_pattern = Regex::new(TWEETS_PATTERN).unwrap();
+        for name in ["data/tweets.js", "data/tweet.js", "data/tweets-part1.js"] {
+            assert!(tweets_pattern.is_match(name), "{}", name);
+        }
+        for name in ["data/tweets_media/1-a.jpg", "data/tweetdeck.js", "tweets.js"] {
+            assert!(!tweets_pattern.is_match(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! module to rebuild the index of downloaded media in the [state database](crate::state) from the files on disk, see
+//! `index rebuild`.
+//!
+//! Runs skip the media recorded as downloaded at their recorded path. Files renamed, moved into subfolders or
+//! downloaded by older versions are found again by scanning the user directory: the media key of a file is taken from
+//! its [sidecar](crate::sidecar), else from its filename, else from a recorded file with the same content. The record
+//! of the media key is then pointed at the file, so later runs skip it whatever the layout.
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::common::sha256_file;
+use crate::migrate::{media_files, user_dirs};
+use crate::sidecar;
+use crate::state::{MediaRecord, StateStore};
+
+/// Outcome of the rebuild of the index of one user.
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub username: String,
+    /// media files recorded that were not recorded before
+    pub files: usize,
+    /// recorded media found at another path, their record now points at it
+    pub moved: usize,
+    /// media files recorded at their path before
+    pub known: usize,
+    /// media files without a media key in their sidecar or filename, nor a recorded file with the same content
+    pub unmatched: usize,
+}
+
+/// Rebuilds the index of the user directories `usernames` under `output_dir`, or of every user directory if empty.
+///
+/// Returns an [IndexReport](IndexReport) per user, in order.
+pub fn rebuild(output_dir: &Path, usernames: &[String], run_id: &str) -> Result<Vec<IndexReport>, Box<dyn Error + Send + Sync>> {
+    let usernames = if usernames.is_empty() { user_dirs(output_dir)? } else { usernames.to_vec() };
+    let state = StateStore::open(output_dir)?;
+    let mut reports = Vec::new();
+    for username in usernames {
+        let user_output_dir = output_dir.join(&username);
+        if !user_output_dir.is_dir() {
+            warn!("username: {}. No user directory under {}, skipping", username, output_dir.display());
+            continue;
+        }
+        reports.push(rebuild_user(&state, &user_output_dir, &username, run_id)?);
+    }
+    Ok(reports)
+}
+
+fn rebuild_user(state: &StateStore, user_output_dir: &Path, username: &str, run_id: &str) -> Result<IndexReport, Box<dyn Error + Send + Sync>> {
+    let mut report = IndexReport { username: username.into(), ..Default::default() };
+    let mut recorded: HashMap<String, MediaRecord> = state.downloaded_media(Some(username))?.into_iter()
+        .map(|record| (record.media_key.clone(), record))
+        .collect();
+    let by_sha256: HashMap<String, String> = recorded.values()
+        .filter_map(|record| record.sha256.clone().map(|sha256| (sha256, record.media_key.clone())))
+        .collect();
+    let recorded_paths: HashSet<PathBuf> = recorded.values().map(|record| record.local_path.clone()).collect();
+    // a media key anywhere in the filename, e.g. `3_1629462731392573441`, not within a longer number
+    let pattern = Regex::new(r"(?:^|[^0-9_])([0-9]{1,2}_[0-9]+)(?:[^0-9]|$)")?;
+
+    let mut files = Vec::new();
+    media_files(user_output_dir, &mut files)?;
+    for path in files {
+        if recorded_paths.contains(&path) {
+            report.known += 1;
+            continue;
+        }
+        let sidecar: Option<Value> = fs::read_to_string(sidecar::sidecar_path(&path)).ok()
+            .and_then(|s| serde_json::from_str(&s).ok());
+        let filename = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        let sha256 = sha256_file(&path).ok();
+
+        let media_key = sidecar.as_ref().and_then(|sidecar| sidecar.get("media_key").and_then(Value::as_str).map(String::from))
+            .or_else(|| pattern.captures(&filename).map(|captures| captures[1].to_string()))
+            .or_else(|| sha256.as_deref().and_then(|sha256| by_sha256.get(sha256)).map(|media_key| media_key.to_string()));
+        let media_key = match media_key {
+            Some(media_key) => media_key,
+            None => {
+                report.unmatched += 1;
+                continue;
+            }
+        };
+
+        let previous = recorded.get(&media_key).cloned();
+        if let Some(previous) = &previous {
+            // the recorded file is still there, this is a copy
+            if previous.local_path.is_file() {
+                report.known += 1;
+                continue;
+            }
+        }
+        let tweet_id = sidecar.as_ref().and_then(|sidecar| sidecar.get("tweet_id").and_then(Value::as_str).map(String::from))
+            .or_else(|| previous.as_ref().map(|previous| previous.tweet_id.clone()))
+            .unwrap_or_default();
+        let record = MediaRecord {
+            username: username.into(),
+            media_key,
+            tweet_id,
+            url: previous.as_ref().map(|previous| previous.url.clone()).unwrap_or_default(),
+            size: fs::metadata(&path)?.len(),
+            sha256,
+            local_path: path,
+            run_id: run_id.into(),
+            duplicate_of: None,
+        };
+        state.record_downloaded(&record)?;
+        if previous.is_some() {
+            report.moved += 1;
+        } else {
+            report.files += 1;
+        }
+        recorded.insert(record.media_key.clone(), record);
+    }
+    info!("username: {}. Index rebuilt, {} files recorded, {} moved, {} recorded before, {} unmatched", username, report.files, report.moved, report.known, report.unmatched);
+    Ok(report)
+}
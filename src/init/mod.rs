@@ -0,0 +1,164 @@
+//! module of the `init` wizard, setting up the [configuration file](crate::settings) for a first download.
+//!
+//! The wizard asks for the bearer token, the users, the output directory and the smallest images to keep. It checks
+//! the token and the users against the Twitter API and estimates how long the first, full download takes under the
+//! rate limit, before writing the configuration file and a token file next to it.
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use twitter_v2::query::UserField;
+
+use crate::messages::Message;
+use crate::settings::{self, Profile, Settings};
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::filename::Layout;
+use crate::twitter::ratelimit::RATE_LIMIT_WINDOW;
+
+/// The API serves at most this many of the latest Tweets of a user.
+const TIMELINE_LIMIT: u64 = 3200;
+
+/// Tweets per page of a download.
+const PAGE_SIZE: u64 = 100;
+
+/// Requests to the user Tweets endpoint an app may make per [RATE_LIMIT_WINDOW](RATE_LIMIT_WINDOW).
+const REQUESTS_PER_WINDOW: u64 = 1500;
+
+/// Runs the wizard on stdin and stdout and writes the configuration file to `path`.
+pub async fn run(path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    println!("{}", Message::InitStart { path });
+    if path.exists() && !is_yes(&ask(&Message::InitOverwrite, Some("n"))?) {
+        return Ok(());
+    }
+
+    let token = ask_until(Message::InitToken, None, |t| {
+        if t.is_empty() { Err(Message::InitTokenRequired.to_string()) } else { Ok(t.to_string()) }
+    })?;
+    let usernames = ask_until(Message::InitUsernames, None, |s| {
+        let usernames: Vec<String> = s.split(|c: char| c == ',' || c.is_whitespace())
+            .map(|u| u.trim_start_matches('@'))
+            .filter(|u| !u.is_empty())
+            .map(String::from)
+            .collect();
+        if usernames.is_empty() { Err(Message::InitUsernamesRequired.to_string()) } else { Ok(usernames) }
+    })?;
+    let output_dir = PathBuf::from(ask(&Message::InitOutputDir, Some("."))?);
+    let min_width = ask_until(Message::InitMinWidth, Some("0"), |s| {
+        s.parse::<usize>().map_err(|_| Message::InitNotANumber { value: s }.to_string())
+    })?;
+    let layout = ask_until(Message::InitLayout, Some("flat"), |s| {
+        Layout::from_str(s).map(|_| s.to_string())
+    })?;
+
+    println!("{}", Message::InitChecking);
+    let usernames = check(&token, usernames).await?;
+
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    let token_file = dir.join(settings::TOKEN_FILENAME);
+    write_secret(&token_file, &token)?;
+
+    println!("{}", Message::InitTokenKept { path: &token_file });
+
+    let settings = Settings {
+        bearer_token_file: Some(token_file),
+        output_dir: Some(output_dir),
+        usernames,
+        defaults: Profile {
+            min_width: Some(min_width).filter(|w| *w > 0),
+            layout: Some(layout).filter(|l| l != "flat"),
+            ..Profile::default()
+        },
+        ..Settings::default()
+    };
+    settings.write(path)?;
+    println!("{}", Message::InitWritten { path });
+    Ok(())
+}
+
+/// Looks up `usernames` with `token` and prints the estimated size of their first download.
+///
+/// Unknown users are left out. A rate limited check passes, as the token was accepted.
+///
+/// Fails if the token is rejected.
+async fn check(token: &str, usernames: Vec<String>) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let api = Credentials::Bearer(token.into()).api();
+    let mut found = Vec::new();
+    let mut pages: u64 = 0;
+    for username in usernames {
+        let response = api.get_user_by_username(&username).user_fields([UserField::PublicMetrics]).send().await;
+        match response.map_err(DownloadError::from) {
+            Ok(user) => match user.into_data() {
+                Some(user) => {
+                    let tweets = user.public_metrics.map_or(0, |m| m.tweet_count as u64);
+                    let scanned = tweets.min(TIMELINE_LIMIT);
+                    let user_pages = scanned.div_ceil(PAGE_SIZE);
+                    println!("{}", Message::InitUser { username: &username, tweets, scanned, pages: user_pages });
+                    pages += user_pages;
+                    found.push(username);
+                }
+                None => println!("{}", Message::InitUserNotFound { username: &username }),
+            },
+            Err(DownloadError::Auth(e)) => return Err(Message::InitTokenRejected { error: &e.to_string() }.to_string().into()),
+            Err(e) if e.is_rate_limited() => {
+                println!("{}", Message::InitUserRateLimited { username: &username });
+                found.push(username);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let windows = pages.div_ceil(REQUESTS_PER_WINDOW);
+    if windows > 1 {
+        println!("{}", Message::InitEstimate { pages, minutes: windows * RATE_LIMIT_WINDOW.as_secs() / 60 });
+    } else {
+        println!("{}", Message::InitEstimateOneWindow { pages });
+    }
+    println!("{}", Message::InitMediaHint);
+    Ok(found)
+}
+
+/// Returns true if `answer` is yes, in English or German.
+fn is_yes(answer: &str) -> bool {
+    answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("j")
+}
+
+/// Prints `question` with its `default` and returns the trimmed answer, or the default for an empty answer.
+fn ask(question: &Message, default: Option<&str>) -> Result<String, io::Error> {
+    match default {
+        Some(default) => print!("{} [{}]: ", question, default),
+        None => print!("{}: ", question),
+    }
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no more input"));
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default.unwrap_or("").to_string() } else { answer.to_string() })
+}
+
+/// Asks `question` until `parse` accepts the answer.
+fn ask_until<T>(question: Message, default: Option<&str>, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, io::Error> {
+    loop {
+        match parse(&ask(&question, default)?) {
+            Ok(value) => return Ok(value),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Writes `secret` to `path`, readable by the owner only.
+fn write_secret(path: &Path, secret: &str) -> Result<(), io::Error> {
+    fs::write(path, format!("{}\n", secret))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! _twitter-media-downloader_ library.
+//!
+//! Downloads the media of "public" Twitter accounts to the local disk. The command line tool is a thin wrapper
+//! around this crate, it can be embedded the same way:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use twitter_media_downloader::{Config, Downloader};
+//!
+//! let config = Config::builder()
+//!     .bearer_token(std::env::var("BEARER_TOKEN").unwrap())
+//!     .username("NASAHubble")
+//!     .output_dir("out")
+//!     .build()
+//!     .unwrap();
+//!
+//! match Downloader::new(config).run().await {
+//!     Ok(report) => println!("{}: {} files downloaded", report.username, report.downloaded),
+//!     Err(e) => eprintln!("{}", e),
+//! }
+//! # }
+//! ```
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+pub use crate::common::{Config, ConfigBuilder, ConfigError};
+pub use crate::twitter::error::DownloadError;
+
+use crate::manifest::ChecksumFormat;
+use crate::similar::NearDupes;
+use crate::state::StateStore;
+use crate::twitter::UserRun;
+use crate::twitter::outcome::Cutoff;
+
+pub mod archive;
+pub mod capture;
+pub mod clock;
+pub mod common;
+pub mod compat;
+pub mod convert;
+pub mod dates;
+pub mod dedup;
+pub mod embed;
+pub mod failed;
+pub mod exec;
+pub mod following;
+pub mod forget;
+pub mod http;
+pub mod import;
+pub mod index;
+pub mod init;
+pub mod links;
+pub mod lock;
+pub mod logging;
+pub mod magic;
+pub mod manifest;
+pub mod messages;
+pub mod metrics;
+pub mod migrate;
+pub mod mirror;
+pub mod notify;
+pub mod progress;
+pub mod rename;
+pub mod schedule;
+pub mod serve;
+pub mod settings;
+pub mod shutdown;
+pub mod sidecar;
+pub mod similar;
+pub mod skipped;
+pub mod source;
+pub mod state;
+pub mod stats;
+pub mod stream;
+pub mod summary;
+pub mod takeout;
+pub mod trash;
+pub mod tweets;
+pub mod tui;
+pub mod twitter;
+pub mod update;
+pub mod urls;
+pub mod verify;
+pub mod volumes;
+
+/// Downloads the media of `Config::username`.
+pub struct Downloader {
+    config: Config,
+}
+
+/// Outcome of a [Downloader::run](Downloader::run).
+#[derive(Debug, Clone)]
+pub struct DownloadReport {
+    pub username: String,
+    /// id of the run, see [common::new_run_id](common::new_run_id)
+    pub run_id: String,
+    /// number of media files downloaded
+    pub downloaded: u32,
+    /// number of consecutive runs of the user without new media, including this one
+    pub empty_streak: u32,
+    /// number of downloaded files that were duplicates of earlier downloads, see [dedup](dedup)
+    pub duplicates: u64,
+    /// bytes the duplicates would have taken
+    pub dedup_saved_bytes: u64,
+    /// number of media not downloaded on purpose, e.g. already there or filtered out
+    pub skipped: u64,
+    /// number of media whose download failed
+    pub failed: u64,
+    /// bytes of the downloaded files
+    pub bytes: u64,
+    /// checkpoint of the user after the run, None if no Tweet was walked yet
+    pub checkpoint: Option<u64>,
+    /// why the run stopped early, see [UserRun::cutoff](UserRun::cutoff)
+    pub cutoff: Option<Cutoff>,
+    pub duration: Duration,
+}
+
+impl Downloader {
+    pub fn new(config: Config) -> Self {
+        Downloader { config }
+    }
+
+    /// Runs the download page by page, see [twitter::UserRun](twitter::UserRun).
+    ///
+    /// The run is recorded in the [state database](state) to track runs without new media.
+    pub async fn run(&self) -> Result<DownloadReport, DownloadError> {
+        let started = Instant::now();
+        let mut run = UserRun::start(self.config.clone()).await?;
+        while !run.is_done() {
+            run.next_page().await?;
+        }
+        report(&self.config, &run, started)
+    }
+
+    /// Runs the downloads of `downloaders` interleaved page by page: the first page of every user, then the second
+    /// page of every user not done yet, and so on. A run cut short by rate limits or a shutdown still advanced every user.
+    ///
+    /// Returns the outcome of every downloader, in order.
+    pub async fn run_round_robin(downloaders: Vec<Downloader>) -> Vec<Result<DownloadReport, DownloadError>> {
+        let started = Instant::now();
+        let mut results: Vec<Option<Result<DownloadReport, DownloadError>>> = downloaders.iter().map(|_| None).collect();
+
+        let mut runs: Vec<(usize, UserRun)> = Vec::new();
+        for (i, downloader) in downloaders.iter().enumerate() {
+            match UserRun::start(downloader.config.clone()).await {
+                Ok(run) => runs.push((i, run)),
+                Err(e) => results[i] = Some(Err(e)),
+            }
+        }
+
+        while !runs.is_empty() {
+            let mut active = Vec::with_capacity(runs.len());
+            for (i, mut run) in runs {
+                match run.next_page().await {
+                    Ok(()) if run.is_done() => results[i] = Some(report(&downloaders[i].config, &run, started)),
+                    Ok(()) => active.push((i, run)),
+                    Err(e) => results[i] = Some(Err(e)),
+                }
+            }
+            runs = active;
+        }
+
+        results.into_iter().map(|r| r.unwrap_or_else(|| Err(DownloadError::Other("Run did not finish".into())))).collect()
+    }
+
+    pub fn username(&self) -> &str {
+        &self.config.username
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.config.run_id
+    }
+}
+
+/// Records the completed run of `config` in the [state database](state) and returns its [DownloadReport](DownloadReport).
+///
+/// The [checksum files](manifest) of the user are rewritten, and with `Config::near_dupes` the
+/// [near-duplicate report](similar::REPORT_FILENAME) as well.
+fn report(config: &Config, run: &UserRun, started: Instant) -> Result<DownloadReport, DownloadError> {
+    let downloaded = run.count();
+    let state = StateStore::open(&config.output_dir)?;
+    let empty_streak = state.record_run(&config.username, downloaded)?;
+    if config.near_dupes != NearDupes::Off {
+        match similar::write_report(&config.output_dir, &state) {
+            Ok((path, clusters)) => info!("username: {}, report: {}. {} clusters of near-duplicates", config.username, path.display(), clusters),
+            Err(e) => error!("username: {}. Cannot write the near-duplicate report: {}", config.username, e),
+        }
+    }
+    let user_output_dir = config.output_dir.join(&config.username);
+    match manifest::rewrite(&state, &config.output_dir, &config.username, &ChecksumFormat::with_manifest(&config.checksums)) {
+        Ok(written) => {
+            for (path, files) in written {
+                info!("username: {}, checksums: {}. {} files listed", config.username, path.display(), files);
+            }
+        }
+        Err(e) => error!("username: {}. Cannot write the checksum files: {}", config.username, e),
+    }
+    Ok(DownloadReport {
+        username: config.username.clone(),
+        run_id: config.run_id.clone(),
+        downloaded,
+        empty_streak,
+        duplicates: run.dedup_stats().files(),
+        dedup_saved_bytes: run.dedup_stats().saved_bytes(),
+        skipped: run.run_stats().skipped(),
+        failed: run.run_stats().failed(),
+        bytes: run.run_stats().bytes(),
+        checkpoint: state.get_checkpoint(&config.username, &user_output_dir)?.filter(|c| *c != u64::MAX),
+        cutoff: run.cutoff(),
+        duration: started.elapsed(),
+    })
+}
@@ -0,0 +1,51 @@
+//! module to archive the links of Tweets.
+//!
+//! Media Tweets often link to the full gallery or a store page. The expanded urls of the Tweet entities are
+//! appended to `<user>/links.jsonl`, one JSON object per Tweet.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use twitter_v2::Tweet;
+
+/// Name of the per user links file.
+pub const LINKS_FILENAME: &str = "links.jsonl";
+
+/// Returns the expanded urls of the entities of `tweet`.
+pub fn expanded_urls(tweet: &Tweet) -> Vec<String> {
+    match tweet.entities.as_ref().and_then(|e| e.urls.as_ref()) {
+        Some(urls) => urls.iter()
+            .map(|u| u.expanded_url.clone())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Appends the links of `tweets` posted by `username` to the links file in `user_output_dir`.
+///
+/// Tweets without links are skipped.
+pub fn append_links<'a>(user_output_dir: &Path, username: &str, tweets: impl IntoIterator<Item = &'a Tweet>) -> Result<(), io::Error> {
+    let mut lines = String::new();
+    for tweet in tweets {
+        let urls = expanded_urls(tweet);
+        if urls.is_empty() {
+            continue;
+        }
+        let line = json!({
+            "tweet_id": tweet.id.to_string(),
+            "username": username,
+            "created_at": tweet.created_at.and_then(|d| d.format(&Rfc3339).ok()),
+            "urls": urls,
+        });
+        lines.push_str(&line.to_string());
+        lines.push('\n');
+    }
+
+    if !lines.is_empty() {
+        let mut file = OpenOptions::new().create(true).append(true).open(user_output_dir.join(LINKS_FILENAME))?;
+        file.write_all(lines.as_bytes())?;
+    }
+    Ok(())
+}
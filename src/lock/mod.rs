@@ -0,0 +1,82 @@
+//! module to keep two runs from downloading the same user at once.
+//!
+//! A run locks [LOCK_FILENAME](LOCK_FILENAME) in the user directory, holding its process id, while it downloads the
+//! user. The lock of a process that is gone, e.g. after a crash, is released by the operating system, so there is no
+//! stale lock to take over.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use fs2::{lock_contended_error, FileExt};
+use tracing::info;
+
+use crate::shutdown;
+use crate::twitter::error::DownloadError;
+
+/// Name of the lock file in the user directory.
+pub const LOCK_FILENAME: &str = ".lock";
+
+/// Pause between the attempts to take a held lock with `--wait`.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The lock of a user directory, released when dropped.
+///
+/// The lock file stays in the user directory, removing it could let a second run lock a new file while a third still
+/// holds the old one.
+#[derive(Debug)]
+pub struct DirLock {
+    file: File,
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Takes the lock of `dir` if it is free. Returns the process id of the holder if it is held, 0 if unknown.
+///
+/// The lock is an exclusive advisory lock of the lock file, the operating system releases it when its process ends.
+fn try_lock(dir: &Path) -> Result<Result<DirLock, u32>, io::Error> {
+    let path = dir.join(LOCK_FILENAME);
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+    match file.try_lock_exclusive() {
+        Ok(()) => {
+            // the pid of an earlier holder is left over
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            file.sync_all()?;
+            Ok(Ok(DirLock { file }))
+        }
+        Err(e) if e.kind() == lock_contended_error().kind() || e.kind() == io::ErrorKind::WouldBlock => {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            Ok(Err(pid.trim().parse().unwrap_or(0)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Locks the user directory `dir` of `username` for a run.
+///
+/// If another running process holds the lock, fails with [DownloadError::Locked](DownloadError::Locked), or with
+/// `wait` waits until it is released or a shutdown is requested.
+pub async fn acquire(dir: &Path, username: &str, wait: bool) -> Result<DirLock, DownloadError> {
+    let mut waiting = false;
+    loop {
+        match try_lock(dir)? {
+            Ok(lock) => return Ok(lock),
+            Err(pid) if wait && !shutdown::is_requested() => {
+                if !waiting {
+                    info!("username: {}, pid: {}. Another run downloads the user, waiting for it to finish", username, pid);
+                    waiting = true;
+                }
+                if !shutdown::sleep(RETRY_INTERVAL).await {
+                    return Err(DownloadError::Locked(dir.join(LOCK_FILENAME), pid));
+                }
+            }
+            Err(pid) => return Err(DownloadError::Locked(dir.join(LOCK_FILENAME), pid)),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! module to set up the logging of a run: human readable text, or JSON lines for log collectors like Loki.
+//!
+//! The level is taken from `LOG_LEVEL`, e.g. `debug` or `twitter_media_downloader=debug`, `info` by default. Log
+//! lines go through the [progress](crate::progress) bars, which are hidden while a line is written.
+//!
+//! Notable events carry an `event` field next to their message, with the details as fields of their own:
+//! * `download_started`, `download_completed`: `username`, `media_key`, and `bytes` and `duration_ms` once completed
+//! * `rate_limited`: `username`, `wait_secs`
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::progress;
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// one human readable line per event
+    Text,
+    /// one JSON object per event, the fields of the event and of the run at the top level
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}'. Expected text or json", s)),
+        }
+    }
+}
+
+/// Installs the logger writing `format` to stderr. Log records of the dependencies are included.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| progress::LogWriter);
+    match format {
+        LogFormat::Text => subscriber.with_ansi(io::stderr().is_terminal()).init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+    }
+}
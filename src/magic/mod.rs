@@ -0,0 +1,107 @@
+//! module to tell the type of a downloaded file by its first bytes.
+//!
+//! A CDN error can answer with an HTML page that gets saved as a `.jpg`, and an existing file is never downloaded
+//! again. Every download is checked against the types of media files before it takes its name: a file that is none of
+//! them, or not the one its extension names, is moved to the [QUARANTINE_DIR](QUARANTINE_DIR) next to it and the
+//! download fails, so it is retried like any failed download.
+use std::fmt;
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Directory of the files with an unexpected type, next to where they were to be saved.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Type of a media file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Mp4,
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileType::Jpeg => "JPEG",
+            FileType::Png => "PNG",
+            FileType::Gif => "GIF",
+            FileType::WebP => "WebP",
+            FileType::Mp4 => "MP4",
+        })
+    }
+}
+
+impl FileType {
+    /// Returns the type of a file starting with `bytes`, None if it is no media file.
+    pub fn sniff(bytes: &[u8]) -> Option<FileType> {
+        match bytes {
+            [0xFF, 0xD8, 0xFF, ..] => Some(FileType::Jpeg),
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some(FileType::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(FileType::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(FileType::WebP),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(FileType::Mp4),
+            _ => None,
+        }
+    }
+
+    /// Returns the type named by the extension of `path`, None for an unknown one.
+    pub fn of_extension(path: &Path) -> Option<FileType> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "jpg" | "jpeg" => Some(FileType::Jpeg),
+            "png" => Some(FileType::Png),
+            "gif" => Some(FileType::Gif),
+            "webp" => Some(FileType::WebP),
+            "mp4" | "m4v" => Some(FileType::Mp4),
+            _ => None,
+        }
+    }
+}
+
+/// Why a downloaded file was rejected by [check](check).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// type of the extension, None for any media file
+    pub expected: Option<FileType>,
+    /// type of the content, None if it is no media file
+    pub found: Option<FileType>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let found = self.found.map_or("no media file".to_string(), |t| format!("a {} file", t));
+        match self.expected {
+            Some(expected) => write!(f, "expected a {} file, got {}", expected, found),
+            None => write!(f, "expected a media file, got {}", found),
+        }
+    }
+}
+
+/// Checks the content of `file`, about to be saved as `output_file`, against the types of media files and the
+/// extension of `output_file`.
+pub fn check(file: &Path, output_file: &Path) -> Result<Result<FileType, Mismatch>, io::Error> {
+    let mut head = Vec::with_capacity(16);
+    File::open(file)?.take(16).read_to_end(&mut head)?;
+    let found = FileType::sniff(&head);
+    let expected = FileType::of_extension(output_file);
+    Ok(match (expected, found) {
+        (_, None) => Err(Mismatch { expected, found }),
+        // Twitter serves some images in another format than their name says, a JPEG for a .png
+        (Some(FileType::Mp4), Some(found)) | (Some(_), Some(found @ FileType::Mp4)) if expected != Some(found) => Err(Mismatch { expected, found: Some(found) }),
+        (_, Some(found)) => Ok(found),
+    })
+}
+
+/// Moves `file`, rejected as `output_file`, into the [QUARANTINE_DIR](QUARANTINE_DIR) next to `output_file`.
+///
+/// Returns its new path.
+pub fn quarantine(file: &Path, output_file: &Path) -> Result<PathBuf, io::Error> {
+    let dir = output_file.parent().unwrap_or(Path::new(".")).join(QUARANTINE_DIR);
+    DirBuilder::new().recursive(true).create(&dir)?;
+    let path = dir.join(output_file.file_name().unwrap_or_default());
+    fs::rename(file, &path)?;
+    Ok(path)
+}
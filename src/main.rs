@@ -1,30 +1,212 @@
-//! _twitter-media-downloader_ main file
-use std::path::PathBuf;
+//! _twitter-media-downloader_ main file. Command line wrapper around the library.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::{ArgAction, Parser};
-use env_logger::Env;
-use log::{error, info};
+use clap::{ArgAction, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap::parser::ValueSource;
+use regex::Regex;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tokio::sync::Semaphore;
+use tracing::{error, info, info_span, warn, Instrument};
 
-use crate::common::Config;
+use twitter_media_downloader::{capture, common, failed, following, forget, http, import, index, init, metrics, migrate, mirror, progress, rename, serve, settings, shutdown, stats, stream, takeout, trash, update, urls, verify};
+use twitter_media_downloader::{clock, Config, ConfigBuilder, DownloadError, DownloadReport, Downloader};
+use twitter_media_downloader::archive::ArchiveFormat;
+use twitter_media_downloader::compat::Compat;
+use twitter_media_downloader::convert::{self, ConvertFormat};
+use twitter_media_downloader::dates::{DatePolicy, Timezone};
+use twitter_media_downloader::dedup::Dedup;
+use twitter_media_downloader::exec::ExecHook;
+use twitter_media_downloader::http::{Header, HttpOptions, HttpVersion};
+use twitter_media_downloader::import::ImportOptions;
+use twitter_media_downloader::logging::{self, LogFormat};
+use twitter_media_downloader::manifest::ChecksumFormat;
+use twitter_media_downloader::similar::NearDupes;
+use twitter_media_downloader::stats::ReportFormat;
+use twitter_media_downloader::settings::{Profile, Settings};
+use twitter_media_downloader::state::{self, StateStore};
+use twitter_media_downloader::schedule::{self, QuietHours};
+use twitter_media_downloader::summary::{RunSummary, EXIT_EMPTY, EXIT_FAILURE, EXIT_PARTIAL, EXIT_USAGE};
+use twitter_media_downloader::messages::{self, Locale, Message};
+use twitter_media_downloader::notify::{Dispatcher, Event, Notification, NotifyWhen, Route, SummaryNotifier, Target};
+use twitter_media_downloader::notify::rollup::{Period, RollUp};
+use twitter_media_downloader::twitter::auth::{AuthMode, Credentials};
+use twitter_media_downloader::twitter::filename::{FilenameTemplate, Layout, DEFAULT_TEMPLATE};
+use twitter_media_downloader::twitter::filter::{Sensitive, TweetFilter};
+use twitter_media_downloader::twitter::hls::HlsMode;
+use twitter_media_downloader::twitter::nitter::FallbackBackend;
+use twitter_media_downloader::twitter::order::Order;
+use twitter_media_downloader::twitter::ratelimit;
+use twitter_media_downloader::twitter::retry::RetryPolicy;
+use twitter_media_downloader::twitter::search::SourceKind;
+use twitter_media_downloader::tui::{self, UserState};
+use twitter_media_downloader::twitter::throttle;
+use twitter_media_downloader::urls::UrlFormat;
+use twitter_media_downloader::volumes::Volume;
 
-pub mod common;
-pub mod twitter;
+/// Shortest pause between the rounds of `--watch`.
+const MIN_WATCH_PAUSE: Duration = Duration::from_secs(60);
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct CliArguments {
-    /// Bearer Token. Can be passed as BEARER_TOKEN
-    #[clap(short, long, value_parser, env)]
-    bearer_token: String,
+    /// Output directory
+    #[clap(short, long, value_parser, default_value = ".", global = true)]
+    output_dir: PathBuf,
+
+    /// Configuration file with defaults and per-user settings, overridden by the options given. Defaults to ~/.config/twitter-media-downloader/config.toml, if it exists
+    #[clap(long, value_parser, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+
+    /// Language of the reports and summaries: en or de. Taken from LC_ALL, LC_MESSAGES or LANG if not given
+    #[clap(long, value_parser, global = true)]
+    lang: Option<Locale>,
+
+    /// Proxy of all requests as http(s)://[user:password@]host:port or socks5://host:port. Defaults to HTTPS_PROXY or ALL_PROXY
+    #[clap(long, value_parser = http::parse_proxy, value_name = "URL", global = true)]
+    proxy: Option<reqwest::Url>,
+
+    /// Time to establish a connection, e.g. 10s
+    #[clap(long, value_parser = common::parse_duration, value_name = "DURATION", default_value = "30s", global = true)]
+    connect_timeout: Duration,
+
+    /// Time a whole request may take, including the body, e.g. 10m. Unlimited by default, the --stall-timeout of downloads catches stalled transfers
+    #[clap(long, value_parser = common::parse_duration, value_name = "DURATION", global = true)]
+    request_timeout: Option<Duration>,
+
+    /// Only use HTTP/1.1
+    #[clap(long, action = ArgAction::SetTrue, conflicts_with = "http2", global = true)]
+    http1: bool,
+
+    /// Use HTTP/2 without negotiating it, for servers known to speak it. By default HTTP/2 is used where offered
+    #[clap(long, action = ArgAction::SetTrue, global = true)]
+    http2: bool,
+
+    /// User-Agent of the media downloads
+    #[clap(long, value_parser, global = true)]
+    user_agent: Option<String>,
+
+    /// Extra header of the media downloads as "Name: value", e.g. "X-Egress-Tag: archive". Can be repeated
+    #[clap(long = "header", value_parser, value_name = "HEADER", global = true)]
+    headers: Vec<Header>,
+
+    /// Format of the log lines on stderr: text, or json with one object per event for log collectors
+    #[clap(long, value_parser, default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+// parsed once, the size of the download arguments does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Command {
+    /// Download the media of one or more users
+    Download(DownloadArguments),
+    /// Report the archive state per user: checkpoint, files, size, oldest and newest Tweet, last run
+    Status(StatusArguments),
+    /// Export statistics of the downloaded media per user as JSON or CSV: files and bytes per month of the Tweets, per file type, and the Tweets with the most media
+    Stats(StatsArguments),
+    /// Check that the recorded files exist with their size and checksum, or that they exist on a mirror
+    Verify(VerifyArguments),
+    /// Package the archive of a user into a single zip with an offline HTML gallery, or list media urls with `export urls`
+    Export(ExportArguments),
+    /// Delete media files and never download them again, e.g. for takedown requests
+    Forget(ForgetArguments),
+    /// Move quarantined media files back and allow downloading them again, or list the quarantine
+    Restore(RestoreArguments),
+    /// Set up a configuration file for a first download: token, users, output directory and image sizes. Written to --config
+    Init,
+    /// Download the photos of an official Twitter data export (the zip of "Download an archive of your data"), reaching back beyond the 3200 Tweets of the API
+    ImportArchive(ImportArchiveArguments),
+    /// Move the archive of a user to a new handle, e.g. after the account was renamed. The old handle keeps working as an alias
+    RenameUser(RenameUserArguments),
+    /// Download only the failed downloads of earlier runs again, without walking the timelines
+    Retry(RetryArguments),
+    /// Record the checkpoint files and the media files of archives of older versions in the state database, so nothing is downloaded again
+    Migrate(MigrateArguments),
+    /// Rebuild the index of downloaded media with `index rebuild`, after files were renamed or moved into subfolders
+    Index(IndexArguments),
+    /// Replace this binary with the latest release from GitHub, after verifying its signature and checksum
+    SelfUpdate(SelfUpdateArguments),
+    /// Serve a REST API queueing downloads of users: POST /downloads, GET /downloads/<id>/status and GET /users/<name>/stats
+    Serve(ServeArguments),
+    /// Download the media of Tweets of the users as they are posted, from the filtered stream, until stopped with Ctrl+C. Catches media deleted within minutes
+    Stream(StreamArguments),
+}
+
+#[derive(Args)]
+struct DownloadArguments {
+    /// Bearer Token. Can be passed as BEARER_TOKEN. Prefer --bearer-token-file, arguments show up in process listings and shell history
+    #[clap(short, long, value_parser, env, hide_env_values = true)]
+    bearer_token: Option<String>,
+
+    /// Read the Bearer Token from this file, or from stdin with -
+    #[clap(long, value_parser, conflicts_with = "bearer_token")]
+    bearer_token_file: Option<PathBuf>,
 
-    /// Twitter handle - username
-    #[clap(short, long, value_parser)]
-    username: String,
+    /// bearer: app-only, with the Bearer Token. oauth1: in the context of a user, with --consumer-key, --consumer-secret, --access-token and --access-token-secret, for the rate limits of the user and the accounts it can see
+    #[clap(long, value_parser, default_value = "bearer")]
+    auth: AuthMode,
 
-    /// Number of media files to download in a batch
-    #[clap(short, long, value_parser, default_value_t = 100)]
+    /// API key of the app, for --auth oauth1
+    #[clap(long, value_parser, env, hide_env_values = true)]
+    consumer_key: Option<String>,
+
+    /// API key secret of the app, for --auth oauth1
+    #[clap(long, value_parser, env, hide_env_values = true)]
+    consumer_secret: Option<String>,
+
+    /// Access token of the user, for --auth oauth1
+    #[clap(long, value_parser, env, hide_env_values = true)]
+    access_token: Option<String>,
+
+    /// Access token secret of the user, for --auth oauth1
+    #[clap(long, value_parser, env, hide_env_values = true)]
+    access_token_secret: Option<String>,
+
+    /// Twitter handle - username. Can be repeated to download several users in one run. Defaults to the users of the configuration file
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Download every user with state under the output directory, in addition to the -u users. Users are tracked once they were downloaded
+    #[clap(long, action = ArgAction::SetTrue)]
+    all_tracked: bool,
+
+    /// Download every account this user follows as well, in addition to the -u users. Bootstraps the archive of a whole community
+    #[clap(long, value_parser, value_name = "USERNAME")]
+    crawl_following: Option<String>,
+
+    /// Only crawl followed accounts with at least this many followers, see --crawl-following
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_followers: u64,
+
+    /// Crawl at most this many followed accounts, the most recently followed first, see --crawl-following
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_accounts: Option<u64>,
+
+    /// Number of users to download in parallel. With 1 the users take turns page by page
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 1)]
+    parallel_users: u16,
+
+    /// Number of Tweets per page, the max_results of the API: 5 to 100
+    #[clap(short, long, value_parser = clap::value_parser!(u8).range(5..=100), default_value_t = 100)]
     count: u8,
 
+    /// Scrape the media tab of the users from a Nitter instance once the API is rate limited or rejects the token, as nitter=<instance-url>. Its photos are keyed by their file name, use --dedup to catch those downloaded through the API as well
+    #[clap(long, value_parser, value_name = "BACKEND")]
+    fallback_backend: Option<FallbackBackend>,
+
+    /// Walk at most this many pages of --count Tweets in a run, e.g. 10 pages of 100, without walking the whole timeline like --download-all
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_pages: Option<u32>,
+
     /// Reset the download marker to the latest tweet
     #[clap(short, long, action = ArgAction::SetTrue)]
     reset_marker: bool,
@@ -33,38 +215,1418 @@ struct CliArguments {
     #[clap(short, long, action = ArgAction::SetTrue)]
     download_all: bool,
 
-    /// Output directory
-    #[clap(short, long, value_parser, default_value = ".")]
-    output_dir: PathBuf,
+    /// Only download media of Tweets newer than the newest Tweet seen by earlier runs, leaving the checkpoint untouched
+    #[clap(long, action = ArgAction::SetTrue)]
+    sync_new: bool,
+
+    /// Output volume for media files as <path>[=<capacity>], e.g. /mnt/disk1=2TiB. Volumes fill up in the given order, the capacity may be exceeded by the downloads in flight. Checkpoints stay in the output directory. Can be repeated
+    #[clap(long = "volume", value_parser)]
+    volumes: Vec<Volume>,
+
+    /// Number of media files to download in parallel
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 4)]
+    concurrency: u16,
+
+    /// Number of retries for media downloads failing with network errors, timeouts or 5xx responses
+    #[clap(long, value_parser, default_value_t = 3)]
+    retries: u32,
+
+    /// Initial delay in milliseconds before retrying a failed media download. Doubles with every retry, with jitter
+    #[clap(long, value_parser, default_value_t = 500)]
+    retry_delay: u64,
+
+    /// Seconds a media download may go without receiving any bytes before it is aborted and retried. Large files on slow links are fine as long as bytes keep arriving
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 30)]
+    stall_timeout: u64,
+
+    /// Skip media files larger than this, e.g. 50MB or 1GiB, by their Content-Length. Skipped files are listed in <user>/skipped.jsonl
+    #[clap(long, value_parser = common::parse_size, value_name = "SIZE")]
+    max_file_size: Option<u64>,
+
+    /// Stop before the next page once the output filesystem has less than this free, e.g. 2GiB. Checked before the first page and between pages, the checkpoint stays at the last complete page
+    #[clap(long, value_parser = common::parse_size, value_name = "SIZE")]
+    min_free_space: Option<u64>,
+
+    /// Limit the bandwidth of all media downloads together, in bytes per second, e.g. 2MiB or 500KB
+    #[clap(long, value_parser = common::parse_size, value_name = "RATE")]
+    limit_rate: Option<u64>,
+
+    /// Set the modification time of downloaded files to the Tweet's date, so photo managers sort the archive chronologically
+    #[clap(long, action = ArgAction::Set, default_value_t = true)]
+    set_mtime: bool,
+
+    /// Date policy for file modification times. camera: photos with an original camera date (EXIF DateTimeOriginal) are stamped with it, for photographer accounts
+    #[clap(long, value_parser, default_value = "tweet")]
+    date_policy: DatePolicy,
+
+    /// Order the Tweets of a page are processed in. top: most liked, retweeted, quoted and replied first, so runs cut short grab the most popular media
+    #[clap(long, value_parser, default_value = "newest")]
+    order: Order,
+
+    /// Where the Tweets come from. timeline: the latest 3200 Tweets of the user. full-archive: the full-archive search, back to the first Tweet, for tokens with access to it (Academic Research, Pro)
+    #[clap(long, value_parser, default_value = "timeline")]
+    source: SourceKind,
+
+    /// Search query the Tweets have to match as well, e.g. '#astrophotography has:images'. With --source full-archive
+    #[clap(long, value_parser)]
+    query: Option<String>,
+
+    /// Only Tweets created at or after this date, YYYY-MM-DD (UTC) or an RFC 3339 date and time. With --source full-archive
+    #[clap(long, value_parser = common::parse_date, value_name = "DATE")]
+    since: Option<OffsetDateTime>,
+
+    /// Only Tweets created before this date, YYYY-MM-DD (UTC) or an RFC 3339 date and time. With --source full-archive
+    #[clap(long, value_parser = common::parse_date, value_name = "DATE")]
+    until: Option<OffsetDateTime>,
+
+    /// Only download the media of Tweets whose text matches this regex, e.g. '#nofilter' or '(?i)nebula'. Can be repeated, matching one is enough
+    #[clap(long = "match", value_parser, value_name = "PATTERN")]
+    matches: Vec<Regex>,
+
+    /// Skip the media of Tweets whose text matches this regex. Can be repeated
+    #[clap(long = "exclude-match", value_parser, value_name = "PATTERN")]
+    exclude_matches: Vec<Regex>,
+
+    /// Only download the media of Tweets with at least this many likes
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_likes: u64,
+
+    /// Only download the media of Tweets with at least this many retweets
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_retweets: u64,
+
+    /// Only download media at least this many pixels wide, skips thumbnails and emoji sized images
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_width: usize,
+
+    /// Only download media at least this many pixels high
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_height: usize,
+
+    /// Media of Tweets flagged as possibly sensitive. skip: leave them out, only: download only them, separate-dir: put them under <user>/sensitive/
+    #[clap(long, value_parser, default_value = "include")]
+    sensitive: Sensitive,
+
+    /// Time zone for date based grouping and naming, e.g. Europe/Berlin. Tweet dates are UTC
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
+
+    /// Template of the local file names. Placeholders: {date} (of the Tweet in --timezone), {tweet_id}, {username}, {media_key}, {index} (of the media in the Tweet, from 1), {count} (of the media in the Tweet), {ext}, {original} (remote file name), {thread} (id of the first Tweet of the thread). Must contain {media_key}, {original} or {tweet_id} and {index}
+    #[clap(long, value_parser, default_value = DEFAULT_TEMPLATE)]
+    filename_template: FilenameTemplate,
+
+    /// Directories of the files under the user directory. date: YYYY/MM/ of the Tweet in --timezone, type: photos/ and videos/, date-type: both
+    #[clap(long, value_parser, default_value = "flat")]
+    layout: Layout,
+
+    /// Mirror the output of another downloader. gallery-dl: user directories under <output-dir>/twitter/, files named {tweet_id}_{num}.{extension} and sidecars with gallery-dl's metadata keys
+    #[clap(long, value_parser, value_name = "DOWNLOADER", conflicts_with_all = &["filename_template", "layout"])]
+    compat: Option<Compat>,
+
+    /// Store media of reposted Tweets ("via @user", "📷: @user", links to other accounts' Tweets) under the directory of the probable original author
+    #[clap(long, action = ArgAction::SetTrue)]
+    organize_by_source: bool,
+
+    /// Append the expanded links of every scanned Tweet to <user>/links.jsonl
+    #[clap(long, action = ArgAction::SetTrue)]
+    save_links: bool,
+
+    /// Append every processed Tweet as returned by the API to <user>/tweets.ndjson
+    #[clap(long, action = ArgAction::SetTrue)]
+    save_tweets: bool,
+
+    /// Write the Tweet's text, date and permalink as <file>.txt next to every media file, readable in file managers and photo tools
+    #[clap(long, action = ArgAction::SetTrue)]
+    save_text: bool,
+
+    /// Download the media of the user's other Tweets in the thread of a Tweet, which the timeline leaves out as replies. Group them with {thread} in --filename-template. Only finds threads of the last 7 days
+    #[clap(long, action = ArgAction::SetTrue)]
+    include_thread: bool,
+
+    /// Download the media of quoted Tweets as well. Their files get the quoted author as {username} and in the sidecar as original_author
+    #[clap(long, action = ArgAction::SetTrue)]
+    include_quoted: bool,
+
+    /// Download videos and animated GIFs as well, the MP4 variant of the highest bit rate
+    #[clap(long, action = ArgAction::SetTrue)]
+    videos: bool,
+
+    /// What to do with videos offered as HLS (m3u8) playlist only. native: stitch the segments into an MP4, through ffmpeg if they are MPEG-TS, ffmpeg: remux the playlist with ffmpeg, skip: report them in skipped.jsonl
+    #[clap(long, value_parser, default_value = "native", value_name = "MODE")]
+    hls: HlsMode,
+
+    /// Save the preview image of videos and animated GIFs that are not downloaded, so every media of a Tweet keeps at least a still frame. Marked as "preview": true in the sidecar
+    #[clap(long, action = ArgAction::SetTrue)]
+    video_previews: bool,
+
+    /// Embed the Tweet's text, author, date and url into downloaded JPEGs (EXIF), or write <file>.xmp for other formats
+    #[clap(long, action = ArgAction::SetTrue)]
+    embed_metadata: bool,
+
+    /// Convert downloaded photos before saving them. webp: lossless WebP, jpeg: JPEG of --quality. The sidecar keeps the original extension
+    #[clap(long, value_parser, value_name = "FORMAT")]
+    convert: Option<ConvertFormat>,
+
+    /// JPEG quality of photos converted with --convert jpeg, 1 to 100
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=100), default_value_t = convert::DEFAULT_QUALITY)]
+    quality: u8,
+
+    /// Deduplicate downloads by their content across Tweets and users. skip: delete the duplicate, hardlink: link it to the earlier download. Cannot be combined with --embed-metadata
+    #[clap(long, value_parser, default_value = "off", conflicts_with = "embed_metadata")]
+    dedup: Dedup,
+
+    /// Find images that look like earlier downloads, e.g. at another resolution or recompressed. report: list them in near-duplicates.json, skip: delete them
+    #[clap(long, value_parser, default_value = "off")]
+    near_dupes: NearDupes,
+
+    /// Keep the checksums of the manifest in this format as well in every user directory, rewritten after each run. sha256sums: SHA256SUMS, checked with `sha256sum -c SHA256SUMS` in the user directory. manifest.sha256 and manifest.jsonl are always kept. Can be repeated
+    #[clap(long, value_parser)]
+    checksums: Vec<ChecksumFormat>,
+
+    /// Notification route as <event>=<target>. Events: run-complete, error, new-media, summary. Targets: http(s) webhook url, discord+<url>, telegram://<bot token>@<chat id>, mailto:<address>, desktop. Can be repeated. Replaces the notify routes of the configuration file
+    #[clap(long = "notify", value_parser)]
+    notify_routes: Vec<Route>,
+
+    /// Send the summary of the run to this target when it ends: an http(s) webhook url receives the --summary-json document, discord+<url> and telegram://<bot token>@<chat id> a short text. Can be repeated
+    #[clap(long, value_parser)]
+    notify_url: Vec<Target>,
+
+    /// Send the summary of --notify-url only after runs with new files (new-media) or failures (error) instead of always. Can be repeated
+    #[clap(long, value_parser, requires = "notify_url")]
+    notify_when: Vec<NotifyWhen>,
+
+    /// Roll run results up into hourly or daily summary notifications instead of notifying run-complete and new-media per run
+    #[clap(long, value_parser)]
+    notify_rollup: Option<Period>,
+
+    /// Wall-clock budget of the run, e.g. 20m or 1h30m. Once exhausted the run stops like on Ctrl+C, with its checkpoints written, and exits successfully, or as rate limited while waiting out a rate limit
+    #[clap(long, value_parser = common::parse_duration, value_name = "DURATION")]
+    time_budget: Option<Duration>,
+
+    /// Write the files downloaded by a run, with their metadata sidecars and the manifests, into an archive per user, <user dir>/<username>-<date>-<time>.<zip|tar.gz>, instead of keeping them as loose files
+    #[clap(long, value_parser, value_name = "zip|tar.gz")]
+    archive: Option<ArchiveFormat>,
+
+    /// Command run by the shell after every downloaded file, e.g. "exiftool -Keywords={username} {path}". Placeholders, shell quoted: {path}, {username}, {tweet_id}, {media_key}, {type}. Also passed as the environment variables TMD_PATH, TMD_USERNAME, TMD_TWEET_ID, TMD_MEDIA_KEY, TMD_MEDIA_TYPE
+    #[clap(long, value_name = "COMMAND")]
+    exec: Option<String>,
+
+    /// Serve Prometheus metrics at http://<ADDR>/metrics while --watch runs, e.g. 127.0.0.1:9898
+    #[clap(long, value_parser, value_name = "ADDR", requires = "watch")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Push Prometheus metrics to this Pushgateway after every run, e.g. http://pushgateway:9091
+    #[clap(long, value_name = "URL")]
+    metrics_push: Option<String>,
+
+    /// Wait for another run downloading the same user to finish instead of failing the user
+    #[clap(long, action = ArgAction::SetTrue)]
+    wait: bool,
+
+    /// Keep running and download the users again every --interval, until stopped with Ctrl+C or --time-budget. Users with an `every` or `quiet_hours` in the configuration file are downloaded on that schedule instead
+    #[clap(long, action = ArgAction::SetTrue)]
+    watch: bool,
+
+    /// Interval of the users of --watch without an `every` of their own, e.g. 15m. The rounds pause at least a rate limit window after a round that hit the rate limit
+    #[clap(long, value_parser = common::parse_duration, value_name = "DURATION", default_value = "15m", requires = "watch")]
+    interval: Duration,
+
+    /// Do not draw progress bars, only log. They are left out anyway when stderr is not a terminal
+    #[clap(long, action = ArgAction::SetTrue)]
+    no_progress: bool,
+
+    /// Show a dashboard of the users, downloads, rate limits and recent errors instead of the progress bars and the log. Keys: up/down select a user, p pauses or resumes it, s skips it, q stops the run
+    #[clap(long, action = ArgAction::SetTrue, conflicts_with = "no_progress")]
+    tui: bool,
+
+    /// Record the API requests and responses of the run, credentials redacted, as JSON files in this directory. For bug reports
+    #[clap(long, value_parser, value_name = "DIR")]
+    debug_http: Option<PathBuf>,
+
+    /// Write a JSON summary of the run to this file, or to stdout with -: files downloaded, skipped and failed, bytes, duration and the checkpoint per user
+    #[clap(long, value_parser, value_name = "PATH|-")]
+    summary_json: Option<PathBuf>,
+
+    /// Exit with the empty code once a user had no new media for this many consecutive runs, to catch scheduled runs silently breaking
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "RUNS")]
+    fail_if_empty: Option<u32>,
+
+    /// Treat runs without new media as success, whatever the streak. The default, wins over --fail-if-empty
+    #[clap(long, action = ArgAction::SetTrue)]
+    ok_if_empty: bool,
+}
+
+#[derive(Args)]
+struct StatusArguments {
+    /// Twitter handle - username. Can be repeated. Every user under the output directory if not given
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Time zone the dates are shown and the months are grouped in, e.g. Europe/Berlin
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
+
+    /// Print the status as JSON instead of a table
+    #[clap(long, action = ArgAction::SetTrue)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct StatsArguments {
+    /// Twitter handle - username. Can be repeated. Every user recorded under the output directory if not given
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Time zone the months of the Tweets are grouped in, e.g. Europe/Berlin
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
+
+    /// json, or csv with one row per user and group: username,group,key,files,bytes. The groups are month, type and tweet
+    #[clap(long, value_parser, default_value = "json")]
+    format: ReportFormat,
+
+    /// Number of Tweets with the most media listed per user
+    #[clap(long, value_parser, default_value_t = 10)]
+    top: usize,
+
+    /// Write the report to this file instead of stdout
+    #[clap(long, value_parser, value_name = "FILE")]
+    to: Option<PathBuf>,
+
+    /// Print the disk usage per user and month of the Tweets du-style instead, from the recorded downloads, largest user first
+    #[clap(long, action = ArgAction::SetTrue, conflicts_with_all = &["format", "top", "to"])]
+    disk: bool,
+
+    /// With --disk, scan the user directories instead of reading the records, counting every file by the month of its modification time
+    #[clap(long, action = ArgAction::SetTrue, requires = "disk")]
+    scan: bool,
+}
+
+#[derive(Args)]
+struct VerifyArguments {
+    /// Check, read-only, that the files exist on a mirror instead: rsync://..., host:path, s3://..., http(s):// (WebDAV) or a local directory
+    #[clap(long = "against", value_parser)]
+    mirror: Option<String>,
+
+    /// Twitter handle - username. Can be repeated. Every user under the output directory if not given
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Re-download missing and corrupted files instead of marking them as failed
+    #[clap(long, action = ArgAction::SetTrue, conflicts_with = "mirror")]
+    repair: bool,
+
+    /// Number of retries for re-downloads failing with network errors, timeouts or 5xx responses
+    #[clap(long, value_parser, default_value_t = 3)]
+    retries: u32,
+}
+
+#[derive(Args)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct ExportArguments {
+    /// Twitter handle - username of the archive to export
+    #[clap(short = 'u', long, value_parser, required = true)]
+    username: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<ExportCommand>,
+}
+
+#[derive(Subcommand)]
+enum ExportCommand {
+    /// Print the original quality media urls of users instead of downloading them, for an external download manager
+    Urls(ExportUrlsArguments),
+}
+
+#[derive(Args)]
+struct ExportUrlsArguments {
+    /// Bearer Token. Can be passed as BEARER_TOKEN. Prefer --bearer-token-file, arguments show up in process listings and shell history
+    #[clap(short, long, value_parser, env, hide_env_values = true, required_unless_present = "bearer_token_file")]
+    bearer_token: Option<String>,
+
+    /// Read the Bearer Token from this file, or from stdin with -
+    #[clap(long, value_parser, conflicts_with = "bearer_token")]
+    bearer_token_file: Option<PathBuf>,
+
+    /// Twitter handle - username. Can be repeated
+    #[clap(short = 'u', long = "username", value_parser, required = true)]
+    usernames: Vec<String>,
+
+    /// plain: one url per line. aria2c: an input file for `aria2c -i`, saving the files under <user>/ with the default file names
+    #[clap(long, value_parser, default_value = "plain")]
+    format: UrlFormat,
+
+    /// Write the urls to this file instead of stdout
+    #[clap(long, value_parser, value_name = "FILE")]
+    to: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ForgetArguments {
+    /// Media key or path of the file to forget. Can be repeated
+    #[clap(value_parser, value_name = "MEDIA_KEY|FILE", required = true)]
+    targets: Vec<String>,
+
+    /// Move the files to <output_dir>/.trash for this long instead of deleting them, e.g. 7d. Defaults to 30d without a value. Undo with restore
+    #[clap(long, value_parser = common::parse_duration, value_name = "DURATION", min_values = 0, default_missing_value = "30d")]
+    quarantine: Option<Duration>,
+}
+
+#[derive(Args)]
+struct RestoreArguments {
+    /// Media key to restore. Can be repeated
+    #[clap(value_parser, value_name = "MEDIA_KEY", required_unless_present = "list")]
+    media_keys: Vec<String>,
+
+    /// List the quarantined media keys with their expiry instead
+    #[clap(long, action = ArgAction::SetTrue)]
+    list: bool,
+}
+
+#[derive(Args)]
+struct ImportArchiveArguments {
+    /// Path of the zip of the data export
+    #[clap(value_parser)]
+    path: PathBuf,
+
+    /// Twitter handle - username to import as. Defaults to the account of the export
+    #[clap(short = 'u', long, value_parser)]
+    username: Option<String>,
+
+    /// Template of the local file names, see `download --filename-template`
+    #[clap(long, value_parser, default_value = DEFAULT_TEMPLATE)]
+    filename_template: FilenameTemplate,
+
+    /// Directories of the files under the user directory, see `download --layout`
+    #[clap(long, value_parser, default_value = "flat")]
+    layout: Layout,
+
+    /// Time zone of the dates of the file names and directories, see `download --timezone`
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
+
+    /// What to do with photos downloaded before under another name, see `download --dedup`
+    #[clap(long, value_parser, default_value = "off")]
+    dedup: Dedup,
+
+    /// Number of retries for downloads failing with network errors, timeouts or 5xx responses
+    #[clap(long, value_parser, default_value_t = 3)]
+    retries: u32,
+}
+
+#[derive(Args)]
+struct RenameUserArguments {
+    /// Current handle of the archive
+    #[clap(value_parser)]
+    old: String,
+
+    /// New handle
+    #[clap(value_parser)]
+    new: String,
+}
+
+#[derive(Args)]
+struct RetryArguments {
+    /// Twitter handle - username to retry. Can be repeated. Defaults to every user
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Bearer Token to refresh expired urls. Can be passed as BEARER_TOKEN. Prefer --bearer-token-file, arguments show up in process listings and shell history
+    #[clap(short, long, value_parser, env, hide_env_values = true)]
+    bearer_token: Option<String>,
+
+    /// Read the Bearer Token from this file, or from stdin with -
+    #[clap(long, value_parser, conflicts_with = "bearer_token")]
+    bearer_token_file: Option<PathBuf>,
+
+    /// Number of retries for downloads failing with network errors, timeouts or 5xx responses
+    #[clap(long, value_parser, default_value_t = 3)]
+    retries: u32,
+
+    /// Leave out the downloads that failed this many times
+    #[clap(long, value_parser)]
+    max_attempts: Option<u32>,
+}
+
+#[derive(Args)]
+struct MigrateArguments {
+    /// Twitter handle - username to migrate. Can be repeated. Defaults to every user directory
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+}
+
+#[derive(Args)]
+struct IndexArguments {
+    #[clap(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Scan the user directories and record every media file by the media key of its sidecar or filename, or by its content, so runs skip them wherever they are
+    Rebuild(IndexRebuildArguments),
+}
+
+#[derive(Args)]
+struct IndexRebuildArguments {
+    /// Twitter handle - username to rebuild the index of. Can be repeated. Defaults to every user directory
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+}
+
+#[derive(Args)]
+struct SelfUpdateArguments {
+    /// Only check whether a newer release is available
+    #[clap(long, action = ArgAction::SetTrue)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct ServeArguments {
+    /// Address to serve the API at
+    #[clap(long, value_parser, default_value = "127.0.0.1:8787")]
+    listen: SocketAddr,
+
+    /// Bearer Token. Can be passed as BEARER_TOKEN. Prefer --bearer-token-file, arguments show up in process listings and shell history
+    #[clap(short, long, value_parser, env, hide_env_values = true)]
+    bearer_token: Option<String>,
+
+    /// Read the Bearer Token from this file, or from stdin with -
+    #[clap(long, value_parser, conflicts_with = "bearer_token")]
+    bearer_token_file: Option<PathBuf>,
+
+    /// Time zone of the dates of the user stats, see `download --timezone`
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
+}
+
+#[derive(Args)]
+struct StreamArguments {
+    /// Twitter handle - username. Can be repeated. Defaults to the users of the configuration file
+    #[clap(short = 'u', long = "username", value_parser)]
+    usernames: Vec<String>,
+
+    /// Bearer Token of an app with access to the filtered stream. Can be passed as BEARER_TOKEN. Prefer --bearer-token-file, arguments show up in process listings and shell history
+    #[clap(short, long, value_parser, env, hide_env_values = true)]
+    bearer_token: Option<String>,
+
+    /// Read the Bearer Token from this file, or from stdin with -
+    #[clap(long, value_parser, conflicts_with = "bearer_token")]
+    bearer_token_file: Option<PathBuf>,
+
+    /// Time zone for date based grouping and naming, see `download --timezone`
+    #[clap(long, value_parser, default_value = "UTC")]
+    timezone: Timezone,
 }
 
 
 #[tokio::main]
-/// Parses the command line arguments into the `Config` object and upon validation starts the download
-/// [twitter::start_download](twitter::start_download)
+/// Parses the command line arguments and runs the command.
 async fn main() {
-    // set the env for logging
-    let env = Env::default().filter_or("LOG_LEVEL", "info");
-    env_logger::init_from_env(env);
+    // parse the command line args, keeping the matches to tell given options from defaults
+    let matches = CliArguments::command().try_get_matches().unwrap_or_else(|e| exit_usage(e));
+    let args = CliArguments::from_arg_matches(&matches).unwrap_or_else(|e| exit_usage(e));
+    logging::init(args.log_format);
+    http::configure(HttpOptions {
+        connect_timeout: Some(args.connect_timeout),
+        request_timeout: args.request_timeout,
+        version: if args.http1 { HttpVersion::Http1 } else if args.http2 { HttpVersion::Http2 } else { HttpVersion::Auto },
+        user_agent: args.user_agent.clone(),
+        headers: args.headers.clone(),
+    });
+    if let Err(e) = http::install_proxy(args.proxy.clone()) {
+        error!("{}", e);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    // every log line carries the run id
+    let run_id = common::new_run_id();
+    let span = info_span!("run", run_id = %run_id);
+    run(args, matches, run_id).instrument(span).await;
+}
+
+/// Runs the command of `args`, with the settings of the configuration file for the options not in `matches`.
+async fn run(args: CliArguments, matches: ArgMatches, run_id: String) {
+    let settings = match args.command {
+        Command::Init => Settings::default(),
+        _ => load_settings(args.config.as_deref()),
+    };
+    let output_dir = match settings.output_dir.clone() {
+        Some(dir) if !given(&matches, "output_dir") => dir,
+        _ => args.output_dir,
+    };
+    if let Some(locale) = args.lang {
+        messages::set_locale(locale);
+    }
+
+    match args.command {
+        Command::Download(download) => run_download(output_dir, download, run_id, &settings, &matches).await,
+        Command::Status(status) => match stats::status(&output_dir, &status.usernames, status.timezone) {
+            Ok(users) if status.json => match serde_json::to_string_pretty(&users) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    error!("Cannot serialize the status: {}", e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            },
+            Ok(users) => stats::print_status(&users),
+            Err(e) => {
+                error!("Cannot read the status of {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Stats(stats_args) if stats_args.disk => {
+            let usage = match stats_args.scan {
+                true => stats::disk_usage(&output_dir, &stats_args.usernames, stats_args.timezone).map_err(|e| e.into()),
+                false => stats::recorded_disk_usage(&output_dir, &stats_args.usernames, stats_args.timezone),
+            };
+            match usage {
+                Ok(usage) => stats::print_disk_usage(&usage),
+                Err(e) => {
+                    error!("Cannot compute the disk usage of {}: {}", output_dir.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        Command::Stats(stats_args) => {
+            let written = stats::report(&output_dir, &stats_args.usernames, stats_args.timezone, stats_args.top)
+                .and_then(|reports| {
+                    match &stats_args.to {
+                        Some(path) => stats::write_report(&reports, stats_args.format, &mut io::BufWriter::new(fs::File::create(path)?))?,
+                        None => stats::write_report(&reports, stats_args.format, &mut io::stdout().lock())?,
+                    }
+                    Ok(())
+                });
+            if let Err(e) = written {
+                error!("Cannot export the statistics of {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        Command::Verify(VerifyArguments { mirror: Some(mirror), usernames, .. }) => match mirror::verify_against(&output_dir, &usernames, &mirror).await {
+            Ok(report) => {
+                mirror::print_report(&report);
+                if !report.missing.is_empty() {
+                    std::process::exit(EXIT_PARTIAL);
+                }
+            }
+            Err(e) => {
+                error!("Cannot verify against {}: {}", mirror, e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Verify(args) => {
+            let retry = RetryPolicy { retries: args.retries, ..RetryPolicy::default() };
+            match verify::verify(&output_dir, &args.usernames, args.repair, &retry, &run_id).await {
+                Ok(report) => {
+                    verify::print_report(&report);
+                    if report.findings.len() > report.repaired {
+                        std::process::exit(EXIT_PARTIAL);
+                    }
+                }
+                Err(e) => {
+                    error!("Cannot verify {}: {}", output_dir.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        Command::Export(ExportArguments { command: Some(ExportCommand::Urls(export_urls)), .. }) => run_export_urls(export_urls).await,
+        Command::Export(export) => {
+            let username = export.username.unwrap_or_default();
+            match takeout::create_takeout(&output_dir, &username) {
+                Ok(path) => println!("{}", path.display()),
+                Err(e) => {
+                    error!("username: {}. Cannot create the takeout: {}", username, e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        Command::Forget(forget_args) => {
+            let mut failed = false;
+            for target in forget_args.targets.iter() {
+                match forget::forget(&output_dir, target, forget_args.quarantine) {
+                    Ok((media_key, files)) => match forget_args.quarantine {
+                        Some(retention) => println!("{}", Message::Quarantined { media_key: &media_key, files, days: retention.as_secs() / (24 * 60 * 60) }),
+                        None => println!("{}", Message::Forgotten { media_key: &media_key, deleted: files }),
+                    },
+                    Err(e) => {
+                        error!("Cannot forget {}: {}", target, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        Command::Restore(restore_args) if restore_args.list => match trash::entries(&output_dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let expires = OffsetDateTime::from_unix_timestamp(entry.expires_at as i64).ok()
+                        .and_then(|d| d.format(format_description!("[year]-[month]-[day] [hour]:[minute]")).ok())
+                        .unwrap_or_default();
+                    println!("{}", Message::TrashEntry { media_key: &entry.media_key, files: entry.files.len(), expires: &expires });
+                }
+            }
+            Err(e) => {
+                error!("Cannot read the quarantine of {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Restore(restore_args) => {
+            let mut failed = false;
+            for media_key in restore_args.media_keys.iter() {
+                match trash::restore(&output_dir, media_key) {
+                    Ok(files) => println!("{}", Message::Restored { media_key, files }),
+                    Err(e) => {
+                        error!("Cannot restore {}: {}", media_key, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        Command::Init => {
+            let path = match args.config.or_else(settings::default_path) {
+                Some(path) => path,
+                None => {
+                    error!("Cannot find a configuration directory, pass --config");
+                    std::process::exit(EXIT_USAGE);
+                }
+            };
+            if let Err(e) = init::run(&path).await {
+                error!("Cannot set up {}: {}", path.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        Command::ImportArchive(import_args) => {
+            let options = ImportOptions {
+                username: import_args.username,
+                filename_template: import_args.filename_template,
+                layout: import_args.layout,
+                timezone: import_args.timezone,
+                dedup: import_args.dedup,
+                retry: RetryPolicy { retries: import_args.retries, ..RetryPolicy::default() },
+                run_id,
+            };
+            match import::import_archive(&import_args.path, &output_dir, &options).await {
+                Ok(report) => println!("{}", Message::Imported { username: &report.username, tweets: report.tweets, downloaded: report.downloaded, skipped: report.skipped, failed: report.failed }),
+                Err(e) => {
+                    error!("Cannot import {}: {}", import_args.path.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        Command::RenameUser(rename_args) => match rename::rename_user(&output_dir, &rename_args.old, &rename_args.new) {
+            Ok(report) => println!("{}", Message::Renamed { old: &rename_args.old, new: &rename_args.new, paths: report.paths }),
+            Err(e) => {
+                error!("Cannot rename {} to {}: {}", rename_args.old, rename_args.new, e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Retry(retry_args) => {
+            let bearer_token_file = match (&retry_args.bearer_token, retry_args.bearer_token_file) {
+                (None, None) => settings.bearer_token_file.clone(),
+                (_, file) => file,
+            };
+            let credentials = Credentials::Bearer(bearer_token(retry_args.bearer_token, bearer_token_file.as_deref()));
+            let credentials = credentials.missing().is_none().then_some(credentials);
+            let retry = RetryPolicy { retries: retry_args.retries, ..RetryPolicy::default() };
+            match failed::retry_failed(&output_dir, &retry_args.usernames, credentials.as_ref(), &retry, retry_args.max_attempts, &run_id).await {
+                Ok(report) => println!("{}", Message::RetrySummary { retried: report.retried, recovered: report.recovered, given_up: report.given_up }),
+                Err(e) => {
+                    error!("Cannot retry the failed downloads of {}: {}", output_dir.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        Command::Migrate(migrate_args) => match migrate::migrate(&output_dir, &migrate_args.usernames, &run_id) {
+            Ok(reports) => {
+                for report in reports {
+                    println!("{}", Message::Migrated { username: &report.username, checkpoint: report.checkpoint, files: report.files, known: report.known });
+                }
+            }
+            Err(e) => {
+                error!("Cannot migrate {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Index(IndexArguments { command: IndexCommand::Rebuild(rebuild_args) }) => match index::rebuild(&output_dir, &rebuild_args.usernames, &run_id) {
+            Ok(reports) => {
+                for report in reports {
+                    println!("{}", Message::IndexRebuilt { username: &report.username, files: report.files, moved: report.moved, known: report.known, unmatched: report.unmatched });
+                }
+            }
+            Err(e) => {
+                error!("Cannot rebuild the index of {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        Command::Serve(serve_args) => run_serve(output_dir, serve_args, settings, matches).await,
+        Command::Stream(stream_args) => run_stream(output_dir, stream_args, run_id, settings, matches).await,
+        Command::SelfUpdate(self_update) => match update::self_update(self_update.check).await {
+            Ok(update::UpdateStatus::UpToDate(version)) => println!("{}", Message::UpToDate { version: &version }),
+            Ok(update::UpdateStatus::Available(version)) => println!("{}", Message::UpdateAvailable { version: &version }),
+            Ok(update::UpdateStatus::Updated(version, path)) => println!("{}", Message::Updated { path: &path, version: &version }),
+            Err(e) => {
+                error!("Cannot update: {}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+    }
+}
+
+/// Returns the users with state under `output_dir`: recorded in the state database or with a legacy checkpoint file.
+fn tracked_users(output_dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut users = if output_dir.join(state::STATE_FILENAME).exists() {
+        StateStore::open(output_dir)?.tracked_users()?
+    } else {
+        Vec::new()
+    };
+    if output_dir.is_dir() {
+        for entry in fs::read_dir(output_dir)? {
+            let entry = entry?;
+            let username = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().join(state::LEGACY_CHECKPOINT_FILENAME).is_file() && !users.contains(&username) {
+                users.push(username);
+            }
+        }
+    }
+    users.sort();
+    Ok(users)
+}
+
+/// Reads the configuration file at `path`, or the default one if it exists.
+///
+/// Exits with [EXIT_USAGE](EXIT_USAGE) if the file cannot be read or is invalid.
+fn load_settings(path: Option<&Path>) -> Settings {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match settings::default_path().filter(|p| p.exists()) {
+            Some(path) => path,
+            None => return Settings::default(),
+        },
+    };
+    match Settings::load(&path) {
+        Ok(settings) => {
+            info!("Using the configuration file {}", path.display());
+            settings
+        }
+        Err(e) => {
+            error!("Cannot read the configuration file {}: {}", path.display(), e);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Exits on the command line error `e`: successfully for --help and --version, with [EXIT_USAGE] otherwise.
+fn exit_usage(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit();
+    }
+    let _ = e.print();
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Returns true if the option `id` was given on the command line or as environment variable, to `matches` or its subcommand.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    let source = matches.try_get_raw(id).ok().and_then(|_| matches.value_source(id));
+    matches!(source, Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable))
+        || matches.subcommand().is_some_and(|(_, sub)| given(sub, id))
+}
+
+/// Applies the configuration file `profile` of a user to `builder` for the options not given on the command line.
+/// `filter` is that of the command line.
+fn apply_profile(mut builder: ConfigBuilder, filter: &TweetFilter, profile: &Profile, matches: &ArgMatches) -> Result<ConfigBuilder, String> {
+    let from_file = |id: &str| !given(matches, id);
+    if let Some(dir) = profile.output_dir.as_ref().filter(|_| from_file("output_dir")) {
+        builder = builder.output_dir(dir);
+    }
+    if let Some(download_all) = profile.download_all.filter(|_| from_file("download_all")) {
+        builder = builder.download_all(download_all);
+    }
+
+    let mut filter = filter.clone();
+    let regexes = |patterns: &[String]| patterns.iter().map(|p| Regex::new(p).map_err(|e| e.to_string())).collect::<Result<Vec<Regex>, String>>();
+    if !profile.matches.is_empty() && from_file("matches") {
+        filter.matches = regexes(&profile.matches)?;
+    }
+    if !profile.exclude_matches.is_empty() && from_file("exclude_matches") {
+        filter.excludes = regexes(&profile.exclude_matches)?;
+    }
+    if let Some(min_likes) = profile.min_likes.filter(|_| from_file("min_likes")) {
+        filter.min_likes = min_likes;
+    }
+    if let Some(min_retweets) = profile.min_retweets.filter(|_| from_file("min_retweets")) {
+        filter.min_retweets = min_retweets;
+    }
+    if let Some(min_width) = profile.min_width.filter(|_| from_file("min_width")) {
+        filter.min_width = min_width;
+    }
+    if let Some(min_height) = profile.min_height.filter(|_| from_file("min_height")) {
+        filter.min_height = min_height;
+    }
+    if let Some(sensitive) = profile.sensitive.as_deref().filter(|_| from_file("sensitive")) {
+        filter.sensitive = sensitive.parse()?;
+    }
+    builder = builder.filter(filter);
+
+    if let Some(layout) = profile.layout.as_deref().filter(|_| from_file("layout")) {
+        builder = builder.layout(layout.parse()?);
+    }
+    if let Some(template) = profile.filename_template.as_deref().filter(|_| from_file("filename_template")) {
+        builder = builder.filename_template(template.parse()?);
+    }
+    Ok(builder)
+}
+
+/// Returns when `config.username` is polled next by the `every` and `quiet_hours` of its profile, see
+/// [schedule](schedule). `every` is the interval of users without one of their own.
+fn next_poll(config: &Config, profile: &Profile, every: Option<Duration>) -> Result<OffsetDateTime, String> {
+    let every = match &profile.every {
+        Some(every) => Some(common::parse_duration(every)?),
+        None => every,
+    };
+    let quiet_hours: Option<QuietHours> = profile.quiet_hours.as_deref().map(str::parse).transpose()?;
+    let last_run = match every {
+        Some(_) => StateStore::open(config.output_dir())
+            .map_err(|e| e.to_string())
+            .and_then(|state| state.user_summary(config.username()).map_err(|e| e.to_string()))?
+            .last_run,
+        None => None,
+    };
+    Ok(schedule::next_poll(last_run, every, quiet_hours.as_ref(), &config.timezone(), OffsetDateTime::from(clock::now())))
+}
+
+/// Returns `usernames` with the handles of renamed archives replaced by the new handles, see [rename](rename).
+fn resolve_aliases(output_dir: &Path, usernames: Vec<String>) -> Vec<String> {
+    if !output_dir.join(state::STATE_FILENAME).exists() {
+        return usernames;
+    }
+    let state = match StateStore::open(output_dir) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Cannot read the renamed users: {}", e);
+            return usernames;
+        }
+    };
+    usernames.into_iter()
+        .map(|username| match state.resolve_alias(&username) {
+            Ok(Some(renamed)) => {
+                info!("username: {}, alias: {}. Renamed, downloading to the archive of {}", renamed, username, renamed);
+                renamed
+            }
+            _ => username,
+        })
+        .collect()
+}
 
-    // parse the command line args
-    let args = CliArguments::parse();
+/// Reads the bearer token from the file at `path`, or from stdin if `path` is `-`. Surrounding whitespace is ignored.
+fn read_bearer_token_file(path: &Path) -> Result<String, io::Error> {
+    let mut token = String::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_string(&mut token)?;
+    } else {
+        token = fs::read_to_string(path)?;
+    }
+    Ok(token.trim().to_string())
+}
+
+/// Returns the bearer token of `--bearer-token` or `--bearer-token-file`.
+///
+/// Exits with [EXIT_USAGE](EXIT_USAGE) if the file cannot be read.
+fn bearer_token(token: Option<String>, file: Option<&Path>) -> String {
+    match (token, file) {
+        (_, Some(path)) => match read_bearer_token_file(path) {
+            Ok(token) => token,
+            Err(e) => {
+                error!("Cannot read the bearer token from {}: {}", path.display(), e);
+                std::process::exit(EXIT_USAGE);
+            }
+        },
+        (Some(token), None) => token,
+        (None, None) => String::new(),
+    }
+}
+
+/// Runs the `export urls` command: writes the media urls of every user to stdout or `--to`.
+async fn run_export_urls(args: ExportUrlsArguments) {
+    let credentials = Credentials::Bearer(bearer_token(args.bearer_token, args.bearer_token_file.as_deref()));
+    let mut out: Box<dyn Write> = match &args.to {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                error!("Cannot create {}: {}", path.display(), e);
+                std::process::exit(EXIT_USAGE);
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut failed = false;
+    for username in args.usernames.iter() {
+        if let Err(e) = urls::export_urls(&credentials, username, args.format, &mut out).await {
+            error!("username: {}. Cannot export the media urls: {}", username, e);
+            failed = true;
+        }
+    }
+    // exiting skips dropping, and flushing, the writer
+    if let Err(e) = out.flush() {
+        error!("Cannot write the media urls: {}", e);
+        failed = true;
+    }
+    if failed {
+        std::process::exit(EXIT_FAILURE);
+    }
+}
 
-    // create the basic common to be passed around
-    let config = Config {
-        bearer_token: args.bearer_token,
-        username: args.username,
-        count: args.count,
-        reset_marker: args.reset_marker,
-        download_all: args.download_all,
-        output_dir: args.output_dir,
+/// Runs the `serve` command: the [API](serve) until the process ends, downloading every queued user with its
+/// profile of the configuration file.
+async fn run_serve(output_dir: PathBuf, args: ServeArguments, settings: Settings, matches: ArgMatches) {
+    let bearer_token_file = match (&args.bearer_token, args.bearer_token_file) {
+        (None, None) => settings.bearer_token_file.clone(),
+        (_, file) => file,
+    };
+    let builder = Config::builder()
+        .credentials(Credentials::Bearer(bearer_token(args.bearer_token, bearer_token_file.as_deref())))
+        .output_dir(&output_dir)
+        .timezone(args.timezone);
+    let configs = move |username: &str| {
+        let profile = settings.profile(username);
+        apply_profile(builder.clone().run_id(common::new_run_id()), &TweetFilter::default(), &profile, &matches)
+            .and_then(|builder| builder.username(username).build().map_err(|e| e.to_string()))
     };
+    if let Err(e) = serve::serve(args.listen, output_dir.clone(), args.timezone, Arc::new(configs)).await {
+        error!("Cannot serve the API at {}: {}", args.listen, e);
+        std::process::exit(EXIT_FAILURE);
+    }
+}
 
-    info!("username: {}. Starting downloading media files", config.username );
+/// Runs the `stream` command: downloads the media of the Tweets of the users from the [filtered stream](stream) until
+/// Ctrl+C, with the per-user settings of the configuration file.
+async fn run_stream(output_dir: PathBuf, args: StreamArguments, run_id: String, settings: Settings, matches: ArgMatches) {
+    shutdown::install();
+    let bearer_token_file = match (&args.bearer_token, args.bearer_token_file) {
+        (None, None) => settings.bearer_token_file.clone(),
+        (_, file) => file,
+    };
+    let credentials = Credentials::Bearer(bearer_token(args.bearer_token, bearer_token_file.as_deref()));
+    let usernames = if args.usernames.is_empty() { settings.all_usernames() } else { args.usernames };
+    if usernames.is_empty() {
+        error!("No users to stream. Pass -u or list them in the configuration file");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let builder = Config::builder()
+        .credentials(credentials.clone())
+        .output_dir(&output_dir)
+        .timezone(args.timezone)
+        .run_id(run_id);
+    let mut configs = Vec::new();
+    for username in usernames {
+        let profile = settings.profile(&username);
+        match apply_profile(builder.clone(), &TweetFilter::default(), &profile, &matches).and_then(|builder| builder.username(&username).build().map_err(|e| e.to_string())) {
+            Ok(config) => configs.push(config),
+            Err(e) => {
+                error!("username: {}. {}", username, e);
+                std::process::exit(EXIT_USAGE);
+            }
+        }
+    }
 
-    match twitter::start_download(config).await {
-        Ok(s) => info!("{}", s),
-        Err(e) => error!("{}", e)
+    match stream::stream(&credentials, configs).await {
+        Ok(report) => println!("{}", Message::StreamSummary { tweets: report.tweets, files: report.downloaded }),
+        Err(e) => {
+            error!("Cannot stream the Tweets: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Runs the `download` command: the [Downloader](Downloader) for every user.
+///
+/// Each user runs as its own task, at most `--parallel-users` at a time.
+///
+/// With `--fail-if-empty` exits with [EXIT_EMPTY](EXIT_EMPTY) if a user reached the streak of runs without new media,
+/// unless `--ok-if-empty` is given too.
+///
+/// Exits with the code of the [run summary](RunSummary::exit_code) if a user failed, the token was rejected or the
+/// run ended rate limited.
+async fn run_download(output_dir: PathBuf, args: DownloadArguments, run_id: String, settings: &Settings, matches: &ArgMatches) {
+    let started = Instant::now();
+    let auth = match settings.auth.as_deref() {
+        Some(auth) if !given(matches, "auth") => auth.parse().unwrap_or_else(|e| {
+            error!("Invalid auth in the configuration file: {}", e);
+            std::process::exit(EXIT_USAGE);
+        }),
+        _ => args.auth,
+    };
+    let credentials = match auth {
+        AuthMode::Bearer => {
+            let bearer_token_file = match (&args.bearer_token, args.bearer_token_file) {
+                (None, None) => settings.bearer_token_file.clone(),
+                (_, file) => file,
+            };
+            Credentials::Bearer(bearer_token(args.bearer_token, bearer_token_file.as_deref()))
+        }
+        AuthMode::OAuth1 => Credentials::OAuth1 {
+            consumer_key: args.consumer_key.or_else(|| settings.consumer_key.clone()).unwrap_or_default(),
+            consumer_secret: args.consumer_secret.or_else(|| settings.consumer_secret.clone()).unwrap_or_default(),
+            access_token: args.access_token.or_else(|| settings.access_token.clone()).unwrap_or_default(),
+            access_token_secret: args.access_token_secret.or_else(|| settings.access_token_secret.clone()).unwrap_or_default(),
+        },
+    };
+    let filter = TweetFilter { matches: args.matches, excludes: args.exclude_matches, min_likes: args.min_likes, min_retweets: args.min_retweets, sensitive: args.sensitive, min_width: args.min_width, min_height: args.min_height };
+
+    // the common settings of all users, `username` is set per user
+    let builder = Config::builder()
+        .credentials(credentials.clone())
+        .count(args.count)
+        .max_pages(args.max_pages)
+        .fallback_backend(args.fallback_backend)
+        .reset_marker(args.reset_marker)
+        .download_all(args.download_all)
+        .output_dir(&output_dir)
+        .concurrency(args.concurrency.into())
+        .volumes(args.volumes)
+        .retry(RetryPolicy { retries: args.retries, base_delay: Duration::from_millis(args.retry_delay), ..RetryPolicy::default() })
+        .stall_timeout(Duration::from_secs(args.stall_timeout))
+        .max_file_size(args.max_file_size)
+        .min_free_space(args.min_free_space)
+        .set_mtime(args.set_mtime)
+        .date_policy(args.date_policy)
+        .order(args.order)
+        .source(args.source)
+        .query(args.query)
+        .since(args.since)
+        .until(args.until)
+        .filter(filter.clone())
+        .timezone(args.timezone)
+        .filename_template(args.filename_template)
+        .layout(args.layout)
+        .compat(args.compat)
+        .organize_by_source(args.organize_by_source)
+        .save_links(args.save_links)
+        .save_tweets(args.save_tweets)
+        .save_text(args.save_text)
+        .include_thread(args.include_thread)
+        .include_quoted(args.include_quoted)
+        .videos(args.videos)
+        .hls(args.hls)
+        .video_previews(args.video_previews)
+        .embed_metadata(args.embed_metadata)
+        .convert(args.convert)
+        .quality(args.quality)
+        .dedup(args.dedup)
+        .near_dupes(args.near_dupes)
+        .checksums(args.checksums)
+        .sync_new(args.sync_new)
+        .wait_for_lock(args.wait)
+        .exec(args.exec.map(ExecHook::new))
+        .archive(args.archive);
+
+    // users of the configuration file run on their schedule, users picked with -u right away, and on the schedule in
+    // the later rounds of --watch
+    let scheduled = args.usernames.is_empty();
+    let mut usernames = if scheduled { settings.all_usernames() } else { args.usernames };
+    if args.all_tracked {
+        match tracked_users(&output_dir) {
+            Ok(tracked) => {
+                info!("Downloading {} tracked users", tracked.len());
+                for username in tracked {
+                    if !usernames.contains(&username) {
+                        usernames.push(username);
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Cannot read the tracked users of {}: {}", output_dir.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        if usernames.is_empty() {
+            warn!("No tracked users under {}. Download a user with -u once to track it", output_dir.display());
+        }
+    }
+    if let Some(crawled) = &args.crawl_following {
+        match following::following(&credentials, crawled, args.min_followers, args.max_accounts.map(|max| max as usize)).await {
+            Ok(followed) => {
+                info!("username: {}. Downloading {} followed accounts", crawled, followed.len());
+                for username in followed {
+                    if !usernames.contains(&username) {
+                        usernames.push(username);
+                    }
+                }
+            }
+            Err(e) => {
+                error!("username: {}. Cannot list the followed accounts: {}", crawled, e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    if usernames.is_empty() && !args.all_tracked && args.crawl_following.is_none() {
+        error!("No users to download. Pass -u or list usernames in the configuration file");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let usernames = resolve_aliases(&output_dir, usernames);
+    // users without an interval of their own are polled every --interval in --watch
+    let every = if args.watch { Some(args.interval) } else { None };
+    // rebuilt every round of --watch, with the run id of the round: every user with its next poll
+    let plan = |run_id: &str| {
+        let mut users = Vec::new();
+        for username in usernames.iter() {
+            let profile = settings.profile(username);
+            let config = apply_profile(builder.clone().run_id(run_id), &filter, &profile, matches)
+                .and_then(|builder| builder.username(username.as_str()).build().map_err(|e| e.to_string()));
+            let config = match config {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("username: {}. Invalid settings: {}", username, e);
+                    std::process::exit(EXIT_USAGE);
+                }
+            };
+            match next_poll(&config, &profile, every) {
+                Ok(next) => users.push((config, next)),
+                Err(e) => {
+                    error!("username: {}. Invalid schedule: {}", username, e);
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+        }
+        users
+    };
+
+    if let Err(e) = trash::purge_expired(&output_dir) {
+        warn!("Cannot purge the expired quarantine: {}", e);
+    }
+
+    shutdown::install();
+    if let Some(budget) = args.time_budget {
+        shutdown::install_time_budget(budget);
+    }
+    let tui = args.tui && tui::install(&usernames);
+    if args.tui && !tui {
+        warn!("Cannot show the dashboard, stdout is not a terminal");
+    }
+    if !args.no_progress && !tui {
+        progress::install();
+    }
+    if let Some(rate) = args.limit_rate {
+        throttle::install(rate);
+        info!("Limiting the downloads to {}/s", stats::format_bytes(rate));
+    }
+
+    if let Some(dir) = &args.debug_http {
+        match capture::install(dir) {
+            Ok(()) => info!("Capturing the HTTP requests and responses to {}", dir.display()),
+            Err(e) => error!("Cannot capture the HTTP requests and responses to {}: {}", dir.display(), e),
+        }
+    }
+
+    let client = http::client().unwrap_or_else(|e| {
+        error!("Cannot build the HTTP client: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    let notify_routes = match given(matches, "notify_routes") {
+        true => args.notify_routes,
+        false => settings.notify.iter().map(|route| route.parse()).collect::<Result<Vec<Route>, String>>().unwrap_or_else(|e| {
+            error!("Invalid notify route in the configuration file: {}", e);
+            std::process::exit(EXIT_USAGE);
+        }),
+    };
+    let notifier = Arc::new(Dispatcher::new(client.clone(), notify_routes));
+    let rollup = Arc::new(args.notify_rollup.map(|period| RollUp::new(&output_dir, period)));
+    let summary_notifier = SummaryNotifier::new(client.clone(), args.notify_url, args.notify_when);
+    if let Some(addr) = args.metrics_addr {
+        if let Err(e) = metrics::serve(addr).await {
+            error!("Cannot serve the metrics at {}: {}", addr, e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+
+    let mut round_run_id = run_id;
+    let mut round_started = started;
+    let mut first_round = true;
+    let (results, summary) = loop {
+        let rate_limit_waits = ratelimit::waits();
+        let now = OffsetDateTime::from(clock::now());
+        let mut configs = Vec::new();
+        for (config, next) in plan(&round_run_id) {
+            if next <= now || (first_round && !scheduled) {
+                configs.push(config);
+            } else if !args.watch {
+                info!("username: {}, next_poll: {}. Not due yet, skipping", config.username(), format_poll(&config, next));
+            }
+        }
+        let results = run_round(configs, args.parallel_users, &notifier, &rollup).await;
+        let summary = RunSummary::new(&round_run_id, round_started.elapsed(), &results);
+        if let Some(path) = &args.summary_json {
+            if let Err(e) = summary.write(path) {
+                error!("Cannot write the run summary to {}: {}", path.display(), e);
+            }
+        }
+        summary_notifier.notify(&summary).await;
+        metrics::record(&summary);
+        if let Some(url) = &args.metrics_push {
+            if let Err(e) = metrics::push(&client, url).await {
+                error!("Cannot push the metrics to {}: {}", url, e);
+            }
+        }
+        if !args.watch || shutdown::is_requested() {
+            break (results, summary);
+        }
+
+        // the next round starts with the first user due, at least a minute from now. A round that ran into the rate
+        // limit leaves the next one a fresh window
+        let now = OffsetDateTime::from(clock::now());
+        let mut next_round: Option<OffsetDateTime> = None;
+        for (config, next) in plan(&round_run_id) {
+            info!("username: {}, next_poll: {}. Next poll planned", config.username(), format_poll(&config, next));
+            next_round = Some(next_round.map_or(next, |round| round.min(next)));
+        }
+        let until_due = next_round.map_or(args.interval, |next| Duration::try_from(next - now).unwrap_or_default());
+        let mut pause = until_due.max(MIN_WATCH_PAUSE);
+        if ratelimit::waits() > rate_limit_waits {
+            pause = pause.max(ratelimit::RATE_LIMIT_WINDOW);
+        }
+        info!("{} files downloaded. Next round in {} seconds", summary.downloaded, pause.as_secs());
+        if !shutdown::sleep(pause).await {
+            break (results, summary);
+        }
+        round_run_id = common::new_run_id();
+        round_started = Instant::now();
+        first_round = false;
+    };
+
+    progress::finish();
+    tui::finish();
+    let mut total_count: u32 = 0;
+
+    let mut duplicates: u64 = 0;
+    let mut dedup_saved_bytes: u64 = 0;
+    let empty_limit = if args.ok_if_empty { None } else { args.fail_if_empty };
+    let mut empty = false;
+    for report in results.into_iter().filter_map(|(_, result)| result.ok()) {
+        total_count += report.downloaded;
+        duplicates += report.duplicates;
+        dedup_saved_bytes += report.dedup_saved_bytes;
+        if empty_limit.is_some_and(|runs| report.empty_streak >= runs) {
+            warn!("username: {}, empty_streak: {}. No new media for {} consecutive runs", report.username, report.empty_streak, report.empty_streak);
+            empty = true;
+        }
+    }
+
+    if duplicates > 0 {
+        info!("{}", Message::Duplicates { files: duplicates, saved: &stats::format_bytes(dedup_saved_bytes) });
+    }
+
+    if shutdown::is_time_budget_exhausted() {
+        info!("{}", Message::TimeBudgetExhausted { downloaded: total_count });
+    } else if shutdown::is_requested() {
+        warn!("{}", Message::Interrupted { downloaded: total_count });
+        std::process::exit(shutdown::EXIT_INTERRUPTED);
+    }
+    if summary.exit_code != 0 {
+        std::process::exit(summary.exit_code);
+    }
+    if empty {
+        std::process::exit(EXIT_EMPTY);
     }
     info!("Exiting.")
 }
+
+/// Returns the poll time `next` of `config.username` in its time zone.
+fn format_poll(config: &Config, next: OffsetDateTime) -> String {
+    config.timezone().local(next).format(format_description!("[year]-[month]-[day] [hour]:[minute]")).unwrap_or_default()
+}
+
+/// Downloads the users of `configs`, `parallel_users` at a time. With 1 the users take turns page by page.
+///
+/// Returns the outcome of every user, in the order of `configs`.
+async fn run_round(configs: Vec<Config>, parallel_users: u16, notifier: &Arc<Dispatcher>, rollup: &Arc<Option<RollUp>>) -> Vec<(String, Result<DownloadReport, DownloadError>)> {
+    let mut results: Vec<(String, Result<DownloadReport, DownloadError>)> = Vec::new();
+    if parallel_users == 1 && configs.len() > 1 {
+        // one user at a time, interleaved page by page
+        let downloaders: Vec<Downloader> = configs.into_iter().map(Downloader::new).collect();
+        let users: Vec<(String, String)> = downloaders.iter().map(|d| (d.username().to_string(), d.run_id().to_string())).collect();
+        info!("Starting downloading media files of {} users, page by page in turns", users.len());
+        let outcomes = Downloader::run_round_robin(downloaders).await;
+        for ((username, run_id), result) in users.into_iter().zip(outcomes) {
+            let result = finish_user(username.clone(), run_id, result, notifier, rollup).await;
+            results.push((username, result));
+        }
+    } else {
+        let semaphore = Arc::new(Semaphore::new(parallel_users.into()));
+        let mut users = Vec::new();
+        for config in configs {
+            let notifier = notifier.clone();
+            let rollup = rollup.clone();
+            let semaphore = semaphore.clone();
+            let username = config.username().to_string();
+            users.push((username, tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                run_user(config, &notifier, &rollup).await
+            }.in_current_span())));
+        }
+        for (username, user) in users {
+            let result = user.await.unwrap_or_else(|e| {
+                error!("username: {}. User task failed: {}", username, e);
+                Err(DownloadError::Other(format!("User task failed: {}", e)))
+            });
+            results.push((username, result));
+        }
+    }
+    results
+}
+
+/// Runs the [Downloader](Downloader) for `config.username`, see [finish_user](finish_user).
+async fn run_user(config: Config, notifier: &Dispatcher, rollup: &Option<RollUp>) -> Result<DownloadReport, DownloadError> {
+    let username = config.username().to_string();
+    let run_id = config.run_id().to_string();
+
+    info!("username: {}. Starting downloading media files", username);
+
+    let result = Downloader::new(config).run().await;
+    finish_user(username, run_id, result, notifier, rollup).await
+}
+
+/// Logs the `result` of the run of `username` and sends the notifications for it, or records it in the `rollup`.
+///
+/// Returns the `result`.
+async fn finish_user(username: String, run_id: String, result: Result<DownloadReport, DownloadError>, notifier: &Dispatcher, rollup: &Option<RollUp>) -> Result<DownloadReport, DownloadError> {
+    tui::set_state(&username, if result.is_ok() { UserState::Done } else { UserState::Failed });
+    let message = match &result {
+        Ok(report) => {
+            let mut message = Message::DownloadComplete { downloaded: report.downloaded }.to_string();
+            if report.duplicates > 0 {
+                message.push_str(&format!(" {}", Message::Duplicates { files: report.duplicates, saved: &stats::format_bytes(report.dedup_saved_bytes) }));
+            }
+            info!("username: {}. {}", username, message);
+            message
+        }
+        Err(e) => {
+            error!("username: {}. {}", username, e);
+            notifier.notify(Notification { event: Event::Error, username: username.clone(), message: e.to_string(), run_id: run_id.clone() }).await;
+            Message::DownloadFailed { error: &e.to_string() }.to_string()
+        }
+    };
+    let count = result.as_ref().ok().map(|r| r.downloaded);
+
+    match rollup {
+        Some(rollup) => match rollup.record(&username, count.unwrap_or(0), count.is_none()) {
+            Ok(Some(summary)) => notifier.notify(Notification { event: Event::Summary, username, message: summary, run_id }).await,
+            Ok(None) => (),
+            Err(e) => error!("username: {}. Cannot update the notification roll-up: {}", username, e),
+        },
+        None => {
+            if let Some(count) = count.filter(|c| *c > 0) {
+                notifier.notify(Notification { event: Event::NewMedia, username: username.clone(), message: Message::NewMedia { files: count }.to_string(), run_id: run_id.clone() }).await;
+            }
+            notifier.notify(Notification { event: Event::RunComplete, username, message, run_id }).await;
+        }
+    }
+
+    result
+}
@@ -0,0 +1,156 @@
+//! module to keep checksum manifests of the downloaded media files, for external integrity checks.
+//!
+//! The manifests of a user directory are rewritten from the [state database](crate::state) at the end of every run
+//! and after `verify --repair`, `retry-failed` and `import`, so they list every recorded file once, with its
+//! current checksum. Each [checksum format](ChecksumFormat) is a set of files in the user directory:
+//! * [Manifest](ChecksumFormat::Manifest), always kept: `manifest.sha256` in the `sha256sum` format, so
+//!   `sha256sum -c manifest.sha256` in the user directory checks it, and `manifest.jsonl` with the hash, size, media
+//!   key and local path of every file, one JSON object per line
+//! * [Sha256Sums](ChecksumFormat::Sha256Sums), with `--checksums sha256sums`: `SHA256SUMS` for stock tools
+//!
+//! Files under the user directory are listed relative to it, files elsewhere, e.g. on another volume, with their full
+//! path. The `sha256sum` files only list the files that exist.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::str::FromStr;
+
+use serde_json::json;
+
+use crate::common::write_atomic;
+use crate::state::{MediaRecord, StateStore};
+
+/// Name of the `sha256sum` manifest.
+pub const SHA256_MANIFEST_FILENAME: &str = "manifest.sha256";
+
+/// Name of the JSON Lines manifest.
+pub const JSON_MANIFEST_FILENAME: &str = "manifest.jsonl";
+
+/// Name of the per user [ChecksumFormat::Sha256Sums](ChecksumFormat::Sha256Sums) file.
+pub const SHA256SUMS_FILENAME: &str = "SHA256SUMS";
+
+/// A format of the checksum files kept per user directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumFormat {
+    /// `manifest.sha256` and `manifest.jsonl`, always kept
+    Manifest,
+    /// `SHA256SUMS` as written by `sha256sum`, checked with `sha256sum -c SHA256SUMS` in the user directory
+    Sha256Sums,
+}
+
+impl FromStr for ChecksumFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manifest" => Ok(ChecksumFormat::Manifest),
+            "sha256sums" => Ok(ChecksumFormat::Sha256Sums),
+            _ => Err(format!("unknown checksum format '{}'. Expected manifest or sha256sums", s)),
+        }
+    }
+}
+
+impl ChecksumFormat {
+    /// Returns the [Manifest](ChecksumFormat::Manifest) followed by the other formats of `extra`, once each.
+    pub fn with_manifest(extra: &[ChecksumFormat]) -> Vec<ChecksumFormat> {
+        let mut formats = vec![ChecksumFormat::Manifest];
+        for format in extra {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        formats
+    }
+
+    /// Name of the checksum file of the format.
+    pub fn filename(&self) -> &'static str {
+        match self {
+            ChecksumFormat::Manifest => SHA256_MANIFEST_FILENAME,
+            ChecksumFormat::Sha256Sums => SHA256SUMS_FILENAME,
+        }
+    }
+
+    /// Rewrites the checksum files of the format in `user_output_dir` with `records`.
+    ///
+    /// Records without a checksum are left out.
+    ///
+    /// Returns the path of the checksum file and the number of files listed.
+    pub fn write(&self, user_output_dir: &Path, records: &[MediaRecord]) -> Result<(PathBuf, usize), io::Error> {
+        let mut files: BTreeMap<String, &str> = BTreeMap::new();
+        for record in records {
+            if let Some(sha256) = &record.sha256 {
+                if let Some(path) = listed_path(user_output_dir, &record.local_path) {
+                    files.insert(path, sha256);
+                }
+            }
+        }
+
+        if *self == ChecksumFormat::Manifest {
+            let mut lines: BTreeMap<String, String> = BTreeMap::new();
+            for record in records {
+                if let Some(sha256) = &record.sha256 {
+                    let line = json!({
+                        "sha256": sha256,
+                        "size": record.size,
+                        "media_key": record.media_key,
+                        "username": record.username,
+                        "local_path": record.local_path.to_string_lossy(),
+                    });
+                    lines.insert(record.local_path.to_string_lossy().into_owned(), format!("{}\n", line));
+                }
+            }
+            write_atomic(&user_output_dir.join(JSON_MANIFEST_FILENAME), lines.into_values().collect::<String>().as_bytes())?;
+        }
+
+        let contents: String = files.iter().map(|(path, sha256)| sha256sum_line(sha256, path)).collect();
+        let path = user_output_dir.join(self.filename());
+        write_atomic(&path, contents.as_bytes())?;
+        Ok((path, files.len()))
+    }
+}
+
+/// Rewrites the checksum files of `formats` in the directory of `username` under `output_dir` from `state`.
+///
+/// Returns the path and the number of files listed of every checksum file.
+pub fn rewrite(state: &StateStore, output_dir: &Path, username: &str, formats: &[ChecksumFormat]) -> Result<Vec<(PathBuf, usize)>, Box<dyn Error + Send + Sync>> {
+    let user_output_dir = output_dir.join(username);
+    let records = state.downloaded_media(Some(username))?;
+    let mut written = Vec::new();
+    for format in formats {
+        written.push(format.write(&user_output_dir, &records)?);
+    }
+    Ok(written)
+}
+
+/// Rewrites the [Manifest](ChecksumFormat::Manifest) of `username` and the checksum files of the other formats kept
+/// in its directory, after files were recorded outside of a run.
+pub fn refresh(state: &StateStore, output_dir: &Path, username: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let user_output_dir = output_dir.join(username);
+    let formats: Vec<ChecksumFormat> = [ChecksumFormat::Manifest, ChecksumFormat::Sha256Sums].into_iter()
+        .filter(|format| *format == ChecksumFormat::Manifest || user_output_dir.join(format.filename()).is_file())
+        .collect();
+    rewrite(state, output_dir, username, &formats)?;
+    Ok(())
+}
+
+/// Returns the path `local_path` is listed with in the checksum files of `user_output_dir`, None if it is missing.
+fn listed_path(user_output_dir: &Path, local_path: &Path) -> Option<String> {
+    let path = match local_path.strip_prefix(user_output_dir) {
+        Ok(relative) if local_path.is_file() => relative.to_path_buf(),
+        _ => fs::canonicalize(local_path).ok()?,
+    };
+    let path = path.to_string_lossy().into_owned();
+    Some(if MAIN_SEPARATOR != '/' { path.replace(MAIN_SEPARATOR, "/") } else { path })
+}
+
+/// Returns the `sha256sum` line of `path`. Like `sha256sum`, a path with a newline or backslash is escaped and the
+/// line marked with a leading backslash.
+fn sha256sum_line(sha256: &str, path: &str) -> String {
+    if path.contains('\n') || path.contains('\\') {
+        format!("\\{}  {}\n", sha256, path.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        format!("{}  {}\n", sha256, path)
+    }
+}
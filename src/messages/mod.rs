@@ -0,0 +1,222 @@
+//! module holding the user facing messages of the command line tool: summaries, reports, prompts and notifications.
+//!
+//! Messages are [Message](Message) values rendered in the [locale](Locale) set with [set_locale](set_locale), by default
+//! the one of the environment. Log lines stay in English, they are meant for bug reports.
+//!
+//! Adding a locale means adding a [Locale](Locale) variant and a branch to every message.
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// A supported language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    De,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Parses a locale name like `de`, `de-AT` or `de_DE.UTF-8`. Only the language counts.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s.split(['_', '-', '.', '@']).next().unwrap_or("");
+        match language.to_ascii_lowercase().as_str() {
+            "en" | "c" | "posix" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            _ => Err(format!("unsupported locale '{}'. Supported are en and de", s)),
+        }
+    }
+}
+
+impl Locale {
+    /// Returns the locale of the environment, `LC_ALL`, `LC_MESSAGES` or `LANG`, English if none is set or supported.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(Locale::En)
+    }
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Sets the locale of the messages. Only the first call counts.
+pub fn set_locale(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+/// Returns the locale of the messages.
+pub fn locale() -> Locale {
+    *LOCALE.get_or_init(Locale::from_env)
+}
+
+/// A user facing message. Displayed in the current [locale](locale).
+#[derive(Debug)]
+pub enum Message<'a> {
+    DownloadComplete { downloaded: u32 },
+    Duplicates { files: u64, saved: &'a str },
+    DownloadFailed { error: &'a str },
+    NewMedia { files: u32 },
+    Interrupted { downloaded: u32 },
+    TimeBudgetExhausted { downloaded: u32 },
+    Forgotten { media_key: &'a str, deleted: usize },
+    Quarantined { media_key: &'a str, files: usize, days: u64 },
+    Restored { media_key: &'a str, files: usize },
+    /// a line of the trash listing
+    TrashEntry { media_key: &'a str, files: usize, expires: &'a str },
+    Renamed { old: &'a str, new: &'a str, paths: usize },
+    Imported { username: &'a str, tweets: usize, downloaded: u32, skipped: u32, failed: u32 },
+    Migrated { username: &'a str, checkpoint: Option<u64>, files: usize, known: usize },
+    IndexRebuilt { username: &'a str, files: usize, moved: usize, known: usize, unmatched: usize },
+    UpToDate { version: &'a str },
+    UpdateAvailable { version: &'a str },
+    Updated { path: &'a Path, version: &'a str },
+    VerifySummary { problems: usize, checked: usize, repaired: usize },
+    RetrySummary { retried: usize, recovered: usize, given_up: usize },
+    StreamSummary { tweets: u64, files: u32 },
+    MirrorMissing { file: &'a str },
+    MirrorSummary { missing: usize, checked: usize },
+    /// `latest` as checkpoint of a user whose next run starts at the latest Tweet
+    CheckpointLatest,
+    StatusHeader,
+    Total,
+    /// the first line of the `init` wizard
+    InitStart { path: &'a Path },
+    InitOverwrite,
+    InitToken,
+    InitTokenRequired,
+    InitUsernames,
+    InitUsernamesRequired,
+    InitOutputDir,
+    InitMinWidth,
+    InitNotANumber { value: &'a str },
+    InitLayout,
+    InitChecking,
+    InitUser { username: &'a str, tweets: u64, scanned: u64, pages: u64 },
+    InitUserNotFound { username: &'a str },
+    InitUserRateLimited { username: &'a str },
+    InitTokenRejected { error: &'a str },
+    /// the estimate of a first download taking more than one rate limit window
+    InitEstimate { pages: u64, minutes: u64 },
+    InitEstimateOneWindow { pages: u64 },
+    InitMediaHint,
+    InitTokenKept { path: &'a Path },
+    InitWritten { path: &'a Path },
+}
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match locale() {
+            Locale::En => self.fmt_en(f),
+            Locale::De => self.fmt_de(f),
+        }
+    }
+}
+
+impl<'a> Message<'a> {
+    fn fmt_en(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::DownloadComplete { downloaded } => write!(f, "Download complete. {} files downloaded.", downloaded),
+            Message::Duplicates { files, saved } => write!(f, "{} duplicates, {} saved.", files, saved),
+            Message::DownloadFailed { error } => write!(f, "Download failed: {}", error),
+            Message::NewMedia { files } => write!(f, "{} new media files", files),
+            Message::Interrupted { downloaded } => write!(f, "Interrupted. {} files downloaded. Checkpoints are written, the next run continues from there.", downloaded),
+            Message::TimeBudgetExhausted { downloaded } => write!(f, "Time budget exhausted. {} files downloaded. Checkpoints are written, the next run continues from there.", downloaded),
+            Message::Forgotten { media_key, deleted } => write!(f, "{}: forgotten, {} files deleted", media_key, deleted),
+            Message::Quarantined { media_key, files, days } => write!(f, "{}: forgotten, {} files quarantined for {} days. Undo with restore {}", media_key, files, days, media_key),
+            Message::Restored { media_key, files } => write!(f, "{}: {} files restored", media_key, files),
+            Message::TrashEntry { media_key, files, expires } => write!(f, "{:<24} {:>5} files  expires {}", media_key, files, expires),
+            Message::Renamed { old, new, paths } => write!(f, "{} renamed to {}, {} paths updated. Downloads of {} go to {}", old, new, paths, old, new),
+            Message::Imported { username, tweets, downloaded, skipped, failed } => write!(f, "{}: {} Tweets imported, {} files downloaded, {} there already, {} failed", username, tweets, downloaded, skipped, failed),
+            Message::Migrated { username, checkpoint: Some(checkpoint), files, known } => write!(f, "{}: checkpoint {} imported, {} files recorded, {} recorded before", username, checkpoint, files, known),
+            Message::Migrated { username, checkpoint: None, files, known } => write!(f, "{}: {} files recorded, {} recorded before", username, files, known),
+            Message::IndexRebuilt { username, files, moved, known, unmatched } => write!(f, "{}: {} files recorded, {} moved, {} recorded before, {} without media key", username, files, moved, known, unmatched),
+            Message::UpToDate { version } => write!(f, "{} is the latest version", version),
+            Message::UpdateAvailable { version } => write!(f, "{} is available, run self-update to install it", version),
+            Message::Updated { path, version } => write!(f, "Updated {} to {}", path.display(), version),
+            Message::VerifySummary { problems, checked, repaired } => write!(f, "{} of {} files have problems, {} re-downloaded", problems, checked, repaired),
+            Message::RetrySummary { retried, recovered, given_up } => write!(f, "{} failed downloads retried, {} recovered, {} given up after too many attempts", retried, recovered, given_up),
+            Message::StreamSummary { tweets, files } => write!(f, "{} Tweets streamed, {} files downloaded", tweets, files),
+            Message::MirrorMissing { file } => write!(f, "missing  {}", file),
+            Message::MirrorSummary { missing, checked } => write!(f, "{} of {} files still need to be replicated", missing, checked),
+            Message::CheckpointLatest => write!(f, "latest"),
+            Message::StatusHeader => write!(f, "{:<20} {:>20} {:>8} {:>10}  {:<16}  {:<16}  {:<16}", "USER", "CHECKPOINT", "FILES", "SIZE", "OLDEST TWEET", "NEWEST TWEET", "LAST RUN"),
+            Message::Total => write!(f, "total"),
+            Message::InitStart { path } => write!(f, "Setting up {}. Press Enter to take the [default].", path.display()),
+            Message::InitOverwrite => write!(f, "The file exists. Overwrite it? [y/N]"),
+            Message::InitToken => write!(f, "Bearer Token of your app, from https://developer.twitter.com/en/portal/dashboard"),
+            Message::InitTokenRequired => write!(f, "The token is required"),
+            Message::InitUsernames => write!(f, "Twitter handles to download, separated by spaces or commas"),
+            Message::InitUsernamesRequired => write!(f, "At least one handle is required"),
+            Message::InitOutputDir => write!(f, "Output directory"),
+            Message::InitMinWidth => write!(f, "Skip images narrower than this many pixels, 0 keeps all"),
+            Message::InitNotANumber { value } => write!(f, "{} is not a number", value),
+            Message::InitLayout => write!(f, "Directories of the files: flat, date, type or date-type"),
+            Message::InitChecking => write!(f, "Checking the token and the users..."),
+            Message::InitUser { username, tweets, scanned, pages } => write!(f, "  {}: {} Tweets, the latest {} are scanned in {} pages", username, tweets, scanned, pages),
+            Message::InitUserNotFound { username } => write!(f, "  {}: not found, left out", username),
+            Message::InitUserRateLimited { username } => write!(f, "  {}: cannot check now, the token is rate limited. Kept as is", username),
+            Message::InitTokenRejected { error } => write!(f, "The token was rejected: {}", error),
+            Message::InitEstimate { pages, minutes } => write!(f, "The first download takes {} pages, about {} minutes of rate limit windows.", pages, minutes),
+            Message::InitEstimateOneWindow { pages } => write!(f, "The first download takes {} pages, within one rate limit window.", pages),
+            Message::InitMediaHint => write!(f, "Media are only counted once downloaded, plan for a few per page on photo accounts."),
+            Message::InitTokenKept { path } => write!(f, "The token is kept in {}.", path.display()),
+            Message::InitWritten { path } => write!(f, "Written {}.", path.display()),
+        }
+    }
+
+    fn fmt_de(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::DownloadComplete { downloaded } => write!(f, "Download abgeschlossen. {} Dateien heruntergeladen.", downloaded),
+            Message::Duplicates { files, saved } => write!(f, "{} Duplikate, {} gespart.", files, saved),
+            Message::DownloadFailed { error } => write!(f, "Download fehlgeschlagen: {}", error),
+            Message::NewMedia { files } => write!(f, "{} neue Mediendateien", files),
+            Message::Interrupted { downloaded } => write!(f, "Abgebrochen. {} Dateien heruntergeladen. Die Checkpoints sind gespeichert, der nächste Lauf macht dort weiter.", downloaded),
+            Message::TimeBudgetExhausted { downloaded } => write!(f, "Zeitbudget aufgebraucht. {} Dateien heruntergeladen. Die Checkpoints sind gespeichert, der nächste Lauf macht dort weiter.", downloaded),
+            Message::Forgotten { media_key, deleted } => write!(f, "{}: vergessen, {} Dateien gelöscht", media_key, deleted),
+            Message::Quarantined { media_key, files, days } => write!(f, "{}: vergessen, {} Dateien für {} Tage in Quarantäne. Rückgängig mit restore {}", media_key, files, days, media_key),
+            Message::Restored { media_key, files } => write!(f, "{}: {} Dateien wiederhergestellt", media_key, files),
+            Message::TrashEntry { media_key, files, expires } => write!(f, "{:<24} {:>5} Dateien  läuft ab {}", media_key, files, expires),
+            Message::Renamed { old, new, paths } => write!(f, "{} in {} umbenannt, {} Pfade angepasst. Downloads von {} landen bei {}", old, new, paths, old, new),
+            Message::Imported { username, tweets, downloaded, skipped, failed } => write!(f, "{}: {} Tweets importiert, {} Dateien heruntergeladen, {} schon vorhanden, {} fehlgeschlagen", username, tweets, downloaded, skipped, failed),
+            Message::Migrated { username, checkpoint: Some(checkpoint), files, known } => write!(f, "{}: Checkpoint {} übernommen, {} Dateien erfasst, {} schon erfasst", username, checkpoint, files, known),
+            Message::Migrated { username, checkpoint: None, files, known } => write!(f, "{}: {} Dateien erfasst, {} schon erfasst", username, files, known),
+            Message::IndexRebuilt { username, files, moved, known, unmatched } => write!(f, "{}: {} Dateien erfasst, {} verschoben, {} schon erfasst, {} ohne Media-Key", username, files, moved, known, unmatched),
+            Message::UpToDate { version } => write!(f, "{} ist die neueste Version", version),
+            Message::UpdateAvailable { version } => write!(f, "{} ist verfügbar, self-update installiert die Version", version),
+            Message::Updated { path, version } => write!(f, "{} auf {} aktualisiert", path.display(), version),
+            Message::VerifySummary { problems, checked, repaired } => write!(f, "{} von {} Dateien haben Probleme, {} erneut heruntergeladen", problems, checked, repaired),
+            Message::RetrySummary { retried, recovered, given_up } => write!(f, "{} fehlgeschlagene Downloads wiederholt, {} gerettet, {} nach zu vielen Versuchen aufgegeben", retried, recovered, given_up),
+            Message::StreamSummary { tweets, files } => write!(f, "{} Tweets empfangen, {} Dateien heruntergeladen", tweets, files),
+            Message::MirrorMissing { file } => write!(f, "fehlt    {}", file),
+            Message::MirrorSummary { missing, checked } => write!(f, "{} von {} Dateien müssen noch gespiegelt werden", missing, checked),
+            Message::CheckpointLatest => write!(f, "neuester"),
+            Message::StatusHeader => write!(f, "{:<20} {:>20} {:>8} {:>10}  {:<16}  {:<16}  {:<16}", "NUTZER", "CHECKPOINT", "DATEIEN", "GRÖSSE", "ÄLTESTER TWEET", "NEUESTER TWEET", "LETZTER LAUF"),
+            Message::Total => write!(f, "gesamt"),
+            Message::InitStart { path } => write!(f, "Einrichtung von {}. Enter übernimmt den [Standardwert].", path.display()),
+            Message::InitOverwrite => write!(f, "Die Datei existiert. Überschreiben? [j/N]"),
+            Message::InitToken => write!(f, "Bearer Token deiner App, von https://developer.twitter.com/en/portal/dashboard"),
+            Message::InitTokenRequired => write!(f, "Der Token ist erforderlich"),
+            Message::InitUsernames => write!(f, "Herunterzuladende Twitter-Handles, getrennt durch Leerzeichen oder Kommas"),
+            Message::InitUsernamesRequired => write!(f, "Mindestens ein Handle ist erforderlich"),
+            Message::InitOutputDir => write!(f, "Ausgabeverzeichnis"),
+            Message::InitMinWidth => write!(f, "Bilder schmaler als so viele Pixel überspringen, 0 behält alle"),
+            Message::InitNotANumber { value } => write!(f, "{} ist keine Zahl", value),
+            Message::InitLayout => write!(f, "Verzeichnisse der Dateien: flat, date, type oder date-type"),
+            Message::InitChecking => write!(f, "Token und Nutzer werden geprüft..."),
+            Message::InitUser { username, tweets, scanned, pages } => write!(f, "  {}: {} Tweets, die neuesten {} werden in {} Seiten durchsucht", username, tweets, scanned, pages),
+            Message::InitUserNotFound { username } => write!(f, "  {}: nicht gefunden, ausgelassen", username),
+            Message::InitUserRateLimited { username } => write!(f, "  {}: gerade nicht prüfbar, der Token ist im Rate Limit. Unverändert übernommen", username),
+            Message::InitTokenRejected { error } => write!(f, "Der Token wurde abgelehnt: {}", error),
+            Message::InitEstimate { pages, minutes } => write!(f, "Der erste Download umfasst {} Seiten, etwa {} Minuten an Rate-Limit-Fenstern.", pages, minutes),
+            Message::InitEstimateOneWindow { pages } => write!(f, "Der erste Download umfasst {} Seiten, innerhalb eines Rate-Limit-Fensters.", pages),
+            Message::InitMediaHint => write!(f, "Medien werden erst beim Download gezählt, bei Foto-Accounts mit einigen pro Seite rechnen."),
+            Message::InitTokenKept { path } => write!(f, "Der Token liegt in {}.", path.display()),
+            Message::InitWritten { path } => write!(f, "{} geschrieben.", path.display()),
+        }
+    }
+}
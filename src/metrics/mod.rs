@@ -0,0 +1,130 @@
+//! module for Prometheus metrics of the download runs, see `--metrics-addr` and `--metrics-push`.
+//!
+//! The metrics are totals since the start of the process, updated with the [summary](crate::summary) of every run,
+//! in the text exposition format:
+//!
+//! - `tmd_runs_total`, `tmd_downloads_total`, `tmd_failures_total`, `tmd_bytes_total`
+//! - `tmd_rate_limit_hits_total`: waits for a rate limit to reset
+//! - `tmd_user_checkpoint_age_seconds{username}`: time since the run of the user last completed without an error
+//! - `tmd_user_downloads_total{username}`
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use reqwest::Client;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::clock;
+use crate::summary::RunSummary;
+use crate::twitter::ratelimit;
+
+/// Job name of the pushed metrics.
+const PUSH_JOB: &str = "twitter-media-downloader";
+
+static RUNS: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static USERS: Mutex<BTreeMap<String, UserMetrics>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default, Clone, Copy)]
+struct UserMetrics {
+    downloads: u64,
+    /// unix time the run of the user last completed without an error
+    completed: Option<u64>,
+}
+
+/// Adds the run of `summary` to the metrics.
+pub fn record(summary: &RunSummary) {
+    RUNS.fetch_add(1, Ordering::Relaxed);
+    DOWNLOADS.fetch_add(summary.downloaded, Ordering::Relaxed);
+    FAILURES.fetch_add(summary.failed + summary.users.iter().filter(|u| u.error.is_some()).count() as u64, Ordering::Relaxed);
+    BYTES.fetch_add(summary.bytes, Ordering::Relaxed);
+
+    let now = clock::unix_now();
+    let mut users = USERS.lock().unwrap_or_else(|e| e.into_inner());
+    for user in summary.users.iter() {
+        let metrics = users.entry(user.username.clone()).or_default();
+        metrics.downloads += user.downloaded;
+        if user.error.is_none() && user.cutoff.is_none() {
+            metrics.completed = Some(now);
+        }
+    }
+}
+
+/// Returns the metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut text = String::new();
+    let mut counter = |name: &str, help: &str, value: u64| {
+        let _ = write!(text, "# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, value);
+    };
+    counter("tmd_runs_total", "Download runs.", RUNS.load(Ordering::Relaxed));
+    counter("tmd_downloads_total", "Downloaded media files.", DOWNLOADS.load(Ordering::Relaxed));
+    counter("tmd_failures_total", "Failed media files and users.", FAILURES.load(Ordering::Relaxed));
+    counter("tmd_bytes_total", "Bytes of the downloaded media files.", BYTES.load(Ordering::Relaxed));
+    counter("tmd_rate_limit_hits_total", "Waits for a rate limit to reset.", ratelimit::waits());
+
+    let now = clock::unix_now();
+    let users = USERS.lock().unwrap_or_else(|e| e.into_inner());
+    text.push_str("# HELP tmd_user_checkpoint_age_seconds Seconds since the run of the user last completed.\n# TYPE tmd_user_checkpoint_age_seconds gauge\n");
+    for (username, metrics) in users.iter() {
+        if let Some(completed) = metrics.completed {
+            let _ = writeln!(text, "tmd_user_checkpoint_age_seconds{{username=\"{}\"}} {}", username, now.saturating_sub(completed));
+        }
+    }
+    text.push_str("# HELP tmd_user_downloads_total Downloaded media files of the user.\n# TYPE tmd_user_downloads_total counter\n");
+    for (username, metrics) in users.iter() {
+        let _ = writeln!(text, "tmd_user_downloads_total{{username=\"{}\"}} {}", username, metrics.downloads);
+    }
+    text
+}
+
+/// Serves the metrics at `http://<addr>/metrics` until the process ends.
+pub async fn serve(addr: SocketAddr) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving the metrics at http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Cannot accept a metrics connection: {}", e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut request_line = String::new();
+                if stream.read_line(&mut request_line).await.is_err() {
+                    return;
+                }
+                // the headers are of no interest
+                let mut line = String::new();
+                while matches!(stream.read_line(&mut line).await, Ok(n) if n > 2) {
+                    line.clear();
+                }
+                let response = match request_line.split_whitespace().nth(1) {
+                    Some("/metrics") => {
+                        let body = render();
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+                    }
+                    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Pushes the metrics to the Prometheus Pushgateway at `url`, e.g. `http://pushgateway:9091`.
+pub async fn push(client: &Client, url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), PUSH_JOB);
+    client.put(url).body(render()).send().await?.error_for_status()?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! module to move the archives of versions before the [state database](crate::state) into it, see `migrate`.
+//!
+//! Older versions kept a `checkpoint` file per user and knew the downloaded media by their files only. For every user
+//! directory the checkpoint is imported and the media files named by the default template,
+//! `{media_key}_{username}_{original}`, are recorded as downloaded, so an upgraded run downloads none of them again.
+//! The Tweet of a file is taken from its [sidecar](crate::sidecar), if any. Their urls are unknown.
+//!
+//! Files already recorded are left alone, so migrating twice does no harm.
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::common::sha256_file;
+use crate::magic::QUARANTINE_DIR;
+use crate::sidecar;
+use crate::state::{MediaRecord, StateStore, LEGACY_CHECKPOINT_FILENAME};
+
+/// Outcome of the migration of one user.
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+    pub username: String,
+    /// checkpoint imported from the `checkpoint` file
+    pub checkpoint: Option<u64>,
+    /// media files recorded as downloaded
+    pub files: usize,
+    /// media files recorded before
+    pub known: usize,
+}
+
+/// Migrates the user directories `usernames` under `output_dir`, or every user directory if empty.
+///
+/// Returns a [MigrateReport](MigrateReport) per user, in order.
+pub fn migrate(output_dir: &Path, usernames: &[String], run_id: &str) -> Result<Vec<MigrateReport>, Box<dyn Error + Send + Sync>> {
+    let usernames = if usernames.is_empty() { user_dirs(output_dir)? } else { usernames.to_vec() };
+    let state = StateStore::open(output_dir)?;
+    let mut reports = Vec::new();
+    for username in usernames {
+        let user_output_dir = output_dir.join(&username);
+        if !user_output_dir.is_dir() {
+            warn!("username: {}. No user directory under {}, skipping", username, output_dir.display());
+            continue;
+        }
+        reports.push(migrate_user(&state, &user_output_dir, &username, run_id)?);
+    }
+    Ok(reports)
+}
+
+/// Returns the names of the directories under `output_dir`, hidden ones left out.
+pub(crate) fn user_dirs(output_dir: &Path) -> Result<Vec<String>, io::Error> {
+    let mut usernames = Vec::new();
+    for entry in fs::read_dir(output_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && !name.starts_with('.') {
+            usernames.push(name);
+        }
+    }
+    usernames.sort();
+    Ok(usernames)
+}
+
+fn migrate_user(state: &StateStore, user_output_dir: &Path, username: &str, run_id: &str) -> Result<MigrateReport, Box<dyn Error + Send + Sync>> {
+    let mut report = MigrateReport { username: username.into(), ..Default::default() };
+    // imports the checkpoint file if there is no checkpoint yet
+    if user_output_dir.join(LEGACY_CHECKPOINT_FILENAME).is_file() {
+        report.checkpoint = state.get_checkpoint(username, user_output_dir)?.filter(|c| *c != u64::MAX);
+    }
+
+    let pattern = Regex::new(&format!(r"(?i)^(\d+_\d+)_{}_.+", regex::escape(username)))?;
+    let mut files = Vec::new();
+    media_files(user_output_dir, &mut files)?;
+    for path in files {
+        let filename = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        let media_key = match pattern.captures(&filename) {
+            Some(captures) => captures[1].to_string(),
+            None => continue,
+        };
+        if state.downloaded_path(username, &media_key)?.is_some() || state.media_key_by_path(&path)?.is_some() {
+            report.known += 1;
+            continue;
+        }
+        let tweet_id = fs::read_to_string(sidecar::sidecar_path(&path)).ok()
+            .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+            .and_then(|sidecar| sidecar.get("tweet_id").and_then(Value::as_str).map(String::from))
+            .unwrap_or_default();
+        let record = MediaRecord {
+            username: username.into(),
+            media_key,
+            tweet_id,
+            url: String::new(),
+            size: fs::metadata(&path)?.len(),
+            sha256: sha256_file(&path).ok(),
+            local_path: path,
+            run_id: run_id.into(),
+            duplicate_of: None,
+        };
+        state.record_downloaded(&record)?;
+        report.files += 1;
+    }
+    info!("username: {}, checkpoint: {:?}. Migrated, {} files recorded, {} recorded before", username, report.checkpoint, report.files, report.known);
+    Ok(report)
+}
+
+/// Adds the files under `dir` to `files`, leaving out sidecars, text files, partial downloads, quarantined and hidden files.
+pub(crate) fn media_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name == QUARANTINE_DIR {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            media_files(&path, files)?;
+        } else if !name.ends_with(".json") && !name.ends_with(".txt") && !name.ends_with(".part") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
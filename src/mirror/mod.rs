@@ -0,0 +1,150 @@
+//! module to check, read-only, that the local archive is replicated to a mirror.
+//!
+//! Supported mirrors:
+//! * `rsync://host/module/path` or `host:path`: listed with `rsync --list-only -r`
+//! * `s3://bucket/prefix`: listed with `aws s3 ls --recursive`
+//! * `http(s)://...`: WebDAV or any plain HTTP server, checked with a HEAD request per file
+//! * a local directory, e.g. a mounted backup disk
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use tracing::{info, warn};
+
+use crate::common::collect_files;
+use crate::http;
+use crate::messages::Message;
+
+/// Files of the local archive missing on the mirror.
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+    pub checked: usize,
+    /// paths relative to the output directory, e.g. `alice/123_alice_abc.jpg`
+    pub missing: Vec<String>,
+}
+
+/// Lists the local files of `usernames` (every user if empty) and checks that each of them exists under `mirror`.
+pub async fn verify_against(output_dir: &Path, usernames: &[String], mirror: &str) -> Result<MirrorReport, Box<dyn Error + Send + Sync>> {
+    let local = list_local(output_dir, usernames)?;
+    info!("mirror: {}. Checking {} local files", mirror, local.len());
+
+    let mut report = MirrorReport { checked: local.len(), ..Default::default() };
+    if mirror.starts_with("http://") || mirror.starts_with("https://") {
+        let client = http::client()?;
+        let base = mirror.trim_end_matches('/');
+        for file in local {
+            let url = format!("{}/{}", base, file);
+            match client.head(&url).send().await {
+                Ok(resp) if resp.status().is_success() => (),
+                Ok(_) => report.missing.push(file),
+                Err(e) => {
+                    warn!("mirror: {}, file: {}. Cannot check: {}", mirror, file, e);
+                    report.missing.push(file);
+                }
+            }
+        }
+    } else {
+        let remote = if mirror.starts_with("s3://") {
+            list_s3(mirror)?
+        } else if mirror.starts_with("rsync://") || is_remote_shell_path(mirror) {
+            list_rsync(mirror)?
+        } else {
+            list_dir(Path::new(mirror))?
+        };
+        report.missing = local.into_iter().filter(|f| !remote.contains(f)).collect();
+    }
+
+    Ok(report)
+}
+
+/// Prints the missing files and a summary line to stdout.
+pub fn print_report(report: &MirrorReport) {
+    for file in report.missing.iter() {
+        println!("{}", Message::MirrorMissing { file });
+    }
+    println!("{}", Message::MirrorSummary { missing: report.missing.len(), checked: report.checked });
+}
+
+/// `host:path` style rsync over ssh. Windows drive letters like `C:\` are local paths.
+fn is_remote_shell_path(mirror: &str) -> bool {
+    match mirror.split_once(':') {
+        Some((host, _)) => host.len() > 1 && !host.contains('/'),
+        None => false,
+    }
+}
+
+/// Relative paths of the files under `output_dir`/`username` of every user.
+fn list_local(output_dir: &Path, usernames: &[String]) -> Result<Vec<String>, io::Error> {
+    let mut files = Vec::new();
+    if usernames.is_empty() {
+        for entry in fs::read_dir(output_dir)? {
+            let entry = entry?;
+            // hidden directories like the quarantine are not mirrored
+            if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                collect_files(output_dir, &entry.path(), &mut files)?;
+            }
+        }
+    } else {
+        for username in usernames {
+            let user_dir = output_dir.join(username);
+            if user_dir.is_dir() {
+                collect_files(output_dir, &user_dir, &mut files)?;
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn list_dir(dir: &Path) -> Result<HashSet<String>, io::Error> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, &mut files)?;
+    Ok(files.into_iter().collect())
+}
+
+/// Runs `command` and returns its stdout.
+fn run(command: &mut Command) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!("{:?} failed: {}", command, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the rest of `line` after skipping `n` whitespace separated fields.
+fn skip_fields(line: &str, n: usize) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+    if rest.is_empty() { None } else { Some(rest) }
+}
+
+/// `rsync --list-only -r` prints `perms size date time path`.
+fn list_rsync(mirror: &str) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    let source = format!("{}/", mirror.trim_end_matches('/'));
+    let listing = run(Command::new("rsync").arg("--list-only").arg("-r").arg(&source))?;
+    Ok(listing.lines()
+        .filter(|l| l.starts_with('-'))
+        .filter_map(|l| skip_fields(l, 4))
+        .map(|p| p.to_string())
+        .collect())
+}
+
+/// `aws s3 ls --recursive` prints `date time size key`, keys include the prefix.
+fn list_s3(mirror: &str) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    let without_scheme = mirror.trim_start_matches("s3://").trim_end_matches('/');
+    let prefix = match without_scheme.split_once('/') {
+        Some((_, prefix)) => format!("{}/", prefix),
+        None => String::new(),
+    };
+    let listing = run(Command::new("aws").arg("s3").arg("ls").arg("--recursive").arg(format!("s3://{}/", without_scheme)))?;
+    Ok(listing.lines()
+        .filter_map(|l| skip_fields(l, 3))
+        .map(|key| key.strip_prefix(prefix.as_str()).unwrap_or(key).to_string())
+        .collect())
+}
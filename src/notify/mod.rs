@@ -0,0 +1,309 @@
+//! module to route notifications about runs to one or more targets.
+//!
+//! A route ties an [Event](Event) to a [Target](Target) and is written as `<event>=<target>`, e.g.
+//! `run-complete=discord+https://discord.com/api/webhooks/...` or `error=desktop`.
+//!
+//! The [SummaryNotifier](SummaryNotifier) of `--notify-url` sends the [summary](crate::summary) of a whole run
+//! instead, once per run.
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{error, info};
+
+use crate::summary::RunSummary;
+
+pub mod rollup;
+
+/// Events a notification can be routed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A run finished, successfully or not.
+    RunComplete,
+    /// A run failed.
+    Error,
+    /// A run downloaded at least one new media file.
+    NewMedia,
+    /// An hourly or daily roll-up of runs. See [rollup](rollup).
+    Summary,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::RunComplete => "run-complete",
+            Event::Error => "error",
+            Event::NewMedia => "new-media",
+            Event::Summary => "summary",
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "run-complete" => Ok(Event::RunComplete),
+            "error" => Ok(Event::Error),
+            "new-media" => Ok(Event::NewMedia),
+            "summary" => Ok(Event::Summary),
+            _ => Err(format!("unknown notification event '{}'. Expected one of run-complete, error, new-media, summary", s)),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single notification.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: Event,
+    pub username: String,
+    pub message: String,
+    /// id of the run the notification is about
+    pub run_id: String,
+}
+
+/// Where a notification is delivered.
+///
+/// Target specs:
+/// * `webhook+<url>` or a plain `http(s)://` url: JSON POST of the notification
+/// * `discord+<webhook url>`: Discord webhook message
+/// * `telegram://<bot token>@<chat id>`: Telegram bot message
+/// * `mailto:<address>`: mail piped through the local `sendmail`
+/// * `desktop`: desktop notification via `notify-send` (or `osascript` on macOS)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Webhook(String),
+    Discord(String),
+    Telegram { bot_token: String, chat_id: String },
+    Email(String),
+    Desktop,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "desktop" {
+            Ok(Target::Desktop)
+        } else if let Some(url) = s.strip_prefix("webhook+") {
+            Ok(Target::Webhook(url.into()))
+        } else if let Some(url) = s.strip_prefix("discord+") {
+            Ok(Target::Discord(url.into()))
+        } else if let Some(rest) = s.strip_prefix("telegram://") {
+            match rest.rsplit_once('@') {
+                Some((bot_token, chat_id)) if !bot_token.is_empty() && !chat_id.is_empty() => Ok(Target::Telegram { bot_token: bot_token.into(), chat_id: chat_id.into() }),
+                _ => Err(format!("invalid telegram target '{}'. Expected telegram://<bot token>@<chat id>", s)),
+            }
+        } else if let Some(address) = s.strip_prefix("mailto:") {
+            Ok(Target::Email(address.into()))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Target::Webhook(s.into()))
+        } else {
+            Err(format!("unknown notification target '{}'", s))
+        }
+    }
+}
+
+/// A target that can deliver a [Notification](Notification).
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, client: &'a Client, notification: &'a Notification) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>>;
+}
+
+impl Notifier for Target {
+    fn send<'a>(&'a self, client: &'a Client, notification: &'a Notification) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        Box::pin(async move {
+            let text = format!("[{}] {}: {} (run {})", notification.event, notification.username, notification.message, notification.run_id);
+            match self {
+                Target::Webhook(url) => {
+                    let body = json!({
+                        "event": notification.event.as_str(),
+                        "username": notification.username,
+                        "message": notification.message,
+                        "run_id": notification.run_id,
+                    });
+                    client.post(url).json(&body).send().await?.error_for_status()?;
+                }
+                Target::Discord(url) => {
+                    client.post(url).json(&json!({ "content": text })).send().await?.error_for_status()?;
+                }
+                Target::Telegram { bot_token, chat_id } => {
+                    let url = format!("https://api.telegram.org/bot{}/sendMessage", bot_token);
+                    client.post(url).json(&json!({ "chat_id": chat_id, "text": text })).send().await?.error_for_status()?;
+                }
+                Target::Email(address) => {
+                    let mail = format!("To: {}\nSubject: twitter-media-downloader {}\n\n{}\n", address, notification.event, text);
+                    run_with_stdin(Command::new("sendmail").arg(address), &mail).await?;
+                }
+                Target::Desktop => {
+                    if cfg!(target_os = "macos") {
+                        let script = format!("display notification {} with title \"twitter-media-downloader\"", json!(text));
+                        Command::new("osascript").arg("-e").arg(script).status().await?;
+                    } else {
+                        Command::new("notify-send").arg("twitter-media-downloader").arg(&text).status().await?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An `<event>=<target>` pair. See [Target](Target) for the target specs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub event: Event,
+    pub target: Target,
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((event, target)) => Ok(Route { event: event.trim().parse()?, target: target.trim().parse()? }),
+            None => Err(format!("invalid notification route '{}'. Expected <event>=<target>", s)),
+        }
+    }
+}
+
+/// Dispatches notifications to every target routed for their event.
+pub struct Dispatcher {
+    client: Client,
+    routes: Vec<Route>,
+}
+
+impl Dispatcher {
+    pub fn new(client: Client, routes: Vec<Route>) -> Self {
+        Dispatcher { client, routes }
+    }
+
+    /// Sends `notification` to all targets routed for its event.
+    ///
+    /// Failures are logged, never returned: a broken notification target must not fail a run.
+    pub async fn notify(&self, notification: Notification) {
+        for route in self.routes.iter().filter(|r| r.event == notification.event) {
+            match route.target.send(&self.client, &notification).await {
+                Ok(()) => info!("username: {}, event: {}. Notification sent", notification.username, notification.event),
+                Err(e) => error!("username: {}, event: {}. Notification failed: {}", notification.username, notification.event, e),
+            }
+        }
+    }
+}
+
+/// When the [SummaryNotifier](SummaryNotifier) sends the summary of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyWhen {
+    /// after every run
+    Always,
+    /// after runs that downloaded new files
+    NewMedia,
+    /// after runs with failed files or users
+    Error,
+}
+
+impl FromStr for NotifyWhen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(NotifyWhen::Always),
+            "new-media" => Ok(NotifyWhen::NewMedia),
+            "error" => Ok(NotifyWhen::Error),
+            _ => Err(format!("unknown notification condition '{}'. Expected always, new-media or error", s)),
+        }
+    }
+}
+
+impl NotifyWhen {
+    fn applies(&self, summary: &RunSummary) -> bool {
+        match self {
+            NotifyWhen::Always => true,
+            NotifyWhen::NewMedia => summary.downloaded > 0,
+            NotifyWhen::Error => summary.exit_code != 0,
+        }
+    }
+}
+
+/// Sends the summary of a run to its targets: a webhook receives the [RunSummary](RunSummary) as JSON, the other
+/// targets a short text of it.
+pub struct SummaryNotifier {
+    client: Client,
+    targets: Vec<Target>,
+    /// conditions of which any must apply, always if empty
+    when: Vec<NotifyWhen>,
+}
+
+impl SummaryNotifier {
+    pub fn new(client: Client, targets: Vec<Target>, when: Vec<NotifyWhen>) -> Self {
+        SummaryNotifier { client, targets, when }
+    }
+
+    /// Sends `summary` to every target if one of the conditions applies.
+    ///
+    /// Failures are logged, never returned.
+    pub async fn notify(&self, summary: &RunSummary) {
+        if self.targets.is_empty() || !(self.when.is_empty() || self.when.iter().any(|when| when.applies(summary))) {
+            return;
+        }
+        let notification = Notification {
+            event: if summary.exit_code == 0 { Event::RunComplete } else { Event::Error },
+            username: format!("{} users", summary.users.len()),
+            message: summary_text(summary),
+            run_id: summary.run_id.clone(),
+        };
+        for target in self.targets.iter() {
+            let sent = match target {
+                Target::Webhook(url) => send_json(&self.client, url, summary).await,
+                target => target.send(&self.client, &notification).await,
+            };
+            match sent {
+                Ok(()) => info!("run_id: {}. Run summary sent", summary.run_id),
+                Err(e) => error!("run_id: {}. Sending the run summary failed: {}", summary.run_id, e),
+            }
+        }
+    }
+}
+
+async fn send_json(client: &Client, url: &str, summary: &RunSummary) -> Result<(), Box<dyn Error + Send + Sync>> {
+    client.post(url).json(summary).send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Returns the summary as one line of totals, followed by a line per failed user.
+fn summary_text(summary: &RunSummary) -> String {
+    let mut text = format!("{} files downloaded, {} skipped, {} failed in {} seconds",
+                           summary.downloaded, summary.skipped, summary.failed, summary.duration_secs.round());
+    for user in summary.users.iter() {
+        if let Some(e) = &user.error {
+            text.push_str(&format!("\n{}: {}", user.username, e));
+        }
+    }
+    text
+}
+
+/// Runs `command` feeding `input` to its stdin.
+async fn run_with_stdin(command: &mut Command, input: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    use std::process::Stdio;
+
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(format!("{:?} exited with {}", command, status).into());
+    }
+    Ok(())
+}
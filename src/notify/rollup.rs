@@ -0,0 +1,151 @@
+//! Aggregates per-run results into hourly or daily roll-ups.
+//!
+//! The roll-up state is kept in a JSON file so that results of separate runs (cron) and cycles (watch mode)
+//! end up in the same roll-up. A roll-up is emitted by the first run after its period has ended.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::clock;
+use crate::common::write_atomic;
+
+/// Name of the roll-up state file under the output directory.
+pub const ROLLUP_FILENAME: &str = "notify-rollup.json";
+
+/// Length of a roll-up period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Hourly,
+    Daily,
+}
+
+impl Period {
+    fn seconds(&self) -> u64 {
+        match self {
+            Period::Hourly => 60 * 60,
+            Period::Daily => 24 * 60 * 60,
+        }
+    }
+
+    /// Returns the start of the period `timestamp` falls into, as unix seconds.
+    fn start_of(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Period::Hourly),
+            "daily" => Ok(Period::Daily),
+            _ => Err(format!("unknown roll-up period '{}'. Expected hourly or daily", s)),
+        }
+    }
+}
+
+/// Results of one user within a roll-up period.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct UserTotals {
+    runs: u32,
+    new_media: u32,
+    errors: u32,
+}
+
+/// Persisted roll-up state.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    period_start: u64,
+    users: BTreeMap<String, UserTotals>,
+}
+
+/// Roll-up of run results backed by a state file.
+///
+/// Runs of users downloaded in parallel are recorded one at a time.
+pub struct RollUp {
+    path: PathBuf,
+    period: Period,
+    lock: Mutex<()>,
+}
+
+impl RollUp {
+    /// Roll-up stored as [ROLLUP_FILENAME](ROLLUP_FILENAME) under `output_dir`.
+    pub fn new(output_dir: &Path, period: Period) -> Self {
+        RollUp { path: output_dir.join(ROLLUP_FILENAME), period, lock: Mutex::new(()) }
+    }
+
+    /// Records the result of a run of `username`.
+    ///
+    /// If the stored results belong to an earlier period, that period is closed first.
+    ///
+    /// Returns the summary of the closed period, if any.
+    pub fn record(&self, username: &str, new_media: u32, failed: bool) -> Result<Option<String>, io::Error> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let now = clock::unix_now();
+        let period_start = self.period.start_of(now);
+
+        let mut state = self.load();
+        let mut summary = None;
+        if state.period_start != period_start {
+            if !state.users.is_empty() {
+                summary = Some(self.summarize(&state));
+            }
+            state = State { period_start, users: BTreeMap::new() };
+        }
+
+        let totals = state.users.entry(username.into()).or_default();
+        totals.runs += 1;
+        totals.new_media += new_media;
+        if failed {
+            totals.errors += 1;
+        }
+
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&state)?)?;
+        Ok(summary)
+    }
+
+    /// Reads the state file. A missing or unreadable state file starts a fresh roll-up.
+    fn load(&self) -> State {
+        fs::read(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// e.g. `2023-07-01 (daily): alice: 12 new, bob: 0 new, 1 error`
+    fn summarize(&self, state: &State) -> String {
+        let label = match self.period {
+            Period::Hourly => "hourly",
+            Period::Daily => "daily",
+        };
+        let format = match self.period {
+            Period::Hourly => format_description!("[year]-[month]-[day] [hour]:00 UTC"),
+            Period::Daily => format_description!("[year]-[month]-[day]"),
+        };
+        let start = OffsetDateTime::from_unix_timestamp(state.period_start as i64)
+            .ok()
+            .and_then(|d| d.format(format).ok())
+            .unwrap_or_else(|| state.period_start.to_string());
+
+        let users: Vec<String> = state.users.iter()
+            .map(|(username, totals)| format!("{}: {} new", username, totals.new_media))
+            .collect();
+        let errors: u32 = state.users.values().map(|t| t.errors).sum();
+
+        let mut summary = format!("{} ({}): {}", start, label, users.join(", "));
+        match errors {
+            0 => (),
+            1 => summary.push_str(", 1 error"),
+            n => summary.push_str(&format!(", {} errors", n)),
+        }
+        summary
+    }
+}
@@ -0,0 +1,153 @@
+//! module to show the progress of a run as progress bars on stderr.
+//!
+//! An overall bar counts the Tweets scanned, the files downloaded and their bytes. Large files get a bar of their
+//! own while they download. Without a terminal on stderr, or with `--no-progress`, nothing is drawn and the log
+//! lines tell the progress as before.
+//!
+//! Log lines are written through [LogWriter](LogWriter), which hides the bars while a line is printed, so the two
+//! never garble each other.
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::tui;
+
+/// Files at least this large get a bar of their own.
+const LARGE_FILE: u64 = 10 * 1024 * 1024;
+
+/// Redraw interval of the overall bar.
+const TICK: Duration = Duration::from_millis(200);
+
+struct Progress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    tweets: AtomicU64,
+    files: AtomicU64,
+    bytes: AtomicU64,
+    note: Mutex<Option<String>>,
+}
+
+static PROGRESS: OnceLock<Progress> = OnceLock::new();
+
+/// Starts drawing the progress bars, unless stderr is not a terminal.
+pub fn install() {
+    let target = ProgressDrawTarget::stderr();
+    if target.is_hidden() {
+        return;
+    }
+    let multi = MultiProgress::with_draw_target(target);
+    let overall = multi.add(ProgressBar::new_spinner());
+    if let Ok(style) = ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}") {
+        overall.set_style(style);
+    }
+    overall.enable_steady_tick(TICK);
+    let _ = PROGRESS.set(Progress {
+        multi,
+        overall,
+        tweets: AtomicU64::new(0),
+        files: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+        note: Mutex::new(None),
+    });
+    update();
+}
+
+/// Counts a scanned Tweet.
+pub fn tweet_scanned() {
+    if let Some(progress) = PROGRESS.get() {
+        progress.tweets.fetch_add(1, Ordering::Relaxed);
+        update();
+    }
+}
+
+/// Counts a downloaded file of `size` bytes.
+pub fn file_downloaded(size: u64) {
+    if let Some(progress) = PROGRESS.get() {
+        progress.files.fetch_add(1, Ordering::Relaxed);
+        progress.bytes.fetch_add(size, Ordering::Relaxed);
+        update();
+    }
+}
+
+/// Shows `note` next to the counts, e.g. why the run is slow. None removes it.
+pub fn set_note(note: Option<&str>) {
+    if let Some(progress) = PROGRESS.get() {
+        *progress.note.lock().unwrap_or_else(|e| e.into_inner()) = note.map(String::from);
+        update();
+    }
+}
+
+fn update() {
+    if let Some(progress) = PROGRESS.get() {
+        let mut message = format!(
+            "{} tweets scanned, {} files downloaded, {}",
+            progress.tweets.load(Ordering::Relaxed),
+            progress.files.load(Ordering::Relaxed),
+            HumanBytes(progress.bytes.load(Ordering::Relaxed)),
+        );
+        if let Some(note) = progress.note.lock().unwrap_or_else(|e| e.into_inner()).as_deref() {
+            message.push_str(" - ");
+            message.push_str(note);
+        }
+        progress.overall.set_message(message);
+    }
+}
+
+/// The bar of a large file download, removed when dropped.
+pub struct FileBar(ProgressBar);
+
+impl FileBar {
+    /// Advances the bar by `bytes` received.
+    pub fn inc(&self, bytes: u64) {
+        self.0.inc(bytes);
+    }
+}
+
+impl Drop for FileBar {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+    }
+}
+
+/// Returns a bar for the download of `name`, if it is a large file of `len` bytes. `offset` bytes are there already.
+pub fn file_bar(name: &str, len: Option<u64>, offset: u64) -> Option<FileBar> {
+    let progress = PROGRESS.get()?;
+    let len = len.filter(|len| *len >= LARGE_FILE)?;
+    let bar = progress.multi.add(ProgressBar::new(len));
+    if let Ok(style) = ProgressStyle::with_template("  {msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}") {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar.set_message(name.to_string());
+    bar.set_position(offset);
+    Some(FileBar(bar))
+}
+
+/// Removes the bars, e.g. before printing the summary of the run.
+pub fn finish() {
+    if let Some(progress) = PROGRESS.get() {
+        progress.overall.finish_and_clear();
+    }
+}
+
+/// Writes log lines to stderr, hiding the progress bars while doing so, or to the [dashboard](crate::tui) while it is
+/// shown.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if tui::log(buf) {
+            return Ok(buf.len());
+        }
+        match PROGRESS.get() {
+            Some(progress) => progress.multi.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
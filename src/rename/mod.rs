@@ -0,0 +1,99 @@
+//! module to move the archive of a user to a new handle, e.g. after the account was renamed.
+//!
+//! The user directory is renamed, the recorded paths in the [state database](crate::state) and the
+//! [manifests](crate::manifest) are updated, and the old handle is kept as an alias: downloads of the old handle go
+//! to the archive of the new one. File names containing the old handle are left alone, the media are recorded by key.
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+use tracing::info;
+
+use crate::common::write_atomic;
+use crate::manifest::JSON_MANIFEST_FILENAME;
+use crate::state::StateStore;
+
+/// Outcome of a [rename_user](rename_user).
+#[derive(Debug, Default)]
+pub struct RenameReport {
+    /// recorded paths moved to the new user directory
+    pub paths: usize,
+    /// manifests rewritten
+    pub manifests: usize,
+}
+
+/// Moves the archive of `old` under `output_dir` to `new`.
+///
+/// Fails without changing anything if `new` already has an archive.
+pub fn rename_user(output_dir: &Path, old: &str, new: &str) -> Result<RenameReport, Box<dyn Error + Send + Sync>> {
+    if old == new {
+        return Err(format!("{} and {} are the same handle", old, new).into());
+    }
+    let state = StateStore::open(output_dir)?;
+    let old_dir = output_dir.join(old);
+    let new_dir = output_dir.join(new);
+    if new_dir.exists() || state.has_user(new)? {
+        return Err(format!("username: {}. Already has an archive under {}", new, output_dir.display()).into());
+    }
+    if !old_dir.is_dir() && !state.has_user(old)? {
+        return Err(format!("username: {}. Nothing archived under {}", old, output_dir.display()).into());
+    }
+
+    if old_dir.is_dir() {
+        fs::rename(&old_dir, &new_dir)?;
+        info!("username: {}, from: {}, to: {}. Moved the user directory", new, old_dir.display(), new_dir.display());
+    }
+    let mut report = RenameReport { paths: state.rename_user(old, new, &old_dir, &new_dir)?, ..Default::default() };
+    if new_dir.is_dir() {
+        report.manifests = rewrite_manifests(&new_dir, old, new, &old_dir, &new_dir)?;
+    }
+    info!("username: {}, alias: {}. Renamed, {} paths and {} manifests updated", new, old, report.paths, report.manifests);
+    Ok(report)
+}
+
+/// Rewrites the JSON manifests under `dir`: paths under `old_dir` move to `new_dir` and the username `old` becomes `new`.
+///
+/// Returns the number of manifests rewritten.
+fn rewrite_manifests(dir: &Path, old: &str, new: &str, old_dir: &Path, new_dir: &Path) -> Result<usize, io::Error> {
+    let mut rewritten = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            rewritten += rewrite_manifests(&path, old, new, old_dir, new_dir)?;
+            continue;
+        }
+        if entry.file_name() != JSON_MANIFEST_FILENAME {
+            continue;
+        }
+
+        let mut contents = String::new();
+        for line in fs::read_to_string(&path)?.lines() {
+            let mut entry: Value = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    // keep what cannot be read as it is
+                    contents.push_str(line);
+                    contents.push('\n');
+                    continue;
+                }
+            };
+            if entry["username"] == old {
+                entry["username"] = Value::from(new);
+            }
+            let moved = entry["local_path"].as_str()
+                .and_then(|p| Path::new(p).strip_prefix(old_dir).ok())
+                .map(|rest| new_dir.join(rest).to_string_lossy().into_owned());
+            if let Some(moved) = moved {
+                entry["local_path"] = Value::from(moved);
+            }
+            contents.push_str(&entry.to_string());
+            contents.push('\n');
+        }
+        write_atomic(&path, contents.as_bytes())?;
+        rewritten += 1;
+    }
+    Ok(rewritten)
+}
@@ -0,0 +1,78 @@
+//! module for the schedule of the users: when a user is polled next, by its interval and quiet hours.
+//!
+//! A user is due `every` after its last run. A poll falling into the quiet hours of the user is put off to their
+//! end, e.g. with `quiet_hours = "23:00-07:00"` a poll due at 02:00 happens at 07:00. Quiet hours are in the time zone
+//! of `--timezone`.
+use std::str::FromStr;
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::dates::Timezone;
+
+/// Minutes of a day.
+const DAY_MINUTES: i64 = 24 * 60;
+
+/// Daily quiet hours, given as `HH:MM-HH:MM`. May span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// minute of the day the quiet hours start at
+    start: i64,
+    /// minute of the day the quiet hours end at, excluded
+    end: i64,
+}
+
+fn parse_minute_of_day(s: &str) -> Option<i64> {
+    let (hour, minute) = s.trim().split_once(':')?;
+    let (hour, minute): (i64, i64) = (hour.parse().ok()?, minute.parse().ok()?);
+    if (0..24).contains(&hour) && (0..60).contains(&minute) { Some(hour * 60 + minute) } else { None }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid quiet hours '{}'. Expected HH:MM-HH:MM, e.g. 23:00-07:00", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (parse_minute_of_day(start).ok_or_else(invalid)?, parse_minute_of_day(end).ok_or_else(invalid)?);
+        if start == end {
+            return Err(invalid());
+        }
+        Ok(QuietHours { start, end })
+    }
+}
+
+impl QuietHours {
+    /// Returns the minutes from `minute` of the day to the end of the quiet hours, 0 if `minute` is outside of them.
+    fn minutes_left(&self, minute: i64) -> i64 {
+        let since_start = (minute - self.start).rem_euclid(DAY_MINUTES);
+        let length = (self.end - self.start).rem_euclid(DAY_MINUTES);
+        if since_start < length { length - since_start } else { 0 }
+    }
+
+    /// Returns `time`, or the end of the quiet hours if `time` falls into them.
+    pub fn defer(&self, time: OffsetDateTime, timezone: &Timezone) -> OffsetDateTime {
+        let local = timezone.local(time);
+        let minutes = self.minutes_left(local.hour() as i64 * 60 + local.minute() as i64);
+        if minutes == 0 {
+            return time;
+        }
+        time + time::Duration::minutes(minutes) - time::Duration::seconds(local.second() as i64)
+    }
+}
+
+/// Returns when a user last run at `last_run` (unix seconds) is polled next: `every` after the last run, or now if it
+/// never ran or has no interval, put off by the `quiet` hours.
+pub fn next_poll(last_run: Option<i64>, every: Option<Duration>, quiet: Option<&QuietHours>, timezone: &Timezone, now: OffsetDateTime) -> OffsetDateTime {
+    let due = match (last_run, every) {
+        (Some(last_run), Some(every)) => OffsetDateTime::from_unix_timestamp(last_run)
+            .map(|last_run| last_run + every)
+            .unwrap_or(now),
+        _ => now,
+    };
+    let due = due.max(now);
+    match quiet {
+        Some(quiet) => quiet.defer(due, timezone),
+        None => due,
+    }
+}
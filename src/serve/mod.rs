@@ -0,0 +1,181 @@
+//! module for the HTTP API of `serve`, queueing downloads for other services.
+//!
+//! - `POST /downloads` with `{"username": "NASAHubble"}` queues a download of the user and answers `202 Accepted`
+//!   with the job, e.g. `{"id": 1, "username": "NASAHubble", "status": "queued", ...}`
+//! - `GET /downloads/<id>/status` answers the job: queued, running, done or failed, with the files downloaded and
+//!   the error of a failed job
+//! - `GET /users/<name>/stats` answers the archive state of the user, like `status --json`
+//!
+//! The jobs run one at a time, in the order they were queued, so they share the rate limits like the users of a
+//! `download` run. Jobs are kept in memory only.
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{error, info, Instrument};
+
+use crate::clock;
+use crate::dates::Timezone;
+use crate::stats;
+use crate::{Config, Downloader};
+
+/// Builds the [Config](Config) of a download of a user.
+pub type ConfigFactory = dyn Fn(&str) -> Result<Config, String> + Send + Sync;
+
+/// State of a [Job](Job).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A queued download of a user.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub username: String,
+    pub status: JobStatus,
+    pub downloaded: u32,
+    pub error: Option<String>,
+    /// unix times of the changes of the job
+    pub queued_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DownloadRequest {
+    username: String,
+}
+
+struct Api {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    queue: UnboundedSender<u64>,
+    output_dir: PathBuf,
+    timezone: Timezone,
+}
+
+impl Api {
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.get_mut(&id)?;
+        f(job);
+        Some(job.clone())
+    }
+
+    fn enqueue(&self, username: String) -> Job {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Job { id, username, status: JobStatus::Queued, downloaded: 0, error: None, queued_at: clock::unix_now(), finished_at: None };
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).insert(id, job.clone());
+        let _ = self.queue.send(id);
+        info!("username: {}, job: {}. Download queued", job.username, id);
+        job
+    }
+}
+
+/// Serves the API at `addr` until the process ends, running the jobs with the configs of `configs`.
+pub async fn serve(addr: SocketAddr, output_dir: PathBuf, timezone: Timezone, configs: Arc<ConfigFactory>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (queue, mut jobs) = mpsc::unbounded_channel();
+    let api = Arc::new(Api { jobs: Mutex::new(BTreeMap::new()), next_id: AtomicU64::new(0), queue, output_dir, timezone });
+
+    let worker = api.clone();
+    tokio::spawn(async move {
+        while let Some(id) = jobs.recv().await {
+            let username = match worker.update(id, |job| job.status = JobStatus::Running) {
+                Some(job) => job.username,
+                None => continue,
+            };
+            let result = match configs(&username) {
+                Ok(config) => Downloader::new(config).run().await.map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            match &result {
+                Ok(report) => info!("username: {}, job: {}. Download done, {} files", username, id, report.downloaded),
+                Err(e) => error!("username: {}, job: {}. Download failed: {}", username, id, e),
+            }
+            worker.update(id, |job| {
+                job.finished_at = Some(clock::unix_now());
+                match result {
+                    Ok(report) => {
+                        job.status = JobStatus::Done;
+                        job.downloaded = report.downloaded;
+                    }
+                    Err(e) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(e);
+                    }
+                }
+            });
+        }
+    }.in_current_span());
+
+    let make_service = make_service_fn(move |_| {
+        let api = api.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(api.clone(), request))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!("Serving the API at http://{}", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_default()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "error": message }))
+}
+
+async fn handle(api: Arc<Api>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let segments: Vec<String> = request.uri().path().split('/').filter(|s| !s.is_empty()).map(String::from).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let response = match (request.method().clone(), segments.as_slice()) {
+        (Method::POST, ["downloads"]) => {
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, &e.to_string())),
+            };
+            match serde_json::from_slice::<DownloadRequest>(&body) {
+                Ok(download) if !download.username.is_empty() => {
+                    let job = api.enqueue(download.username);
+                    json_response(StatusCode::ACCEPTED, json!(job))
+                }
+                Ok(_) => error_response(StatusCode::BAD_REQUEST, "username is empty"),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &format!("expected {{\"username\": \"...\"}}: {}", e)),
+            }
+        }
+        (Method::GET, ["downloads", id, "status"]) => {
+            let job = id.parse::<u64>().ok().and_then(|id| api.jobs.lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned());
+            match job {
+                Some(job) => json_response(StatusCode::OK, json!(job)),
+                None => error_response(StatusCode::NOT_FOUND, "no such download"),
+            }
+        }
+        (Method::GET, ["users", username, "stats"]) => match stats::status(&api.output_dir, &[username.to_string()], api.timezone) {
+            Ok(status) => match status.into_iter().next() {
+                Some(status) => json_response(StatusCode::OK, json!(status)),
+                None => error_response(StatusCode::NOT_FOUND, "no such user"),
+            },
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
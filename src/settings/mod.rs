@@ -0,0 +1,193 @@
+//! module for the configuration file, `~/.config/twitter-media-downloader/config.toml` by default.
+//!
+//! The file holds the settings of `download` that would otherwise be passed as options every time. `[defaults]`
+//! apply to every user, a `[users.<handle>]` section to that user only and wins over the defaults. Options given on
+//! the command line win over both, e.g.
+//!
+//! ```toml
+//! bearer_token_file = "/home/me/.config/twitter-media-downloader/token"
+//! output_dir = "/srv/archive"
+//! usernames = ["NASAHubble"]
+//!
+//! [defaults]
+//! min_width = 640
+//! layout = "date"
+//!
+//! [users.ESA_Webb]
+//! output_dir = "/srv/webb"
+//! matches = ["(?i)nebula"]
+//! every = "6h"
+//! quiet_hours = "23:00-07:00"
+//! ```
+//!
+//! Users with a section of their own are downloaded like the `usernames`, unless `-u` picks the users of a run. See
+//! [schedule](crate::schedule) for `every` and `quiet_hours`.
+//!
+//! Notification routes of the whole run are top-level settings, `notify = ["error=desktop"]` like `--notify`, see
+//! [Route](crate::notify::Route).
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::write_atomic;
+
+/// Name of the directory of the configuration file.
+const CONFIG_DIR: &str = "twitter-media-downloader";
+
+/// Name of the configuration file.
+pub const CONFIG_FILENAME: &str = "config.toml";
+
+/// Name of the file `init` stores the bearer token in, next to the configuration file.
+pub const TOKEN_FILENAME: &str = "token";
+
+/// Settings of the configuration file. Settings left out keep the defaults of the options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// file holding the bearer token, see `--bearer-token-file`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearer_token_file: Option<PathBuf>,
+    /// see `--auth`: bearer or oauth1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<String>,
+    /// credentials of `--auth oauth1`, see `--consumer-key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token_secret: Option<String>,
+    /// see `--output-dir`, for every subcommand
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+    /// users to download
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub usernames: Vec<String>,
+    /// notification routes as `<event>=<target>`, see `--notify`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notify: Vec<String>,
+    /// settings of every user
+    #[serde(skip_serializing_if = "Profile::is_empty")]
+    pub defaults: Profile,
+    /// settings per user, by handle
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, Profile>,
+}
+
+/// Download settings of a user, or of every user as `[defaults]`. Values are those of the options of the same name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// output directory of the user, holding its own state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_all: Option<bool>,
+    /// see `--match`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<String>,
+    /// see `--exclude-match`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_matches: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_likes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_retweets: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_width: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_height: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename_template: Option<String>,
+    /// schedule of the user as a duration, e.g. 6h: runs within this long of its last run leave the user out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub every: Option<String>,
+    /// daily hours the user is not polled in, e.g. 23:00-07:00, see [QuietHours](crate::schedule::QuietHours)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<String>,
+}
+
+impl Profile {
+    fn is_empty(&self) -> bool {
+        self == &Profile::default()
+    }
+
+    /// Returns this profile with the settings it leaves out taken from `defaults`.
+    fn or(self, defaults: &Profile) -> Profile {
+        Profile {
+            output_dir: self.output_dir.or_else(|| defaults.output_dir.clone()),
+            download_all: self.download_all.or(defaults.download_all),
+            matches: if self.matches.is_empty() { defaults.matches.clone() } else { self.matches },
+            exclude_matches: if self.exclude_matches.is_empty() { defaults.exclude_matches.clone() } else { self.exclude_matches },
+            min_likes: self.min_likes.or(defaults.min_likes),
+            min_retweets: self.min_retweets.or(defaults.min_retweets),
+            min_width: self.min_width.or(defaults.min_width),
+            min_height: self.min_height.or(defaults.min_height),
+            sensitive: self.sensitive.or_else(|| defaults.sensitive.clone()),
+            layout: self.layout.or_else(|| defaults.layout.clone()),
+            filename_template: self.filename_template.or_else(|| defaults.filename_template.clone()),
+            every: self.every.or_else(|| defaults.every.clone()),
+            quiet_hours: self.quiet_hours.or_else(|| defaults.quiet_hours.clone()),
+        }
+    }
+}
+
+/// Returns the directory of the configuration file: `$XDG_CONFIG_HOME/twitter-media-downloader`, falling back to
+/// `~/.config/twitter-media-downloader`, or `%APPDATA%\twitter-media-downloader` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join(CONFIG_DIR))
+}
+
+/// Returns the path of the default configuration file, see [config_dir](config_dir).
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(CONFIG_FILENAME))
+}
+
+impl Settings {
+    /// Reads the settings from the TOML file at `path`.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Writes the settings as TOML to `path`, creating its directory if needed.
+    pub fn write(&self, path: &Path) -> Result<(), io::Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic(path, contents.as_bytes())
+    }
+
+    /// Returns the users to download without `-u`: the `usernames` followed by the users with a section, in order.
+    pub fn all_usernames(&self) -> Vec<String> {
+        let mut usernames = self.usernames.clone();
+        for username in self.users.keys() {
+            if !usernames.contains(username) {
+                usernames.push(username.clone());
+            }
+        }
+        usernames
+    }
+
+    /// Returns the settings of `username`: its section, completed by the `[defaults]`.
+    pub fn profile(&self, username: &str) -> Profile {
+        match self.users.get(username) {
+            Some(profile) => profile.clone().or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+}
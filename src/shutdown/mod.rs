@@ -0,0 +1,83 @@
+//! module to stop a run gracefully on Ctrl+C or when its time budget is exhausted.
+//!
+//! The first Ctrl+C requests a shutdown: no new downloads are started, in-flight ones are finished and the
+//! checkpoint is written. A second Ctrl+C exits immediately.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{warn, Instrument};
+
+use crate::clock;
+
+/// Exit code of a run stopped by Ctrl+C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+static TIME_BUDGET_EXHAUSTED: AtomicBool = AtomicBool::new(false);
+
+fn token() -> &'static CancellationToken {
+    TOKEN.get_or_init(CancellationToken::new)
+}
+
+/// Installs the Ctrl+C handler. Must be called from within the tokio runtime.
+pub fn install() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Interrupted. Finishing in-flight downloads and writing the checkpoint. Press Ctrl+C again to exit immediately");
+        token().cancel();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    }.in_current_span());
+}
+
+/// Requests a shutdown once `budget` has passed, the same way as Ctrl+C. Must be called from within the tokio runtime.
+pub fn install_time_budget(budget: Duration) {
+    tokio::spawn(async move {
+        clock::sleep(budget).await;
+        if !is_requested() {
+            warn!("Time budget of {} seconds exhausted. Finishing in-flight downloads and writing the checkpoint", budget.as_secs());
+            TIME_BUDGET_EXHAUSTED.store(true, Ordering::SeqCst);
+            token().cancel();
+        }
+    }.in_current_span());
+}
+
+/// Returns true if the shutdown was requested by the time budget, see [install_time_budget](install_time_budget).
+pub fn is_time_budget_exhausted() -> bool {
+    TIME_BUDGET_EXHAUSTED.load(Ordering::SeqCst)
+}
+
+/// Requests a shutdown the same way as Ctrl+C, e.g. from a key of the [dashboard](crate::tui).
+pub fn request() {
+    if !is_requested() {
+        warn!("Stopping. Finishing in-flight downloads and writing the checkpoint");
+        token().cancel();
+    }
+}
+
+/// Returns true once a shutdown was requested.
+pub fn is_requested() -> bool {
+    token().is_cancelled()
+}
+
+/// Completes once a shutdown is requested.
+pub async fn requested() {
+    token().cancelled().await
+}
+
+/// Sleeps for `duration` on the [clock](crate::clock) unless a shutdown is requested earlier.
+///
+/// Returns false if the sleep was cut short by a shutdown.
+pub async fn sleep(duration: Duration) -> bool {
+    tokio::select! {
+        _ = clock::sleep(duration) => true,
+        _ = token().cancelled() => false,
+    }
+}
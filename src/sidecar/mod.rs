@@ -0,0 +1,72 @@
+//! module to write a JSON metadata sidecar next to every downloaded media file.
+//!
+//! The sidecar `<filename>.json` keeps the context of the media: the Tweet, its author, text and metrics, and the
+//! alt text of the media. The preview image of a video, see `--video-previews`, is marked `"preview": true`.
+//!
+//! With `--save-text` a plain `<filename>.txt` with the text, date and permalink of the Tweet is written as well, for
+//! file managers and photo tools.
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use twitter_v2::{Media, Tweet};
+
+use crate::common::write_atomic;
+use crate::twitter::media_count;
+
+/// Returns the path of the sidecar of `media_file`: `media_file` with `.json` appended.
+pub fn sidecar_path(media_file: &Path) -> PathBuf {
+    let mut path = media_file.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// Returns the path of the text file of `media_file`: `media_file` with `.txt` appended.
+pub fn text_path(media_file: &Path) -> PathBuf {
+    let mut path = media_file.as_os_str().to_owned();
+    path.push(".txt");
+    PathBuf::from(path)
+}
+
+/// Returns the sidecar metadata of `media` attached to `tweet` of `username` at `media_index` of its attachments.
+///
+/// The position is kept as `position` and `media_count`, e.g. 2 and 4 for the second of four photos, so galleries
+/// can restore the order of the media of a Tweet.
+///
+/// `original_author` is the probable original author of a reposted Tweet, see [source](crate::source).
+pub fn metadata(username: &str, tweet: &Tweet, media: &Media, media_index: usize, original_author: Option<&str>) -> Value {
+    json!({
+        "tweet_id": tweet.id.to_string(),
+        "username": username,
+        "author_id": tweet.author_id.as_ref().map(|id| id.to_string()),
+        "original_author": original_author,
+        "created_at": tweet.created_at.and_then(|d| d.format(&Rfc3339).ok()),
+        "text": tweet.text,
+        "media_key": media.media_key.to_string(),
+        "alt_text": media.alt_text,
+        "position": media_index + 1,
+        "media_count": media_count(tweet),
+        "public_metrics": tweet.public_metrics,
+    })
+}
+
+/// Writes `metadata` as the sidecar of `media_file`.
+pub fn write(media_file: &Path, metadata: &Value) -> Result<(), io::Error> {
+    let contents = serde_json::to_vec_pretty(metadata)?;
+    write_atomic(&sidecar_path(media_file), &contents)
+}
+
+/// Writes the text file of `media_file`: the Tweet `text`, its local `date` if known and its `permalink`.
+pub fn write_text(media_file: &Path, text: &str, date: Option<OffsetDateTime>, permalink: &str) -> Result<(), io::Error> {
+    let mut contents = format!("{}\n\n", text.trim_end());
+    if let Some(date) = date.and_then(|d| d.format(format_description!("[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]")).ok()) {
+        contents.push_str(&date);
+        contents.push('\n');
+    }
+    contents.push_str(permalink);
+    contents.push('\n');
+    write_atomic(&text_path(media_file), contents.as_bytes())
+}
@@ -0,0 +1,142 @@
+//! module to find visually identical images, e.g. the same photo saved at another resolution or recompressed.
+//!
+//! Every downloaded image gets a 64 bit difference hash (dHash) stored in the [state database](crate::state). Images
+//! whose hashes differ in at most [MAX_DISTANCE](MAX_DISTANCE) bits are near-duplicates. Unlike [dedup](crate::dedup),
+//! which compares the bytes of files, this compares what they show.
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use image::imageops::FilterType;
+use serde::Serialize;
+
+use crate::common::write_atomic;
+use crate::state::{HashedMedia, StateStore};
+
+/// Name of the near-duplicate report under the output directory.
+pub const REPORT_FILENAME: &str = "near-duplicates.json";
+
+/// Most bits two hashes of near-duplicates differ in.
+pub const MAX_DISTANCE: u32 = 6;
+
+/// Serializes the writes of the report by the users of a run.
+static REPORT_LOCK: Mutex<()> = Mutex::new(());
+
+/// What to do with a downloaded image that is a near-duplicate of an earlier download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NearDupes {
+    /// no hashing
+    Off,
+    /// keep the image, list it in the [report](REPORT_FILENAME)
+    Report,
+    /// delete the image, the media is recorded with the path of the earlier download
+    Skip,
+}
+
+impl FromStr for NearDupes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(NearDupes::Off),
+            "report" => Ok(NearDupes::Report),
+            "skip" => Ok(NearDupes::Skip),
+            _ => Err(format!("unknown near-dupes mode '{}'. Expected report, skip or off", s)),
+        }
+    }
+}
+
+/// Returns the difference hash of the image at `path`.
+///
+/// The image is shrunk to 9x8 grayscale pixels, every bit tells whether a pixel is brighter than its right neighbour.
+pub fn dhash(path: &Path) -> Result<u64, image::ImageError> {
+    let pixels = image::open(path)?.grayscale().resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// Number of bits `a` and `b` differ in.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Returns the path of a downloaded image other than `media_key` that is a near-duplicate of `dhash`, if any.
+pub fn find_similar(state: &StateStore, media_key: &str, dhash: u64) -> Result<Option<PathBuf>, rusqlite::Error> {
+    Ok(state.hashed_media()?
+        .into_iter()
+        .filter(|m| m.media_key != media_key && distance(m.dhash, dhash) <= MAX_DISTANCE)
+        .min_by_key(|m| distance(m.dhash, dhash))
+        .map(|m| m.local_path)
+        .filter(|p| p.is_file()))
+}
+
+/// A near-duplicate image in the [report](REPORT_FILENAME).
+#[derive(Debug, Serialize)]
+pub struct ClusterEntry {
+    pub username: String,
+    pub media_key: String,
+    pub local_path: PathBuf,
+    pub dhash: String,
+}
+
+/// Groups `media` into clusters of near-duplicates. Images without near-duplicates are left out.
+pub fn clusters(media: Vec<HashedMedia>) -> Vec<Vec<HashedMedia>> {
+    // union find over the pairs of near-duplicates
+    let mut parent: Vec<usize> = (0..media.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..media.len() {
+        for j in i + 1..media.len() {
+            if distance(media[i].dhash, media[j].dhash) <= MAX_DISTANCE {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..media.len()).map(|i| root(&mut parent, i)).collect();
+    let mut clusters: Vec<Vec<HashedMedia>> = Vec::new();
+    let mut cluster_of_root = HashMap::new();
+    for (m, r) in media.into_iter().zip(roots) {
+        let index = *cluster_of_root.entry(r).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[index].push(m);
+    }
+    clusters.retain(|c| c.len() > 1);
+    clusters
+}
+
+/// Writes the clusters of near-duplicates of every user under `output_dir` to [REPORT_FILENAME](REPORT_FILENAME).
+///
+/// Returns the path of the report and the number of clusters.
+pub fn write_report(output_dir: &Path, state: &StateStore) -> Result<(PathBuf, usize), Box<dyn Error + Send + Sync>> {
+    let _lock = REPORT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let clusters: Vec<Vec<ClusterEntry>> = clusters(state.hashed_media()?)
+        .into_iter()
+        .map(|c| c.into_iter()
+            .map(|m| ClusterEntry { username: m.username, media_key: m.media_key, local_path: m.local_path, dhash: format!("{:016x}", m.dhash) })
+            .collect())
+        .collect();
+
+    let path = output_dir.join(REPORT_FILENAME);
+    write_atomic(&path, serde_json::to_string_pretty(&clusters)?.as_bytes())?;
+    Ok((path, clusters.len()))
+}
@@ -0,0 +1,39 @@
+//! module to report the media files a run left out on purpose.
+//!
+//! Media over the `--max-file-size` limit, and with `--hls skip` videos offered as HLS playlist only, are not downloaded but appended to `<user>/skipped.jsonl`, one JSON
+//! object per file, so they can be fetched by hand or with a higher limit later.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+/// Name of the per user report of skipped files.
+pub const SKIPPED_FILENAME: &str = "skipped.jsonl";
+
+/// Serializes the appends of the download tasks, so lines never interleave.
+static APPEND_LOCK: Mutex<()> = Mutex::new(());
+
+/// A media file that was not downloaded.
+#[derive(Debug, Serialize)]
+pub struct Skipped<'a> {
+    pub username: &'a str,
+    pub media_key: &'a str,
+    pub tweet_id: &'a str,
+    pub url: &'a str,
+    /// why it was skipped, `too_large` or `hls`
+    pub reason: &'a str,
+    /// size in bytes announced by the server, 0 if none
+    pub size: u64,
+    /// the limit the size exceeds, 0 if none
+    pub limit: u64,
+}
+
+/// Appends `skipped` to the report in `user_output_dir`.
+pub fn append(user_output_dir: &Path, skipped: &Skipped) -> Result<(), io::Error> {
+    let line = format!("{}\n", serde_json::to_string(skipped)?);
+    let _lock = APPEND_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = OpenOptions::new().create(true).append(true).open(user_output_dir.join(SKIPPED_FILENAME))?;
+    file.write_all(line.as_bytes())
+}
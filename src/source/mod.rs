@@ -0,0 +1,42 @@
+//! module to guess the original author of media reposted by aggregator accounts.
+//!
+//! The heuristics look for credits in the Tweet text (`via @user`, `📷: @user`, `credit: @user`, ...) and for
+//! links to Tweets of other accounts in the expanded entity urls.
+use std::sync::OnceLock;
+
+use regex::Regex;
+use twitter_v2::Tweet;
+
+/// Words that credit the @handle following them.
+const CREDIT_PATTERN: &str = r"(?i)(?:\b(?:via|by|credits?|source|src|photo|pic|art|artist|cc)\b|📷|📸|🎨)\s*[:\-–(]*\s*@([A-Za-z0-9_]{1,15})";
+
+/// Links to a Tweet of `<handle>`.
+const STATUS_LINK_PATTERN: &str = r"(?i)^https?://(?:www\.|mobile\.)?(?:twitter|x)\.com/([A-Za-z0-9_]{1,15})/status/\d+";
+
+fn credit_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(CREDIT_PATTERN).unwrap())
+}
+
+fn status_link_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(STATUS_LINK_PATTERN).unwrap())
+}
+
+/// Returns the probable original author of the media of `tweet` posted by `username`.
+///
+/// Credits in the text win over status links. Returns None if the Tweet looks like original content.
+pub fn detect_original_author(tweet: &Tweet, username: &str) -> Option<String> {
+    let is_other = |handle: &str| !handle.eq_ignore_ascii_case(username);
+
+    if let Some(handle) = credit_regex().captures_iter(&tweet.text)
+        .filter_map(|c| c.get(1).map(|m| m.as_str().to_string()))
+        .find(|h| is_other(h)) {
+        return Some(handle);
+    }
+
+    let urls = tweet.entities.as_ref().and_then(|e| e.urls.as_ref())?;
+    urls.iter()
+        .filter_map(|u| status_link_regex().captures(&u.expanded_url).and_then(|c| c.get(1)).map(|m| m.as_str().to_string()))
+        .find(|h| is_other(h))
+}
@@ -0,0 +1,807 @@
+//! module to keep the download state of an output directory in an embedded SQLite database.
+//!
+//! The state database records every media file that was fetched or failed, and the checkpoint of every user.
+//! It replaces the per user `checkpoint` file, which is only read once to import its value.
+//!
+//! The database is stamped with its [SCHEMA_VERSION](SCHEMA_VERSION) and the versions of the program that created and
+//! last opened it, so an output directory shared between machines is never changed by a version that does not understand it.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use thiserror::Error;
+use tracing::error;
+
+/// Name of the state database under the output directory.
+pub const STATE_FILENAME: &str = "state.db";
+
+/// Name of the legacy per user checkpoint file.
+pub const LEGACY_CHECKPOINT_FILENAME: &str = "checkpoint";
+
+/// Version of the database layout written by this version of the program.
+///
+/// Bump it on every change of the layout. Changes older versions can safely ignore, like a new table or column,
+/// leave [MIN_READER_SCHEMA_VERSION](MIN_READER_SCHEMA_VERSION) alone, others raise it to the new version.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// Oldest schema version of a program that can safely use a database written by this version.
+const MIN_READER_SCHEMA_VERSION: u32 = 2;
+
+/// Version of the program, stamped into the database.
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS media (
+    username TEXT NOT NULL,
+    media_key TEXT NOT NULL,
+    tweet_id TEXT NOT NULL,
+    url TEXT,
+    local_path TEXT,
+    size INTEGER,
+    sha256 TEXT,
+    status TEXT NOT NULL,
+    error TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (username, media_key)
+);
+CREATE INDEX IF NOT EXISTS media_sha256 ON media (sha256);
+CREATE TABLE IF NOT EXISTS forgotten (
+    media_key TEXT PRIMARY KEY,
+    forgotten_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS resume_positions (
+    username TEXT PRIMARY KEY,
+    tweet_id TEXT NOT NULL,
+    media_index INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS empty_streaks (
+    username TEXT PRIMARY KEY,
+    runs INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS aliases (
+    alias TEXT PRIMARY KEY,
+    username TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS checkpoints (
+    username TEXT PRIMARY KEY,
+    oldest_id TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+";
+
+/// Why the [state database](self) cannot be used.
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    /// The database was written by a newer version of the program that changed it incompatibly.
+    #[error("{} was written by twitter-media-downloader {written_by} with schema version {schema_version}, which needs schema version {min_reader} or newer. \
+             This is version {} with schema version {}. Update twitter-media-downloader to use this output directory", .path.display(), CRATE_VERSION, SCHEMA_VERSION)]
+    NewerVersion { path: PathBuf, written_by: String, schema_version: u32, min_reader: u32 },
+}
+
+/// Status of a media file in the state database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaStatus {
+    Downloaded,
+    Failed,
+    /// Deleted on request and never to be downloaded again, see [StateStore::forget](StateStore::forget).
+    Forgotten,
+}
+
+impl MediaStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaStatus::Downloaded => "downloaded",
+            MediaStatus::Failed => "failed",
+            MediaStatus::Forgotten => "forgotten",
+        }
+    }
+}
+
+/// A downloaded media file.
+#[derive(Debug, Clone)]
+pub struct MediaRecord {
+    pub username: String,
+    pub media_key: String,
+    pub tweet_id: String,
+    pub url: String,
+    pub local_path: PathBuf,
+    pub size: u64,
+    pub sha256: Option<String>,
+    /// id of the run that recorded the file
+    pub run_id: String,
+    /// for a duplicate deleted by [dedup](crate::dedup), the earlier download with its content. The record then owns
+    /// no file, `local_path` is not stored
+    pub duplicate_of: Option<PathBuf>,
+}
+
+/// A downloaded media file with its [perceptual hash](crate::similar::dhash).
+#[derive(Debug, Clone)]
+pub struct HashedMedia {
+    pub username: String,
+    pub media_key: String,
+    pub local_path: PathBuf,
+    pub dhash: u64,
+}
+
+/// The state database of an output directory.
+///
+/// The connection is shared by the download tasks of a run, one statement at a time.
+pub struct StateStore {
+    conn: Mutex<Connection>,
+}
+
+impl StateStore {
+    /// Opens or creates [STATE_FILENAME](STATE_FILENAME) under `output_dir`.
+    ///
+    /// Returns [StateError::NewerVersion](StateError::NewerVersion), without changing the database, if it was written by
+    /// a newer version this version cannot safely use.
+    pub fn open(output_dir: &Path) -> Result<Self, StateError> {
+        let path = output_dir.join(STATE_FILENAME);
+        let conn = Connection::open(&path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        check_version(&conn, &path)?;
+        conn.execute_batch(SCHEMA)?;
+        add_column_if_missing(&conn, "checkpoints", "newest_id", "TEXT")?;
+        add_column_if_missing(&conn, "checkpoints", "previous_id", "TEXT")?;
+        add_column_if_missing(&conn, "media", "run_id", "TEXT")?;
+        add_column_if_missing(&conn, "media", "duplicate_of", "TEXT")?;
+        add_column_if_missing(&conn, "media", "dhash", "TEXT")?;
+        add_column_if_missing(&conn, "media", "attempts", "INTEGER")?;
+        stamp_version(&conn)?;
+        Ok(StateStore { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the checkpoint of `username`, the id of the oldest Tweet processed.
+    ///
+    /// Without a stored checkpoint the legacy `checkpoint` file in `user_output_dir` is imported, if any.
+    ///
+    /// An invalid checkpoint, e.g. garbled by a broken disk, is replaced by the previous one. Without a valid previous
+    /// one, or with an invalid legacy file, returns None: the next run starts over. Either is logged as an error.
+    pub fn get_checkpoint(&self, username: &str, user_output_dir: &Path) -> Result<Option<u64>, rusqlite::Error> {
+        let stored: Option<(String, Option<String>)> = self.conn()
+            .query_row("SELECT oldest_id, previous_id FROM checkpoints WHERE username = ?1", params![username], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+
+        match stored {
+            Some((oldest_id, previous_id)) => match parse_checkpoint(&oldest_id) {
+                Some(checkpoint) => Ok(Some(checkpoint)),
+                None => match previous_id.as_deref().and_then(parse_checkpoint) {
+                    Some(previous) => {
+                        error!("username: {}, checkpoint: {:?}, previous: {}. Invalid checkpoint, restored the previous one. Tweets already walked may be walked again", username, oldest_id, previous);
+                        self.conn().execute(
+                            "UPDATE checkpoints SET oldest_id = ?2, updated_at = ?3 WHERE username = ?1",
+                            params![username, previous.to_string(), now()],
+                        )?;
+                        Ok(Some(previous))
+                    }
+                    None => {
+                        error!("username: {}, checkpoint: {:?}. Invalid checkpoint without a valid previous one. Starting over from the newest Tweet, all Tweets are walked again", username, oldest_id);
+                        Ok(None)
+                    }
+                },
+            },
+            None => {
+                let path = user_output_dir.join(LEGACY_CHECKPOINT_FILENAME);
+                let contents = match fs::read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(_) => return Ok(None),
+                };
+                let legacy = parse_checkpoint(&contents);
+                match legacy {
+                    Some(checkpoint) => self.set_checkpoint(username, checkpoint)?,
+                    None => error!("username: {}, checkpoint: {:?}. Invalid checkpoint file {}, ignored. Starting over from the newest Tweet, all Tweets are walked again", username, contents, path.display()),
+                }
+                Ok(legacy)
+            }
+        }
+    }
+
+    /// Stores the checkpoint of `username`. The checkpoint it replaces is kept as the previous one, restored by
+    /// [get_checkpoint](StateStore::get_checkpoint) if the checkpoint turns out invalid.
+    pub fn set_checkpoint(&self, username: &str, oldest_id: u64) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "INSERT INTO checkpoints (username, oldest_id, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username) DO UPDATE SET previous_id = checkpoints.oldest_id, oldest_id = excluded.oldest_id, updated_at = excluded.updated_at",
+            params![username, oldest_id.to_string(), now()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the local path of the media `media_key` of `username` if it was downloaded, for a skipped duplicate the
+    /// path of the earlier download.
+    pub fn downloaded_path(&self, username: &str, media_key: &str) -> Result<Option<PathBuf>, rusqlite::Error> {
+        let path: Option<Option<String>> = self.conn()
+            .query_row(
+                "SELECT COALESCE(local_path, duplicate_of) FROM media WHERE username = ?1 AND media_key = ?2 AND status = ?3",
+                params![username, media_key, MediaStatus::Downloaded.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(path.flatten().map(PathBuf::from))
+    }
+
+    /// Returns the local paths of the downloaded media other than `media_key` with content `sha256`, oldest first.
+    pub fn paths_by_sha256(&self, sha256: &str, media_key: &str) -> Result<Vec<PathBuf>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT local_path FROM media WHERE sha256 = ?1 AND media_key != ?2 AND status = ?3 AND local_path IS NOT NULL ORDER BY updated_at",
+        )?;
+        let paths = stmt.query_map(params![sha256, media_key, MediaStatus::Downloaded.as_str()], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Records a downloaded media file, or with `duplicate_of` a duplicate without a file of its own.
+    pub fn record_downloaded(&self, record: &MediaRecord) -> Result<(), rusqlite::Error> {
+        let local_path = match record.duplicate_of {
+            Some(_) => None,
+            None => Some(record.local_path.to_string_lossy()),
+        };
+        self.conn().execute(
+            "INSERT INTO media (username, media_key, tweet_id, url, local_path, size, sha256, status, error, updated_at, run_id, duplicate_of)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, NULL, ?9, ?10, ?11)
+             ON CONFLICT (username, media_key) DO UPDATE SET tweet_id = excluded.tweet_id, url = excluded.url,
+                local_path = excluded.local_path, size = excluded.size, sha256 = excluded.sha256,
+                status = excluded.status, error = NULL, updated_at = excluded.updated_at, run_id = excluded.run_id,
+                duplicate_of = excluded.duplicate_of",
+            params![
+                record.username,
+                record.media_key,
+                record.tweet_id,
+                record.url,
+                local_path,
+                record.size as i64,
+                record.sha256,
+                MediaStatus::Downloaded.as_str(),
+                now(),
+                record.run_id,
+                record.duplicate_of.as_ref().map(|p| p.to_string_lossy()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Marks the skipped duplicates of the file at `original` as failed, e.g. when it was forgotten, so the next run
+    /// downloads them to files of their own. Returns the number of duplicates.
+    pub fn release_duplicates(&self, original: &Path) -> Result<usize, rusqlite::Error> {
+        self.conn().execute(
+            "UPDATE media SET status = ?1, error = ?2, updated_at = ?3, duplicate_of = NULL WHERE duplicate_of = ?4 AND status = ?5",
+            params![MediaStatus::Failed.as_str(), "the earlier download with the same content is gone", now(), original.to_string_lossy(), MediaStatus::Downloaded.as_str()],
+        )
+    }
+
+    /// Returns the downloaded media files of `username`, or of every user if None. Skipped duplicates, owning no file,
+    /// are left out.
+    pub fn downloaded_media(&self, username: Option<&str>) -> Result<Vec<MediaRecord>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT username, media_key, tweet_id, url, local_path, size, sha256, run_id FROM media
+             WHERE status = ?1 AND local_path IS NOT NULL AND (?2 IS NULL OR username = ?2)
+             ORDER BY username, media_key",
+        )?;
+        let records = stmt.query_map(params![MediaStatus::Downloaded.as_str(), username], |row| {
+            Ok(MediaRecord {
+                username: row.get(0)?,
+                media_key: row.get(1)?,
+                tweet_id: row.get(2)?,
+                url: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                local_path: PathBuf::from(row.get::<_, String>(4)?),
+                size: row.get::<_, Option<i64>>(5)?.unwrap_or(0).max(0) as u64,
+                sha256: row.get(6)?,
+                run_id: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                duplicate_of: None,
+            })
+        })?;
+        records.collect()
+    }
+
+    /// Marks the downloaded media file `media_key` of `username` as failed with `error`, e.g. when it went missing.
+    pub fn requeue(&self, run_id: &str, username: &str, media_key: &str, error: &str) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "UPDATE media SET status = ?1, error = ?2, updated_at = ?3, run_id = ?4 WHERE username = ?5 AND media_key = ?6",
+            params![MediaStatus::Failed.as_str(), error, now(), run_id, username, media_key],
+        )?;
+        Ok(())
+    }
+
+    /// Records a failed download of run `run_id` to `local_path`, counting the attempts. A media file already
+    /// downloaded stays downloaded.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_failed(&self, run_id: &str, username: &str, media_key: &str, tweet_id: &str, url: &str, local_path: Option<&Path>, error: &str) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "INSERT INTO media (username, media_key, tweet_id, url, local_path, status, error, updated_at, run_id, attempts)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 1)
+             ON CONFLICT (username, media_key) DO UPDATE SET url = excluded.url, local_path = COALESCE(excluded.local_path, media.local_path),
+                error = excluded.error, updated_at = excluded.updated_at, run_id = excluded.run_id, attempts = COALESCE(media.attempts, 0) + 1
+                WHERE media.status != 'downloaded'",
+            params![username, media_key, tweet_id, url, local_path.map(|p| p.to_string_lossy().into_owned()), MediaStatus::Failed.as_str(), error, now(), run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the failed downloads of `username`, or of every user if None.
+    pub fn failed_media(&self, username: Option<&str>) -> Result<Vec<FailedMedia>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT username, media_key, tweet_id, url, local_path, error, attempts FROM media
+             WHERE status = ?1 AND (?2 IS NULL OR username = ?2)
+             ORDER BY username, CAST(tweet_id AS INTEGER) DESC, media_key",
+        )?;
+        let failed = stmt.query_map(params![MediaStatus::Failed.as_str(), username], |row| {
+            Ok(FailedMedia {
+                username: row.get(0)?,
+                media_key: row.get(1)?,
+                tweet_id: row.get(2)?,
+                url: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                local_path: row.get::<_, Option<String>>(4)?.map(PathBuf::from),
+                error: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                attempts: row.get::<_, Option<i64>>(6)?.unwrap_or(1).max(0) as u32,
+            })
+        })?;
+        failed.collect()
+    }
+}
+
+/// A failed download, see [StateStore::failed_media](StateStore::failed_media).
+#[derive(Debug, Clone)]
+pub struct FailedMedia {
+    pub username: String,
+    pub media_key: String,
+    pub tweet_id: String,
+    pub url: String,
+    /// where the file was to be saved, None if unknown
+    pub local_path: Option<PathBuf>,
+    /// error of the last attempt
+    pub error: String,
+    pub attempts: u32,
+}
+
+impl StateStore {
+    /// Returns the media key of the media file at `local_path`, if recorded.
+    pub fn media_key_by_path(&self, local_path: &Path) -> Result<Option<String>, rusqlite::Error> {
+        self.conn()
+            .query_row("SELECT media_key FROM media WHERE local_path = ?1", params![local_path.to_string_lossy()], |row| row.get(0))
+            .optional()
+    }
+
+    /// Returns the media keys other than `media_key` recorded at `local_path`.
+    ///
+    /// Databases of older versions recorded skipped duplicates at the path of the earlier download.
+    pub fn other_media_keys_at(&self, local_path: &Path, media_key: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT media_key FROM media WHERE local_path = ?1 AND media_key != ?2 AND status = ?3")?;
+        let media_keys = stmt.query_map(params![local_path.to_string_lossy(), media_key, MediaStatus::Downloaded.as_str()], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(media_keys)
+    }
+
+    /// Returns the local paths recorded for `media_key`, for every user. Skipped duplicates own no path.
+    pub fn local_paths(&self, media_key: &str) -> Result<Vec<PathBuf>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT local_path FROM media WHERE media_key = ?1 AND local_path IS NOT NULL")?;
+        let paths = stmt.query_map(params![media_key], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Stores the [perceptual hash](crate::similar::dhash) of the downloaded media `media_key` of `username`.
+    pub fn set_dhash(&self, username: &str, media_key: &str, dhash: u64) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "UPDATE media SET dhash = ?1 WHERE username = ?2 AND media_key = ?3",
+            params![format!("{:016x}", dhash), username, media_key],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the downloaded media with a [perceptual hash](crate::similar::dhash), for every user.
+    pub fn hashed_media(&self) -> Result<Vec<HashedMedia>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT username, media_key, local_path, dhash FROM media WHERE status = ?1 AND dhash IS NOT NULL AND local_path IS NOT NULL",
+        )?;
+        let rows = stmt.query_map(params![MediaStatus::Downloaded.as_str()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })?;
+
+        let mut media = Vec::new();
+        for row in rows {
+            let (username, media_key, local_path, dhash) = row?;
+            if let Ok(dhash) = u64::from_str_radix(&dhash, 16) {
+                media.push(HashedMedia { username, media_key, local_path: PathBuf::from(local_path), dhash });
+            }
+        }
+        Ok(media)
+    }
+
+    /// Adds `media_key` to the do-not-redownload list and marks its records as forgotten.
+    pub fn forget(&self, media_key: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO forgotten (media_key, forgotten_at) VALUES (?1, ?2) ON CONFLICT (media_key) DO NOTHING",
+            params![media_key, now()],
+        )?;
+        conn.execute(
+            "UPDATE media SET status = ?1, updated_at = ?2 WHERE media_key = ?3",
+            params![MediaStatus::Forgotten.as_str(), now(), media_key],
+        )?;
+        Ok(())
+    }
+
+    /// Takes `media_key` off the do-not-redownload list and marks its forgotten records as downloaded again.
+    pub fn unforget(&self, media_key: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM forgotten WHERE media_key = ?1", params![media_key])?;
+        conn.execute(
+            "UPDATE media SET status = ?1, updated_at = ?2 WHERE media_key = ?3 AND status = ?4",
+            params![MediaStatus::Downloaded.as_str(), now(), media_key, MediaStatus::Forgotten.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns true if `media_key` is on the do-not-redownload list.
+    pub fn is_forgotten(&self, media_key: &str) -> Result<bool, rusqlite::Error> {
+        let found: Option<i64> = self.conn()
+            .query_row("SELECT 1 FROM forgotten WHERE media_key = ?1", params![media_key], |row| row.get(0))
+            .optional()?;
+        Ok(found.is_some())
+    }
+}
+
+impl StateStore {
+    /// Returns the id of the newest Tweet of `username` seen by any run.
+    pub fn get_newest_id(&self, username: &str) -> Result<Option<u64>, rusqlite::Error> {
+        let stored: Option<Option<String>> = self.conn()
+            .query_row("SELECT newest_id FROM checkpoints WHERE username = ?1", params![username], |row| row.get(0))
+            .optional()?;
+        Ok(stored.flatten().and_then(|n| n.parse::<u64>().ok()))
+    }
+
+    /// Raises the newest Tweet id of `username` to `newest_id` if it is newer.
+    pub fn update_newest_id(&self, username: &str, newest_id: u64) -> Result<(), rusqlite::Error> {
+        if self.get_newest_id(username)?.is_some_and(|stored| stored >= newest_id) {
+            return Ok(());
+        }
+        self.conn().execute(
+            "INSERT INTO checkpoints (username, oldest_id, newest_id, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (username) DO UPDATE SET newest_id = excluded.newest_id, updated_at = excluded.updated_at",
+            params![username, u64::MAX.to_string(), newest_id.to_string(), now()],
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns the `meta` value of `key`, None if not set or the database has no `meta` table yet.
+fn get_meta(conn: &Connection, key: &str) -> Result<Option<String>, rusqlite::Error> {
+    let has_meta: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'meta')", [], |row| row.get(0))?;
+    if !has_meta {
+        return Ok(None);
+    }
+    conn.query_row("SELECT value FROM meta WHERE key = ?1", params![key], |row| row.get(0)).optional()
+}
+
+fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = excluded.value", params![key, value])?;
+    Ok(())
+}
+
+/// Fails if the database at `path` needs a newer schema version than [SCHEMA_VERSION](SCHEMA_VERSION).
+///
+/// Databases without a stamp predate it and are schema version 1.
+fn check_version(conn: &Connection, path: &Path) -> Result<(), StateError> {
+    let number = |key: &str| -> Result<Option<u32>, rusqlite::Error> { Ok(get_meta(conn, key)?.and_then(|v| v.parse().ok())) };
+    let min_reader = number("min_reader_schema_version")?.unwrap_or(1);
+    if min_reader > SCHEMA_VERSION {
+        return Err(StateError::NewerVersion {
+            path: path.to_path_buf(),
+            written_by: get_meta(conn, "last_written_by")?.unwrap_or_else(|| "unknown".into()),
+            schema_version: number("schema_version")?.unwrap_or(min_reader),
+            min_reader,
+        });
+    }
+    Ok(())
+}
+
+/// Stamps the database with the versions of this program.
+///
+/// The schema versions are only ever raised, so an older but compatible version does not hide the changes of a newer one.
+fn stamp_version(conn: &Connection) -> Result<(), rusqlite::Error> {
+    if get_meta(conn, "created_by")?.is_none() {
+        set_meta(conn, "created_by", CRATE_VERSION)?;
+    }
+    let schema_version = get_meta(conn, "schema_version")?.and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    if SCHEMA_VERSION >= schema_version {
+        set_meta(conn, "schema_version", &SCHEMA_VERSION.to_string())?;
+        set_meta(conn, "min_reader_schema_version", &MIN_READER_SCHEMA_VERSION.to_string())?;
+    }
+    set_meta(conn, "last_written_by", CRATE_VERSION)
+}
+
+/// Returns the Tweet id of the stored checkpoint `value`, None if it is no Tweet id, e.g. truncated or garbled.
+fn parse_checkpoint(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse::<u64>().ok()
+}
+
+/// Adds `column` to `table` for databases created before the column existed.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt.query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|c| c.ok())
+        .any(|c| c == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+    }
+    Ok(())
+}
+
+/// Where a run stopped within a Tweet: the media of `tweet_id` before `media_index` are processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumePosition {
+    pub tweet_id: u64,
+    pub media_index: usize,
+}
+
+impl StateStore {
+    /// Returns the position a run of `username` stopped at within a Tweet, if any.
+    pub fn get_resume_position(&self, username: &str) -> Result<Option<ResumePosition>, rusqlite::Error> {
+        let stored: Option<(String, i64)> = self.conn()
+            .query_row("SELECT tweet_id, media_index FROM resume_positions WHERE username = ?1", params![username], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        Ok(stored.and_then(|(tweet_id, media_index)| {
+            tweet_id.parse::<u64>().ok().map(|tweet_id| ResumePosition { tweet_id, media_index: media_index.max(0) as usize })
+        }))
+    }
+
+    pub fn set_resume_position(&self, username: &str, position: ResumePosition) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "INSERT INTO resume_positions (username, tweet_id, media_index) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username) DO UPDATE SET tweet_id = excluded.tweet_id, media_index = excluded.media_index",
+            params![username, position.tweet_id.to_string(), position.media_index as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_resume_position(&self, username: &str) -> Result<(), rusqlite::Error> {
+        self.conn().execute("DELETE FROM resume_positions WHERE username = ?1", params![username])?;
+        Ok(())
+    }
+}
+
+/// What the state database knows about a user.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserSummary {
+    /// None if there is no checkpoint or it points at the latest Tweet
+    pub checkpoint: Option<u64>,
+    pub oldest_tweet_id: Option<u64>,
+    pub newest_tweet_id: Option<u64>,
+    /// unix time in seconds of the last completed run
+    pub last_run: Option<i64>,
+}
+
+impl StateStore {
+    /// Returns the [UserSummary](UserSummary) of `username`. Tweet ids are those of downloaded media.
+    pub fn user_summary(&self, username: &str) -> Result<UserSummary, rusqlite::Error> {
+        let conn = self.conn();
+        let checkpoint: Option<String> = conn
+            .query_row("SELECT oldest_id FROM checkpoints WHERE username = ?1", params![username], |row| row.get(0))
+            .optional()?;
+        let (oldest_tweet_id, newest_tweet_id): (Option<i64>, Option<i64>) = conn.query_row(
+            "SELECT MIN(CAST(tweet_id AS INTEGER)), MAX(CAST(tweet_id AS INTEGER)) FROM media WHERE username = ?1 AND status = ?2 AND tweet_id != ''",
+            params![username, MediaStatus::Downloaded.as_str()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let last_run: Option<i64> = conn
+            .query_row("SELECT updated_at FROM empty_streaks WHERE username = ?1", params![username], |row| row.get(0))
+            .optional()?;
+
+        Ok(UserSummary {
+            checkpoint: checkpoint.and_then(|c| c.parse::<u64>().ok()).filter(|c| *c != u64::MAX),
+            oldest_tweet_id: oldest_tweet_id.map(|id| id as u64),
+            newest_tweet_id: newest_tweet_id.map(|id| id as u64),
+            last_run,
+        })
+    }
+
+    /// Records a completed run of `username` that downloaded `new_media` files.
+    ///
+    /// Returns the number of consecutive runs without new media, 0 if this run had some.
+    pub fn record_run(&self, username: &str, new_media: u32) -> Result<u32, rusqlite::Error> {
+        let conn = self.conn();
+        if new_media > 0 {
+            conn.execute(
+                "INSERT INTO empty_streaks (username, runs, updated_at) VALUES (?1, 0, ?2)
+                 ON CONFLICT (username) DO UPDATE SET runs = 0, updated_at = excluded.updated_at",
+                params![username, now()],
+            )?;
+            return Ok(0);
+        }
+        conn.execute(
+            "INSERT INTO empty_streaks (username, runs, updated_at) VALUES (?1, 1, ?2)
+             ON CONFLICT (username) DO UPDATE SET runs = runs + 1, updated_at = excluded.updated_at",
+            params![username, now()],
+        )?;
+        let runs: i64 = conn.query_row("SELECT runs FROM empty_streaks WHERE username = ?1", params![username], |row| row.get(0))?;
+        Ok(runs.max(0) as u32)
+    }
+}
+
+impl StateStore {
+    /// Returns true if anything is recorded for `username`.
+    pub fn has_user(&self, username: &str) -> Result<bool, rusqlite::Error> {
+        self.conn().query_row(
+            "SELECT EXISTS (SELECT 1 FROM media WHERE username = ?1) OR EXISTS (SELECT 1 FROM checkpoints WHERE username = ?1)",
+            params![username],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns the users with a checkpoint or recorded media, sorted. Renamed handles are not included.
+    pub fn tracked_users(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT username FROM checkpoints UNION SELECT username FROM media
+             EXCEPT SELECT alias FROM aliases ORDER BY username",
+        )?;
+        let users = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>, _>>()?;
+        Ok(users)
+    }
+
+    /// Returns the handle the archive of `alias` was renamed to, if it was.
+    pub fn resolve_alias(&self, alias: &str) -> Result<Option<String>, rusqlite::Error> {
+        self.conn()
+            .query_row("SELECT username FROM aliases WHERE alias = ?1", params![alias], |row| row.get(0))
+            .optional()
+    }
+
+    /// Moves everything recorded for `old` to `new` and records `old` as an alias of `new`.
+    ///
+    /// Recorded paths under `old_dir`, of any user, are moved under `new_dir`. Returns the number of paths moved.
+    pub fn rename_user(&self, old: &str, new: &str, old_dir: &Path, new_dir: &Path) -> Result<usize, rusqlite::Error> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        let moved_paths: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare("SELECT rowid, local_path FROM media WHERE local_path IS NOT NULL")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.into_iter()
+                .filter_map(|(rowid, path)| Path::new(&path).strip_prefix(old_dir).ok()
+                    .map(|rest| (rowid, new_dir.join(rest).to_string_lossy().into_owned())))
+                .collect()
+        };
+        for (rowid, path) in moved_paths.iter() {
+            tx.execute("UPDATE media SET local_path = ?1 WHERE rowid = ?2", params![path, rowid])?;
+        }
+        let moved_originals: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare("SELECT rowid, duplicate_of FROM media WHERE duplicate_of IS NOT NULL")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.into_iter()
+                .filter_map(|(rowid, path)| Path::new(&path).strip_prefix(old_dir).ok()
+                    .map(|rest| (rowid, new_dir.join(rest).to_string_lossy().into_owned())))
+                .collect()
+        };
+        for (rowid, path) in moved_originals.iter() {
+            tx.execute("UPDATE media SET duplicate_of = ?1 WHERE rowid = ?2", params![path, rowid])?;
+        }
+
+        for table in ["media", "checkpoints", "resume_positions", "empty_streaks"] {
+            tx.execute(&format!("UPDATE {} SET username = ?1 WHERE username = ?2", table), params![new, old])?;
+        }
+        // earlier handles of `old` now map to `new` as well, and `new` is no longer an alias
+        tx.execute("UPDATE aliases SET username = ?1 WHERE username = ?2", params![new, old])?;
+        tx.execute("DELETE FROM aliases WHERE alias = ?1", params![new])?;
+        tx.execute(
+            "INSERT INTO aliases (alias, username, created_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (alias) DO UPDATE SET username = excluded.username, created_at = excluded.created_at",
+            params![old, new, now()],
+        )?;
+
+        tx.commit()?;
+        Ok(moved_paths.len())
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn meta(dir: &Path, key: &str) -> Option<String> {
+        get_meta(&Connection::open(dir.join(STATE_FILENAME)).unwrap(), key).unwrap()
+    }
+
+    #[test]
+    fn migrates_the_first_layout() {
+        let dir = test_dir("first-layout");
+        let conn = Connection::open(dir.join(STATE_FILENAME)).unwrap();
+        conn.execute_batch("
+            CREATE TABLE media (username TEXT NOT NULL, media_key TEXT NOT NULL, tweet_id TEXT NOT NULL, url TEXT, local_path TEXT,
+                size INTEGER, sha256 TEXT, status TEXT NOT NULL, error TEXT, updated_at INTEGER NOT NULL, PRIMARY KEY (username, media_key));
+            CREATE TABLE checkpoints (username TEXT PRIMARY KEY, oldest_id TEXT NOT NULL, updated_at INTEGER NOT NULL);
+            INSERT INTO media VALUES ('alice', '3_1', '20', 'https://pbs.twimg.com/media/a.jpg', '/archive/alice/a.jpg', 5, 'abc', 'downloaded', NULL, 1);
+            INSERT INTO checkpoints VALUES ('alice', '42', 1);
+        ").unwrap();
+        drop(conn);
+
+        let state = StateStore::open(&dir).unwrap();
+        assert_eq!(state.downloaded_path("alice", "3_1").unwrap(), Some(PathBuf::from("/archive/alice/a.jpg")));
+        assert_eq!(state.get_checkpoint("alice", &dir.join("alice")).unwrap(), Some(42));
+        // the added columns are usable
+        state.update_newest_id("alice", 50).unwrap();
+        assert_eq!(state.get_newest_id("alice").unwrap(), Some(50));
+        state.set_dhash("alice", "3_1", 7).unwrap();
+        assert_eq!(state.hashed_media().unwrap().len(), 1);
+        drop(state);
+
+        assert_eq!(meta(&dir, "schema_version"), Some(SCHEMA_VERSION.to_string()));
+        assert_eq!(meta(&dir, "min_reader_schema_version"), Some(MIN_READER_SCHEMA_VERSION.to_string()));
+        assert_eq!(meta(&dir, "created_by").as_deref(), Some(CRATE_VERSION));
+
+        // opening again changes nothing
+        StateStore::open(&dir).unwrap();
+        assert_eq!(meta(&dir, "schema_version"), Some(SCHEMA_VERSION.to_string()));
+    }
+
+    #[test]
+    fn refuses_databases_of_incompatible_newer_versions() {
+        let dir = test_dir("newer");
+        StateStore::open(&dir).unwrap();
+        let conn = Connection::open(dir.join(STATE_FILENAME)).unwrap();
+        set_meta(&conn, "schema_version", &(SCHEMA_VERSION + 2).to_string()).unwrap();
+        set_meta(&conn, "min_reader_schema_version", &(SCHEMA_VERSION + 1).to_string()).unwrap();
+        set_meta(&conn, "last_written_by", "99.0.0").unwrap();
+        drop(conn);
+
+        match StateStore::open(&dir) {
+            Err(StateError::NewerVersion { written_by, schema_version, min_reader, .. }) => {
+                assert_eq!((written_by.as_str(), schema_version, min_reader), ("99.0.0", SCHEMA_VERSION + 2, SCHEMA_VERSION + 1));
+            }
+            other => panic!("expected NewerVersion, got {:?}", other.map(|_| ())),
+        }
+        // left untouched
+        assert_eq!(meta(&dir, "last_written_by").as_deref(), Some("99.0.0"));
+    }
+
+    #[test]
+    fn keeps_the_versions_of_compatible_newer_versions() {
+        let dir = test_dir("compatible");
+        StateStore::open(&dir).unwrap();
+        let conn = Connection::open(dir.join(STATE_FILENAME)).unwrap();
+        set_meta(&conn, "schema_version", &(SCHEMA_VERSION + 1).to_string()).unwrap();
+        set_meta(&conn, "min_reader_schema_version", &SCHEMA_VERSION.to_string()).unwrap();
+        drop(conn);
+
+        StateStore::open(&dir).unwrap();
+        assert_eq!(meta(&dir, "schema_version"), Some((SCHEMA_VERSION + 1).to_string()));
+        assert_eq!(meta(&dir, "min_reader_schema_version"), Some(SCHEMA_VERSION.to_string()));
+        assert_eq!(meta(&dir, "last_written_by").as_deref(), Some(CRATE_VERSION));
+    }
+}
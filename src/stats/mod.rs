@@ -0,0 +1,427 @@
+//! module to report on what is stored under the output directory.
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::dates::{self, Timezone};
+use crate::messages::Message;
+use crate::state::StateStore;
+
+/// Disk usage of one user's output directory.
+#[derive(Debug, Default)]
+pub struct UserDiskUsage {
+    pub username: String,
+    pub files: u64,
+    pub bytes: u64,
+    /// bytes per `YYYY-MM` in the report's time zone, of the Tweet date for [recorded_disk_usage](recorded_disk_usage)
+    /// and of the files' modification time for [disk_usage](disk_usage)
+    pub bytes_per_month: BTreeMap<String, u64>,
+}
+
+/// Sums up the size of the downloads of `usernames`, or of every user if empty, recorded in the
+/// [state database](crate::state) under `output_dir`, without touching the files. See [disk_usage](disk_usage) for a
+/// scan of the user directories, which also counts sidecars and files not recorded.
+///
+/// Months are those of the Tweet dates in `timezone`, `unknown` for media without a Tweet.
+///
+/// Returns the usage sorted by bytes, largest first.
+pub fn recorded_disk_usage(output_dir: &Path, usernames: &[String], timezone: Timezone) -> Result<Vec<UserDiskUsage>, Box<dyn Error + Send + Sync>> {
+    let state = StateStore::open(output_dir)?;
+    let mut usage: BTreeMap<String, UserDiskUsage> = usernames.iter()
+        .map(|username| (username.clone(), UserDiskUsage { username: username.clone(), ..Default::default() }))
+        .collect();
+    for record in state.downloaded_media(None)? {
+        if !usernames.is_empty() && !usernames.contains(&record.username) {
+            continue;
+        }
+        let month = tweet_month(&record.tweet_id, timezone);
+        let user_usage = usage.entry(record.username.clone())
+            .or_insert_with(|| UserDiskUsage { username: record.username.clone(), ..Default::default() });
+        user_usage.files += 1;
+        user_usage.bytes += record.size;
+        *user_usage.bytes_per_month.entry(month).or_default() += record.size;
+    }
+    let mut usage: Vec<UserDiskUsage> = usage.into_values().collect();
+    usage.sort_by_key(|u| std::cmp::Reverse(u.bytes));
+    Ok(usage)
+}
+
+/// Scans the user directories under `output_dir` and sums up the size of their files.
+///
+/// If `usernames` is empty every directory under `output_dir` is treated as a user.
+///
+/// Months are those of `timezone`.
+///
+/// Returns the usage sorted by bytes, largest first.
+pub fn disk_usage(output_dir: &Path, usernames: &[String], timezone: Timezone) -> Result<Vec<UserDiskUsage>, io::Error> {
+    let usernames: Vec<String> = if usernames.is_empty() {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(output_dir)? {
+            let entry = entry?;
+            // hidden directories like the quarantine are no users
+            if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                found.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        found
+    } else {
+        usernames.to_vec()
+    };
+
+    let mut usage = Vec::new();
+    for username in usernames {
+        let mut user_usage = UserDiskUsage { username: username.clone(), ..Default::default() };
+        let user_output_dir = output_dir.join(&username);
+        if user_output_dir.is_dir() {
+            scan_dir(&user_output_dir, timezone, &mut user_usage)?;
+        }
+        usage.push(user_usage);
+    }
+    usage.sort_by_key(|u| std::cmp::Reverse(u.bytes));
+
+    Ok(usage)
+}
+
+/// Adds the files under `dir` to `usage`, recursing into sub directories.
+fn scan_dir(dir: &Path, timezone: Timezone, usage: &mut UserDiskUsage) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            scan_dir(&entry.path(), timezone, usage)?;
+        } else if metadata.is_file() {
+            let month = metadata.modified()
+                .ok()
+                .and_then(|m| timezone.local(OffsetDateTime::from(m)).format(format_description!("[year]-[month]")).ok())
+                .unwrap_or_else(|| "unknown".into());
+
+            usage.files += 1;
+            usage.bytes += metadata.len();
+            *usage.bytes_per_month.entry(month).or_default() += metadata.len();
+        }
+    }
+    Ok(())
+}
+
+/// Archive state of one user.
+#[derive(Debug, Serialize)]
+pub struct UserStatus {
+    pub username: String,
+    /// id of the oldest Tweet processed, None if the next run starts at the latest Tweet
+    pub checkpoint: Option<u64>,
+    pub files: u64,
+    pub bytes: u64,
+    /// date of the oldest Tweet with downloaded media
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub oldest_tweet: Option<OffsetDateTime>,
+    /// date of the newest Tweet with downloaded media
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub newest_tweet: Option<OffsetDateTime>,
+    /// time of the last completed download run
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_run: Option<OffsetDateTime>,
+}
+
+/// Returns the [UserStatus](UserStatus) of the users under `output_dir`, see [disk_usage](disk_usage).
+///
+/// The dates are in `timezone`.
+pub fn status(output_dir: &Path, usernames: &[String], timezone: Timezone) -> Result<Vec<UserStatus>, Box<dyn Error + Send + Sync>> {
+    let state = StateStore::open(output_dir)?;
+
+    let mut status = Vec::new();
+    for usage in disk_usage(output_dir, usernames, timezone)? {
+        let summary = state.user_summary(&usage.username)?;
+        status.push(UserStatus {
+            checkpoint: summary.checkpoint,
+            files: usage.files,
+            bytes: usage.bytes,
+            oldest_tweet: summary.oldest_tweet_id.and_then(dates::tweet_id_date).map(|d| timezone.local(d)),
+            newest_tweet: summary.newest_tweet_id.and_then(dates::tweet_id_date).map(|d| timezone.local(d)),
+            last_run: summary.last_run.and_then(|t| OffsetDateTime::from_unix_timestamp(t).ok()).map(|d| timezone.local(d)),
+            username: usage.username,
+        });
+    }
+    Ok(status)
+}
+
+/// Prints `status` as a table to stdout.
+pub fn print_status(status: &[UserStatus]) {
+    let date = |d: &Option<OffsetDateTime>| d
+        .and_then(|d| d.format(format_description!("[year]-[month]-[day] [hour]:[minute]")).ok())
+        .unwrap_or_else(|| "-".into());
+
+    println!("{}", Message::StatusHeader);
+    for user in status {
+        let checkpoint = user.checkpoint.map(|c| c.to_string()).unwrap_or_else(|| Message::CheckpointLatest.to_string());
+        println!("{:<20} {:>20} {:>8} {:>10}  {:<16}  {:<16}  {:<16}",
+                 user.username, checkpoint, user.files, format_bytes(user.bytes), date(&user.oldest_tweet), date(&user.newest_tweet), date(&user.last_run));
+    }
+}
+
+/// Prints the `du`-style report of `usage` to stdout.
+pub fn print_disk_usage(usage: &[UserDiskUsage]) {
+    let total: u64 = usage.iter().map(|u| u.bytes).sum();
+    for user in usage {
+        println!("{:>10}  {:>7} files  {}", format_bytes(user.bytes), user.files, user.username);
+        for (month, bytes) in user.bytes_per_month.iter() {
+            println!("{:>10}                 {}/{}", format_bytes(*bytes), user.username, month);
+        }
+    }
+    println!("{:>10}  {}", format_bytes(total), Message::Total);
+}
+
+/// Files and bytes of a group of media files.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Tally {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// A Tweet with its number of downloaded media files.
+#[derive(Debug, Serialize)]
+pub struct TopTweet {
+    pub tweet_id: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Statistics of the downloaded media of one user, from the [state database](crate::state).
+#[derive(Debug, Serialize)]
+pub struct UserReport {
+    pub username: String,
+    pub files: u64,
+    pub bytes: u64,
+    /// per `YYYY-MM` of the Tweet date in the report's time zone, `unknown` for media without a Tweet
+    pub months: BTreeMap<String, Tally>,
+    /// per file type, e.g. `jpg` or `png`
+    pub types: BTreeMap<String, Tally>,
+    /// Tweets with the most media files, most first
+    pub top_tweets: Vec<TopTweet>,
+}
+
+/// Format of the [statistics](report) export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    /// one row per user and group: `username,group,key,files,bytes`, with the groups `month`, `type` and `tweet`
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("unknown report format '{}'. Expected json or csv", s)),
+        }
+    }
+}
+
+/// Aggregates the downloaded media of `usernames` recorded under `output_dir`, or of every user if empty, with the
+/// `top` Tweets with the most media per user.
+///
+/// Months are those of `timezone`. Returns the reports sorted by username.
+pub fn report(output_dir: &Path, usernames: &[String], timezone: Timezone, top: usize) -> Result<Vec<UserReport>, Box<dyn Error + Send + Sync>> {
+    let state = StateStore::open(output_dir)?;
+    let mut records = state.downloaded_media(None)?;
+    if !usernames.is_empty() {
+        records.retain(|record| usernames.contains(&record.username));
+    }
+
+    // records are ordered by username
+    let mut reports: Vec<UserReport> = Vec::new();
+    let mut tweets: HashMap<String, Tally> = HashMap::new();
+    for record in records {
+        if reports.last().is_none_or(|report| report.username != record.username) {
+            if let Some(report) = reports.last_mut() {
+                report.top_tweets = top_tweets(&mut tweets, top);
+            }
+            reports.push(UserReport { username: record.username.clone(), files: 0, bytes: 0, months: BTreeMap::new(), types: BTreeMap::new(), top_tweets: Vec::new() });
+        }
+        let report = reports.last_mut().unwrap();
+        report.files += 1;
+        report.bytes += record.size;
+
+        report.months.entry(tweet_month(&record.tweet_id, timezone)).or_default().add(record.size);
+        let kind = record.local_path.extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "unknown".into());
+        report.types.entry(kind).or_default().add(record.size);
+        if !record.tweet_id.is_empty() {
+            tweets.entry(record.tweet_id).or_default().add(record.size);
+        }
+    }
+    if let Some(report) = reports.last_mut() {
+        report.top_tweets = top_tweets(&mut tweets, top);
+    }
+    Ok(reports)
+}
+
+/// Returns the `YYYY-MM` of Tweet `tweet_id` in `timezone`, `unknown` if it is none.
+fn tweet_month(tweet_id: &str, timezone: Timezone) -> String {
+    tweet_id.parse().ok()
+        .and_then(dates::tweet_id_date)
+        .and_then(|d| timezone.local(d).format(format_description!("[year]-[month]")).ok())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Returns the `top` Tweets of `tweets` with the most media files and clears `tweets`.
+fn top_tweets(tweets: &mut HashMap<String, Tally>, top: usize) -> Vec<TopTweet> {
+    let mut top_tweets: Vec<TopTweet> = tweets.drain()
+        .map(|(tweet_id, tally)| TopTweet { tweet_id, files: tally.files, bytes: tally.bytes })
+        .collect();
+    top_tweets.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| b.tweet_id.cmp(&a.tweet_id)));
+    top_tweets.truncate(top);
+    top_tweets
+}
+
+/// Writes `reports` as `format` to `out`.
+pub fn write_report(reports: &[UserReport], format: ReportFormat, out: &mut dyn Write) -> Result<(), io::Error> {
+    match format {
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, reports)?;
+            writeln!(out)
+        }
+        ReportFormat::Csv => {
+            writeln!(out, "username,group,key,files,bytes")?;
+            for report in reports {
+                for (month, tally) in report.months.iter() {
+                    writeln!(out, "{},month,{},{},{}", report.username, month, tally.files, tally.bytes)?;
+                }
+                for (kind, tally) in report.types.iter() {
+                    writeln!(out, "{},type,{},{},{}", report.username, kind, tally.files, tally.bytes)?;
+                }
+                for tweet in report.top_tweets.iter() {
+                    writeln!(out, "{},tweet,{},{},{}", report.username, tweet.tweet_id, tweet.files, tweet.bytes)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Formats `bytes` with a binary unit, e.g. `1.5 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-stats-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(username: &str, media_key: &str, tweet_id: &str, file_name: &str, size: u64) -> crate::state::MediaRecord {
+        crate::state::MediaRecord {
+            username: username.into(),
+            media_key: media_key.into(),
+            tweet_id: tweet_id.into(),
+            url: format!("https://pbs.twimg.com/media/{}", file_name),
+            local_path: PathBuf::from(username).join(file_name),
+            size,
+            sha256: None,
+            run_id: "run".into(),
+            duplicate_of: None,
+        }
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(format_bytes(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn groups_tweets_by_month() {
+        let utc = Timezone::default();
+        assert_eq!(tweet_month("1600000000000000000", utc), "2022-12");
+        assert_eq!(tweet_month("", utc), "unknown");
+        assert_eq!(tweet_month("not a tweet", utc), "unknown");
+    }
+
+    #[test]
+    fn reports_the_recorded_media() {
+        let dir = test_dir("report");
+        let state = StateStore::open(&dir).unwrap();
+        state.record_downloaded(&record("bob", "3_1", "1600000000000000000", "a.jpg", 100)).unwrap();
+        state.record_downloaded(&record("bob", "3_2", "1600000000000000000", "b.png", 200)).unwrap();
+        state.record_downloaded(&record("bob", "3_3", "1500000000000000000", "c.jpg", 300)).unwrap();
+        state.record_downloaded(&record("alice", "3_4", "1600000000000000000", "d.jpg", 50)).unwrap();
+        drop(state);
+
+        let reports = report(&dir, &[], Timezone::default(), 1).unwrap();
+        assert_eq!(reports.iter().map(|r| r.username.as_str()).collect::<Vec<_>>(), ["alice", "bob"]);
+        let bob = &reports[1];
+        assert_eq!((bob.files, bob.bytes), (3, 600));
+        assert_eq!(bob.months.keys().collect::<Vec<_>>(), ["2022-03", "2022-12"]);
+        assert_eq!((bob.types["jpg"].files, bob.types["jpg"].bytes), (2, 400));
+        assert_eq!(bob.top_tweets.len(), 1);
+        assert_eq!((bob.top_tweets[0].tweet_id.as_str(), bob.top_tweets[0].files), ("1600000000000000000", 2));
+
+        let reports = report(&dir, &["alice".to_string()], Timezone::default(), 5).unwrap();
+        assert_eq!(reports.len(), 1);
+        let mut csv = Vec::new();
+        write_report(&reports, ReportFormat::Csv, &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "username,group,key,files,bytes\n\
+                                                     alice,month,2022-12,1,50\n\
+                                                     alice,type,jpg,1,50\n\
+                                                     alice,tweet,1600000000000000000,1,50\n");
+    }
+
+    #[test]
+    fn sums_up_the_disk_usage() {
+        let dir = test_dir("disk-usage");
+        fs::create_dir_all(dir.join("alice/2022")).unwrap();
+        fs::write(dir.join("alice/a.jpg"), [0; 10]).unwrap();
+        fs::write(dir.join("alice/2022/b.jpg"), [0; 20]).unwrap();
+        fs::create_dir_all(dir.join("bob")).unwrap();
+        fs::write(dir.join("bob/c.jpg"), [0; 5]).unwrap();
+        fs::create_dir_all(dir.join(".trash")).unwrap();
+        fs::write(dir.join(".trash/d.jpg"), [0; 100]).unwrap();
+
+        let usage = disk_usage(&dir, &[], Timezone::default()).unwrap();
+        assert_eq!(usage.iter().map(|u| (u.username.as_str(), u.files, u.bytes)).collect::<Vec<_>>(), [("alice", 2, 30), ("bob", 1, 5)]);
+        assert_eq!(usage[0].bytes_per_month.values().sum::<u64>(), 30);
+
+        let usage = disk_usage(&dir, &["carol".to_string()], Timezone::default()).unwrap();
+        assert_eq!(usage.iter().map(|u| (u.username.as_str(), u.files, u.bytes)).collect::<Vec<_>>(), [("carol", 0, 0)]);
+    }
+}
@@ -0,0 +1,180 @@
+//! module to download the media of Tweets as they are posted, see `stream`.
+//!
+//! A rule `from:<user> has:media` per user is added to the filtered stream of the app, tagged `tmd:<user>`. Every
+//! Tweet of the stream is downloaded like the Tweets of a timeline, with the same layout, sidecars, manifest and state,
+//! so media deleted within minutes are kept. The checkpoints are left alone.
+//!
+//! The stream is reconnected after errors, waiting longer after every failed attempt. The rules are removed again when
+//! the stream ends, e.g. on Ctrl+C.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde_json::json;
+use tracing::{error, info, warn};
+use twitter_v2::TwitterApi;
+use twitter_v2::query::{MediaField, TweetExpansion, TweetField};
+
+use crate::capture;
+use crate::shutdown;
+use crate::twitter::api::TweetsPage;
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::{generate_media_map, UserRun};
+use crate::Config;
+
+/// Prefix of the tags of the rules added by `stream`, other rules of the app are left alone.
+pub const RULE_TAG_PREFIX: &str = "tmd:";
+
+/// Wait before the first reconnect, doubled after every failed attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest wait before a reconnect.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(320);
+
+/// Outcome of [stream](stream).
+#[derive(Debug, Default)]
+pub struct StreamReport {
+    /// Tweets received from the stream
+    pub tweets: u64,
+    /// media files downloaded
+    pub downloaded: u32,
+}
+
+/// Returns the rule of the filtered stream for the Tweets with media of `username`.
+pub fn rule(username: &str) -> String {
+    format!("from:{} has:media", username)
+}
+
+/// Downloads the media of the Tweets of the users of `configs` from the filtered stream of the app of `credentials`,
+/// until a shutdown is requested.
+pub async fn stream(credentials: &Credentials, configs: Vec<Config>) -> Result<StreamReport, DownloadError> {
+    let api = credentials.api();
+    // by user id, the stream tells the author by its id
+    let mut runs: HashMap<u64, UserRun> = HashMap::new();
+    for config in configs {
+        let run = UserRun::start(config).await?;
+        runs.insert(run.user_id(), run);
+    }
+
+    let usernames: Vec<String> = runs.values().map(|run| run.username().to_string()).collect();
+    add_rules(&api, &usernames).await?;
+    let report = read_stream(&api, &mut runs).await;
+    if let Err(e) = remove_rules(&api).await {
+        warn!("Cannot remove the rules of the stream: {}", e);
+    }
+    Ok(report)
+}
+
+/// Replaces the rules added before, e.g. by a stream that did not end cleanly, with the rules of `usernames`.
+async fn add_rules(api: &TwitterApi<Credentials>, usernames: &[String]) -> Result<(), DownloadError> {
+    remove_rules(api).await?;
+    let mut request = api.post_tweets_search_stream_rule();
+    for username in usernames {
+        request.add_tagged(rule(username), format!("{}{}", RULE_TAG_PREFIX, username));
+    }
+    request.send().await?;
+    info!("Streaming the Tweets of {} users: {}", usernames.len(), usernames.join(", "));
+    Ok(())
+}
+
+/// Removes the rules tagged with [RULE_TAG_PREFIX](RULE_TAG_PREFIX).
+async fn remove_rules(api: &TwitterApi<Credentials>) -> Result<(), DownloadError> {
+    let rules = api.get_tweets_search_stream_rules().send().await?.into_data().unwrap_or_default();
+    let ids: Vec<_> = rules.into_iter()
+        .filter(|rule| rule.tag.as_deref().is_some_and(|tag| tag.starts_with(RULE_TAG_PREFIX)))
+        .map(|rule| rule.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let mut request = api.post_tweets_search_stream_rule();
+    for id in ids {
+        request.delete_id(id);
+    }
+    request.send().await?;
+    Ok(())
+}
+
+/// Downloads the media of the Tweets of the stream with the run of their author, reconnecting until a shutdown is
+/// requested.
+async fn read_stream(api: &TwitterApi<Credentials>, runs: &mut HashMap<u64, UserRun>) -> StreamReport {
+    let mut report = StreamReport::default();
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown::is_requested() {
+        let connected = api.get_tweets_search_stream()
+            .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+            .tweet_fields(
+                [TweetField::AuthorId,
+                    TweetField::CreatedAt,
+                    TweetField::Attachments,
+                    TweetField::ConversationId,
+                    TweetField::Entities,
+                    TweetField::PublicMetrics,
+                    TweetField::PossiblySensitive,
+                    TweetField::ReferencedTweets,
+                    TweetField::Text
+                ])
+            .expansions([TweetExpansion::AttachmentsMediaKeys, ])
+            .stream()
+            .await;
+        let mut stream = match connected {
+            Ok(stream) => Box::pin(stream),
+            Err(e) => {
+                error!("Cannot connect to the stream: {}. Reconnecting in {}s", e, delay.as_secs());
+                if !shutdown::sleep(delay).await {
+                    break;
+                }
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+        info!("Connected to the stream");
+
+        loop {
+            let item = tokio::select! {
+                item = stream.next() => item,
+                _ = shutdown::requested() => return report,
+            };
+            let payload = match item {
+                Some(Ok(payload)) => payload,
+                Some(Err(e)) => {
+                    warn!("The stream failed: {}. Reconnecting in {}s", e, delay.as_secs());
+                    break;
+                }
+                None => {
+                    warn!("The stream ended. Reconnecting in {}s", delay.as_secs());
+                    break;
+                }
+            };
+            delay = RECONNECT_DELAY;
+            if capture::is_enabled() {
+                capture::record("stream", json!({ "endpoint": "GET /2/tweets/search/stream" }), json!({ "data": payload.data(), "includes": payload.includes() }));
+            }
+
+            let tweet = match payload.clone().into_data() {
+                Some(tweet) => tweet,
+                None => continue,
+            };
+            report.tweets += 1;
+            let run = match tweet.author_id.as_ref().and_then(|author_id| runs.get_mut(&author_id.as_u64())) {
+                Some(run) => run,
+                None => {
+                    warn!("tweet_id: {}, author_id: {:?}. Not by a streamed user, skipping", tweet.id, tweet.author_id);
+                    continue;
+                }
+            };
+            info!("username: {}, tweet_id: {}. Tweet posted", run.username(), tweet.id);
+            let page = TweetsPage { tweets: Some(vec![tweet]), media: generate_media_map(payload.into_includes()), meta: None, authors: HashMap::new() };
+            match run.download_tweets(&page).await {
+                Ok(count) => report.downloaded += count,
+                Err(e) => error!("username: {}. Cannot download the media of the tweet: {}", run.username(), e),
+            }
+        }
+        if !shutdown::sleep(delay).await {
+            break;
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+    report
+}
@@ -0,0 +1,140 @@
+//! module for the machine-readable summary of a download run and its exit code.
+//!
+//! The summary totals the downloaded, skipped and failed files and the bytes written, and lists every user with its
+//! final checkpoint and why its run stopped early, if it did. Scheduled runs can alert on the exit code alone:
+//!
+//! - 0: every user was downloaded
+//! - [EXIT_FAILURE](EXIT_FAILURE): the run could not start or a command failed
+//! - [EXIT_PARTIAL](EXIT_PARTIAL): some files or users failed, or `verify` found problems it did not repair
+//! - [EXIT_AUTH](EXIT_AUTH): the token was rejected
+//! - [EXIT_RATE_LIMITED](EXIT_RATE_LIMITED): the run ended waiting out a rate limit, e.g. on `--time-budget`
+//! - [EXIT_USAGE](EXIT_USAGE): invalid arguments or settings
+//! - [EXIT_EMPTY](EXIT_EMPTY): a user had no new media for `--fail-if-empty` consecutive runs
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::common::write_atomic;
+use crate::twitter::outcome::Cutoff;
+use crate::{DownloadError, DownloadReport};
+
+/// Exit code when the run could not start or a command failed.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Exit code when some files or users failed, or `verify` found files missing, damaged or absent from the mirror.
+pub const EXIT_PARTIAL: i32 = 2;
+
+/// Exit code when the token was rejected.
+pub const EXIT_AUTH: i32 = 3;
+
+/// Exit code when the run ended rate limited.
+pub const EXIT_RATE_LIMITED: i32 = 4;
+
+/// Exit code for invalid arguments or settings, EX_USAGE of sysexits.h.
+pub const EXIT_USAGE: i32 = 64;
+
+/// Exit code when a user had no new media for `--fail-if-empty` consecutive runs, EX_DATAERR of sysexits.h.
+pub const EXIT_EMPTY: i32 = 65;
+
+/// Summary of a download run over all its users.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub duration_secs: f64,
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    /// code the run exits with, see [exit_code](RunSummary::exit_code)
+    pub exit_code: i32,
+    pub users: Vec<UserResult>,
+}
+
+/// Outcome of the run of one user.
+#[derive(Debug, Serialize)]
+pub struct UserResult {
+    pub username: String,
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    /// id of the oldest Tweet processed after the run, None if the next run starts at the latest Tweet
+    pub checkpoint: Option<u64>,
+    /// why the run stopped early, None if it completed
+    pub cutoff: Option<Cutoff>,
+    /// the error that ended the run, if any
+    pub error: Option<String>,
+}
+
+impl UserResult {
+    fn from_result(username: &str, result: &Result<DownloadReport, DownloadError>) -> Self {
+        match result {
+            Ok(report) => UserResult {
+                username: report.username.clone(),
+                downloaded: report.downloaded.into(),
+                skipped: report.skipped,
+                failed: report.failed,
+                bytes: report.bytes,
+                checkpoint: report.checkpoint,
+                cutoff: report.cutoff,
+                error: None,
+            },
+            Err(e) => UserResult {
+                username: username.into(),
+                downloaded: 0,
+                skipped: 0,
+                failed: 0,
+                bytes: 0,
+                checkpoint: None,
+                cutoff: Some(Cutoff::of(e)),
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+impl RunSummary {
+    /// Sums up the `results` of the users of run `run_id`, a user result per username.
+    pub fn new(run_id: &str, duration: Duration, results: &[(String, Result<DownloadReport, DownloadError>)]) -> Self {
+        let users: Vec<UserResult> = results.iter().map(|(username, result)| UserResult::from_result(username, result)).collect();
+        let mut summary = RunSummary {
+            run_id: run_id.into(),
+            duration_secs: duration.as_secs_f64(),
+            downloaded: users.iter().map(|u| u.downloaded).sum(),
+            skipped: users.iter().map(|u| u.skipped).sum(),
+            failed: users.iter().map(|u| u.failed).sum(),
+            bytes: users.iter().map(|u| u.bytes).sum(),
+            exit_code: 0,
+            users,
+        };
+        summary.exit_code = summary.exit_code();
+        summary
+    }
+
+    /// Returns the exit code of the run. A rejected token wins over rate limiting, which wins over other failures.
+    pub fn exit_code(&self) -> i32 {
+        let cutoffs = || self.users.iter().filter_map(|u| u.cutoff);
+        if cutoffs().any(|c| c == Cutoff::Auth) {
+            EXIT_AUTH
+        } else if cutoffs().any(|c| c == Cutoff::RateLimited) {
+            EXIT_RATE_LIMITED
+        } else if self.failed > 0 || cutoffs().any(|c| c == Cutoff::Error || c == Cutoff::LowDiskSpace) {
+            EXIT_PARTIAL
+        } else {
+            0
+        }
+    }
+
+    /// Writes the summary as JSON to `path`, or to stdout if `path` is `-`.
+    pub fn write(&self, path: &Path) -> Result<(), io::Error> {
+        let json = serde_json::to_vec_pretty(self)?;
+        if path == Path::new("-") {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&json)?;
+            return writeln!(stdout);
+        }
+        write_atomic(path, &json)
+    }
+}
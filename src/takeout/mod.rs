@@ -0,0 +1,126 @@
+//! module to package a user's archive into a single self-contained zip "takeout".
+//!
+//! The takeout holds the user's files, an `ATTRIBUTION.txt` and an offline `index.html` gallery. The files of the
+//! downloader itself, like the lock, partial downloads and the trash, are left out.
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tracing::info;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::common::collect_files;
+use crate::state::{LEGACY_CHECKPOINT_FILENAME, STATE_FILENAME};
+
+/// Extensions shown as images in the gallery.
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Extensions shown as videos in the gallery.
+const VIDEO_EXTENSIONS: [&str; 2] = ["mp4", "mov"];
+
+/// Characters of a path segment left as they are in the links of the gallery.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// Writes the takeout of `username` as `<output_dir>/<username>-takeout-<date>.zip`.
+///
+/// Returns the path of the takeout.
+pub fn create_takeout(output_dir: &Path, username: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let user_output_dir = output_dir.join(username);
+    if !user_output_dir.is_dir() {
+        return Err(format!("username: {}. Nothing archived under {}", username, user_output_dir.display()).into());
+    }
+
+    let mut files = Vec::new();
+    collect_files(&user_output_dir, &user_output_dir, &mut files)?;
+    files.retain(|file| !is_internal(file));
+    files.sort();
+
+    let now = OffsetDateTime::now_utc();
+    let date = now.format(format_description!("[year][month][day]"))?;
+    let takeout_path = output_dir.join(format!("{}-takeout-{}.zip", username, date));
+
+    let mut zip = ZipWriter::new(File::create(&takeout_path)?);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for file in files.iter() {
+        let options = if has_extension(file, &IMAGE_EXTENSIONS) || has_extension(file, &VIDEO_EXTENSIONS) { stored } else { deflated };
+        zip.start_file(format!("{}/{}", username, file), options)?;
+        io::copy(&mut File::open(user_output_dir.join(file))?, &mut zip)?;
+    }
+
+    zip.start_file(format!("{}/ATTRIBUTION.txt", username), deflated)?;
+    zip.write_all(attribution(username, &now, files.len()).as_bytes())?;
+
+    zip.start_file(format!("{}/index.html", username), deflated)?;
+    zip.write_all(gallery(username, &files).as_bytes())?;
+
+    zip.finish()?;
+
+    info!("username: {}, takeout: {}. {} files packaged", username, takeout_path.display(), files.len());
+    Ok(takeout_path)
+}
+
+fn attribution(username: &str, created: &OffsetDateTime, files: usize) -> String {
+    format!("All media in this archive was posted by @{} on Twitter: https://twitter.com/{}\n\
+             Copyright remains with the original author.\n\n\
+             {} files, packaged {} by {} {}.\n",
+            username, username, files, created, env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// Offline HTML gallery of the images and videos in `files`, newest file names first.
+fn gallery(username: &str, files: &[String]) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>@{}</title>\n", escape_html(username)));
+    html.push_str("<style>body{background:#111;color:#eee;font-family:sans-serif}\
+                   .grid{display:flex;flex-wrap:wrap;gap:8px}\
+                   .grid a img,.grid video{height:240px;object-fit:cover}</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>@{}</h1>\n<div class=\"grid\">\n", escape_html(username)));
+    for file in files.iter().rev() {
+        let src = escape_html(&encode_path(file));
+        if has_extension(file, &IMAGE_EXTENSIONS) {
+            html.push_str(&format!("<a href=\"{}\"><img src=\"{}\" loading=\"lazy\" alt=\"{}\"></a>\n", src, src, src));
+        } else if has_extension(file, &VIDEO_EXTENSIONS) {
+            html.push_str(&format!("<video src=\"{}\" controls preload=\"none\"></video>\n", src));
+        }
+    }
+    html.push_str("</div>\n</body>\n</html>\n");
+    html
+}
+
+fn has_extension(file: &str, extensions: &[&str]) -> bool {
+    match Path::new(file).extension().and_then(|e| e.to_str()) {
+        Some(ext) => extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)),
+        None => false,
+    }
+}
+
+/// Returns true for the files of the downloader: hidden files like the lock and the trash, partial downloads, files
+/// being written and the state.
+fn is_internal(file: &str) -> bool {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    file.split('/').any(|part| part.starts_with('.'))
+        || name.ends_with(".part")
+        || name.rsplit_once(".tmp").is_some_and(|(_, pid)| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()))
+        || name == STATE_FILENAME
+        || name == LEGACY_CHECKPOINT_FILENAME
+}
+
+/// Returns the `/` separated `path` with its segments percent-encoded, for an href.
+fn encode_path(path: &str) -> String {
+    path.split('/').map(|part| utf8_percent_encode(part, PATH_SEGMENT).to_string()).collect::<Vec<_>>().join("/")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,230 @@
+//! module to quarantine removed media files for a while instead of deleting them right away.
+//!
+//! Quarantined files are moved to `<output_dir>/.trash/<media_key>/`, next to a `trash.json` recording where they
+//! came from and when the entry expires. `restore` moves them back and takes the media key off the
+//! do-not-redownload list. Expired entries are purged by later `forget`, `restore` and `download` runs.
+use std::error::Error;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::clock;
+use crate::common::write_atomic;
+use crate::state::StateStore;
+
+/// Directory of the trash under the output directory.
+pub const TRASH_DIR: &str = ".trash";
+
+/// Name of the entry file in the trash directory of a media key.
+const ENTRY_FILENAME: &str = "trash.json";
+
+/// How long quarantined files are kept by default: 30 days.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The quarantined files of a media key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub media_key: String,
+    /// unix time the files were quarantined
+    pub trashed_at: u64,
+    /// unix time from which the files may be purged
+    pub expires_at: u64,
+    pub files: Vec<TrashedFile>,
+}
+
+/// A quarantined file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedFile {
+    /// where the file was
+    pub original: PathBuf,
+    /// name of the file in the trash directory of its media key
+    pub name: String,
+}
+
+/// Returns the trash directory of `media_key` under `output_dir`.
+fn entry_dir(output_dir: &Path, media_key: &str) -> PathBuf {
+    output_dir.join(TRASH_DIR).join(media_key)
+}
+
+fn read_entry(dir: &Path) -> Result<TrashEntry, io::Error> {
+    let contents = fs::read(dir.join(ENTRY_FILENAME))?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+fn write_entry(dir: &Path, entry: &TrashEntry) -> Result<(), io::Error> {
+    write_atomic(&dir.join(ENTRY_FILENAME), &serde_json::to_vec_pretty(entry)?)
+}
+
+/// Moves `from` to `to`, copying across file systems.
+fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Moves the existing `paths` of `media_key` into the trash, kept for `retention`.
+///
+/// Quarantining a media key again adds to its entry and restarts the expiry.
+///
+/// Returns the number of files moved.
+pub fn quarantine(output_dir: &Path, media_key: &str, paths: &[PathBuf], retention: Duration) -> Result<usize, io::Error> {
+    let dir = entry_dir(output_dir, media_key);
+    DirBuilder::new().recursive(true).create(&dir)?;
+    let now = clock::unix_now();
+    let mut entry = read_entry(&dir).unwrap_or_else(|_| TrashEntry {
+        media_key: media_key.into(),
+        trashed_at: now,
+        expires_at: now,
+        files: Vec::new(),
+    });
+    entry.expires_at = now + retention.as_secs();
+
+    let mut moved = 0;
+    for path in paths.iter().filter(|p| p.is_file()) {
+        let filename = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = format!("{}-{}", entry.files.len() + 1, filename);
+        move_file(path, &dir.join(&name))?;
+        info!("media_key: {}, local: {}. Quarantined", media_key, path.display());
+        entry.files.push(TrashedFile { original: path.clone(), name });
+        moved += 1;
+        // written after every file, so the entry never misses a moved file
+        write_entry(&dir, &entry)?;
+    }
+    if moved == 0 && entry.files.is_empty() {
+        fs::remove_dir_all(&dir)?;
+    }
+    Ok(moved)
+}
+
+/// Moves the quarantined files of `media_key` back and takes it off the do-not-redownload list.
+///
+/// Fails without moving anything if a file was downloaded again in the meantime.
+///
+/// Returns the number of files restored.
+pub fn restore(output_dir: &Path, media_key: &str) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    purge_expired(output_dir)?;
+    let dir = entry_dir(output_dir, media_key);
+    let entry = read_entry(&dir).map_err(|e| format!("media_key: {}. Nothing quarantined: {}", media_key, e))?;
+    if let Some(taken) = entry.files.iter().find(|f| f.original.exists()) {
+        return Err(format!("media_key: {}. {} exists, move it away to restore", media_key, taken.original.display()).into());
+    }
+
+    for file in entry.files.iter() {
+        if let Some(parent) = file.original.parent() {
+            DirBuilder::new().recursive(true).create(parent)?;
+        }
+        move_file(&dir.join(&file.name), &file.original)?;
+        info!("media_key: {}, local: {}. Restored", media_key, file.original.display());
+    }
+    fs::remove_dir_all(&dir)?;
+
+    StateStore::open(output_dir)?.unforget(media_key)?;
+    Ok(entry.files.len())
+}
+
+/// Returns the entries of the trash under `output_dir`, oldest first.
+pub fn entries(output_dir: &Path) -> Result<Vec<TrashEntry>, io::Error> {
+    let trash = output_dir.join(TRASH_DIR);
+    if !trash.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for dir in fs::read_dir(trash)? {
+        let dir = dir?.path();
+        if let Ok(entry) = read_entry(&dir) {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|e| e.trashed_at);
+    Ok(entries)
+}
+
+/// Deletes the expired entries of the trash under `output_dir`.
+///
+/// Returns the number of entries deleted.
+pub fn purge_expired(output_dir: &Path) -> Result<usize, io::Error> {
+    let now = clock::unix_now();
+    let mut purged = 0;
+    for entry in entries(output_dir)?.into_iter().filter(|e| e.expires_at <= now) {
+        fs::remove_dir_all(entry_dir(output_dir, &entry.media_key))?;
+        info!("media_key: {}. Quarantine expired, {} files deleted", entry.media_key, entry.files.len());
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-trash-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn quarantines_and_restores_media() {
+        let dir = test_dir("restore");
+        let state = StateStore::open(&dir).unwrap();
+        state.forget("3_1").unwrap();
+        drop(state);
+        fs::create_dir_all(dir.join("alice")).unwrap();
+        let image = dir.join("alice/a.jpg");
+        let sidecar = dir.join("alice/a.jpg.json");
+        fs::write(&image, "image").unwrap();
+        fs::write(&sidecar, "{}").unwrap();
+
+        let moved = quarantine(&dir, "3_1", &[image.clone(), sidecar.clone(), dir.join("alice/missing.jpg")], DEFAULT_RETENTION).unwrap();
+        assert_eq!(moved, 2);
+        assert!(!image.exists() && !sidecar.exists());
+        let trashed = entries(&dir).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].media_key, "3_1");
+        assert_eq!(trashed[0].files.iter().map(|f| f.original.clone()).collect::<Vec<_>>(), [image.clone(), sidecar.clone()]);
+
+        assert_eq!(restore(&dir, "3_1").unwrap(), 2);
+        assert_eq!(fs::read_to_string(&image).unwrap(), "image");
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), "{}");
+        assert!(entries(&dir).unwrap().is_empty());
+        assert!(!StateStore::open(&dir).unwrap().is_forgotten("3_1").unwrap());
+
+        assert!(restore(&dir, "3_1").is_err());
+    }
+
+    #[test]
+    fn restores_nothing_over_downloaded_again_files() {
+        let dir = test_dir("taken");
+        let image = dir.join("a.jpg");
+        fs::write(&image, "old").unwrap();
+        quarantine(&dir, "3_1", std::slice::from_ref(&image), DEFAULT_RETENTION).unwrap();
+        fs::write(&image, "new").unwrap();
+
+        assert!(restore(&dir, "3_1").is_err());
+        assert_eq!(fs::read_to_string(&image).unwrap(), "new");
+        assert_eq!(entries(&dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn purges_expired_entries() {
+        let dir = test_dir("purge");
+        let (kept, expired) = (dir.join("kept.jpg"), dir.join("expired.jpg"));
+        fs::write(&kept, "kept").unwrap();
+        fs::write(&expired, "expired").unwrap();
+        quarantine(&dir, "3_1", &[kept], DEFAULT_RETENTION).unwrap();
+        quarantine(&dir, "3_2", &[expired], Duration::ZERO).unwrap();
+        // nothing to move leaves no entry
+        assert_eq!(quarantine(&dir, "3_3", &[dir.join("missing.jpg")], DEFAULT_RETENTION).unwrap(), 0);
+
+        assert_eq!(purge_expired(&dir).unwrap(), 1);
+        assert_eq!(entries(&dir).unwrap().iter().map(|e| e.media_key.as_str()).collect::<Vec<_>>(), ["3_1"]);
+        assert!(!dir.join(TRASH_DIR).join("3_2").exists() && !dir.join(TRASH_DIR).join("3_3").exists());
+    }
+}
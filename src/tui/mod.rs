@@ -0,0 +1,358 @@
+//! module for the terminal dashboard of `--tui`, in place of the progress bars.
+//!
+//! The dashboard shows every user with its state and counts, the downloads in flight with their speed, the rate
+//! limit countdowns and the recent warnings and errors of the log. Keys:
+//!
+//! - up/down: select a user
+//! - p: pause or resume the selected user, after its current page
+//! - s: skip the selected user, after its current page, its checkpoint written
+//! - q or Ctrl+C: stop the run like Ctrl+C, a second time exits immediately
+//!
+//! The log lines go to the dashboard while it is shown, see [log](log).
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Row, Table};
+use ratatui::Terminal;
+
+use crate::shutdown;
+use crate::stats::format_bytes;
+
+/// Redraw interval.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Number of log lines kept.
+const LOG_LINES: usize = 200;
+
+/// Widths of the columns of the users.
+const USER_WIDTHS: [Constraint; 4] = [Constraint::Percentage(35), Constraint::Percentage(35), Constraint::Percentage(15), Constraint::Percentage(15)];
+
+/// State of a user on the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserState {
+    Waiting,
+    Running,
+    Done,
+    Failed,
+}
+
+struct UserView {
+    username: String,
+    state: UserState,
+    files: u64,
+    bytes: u64,
+    paused: bool,
+    skipped: bool,
+    rate_limited_until: Option<Instant>,
+}
+
+struct DownloadView {
+    id: u64,
+    name: String,
+    len: Option<u64>,
+    received: u64,
+    started: Instant,
+}
+
+struct Dashboard {
+    users: Mutex<Vec<UserView>>,
+    downloads: Mutex<Vec<DownloadView>>,
+    log: Mutex<VecDeque<String>>,
+    selected: AtomicUsize,
+    next_download: AtomicU64,
+    started: Instant,
+    active: AtomicBool,
+}
+
+static DASHBOARD: OnceLock<Dashboard> = OnceLock::new();
+
+/// Returns the dashboard while it is shown.
+fn dashboard() -> Option<&'static Dashboard> {
+    DASHBOARD.get().filter(|d| d.active.load(Ordering::Relaxed))
+}
+
+/// Shows the dashboard for `usernames`. Returns false, showing nothing, if stdout is not a terminal.
+pub fn install(usernames: &[String]) -> bool {
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    let users = usernames.iter().map(|username| UserView {
+        username: username.clone(),
+        state: UserState::Waiting,
+        files: 0,
+        bytes: 0,
+        paused: false,
+        skipped: false,
+        rate_limited_until: None,
+    }).collect();
+    let installed = DASHBOARD.set(Dashboard {
+        users: Mutex::new(users),
+        downloads: Mutex::new(Vec::new()),
+        log: Mutex::new(VecDeque::new()),
+        selected: AtomicUsize::new(0),
+        next_download: AtomicU64::new(0),
+        started: Instant::now(),
+        active: AtomicBool::new(true),
+    }).is_ok();
+    if !installed || enable_raw_mode().is_err() || execute!(io::stdout(), EnterAlternateScreen).is_err() {
+        return false;
+    }
+    thread::spawn(run);
+    true
+}
+
+/// Hides the dashboard and gives the terminal back.
+pub fn finish() {
+    if let Some(dashboard) = dashboard() {
+        dashboard.active.store(false, Ordering::Relaxed);
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Draws the dashboard and handles the keys until it is finished.
+fn run() {
+    let mut terminal = match Terminal::new(CrosstermBackend::new(io::stdout())) {
+        Ok(terminal) => terminal,
+        Err(_) => return,
+    };
+    while let Some(dashboard) = dashboard() {
+        let _ = terminal.draw(|frame| {
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(1), Constraint::Percentage(40), Constraint::Percentage(25), Constraint::Min(5)])
+                .split(frame.size());
+            frame.render_widget(Paragraph::new(header(dashboard)), areas[0]);
+            frame.render_widget(users_table(dashboard), areas[1]);
+            frame.render_widget(downloads_list(dashboard), areas[2]);
+            frame.render_widget(log_list(dashboard, areas[3].height as usize), areas[3]);
+        });
+
+        if event::poll(TICK).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Up => select(dashboard, -1),
+                    KeyCode::Down => select(dashboard, 1),
+                    KeyCode::Char('p') => toggle_pause(dashboard),
+                    KeyCode::Char('s') => skip(dashboard),
+                    KeyCode::Char('q') => quit(),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => quit(),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+fn header(dashboard: &Dashboard) -> String {
+    let users = dashboard.users.lock().unwrap_or_else(|e| e.into_inner());
+    let files: u64 = users.iter().map(|u| u.files).sum();
+    let bytes: u64 = users.iter().map(|u| u.bytes).sum();
+    let mut header = format!("twitter-media-downloader  {}s  {} files, {}  [up/down] select [p] pause/resume [s] skip [q] quit",
+                             dashboard.started.elapsed().as_secs(), files, format_bytes(bytes));
+    if shutdown::is_requested() {
+        header.push_str("  - stopping");
+    }
+    header
+}
+
+fn users_table(dashboard: &Dashboard) -> Table<'static> {
+    let users = dashboard.users.lock().unwrap_or_else(|e| e.into_inner());
+    let selected = dashboard.selected.load(Ordering::Relaxed);
+    let rows: Vec<Row> = users.iter().enumerate().map(|(i, user)| {
+        let state = match (user.state, user.rate_limited_until) {
+            (UserState::Running, Some(until)) if until > Instant::now() => format!("rate limited, {}s", (until - Instant::now()).as_secs()),
+            (_, _) if user.skipped && user.state == UserState::Running => "skipping".into(),
+            (_, _) if user.paused => "paused".into(),
+            (state, _) => format!("{:?}", state).to_lowercase(),
+        };
+        let style = if i == selected { Style::default().add_modifier(Modifier::REVERSED) } else { Style::default() };
+        Row::new(vec![user.username.clone(), state, user.files.to_string(), format_bytes(user.bytes)]).style(style)
+    }).collect();
+    Table::new(rows)
+        .header(Row::new(vec!["USER", "STATE", "FILES", "SIZE"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title("Users"))
+        .widths(&USER_WIDTHS)
+}
+
+fn downloads_list(dashboard: &Dashboard) -> List<'static> {
+    let downloads = dashboard.downloads.lock().unwrap_or_else(|e| e.into_inner());
+    let items: Vec<ListItem> = downloads.iter().map(|download| {
+        let speed = download.received as f64 / download.started.elapsed().as_secs_f64().max(0.001);
+        let total = download.len.map(format_bytes).unwrap_or_else(|| "?".into());
+        ListItem::new(format!("{}  {}/{}  {}/s", download.name, format_bytes(download.received), total, format_bytes(speed as u64)))
+    }).collect();
+    List::new(items).block(Block::default().borders(Borders::ALL).title("Downloads"))
+}
+
+fn log_list(dashboard: &Dashboard, height: usize) -> List<'static> {
+    let log = dashboard.log.lock().unwrap_or_else(|e| e.into_inner());
+    let items: Vec<ListItem> = log.iter().rev().take(height.saturating_sub(2)).rev().map(|line| {
+        let style = if line.contains("ERROR") {
+            Style::default().fg(Color::Red)
+        } else if line.contains("WARN") {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        ListItem::new(line.clone()).style(style)
+    }).collect();
+    List::new(items).block(Block::default().borders(Borders::ALL).title("Log"))
+}
+
+fn select(dashboard: &Dashboard, step: isize) {
+    let len = dashboard.users.lock().unwrap_or_else(|e| e.into_inner()).len();
+    if len > 0 {
+        let selected = dashboard.selected.load(Ordering::Relaxed) as isize;
+        dashboard.selected.store((selected + step).rem_euclid(len as isize) as usize, Ordering::Relaxed);
+    }
+}
+
+fn toggle_pause(dashboard: &Dashboard) {
+    let mut users = dashboard.users.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(user) = users.get_mut(dashboard.selected.load(Ordering::Relaxed)) {
+        user.paused = !user.paused;
+    }
+}
+
+fn skip(dashboard: &Dashboard) {
+    let mut users = dashboard.users.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(user) = users.get_mut(dashboard.selected.load(Ordering::Relaxed)) {
+        user.skipped = true;
+        user.paused = false;
+    }
+}
+
+fn quit() {
+    if shutdown::is_requested() {
+        finish();
+        std::process::exit(shutdown::EXIT_INTERRUPTED);
+    }
+    shutdown::request();
+}
+
+fn with_user(username: &str, f: impl FnOnce(&mut UserView)) {
+    if let Some(dashboard) = dashboard() {
+        let mut users = dashboard.users.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(user) = users.iter_mut().find(|u| u.username == username) {
+            f(user);
+        }
+    }
+}
+
+/// Shows `username` in `state`.
+pub fn set_state(username: &str, state: UserState) {
+    with_user(username, |user| user.state = state);
+}
+
+/// Counts a downloaded file of `size` bytes of `username`.
+pub fn file_downloaded(username: &str, size: u64) {
+    with_user(username, |user| {
+        user.files += 1;
+        user.bytes += size;
+    });
+}
+
+/// Shows the countdown of a rate limit wait of `username` for `duration`.
+pub fn rate_limited(username: &str, duration: Duration) {
+    with_user(username, |user| user.rate_limited_until = Some(Instant::now() + duration));
+}
+
+/// Returns true while `username` is paused on the dashboard.
+pub fn is_paused(username: &str) -> bool {
+    let mut paused = false;
+    with_user(username, |user| paused = user.paused);
+    paused
+}
+
+/// Returns true if `username` was skipped on the dashboard.
+pub fn is_skipped(username: &str) -> bool {
+    let mut skipped = false;
+    with_user(username, |user| skipped = user.skipped);
+    skipped
+}
+
+/// A download shown on the dashboard, removed when dropped.
+pub struct DownloadHandle(u64);
+
+impl DownloadHandle {
+    /// Adds `bytes` received.
+    pub fn inc(&self, bytes: u64) {
+        if let Some(dashboard) = dashboard() {
+            let mut downloads = dashboard.downloads.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(download) = downloads.iter_mut().find(|d| d.id == self.0) {
+                download.received += bytes;
+            }
+        }
+    }
+}
+
+impl Drop for DownloadHandle {
+    fn drop(&mut self) {
+        if let Some(dashboard) = dashboard() {
+            dashboard.downloads.lock().unwrap_or_else(|e| e.into_inner()).retain(|d| d.id != self.0);
+        }
+    }
+}
+
+/// Shows the download of `name`, `len` bytes if known, `offset` bytes of it there already.
+pub fn download(name: &str, len: Option<u64>, offset: u64) -> Option<DownloadHandle> {
+    let dashboard = dashboard()?;
+    let id = dashboard.next_download.fetch_add(1, Ordering::Relaxed);
+    dashboard.downloads.lock().unwrap_or_else(|e| e.into_inner()).push(DownloadView {
+        id,
+        name: name.into(),
+        len,
+        received: offset,
+        started: Instant::now(),
+    });
+    Some(DownloadHandle(id))
+}
+
+/// Adds the log output `buf` to the dashboard. Returns false if the dashboard is not shown.
+pub fn log(buf: &[u8]) -> bool {
+    let dashboard = match dashboard() {
+        Some(dashboard) => dashboard,
+        None => return false,
+    };
+    let mut log = dashboard.log.lock().unwrap_or_else(|e| e.into_inner());
+    for line in String::from_utf8_lossy(buf).lines().filter(|line| !line.trim().is_empty()) {
+        if log.len() == LOG_LINES {
+            log.pop_front();
+        }
+        log.push_back(strip_ansi(line));
+    }
+    true
+}
+
+/// Returns `line` without the color codes of the log.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip the escape sequence up to its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
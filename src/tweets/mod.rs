@@ -0,0 +1,27 @@
+//! module to archive the Tweets themselves.
+//!
+//! Every processed Tweet is appended to `<user>/tweets.ndjson` as returned by the API, one JSON object per line,
+//! so the metadata can be rebuilt later without the API.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use twitter_v2::Tweet;
+
+/// Name of the per user Tweets file.
+pub const TWEETS_FILENAME: &str = "tweets.ndjson";
+
+/// Appends `tweets` to the Tweets file in `user_output_dir`.
+pub fn append_tweets<'a>(user_output_dir: &Path, tweets: impl IntoIterator<Item = &'a Tweet>) -> Result<(), io::Error> {
+    let mut lines = String::new();
+    for tweet in tweets {
+        lines.push_str(&serde_json::to_string(tweet)?);
+        lines.push('\n');
+    }
+
+    if !lines.is_empty() {
+        let mut file = OpenOptions::new().create(true).append(true).open(user_output_dir.join(TWEETS_FILENAME))?;
+        file.write_all(lines.as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,414 @@
+//! The source of the Tweets of a run: the Twitter API, or Tweets held in memory.
+//!
+//! A [UserRun](crate::twitter::UserRun) only looks up the user and its pinned Tweet, walks the pages of its Tweets and,
+//! with `--include-thread`, fetches its threads through [TweetSource](TweetSource), so runs can be driven by a [MemorySource](MemorySource) without the API, e.g. in tests.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde_json::json;
+use twitter_v2::{Media, Tweet, TwitterApi};
+use twitter_v2::query::{Exclude, MediaField, TweetExpansion, TweetField, UserField};
+
+/// Most Tweets the API looks up at once.
+const LOOKUP_LIMIT: usize = 100;
+
+use crate::capture;
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::generate_media_map;
+
+/// Request of a page of the Tweets of a user, newest first. Replies and Retweets are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageRequest {
+    pub user_id: u64,
+    pub max_results: u8,
+    /// only Tweets older than this one
+    pub until_id: Option<u64>,
+    /// only Tweets newer than this one
+    pub since_id: Option<u64>,
+    /// token of the page, None for the first one
+    pub pagination_token: Option<String>,
+}
+
+/// Ids and pagination of a [TweetsPage](TweetsPage).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageMeta {
+    pub oldest_id: Option<String>,
+    pub newest_id: Option<String>,
+    /// token of the next page, None on the last one
+    pub next_token: Option<String>,
+}
+
+/// A page of Tweets with their media, by media key.
+#[derive(Debug, Clone, Default)]
+pub struct TweetsPage {
+    /// None if there are no Tweets in the range
+    pub tweets: Option<Vec<Tweet>>,
+    pub media: HashMap<String, Media>,
+    /// None if the API answered without one
+    pub meta: Option<PageMeta>,
+    /// usernames of the authors of the Tweets by user id, if looked up
+    pub authors: HashMap<u64, String>,
+}
+
+/// Where the Tweets of the users come from.
+#[async_trait]
+pub trait TweetSource: Send + Sync {
+    /// Returns the id of the user `username`, or [DownloadError::UserNotFound](DownloadError::UserNotFound).
+    async fn get_user_id(&self, username: &str) -> Result<u64, DownloadError>;
+
+    /// Returns the page of Tweets of `request`.
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError>;
+
+    /// Returns the Tweets of the user `user_id` in the conversation `conversation_id`, its self-thread, oldest first.
+    async fn fetch_thread(&self, user_id: u64, conversation_id: u64) -> Result<TweetsPage, DownloadError>;
+
+    /// Returns the pinned Tweet of the user `user_id`, no Tweets if there is none.
+    async fn fetch_pinned_tweet(&self, user_id: u64) -> Result<TweetsPage, DownloadError>;
+
+    /// Returns the Tweets `ids` that exist, with the usernames of their authors.
+    async fn fetch_tweets(&self, ids: &[u64]) -> Result<TweetsPage, DownloadError>;
+}
+
+#[async_trait]
+impl TweetSource for TwitterApi<Credentials> {
+    async fn get_user_id(&self, username: &str) -> Result<u64, DownloadError> {
+        let response = self.get_user_by_username(username)
+            .send()
+            .await;
+        if capture::is_enabled() {
+            let captured = match &response {
+                Ok(user) => json!({ "data": user.data() }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            capture::record("user-lookup", json!({ "endpoint": "GET /2/users/by/username/:username", "username": username }), captured);
+        }
+        let user = response?;
+
+        match user.into_data().map(|data| data.id.as_u64()) {
+            Some(id) if id > 0 => Ok(id),
+            _ => Err(DownloadError::UserNotFound(username.into())),
+        }
+    }
+
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError> {
+        let mut req_tweets = self.get_user_tweets(request.user_id);
+
+        req_tweets
+            .max_results(request.max_results.into())
+            .exclude([Exclude::Replies, Exclude::Retweets])
+            .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+            .tweet_fields(
+                [TweetField::AuthorId,
+                    TweetField::CreatedAt,
+                    TweetField::Attachments,
+                    TweetField::ConversationId,
+                    TweetField::Entities,
+                    TweetField::PublicMetrics,
+                    TweetField::PossiblySensitive,
+                    TweetField::ReferencedTweets,
+                    TweetField::Text
+                ])
+            .expansions([TweetExpansion::AttachmentsMediaKeys, ]);
+
+        if let Some(until_id) = request.until_id {
+            req_tweets.until_id(until_id);
+        }
+        if let Some(since_id) = request.since_id {
+            req_tweets.since_id(since_id);
+        }
+        if let Some(token) = &request.pagination_token {
+            req_tweets.pagination_token(token);
+        }
+
+        let response = req_tweets.send().await;
+        if capture::is_enabled() {
+            let captured_request = json!({
+                "endpoint": "GET /2/users/:id/tweets",
+                "id": request.user_id,
+                "max_results": request.max_results,
+                "until_id": request.until_id,
+                "since_id": request.since_id,
+                "pagination_token": request.pagination_token,
+            });
+            let captured = match &response {
+                Ok(tweets) => json!({ "data": tweets.data(), "includes": tweets.includes(), "meta": tweets.meta() }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            capture::record("user-tweets", captured_request, captured);
+        }
+        let tweets_response = response?;
+        let meta = tweets_response.clone().into_meta().map(|meta| PageMeta {
+            oldest_id: meta.oldest_id,
+            newest_id: meta.newest_id,
+            next_token: meta.next_token,
+        });
+        Ok(TweetsPage {
+            tweets: tweets_response.clone().into_data(),
+            media: generate_media_map(tweets_response.into_includes()),
+            meta,
+            authors: HashMap::new(),
+        })
+    }
+
+    /// Searches the recent Tweets, of the last 7 days, page by page.
+    async fn fetch_thread(&self, user_id: u64, conversation_id: u64) -> Result<TweetsPage, DownloadError> {
+        let query = format!("conversation_id:{} from:{}", conversation_id, user_id);
+        let mut thread = TweetsPage::default();
+        let mut next_token: Option<String> = None;
+        loop {
+            let mut req_tweets = self.get_tweets_search_recent(&query);
+            req_tweets
+                .max_results(100)
+                .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+                .tweet_fields(
+                    [TweetField::AuthorId,
+                        TweetField::CreatedAt,
+                        TweetField::Attachments,
+                        TweetField::ConversationId,
+                        TweetField::Entities,
+                        TweetField::PublicMetrics,
+                        TweetField::PossiblySensitive,
+                        TweetField::ReferencedTweets,
+                        TweetField::Text
+                    ])
+                .expansions([TweetExpansion::AttachmentsMediaKeys, ]);
+            // the search endpoints take the next_token of a response as pagination_token
+            if let Some(token) = &next_token {
+                req_tweets.pagination_token(token);
+            }
+
+            let response = req_tweets.send().await;
+            if capture::is_enabled() {
+                let captured = match &response {
+                    Ok(tweets) => json!({ "data": tweets.data(), "includes": tweets.includes(), "meta": tweets.meta() }),
+                    Err(e) => json!({ "error": e.to_string() }),
+                };
+                capture::record("thread-search", json!({ "endpoint": "GET /2/tweets/search/recent", "query": query, "next_token": next_token }), captured);
+            }
+            let tweets_response = response?;
+            next_token = tweets_response.meta().and_then(|meta| meta.next_token.clone());
+            thread.tweets.get_or_insert_with(Vec::new).extend(tweets_response.clone().into_data().unwrap_or_default());
+            thread.media.extend(generate_media_map(tweets_response.into_includes()));
+            if next_token.is_none() {
+                break;
+            }
+        }
+        if let Some(tweets) = thread.tweets.as_mut() {
+            tweets.sort_by_key(|tweet| tweet.id.as_u64());
+        }
+        Ok(thread)
+    }
+
+    async fn fetch_pinned_tweet(&self, user_id: u64) -> Result<TweetsPage, DownloadError> {
+        let user = self.get_user(user_id)
+            .user_fields([UserField::PinnedTweetId])
+            .send()
+            .await?;
+        let pinned_tweet_id = match user.into_data().and_then(|data| data.pinned_tweet_id) {
+            Some(id) => id,
+            None => return Ok(TweetsPage::default()),
+        };
+
+        let response = self.get_tweet(pinned_tweet_id)
+            .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+            .tweet_fields(
+                [TweetField::AuthorId,
+                    TweetField::CreatedAt,
+                    TweetField::Attachments,
+                    TweetField::ConversationId,
+                    TweetField::Entities,
+                    TweetField::PublicMetrics,
+                    TweetField::PossiblySensitive,
+                    TweetField::ReferencedTweets,
+                    TweetField::Text
+                ])
+            .expansions([TweetExpansion::AttachmentsMediaKeys, ])
+            .send()
+            .await;
+        if capture::is_enabled() {
+            let captured = match &response {
+                Ok(tweet) => json!({ "data": tweet.data(), "includes": tweet.includes() }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            capture::record("pinned-tweet", json!({ "endpoint": "GET /2/tweets/:id", "id": pinned_tweet_id.as_u64() }), captured);
+        }
+        let tweet_response = response?;
+        Ok(TweetsPage {
+            tweets: tweet_response.clone().into_data().map(|tweet| vec![tweet]),
+            media: generate_media_map(tweet_response.into_includes()),
+            meta: None,
+            authors: HashMap::new(),
+        })
+    }
+
+    async fn fetch_tweets(&self, ids: &[u64]) -> Result<TweetsPage, DownloadError> {
+        let mut page = TweetsPage::default();
+        for chunk in ids.chunks(LOOKUP_LIMIT) {
+            let response = self.get_tweets(chunk.to_vec())
+                .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+                .tweet_fields(
+                    [TweetField::AuthorId,
+                        TweetField::CreatedAt,
+                        TweetField::Attachments,
+                        TweetField::ConversationId,
+                        TweetField::Entities,
+                        TweetField::PublicMetrics,
+                        TweetField::PossiblySensitive,
+                        TweetField::ReferencedTweets,
+                        TweetField::Text
+                    ])
+                .expansions([TweetExpansion::AttachmentsMediaKeys, TweetExpansion::AuthorId])
+                .send()
+                .await;
+            if capture::is_enabled() {
+                let captured = match &response {
+                    Ok(tweets) => json!({ "data": tweets.data(), "includes": tweets.includes() }),
+                    Err(e) => json!({ "error": e.to_string() }),
+                };
+                capture::record("tweets-lookup", json!({ "endpoint": "GET /2/tweets", "ids": chunk }), captured);
+            }
+            let tweets_response = response?;
+            if let Some(users) = tweets_response.includes().and_then(|includes| includes.users.as_ref()) {
+                page.authors.extend(users.iter().map(|user| (user.id.as_u64(), user.username.clone())));
+            }
+            page.tweets.get_or_insert_with(Vec::new).extend(tweets_response.clone().into_data().unwrap_or_default());
+            page.media.extend(generate_media_map(tweets_response.into_includes()));
+        }
+        Ok(page)
+    }
+}
+
+/// Tweets held in memory, paged like the API pages the Tweets of a user: newest first, `max_results` at a time, within
+/// `until_id` and `since_id`, replies in a conversation left out. The pagination tokens are the offsets of the pages.
+///
+/// Every page request is kept, see [requests](MemorySource::requests).
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    users: HashMap<String, u64>,
+    tweets: HashMap<u64, Vec<Tweet>>,
+    /// id of the pinned Tweet, by user id
+    pinned: HashMap<u64, u64>,
+    media: HashMap<String, Media>,
+    requests: Mutex<Vec<PageRequest>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        MemorySource::default()
+    }
+
+    /// Adds the user `username` with the id `user_id`, its `tweets` and the `media` they refer to.
+    pub fn user(mut self, username: &str, user_id: u64, tweets: Vec<Tweet>, media: Vec<Media>) -> Self {
+        self.users.insert(username.to_lowercase(), user_id);
+        let user_tweets = self.tweets.entry(user_id).or_default();
+        user_tweets.extend(tweets);
+        user_tweets.sort_by_key(|tweet| std::cmp::Reverse(tweet.id.as_u64()));
+        self.media.extend(media.into_iter().map(|m| (m.media_key.to_string(), m)));
+        self
+    }
+
+    /// Pins the Tweet `tweet_id` of the user `username`, added before.
+    pub fn pinned(mut self, username: &str, tweet_id: u64) -> Self {
+        if let Some(user_id) = self.users.get(&username.to_lowercase()) {
+            self.pinned.insert(*user_id, tweet_id);
+        }
+        self
+    }
+
+    /// Returns the page requests so far, in order.
+    pub fn requests(&self) -> Vec<PageRequest> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Returns the media attached to `tweets`, by media key.
+    fn media_of(&self, tweets: &[Tweet]) -> HashMap<String, Media> {
+        tweets.iter()
+            .filter_map(|tweet| tweet.attachments.as_ref()?.media_keys.as_ref())
+            .flatten()
+            .filter_map(|key| self.media.get(&key.to_string()))
+            .map(|m| (m.media_key.to_string(), m.clone()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl TweetSource for MemorySource {
+    async fn get_user_id(&self, username: &str) -> Result<u64, DownloadError> {
+        self.users.get(&username.to_lowercase()).copied().ok_or_else(|| DownloadError::UserNotFound(username.into()))
+    }
+
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).push(request.clone());
+
+        let in_range: Vec<&Tweet> = self.tweets.get(&request.user_id).into_iter().flatten()
+            .filter(|tweet| tweet.conversation_id.as_ref().is_none_or(|id| id.as_u64() == tweet.id.as_u64()))
+            .filter(|tweet| request.until_id.is_none_or(|until_id| tweet.id.as_u64() < until_id))
+            .filter(|tweet| request.since_id.is_none_or(|since_id| tweet.id.as_u64() > since_id))
+            .collect();
+        let offset = match &request.pagination_token {
+            Some(token) => token.parse::<usize>().map_err(|_| DownloadError::Other(format!("Invalid pagination token {}", token)))?,
+            None => 0,
+        };
+        let end = (offset + usize::from(request.max_results.max(1))).min(in_range.len());
+        let tweets: Vec<Tweet> = in_range.get(offset..end).unwrap_or_default().iter().map(|tweet| (*tweet).clone()).collect();
+
+        let media = self.media_of(&tweets);
+        let meta = PageMeta {
+            oldest_id: tweets.last().map(|tweet| tweet.id.to_string()),
+            newest_id: tweets.first().map(|tweet| tweet.id.to_string()),
+            next_token: (end < in_range.len()).then(|| end.to_string()),
+        };
+        Ok(TweetsPage {
+            tweets: if tweets.is_empty() { None } else { Some(tweets) },
+            media,
+            meta: Some(meta),
+            authors: HashMap::new(),
+        })
+    }
+
+    async fn fetch_thread(&self, user_id: u64, conversation_id: u64) -> Result<TweetsPage, DownloadError> {
+        let mut tweets: Vec<Tweet> = self.tweets.get(&user_id).into_iter().flatten()
+            .filter(|tweet| tweet.conversation_id.as_ref().map(|id| id.as_u64()) == Some(conversation_id))
+            .cloned()
+            .collect();
+        tweets.sort_by_key(|tweet| tweet.id.as_u64());
+        Ok(TweetsPage {
+            media: self.media_of(&tweets),
+            tweets: if tweets.is_empty() { None } else { Some(tweets) },
+            meta: None,
+            authors: HashMap::new(),
+        })
+    }
+
+    async fn fetch_pinned_tweet(&self, user_id: u64) -> Result<TweetsPage, DownloadError> {
+        let tweets: Vec<Tweet> = match self.pinned.get(&user_id) {
+            Some(pinned_id) => self.tweets.get(&user_id).into_iter().flatten().filter(|tweet| tweet.id.as_u64() == *pinned_id).cloned().collect(),
+            None => Vec::new(),
+        };
+        Ok(TweetsPage {
+            media: self.media_of(&tweets),
+            tweets: if tweets.is_empty() { None } else { Some(tweets) },
+            meta: None,
+            authors: HashMap::new(),
+        })
+    }
+
+    async fn fetch_tweets(&self, ids: &[u64]) -> Result<TweetsPage, DownloadError> {
+        let mut tweets = Vec::new();
+        let mut authors = HashMap::new();
+        for (username, user_id) in self.users.iter() {
+            for tweet in self.tweets.get(user_id).into_iter().flatten().filter(|tweet| ids.contains(&tweet.id.as_u64())) {
+                tweets.push(tweet.clone());
+                authors.insert(*user_id, username.clone());
+            }
+        }
+        Ok(TweetsPage {
+            media: self.media_of(&tweets),
+            tweets: if tweets.is_empty() { None } else { Some(tweets) },
+            meta: None,
+            authors,
+        })
+    }
+}
@@ -0,0 +1,103 @@
+//! Credentials of the API requests: an app-only bearer token or OAuth 1.0a user context.
+//!
+//! User context comes with the rate limits of the user rather than those of the app, and reaches Tweets an app-only
+//! token cannot, e.g. of protected accounts the user follows.
+use std::fmt;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Method, Request, RequestBuilder, Url};
+use twitter_v2::authorization::{Authorization, BearerToken, Oauth1aToken};
+use twitter_v2::TwitterApi;
+
+/// Authentication mode of `--auth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuthMode {
+    /// app-only, with a bearer token
+    #[default]
+    Bearer,
+    /// user context, with the consumer key and secret of the app and the access token and secret of the user
+    OAuth1,
+}
+
+impl FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bearer" => Ok(AuthMode::Bearer),
+            "oauth1" => Ok(AuthMode::OAuth1),
+            _ => Err(format!("unknown auth mode '{}'. Expected bearer or oauth1", s)),
+        }
+    }
+}
+
+/// Credentials of the API requests, used as the authorization of a [TwitterApi](TwitterApi).
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Bearer(String),
+    OAuth1 {
+        consumer_key: String,
+        consumer_secret: String,
+        access_token: String,
+        access_token_secret: String,
+    },
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Credentials::Bearer(String::new())
+    }
+}
+
+// keeps the secrets out of logs of a Config
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Bearer(_) => f.write_str("Bearer(..)"),
+            Credentials::OAuth1 { .. } => f.write_str("OAuth1(..)"),
+        }
+    }
+}
+
+impl Credentials {
+    /// Returns the name of the first credential left empty, None if all are set.
+    pub fn missing(&self) -> Option<&'static str> {
+        match self {
+            Credentials::Bearer(token) if token.is_empty() => Some("bearer_token"),
+            Credentials::Bearer(_) => None,
+            Credentials::OAuth1 { consumer_key, consumer_secret, access_token, access_token_secret } => [
+                ("consumer_key", consumer_key),
+                ("consumer_secret", consumer_secret),
+                ("access_token", access_token),
+                ("access_token_secret", access_token_secret),
+            ].into_iter().find(|(_, value)| value.is_empty()).map(|(name, _)| name),
+        }
+    }
+
+    /// Returns a client of the Twitter API authorized with these credentials.
+    pub fn api(&self) -> TwitterApi<Credentials> {
+        TwitterApi::new(self.clone())
+    }
+
+    /// Adds the authorization header of a `method` request to `url` to `request`, for requests made outside of the
+    /// [TwitterApi](TwitterApi).
+    pub async fn authorize(&self, request: RequestBuilder, method: &Method, url: &Url) -> Result<RequestBuilder, twitter_v2::Error> {
+        // OAuth 1.0a signs the method, the url and its query
+        let signed = Request::new(method.clone(), url.clone());
+        Ok(request.header(AUTHORIZATION, self.header(&signed).await?))
+    }
+}
+
+#[async_trait]
+impl Authorization for Credentials {
+    async fn header(&self, request: &Request) -> twitter_v2::Result<HeaderValue> {
+        match self {
+            Credentials::Bearer(token) => BearerToken::new(token).header(request).await,
+            Credentials::OAuth1 { consumer_key, consumer_secret, access_token, access_token_secret } => {
+                Oauth1aToken::new(consumer_key, consumer_secret, access_token, access_token_secret).header(request).await
+            }
+        }
+    }
+}
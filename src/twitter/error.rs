@@ -0,0 +1,97 @@
+//! Errors of a download run.
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+use crate::magic::Mismatch;
+use crate::state::StateError;
+use crate::twitter::retry;
+
+/// Why a download run, or a part of it, failed.
+///
+/// Callers branch on the variant, e.g. to wait for a rate limit or to pick an exit code.
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    /// The credentials were rejected, `401 Unauthorized` or `403 Forbidden`.
+    #[error("Authentication failed: {0}")]
+    Auth(twitter_v2::Error),
+    /// The Twitter API answered `429 Too Many Requests`.
+    #[error("Rate limited: {0}")]
+    RateLimited(twitter_v2::Error),
+    /// The Twitter user does not exist.
+    #[error("Cannot find id for username {0}")]
+    UserNotFound(String),
+    /// Any other Twitter API error.
+    #[error("Twitter API error: {0}")]
+    Api(twitter_v2::Error),
+    /// A media download failed.
+    #[error("Download failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// A media download received no bytes for the stall timeout.
+    #[error("Download stalled, no data for {} seconds", .0.as_secs())]
+    Stalled(Duration),
+    /// A media file is larger than the `--max-file-size` limit.
+    #[error("File of {size} bytes exceeds the limit of {limit} bytes")]
+    TooLarge { size: u64, limit: u64 },
+    /// A downloaded file is not of the type of its media, e.g. an HTML error page, see [magic](crate::magic).
+    #[error("Unexpected content, {mismatch}. Moved to {}", .path.display())]
+    Corrupt { mismatch: Mismatch, path: PathBuf },
+    /// Reading or writing the output directory failed, e.g. the disk is full.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Reading or writing the [state database](crate::state) failed.
+    #[error("State database error: {0}")]
+    State(#[from] StateError),
+    /// Another running process downloads the same user, see [lock](crate::lock).
+    #[error("{} is held by the running process {1}. Pass --wait to wait for it", .0.display())]
+    Locked(PathBuf, u32),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl DownloadError {
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, DownloadError::RateLimited(_))
+    }
+
+    /// Returns true if a media url was rejected with `403 Forbidden` or `410 Gone`, the answers to an expired signed url.
+    pub fn is_expired_link(&self) -> bool {
+        match self {
+            DownloadError::Http(e) => matches!(e.status(), Some(StatusCode::FORBIDDEN) | Some(StatusCode::GONE)),
+            _ => false,
+        }
+    }
+
+    /// Returns true for errors worth retrying, see [retry::is_transient](retry::is_transient).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Http(e) => retry::is_transient(e),
+            DownloadError::Stalled(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DownloadError {
+    fn from(err: rusqlite::Error) -> Self {
+        DownloadError::State(StateError::Sqlite(err))
+    }
+}
+
+impl From<twitter_v2::Error> for DownloadError {
+    fn from(err: twitter_v2::Error) -> Self {
+        let status = match &err {
+            twitter_v2::Error::Api(api_error) => Some(api_error.status),
+            twitter_v2::Error::Request(request_error) => request_error.status(),
+            _ => None,
+        };
+        match status {
+            Some(StatusCode::TOO_MANY_REQUESTS) => DownloadError::RateLimited(err),
+            Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => DownloadError::Auth(err),
+            _ => DownloadError::Api(err),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Naming of the downloaded media files.
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use time::macros::format_description;
+use time::OffsetDateTime;
+use twitter_v2::data::MediaType;
+
+/// Template of the default file names: `{media_key}_{username}_{original}`.
+pub const DEFAULT_TEMPLATE: &str = "{media_key}_{username}_{original}";
+
+/// Longest file name in bytes. Most file systems allow 255, some room is left for `.part`, `.json` and `.xmp`.
+const MAX_FILENAME_LEN: usize = 200;
+
+/// Characters not allowed in file names on Windows, replaced by `_` on every platform so archives can be moved around.
+const INVALID_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names Windows reserves regardless of the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A placeholder of a [FilenameTemplate](FilenameTemplate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// `{date}`: date of the Tweet, `YYYY-MM-DD`
+    Date,
+    TweetId,
+    Username,
+    MediaKey,
+    /// `{index}`: position of the media in the Tweet, starting at 1
+    Index,
+    /// `{count}`: number of media attached to the Tweet
+    Count,
+    /// `{ext}`: extension of the remote file name, e.g. `jpg`
+    Ext,
+    /// `{original}`: the remote file name, e.g. `FqX1a2b3.jpg`
+    Original,
+    /// `{thread}`: id of the conversation of the Tweet, the first Tweet of its thread
+    Thread,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "date" => Some(Placeholder::Date),
+            "tweet_id" => Some(Placeholder::TweetId),
+            "username" => Some(Placeholder::Username),
+            "media_key" => Some(Placeholder::MediaKey),
+            "index" => Some(Placeholder::Index),
+            "count" => Some(Placeholder::Count),
+            "ext" => Some(Placeholder::Ext),
+            "original" => Some(Placeholder::Original),
+            "thread" => Some(Placeholder::Thread),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Template of the local file name of a media file, e.g. `{date}_{tweet_id}_{index}.{ext}`.
+///
+/// Placeholders are `{date}`, `{tweet_id}`, `{username}`, `{media_key}`, `{index}`, `{count}`, `{ext}`, `{original}` and
+/// `{thread}`.
+/// A template must name every media file of a user uniquely, so it has to contain `{media_key}`, `{original}`
+/// or both `{tweet_id}` and `{index}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().expect("the default template is valid")
+    }
+}
+
+impl fmt::Display for FilenameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') || s.contains('\\') {
+            return Err(format!("filename template '{}' cannot contain path separators", s));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(i) if rest[i..].starts_with('}') => return Err(format!("unmatched '}}' in filename template '{}'", s)),
+                Some(i) => {
+                    if i > 0 {
+                        parts.push(Part::Literal(rest[..i].to_string()));
+                    }
+                    let end = rest[i..].find('}').ok_or_else(|| format!("unclosed '{{' in filename template '{}'", s))? + i;
+                    let name = &rest[i + 1..end];
+                    let placeholder = Placeholder::from_name(name)
+                        .ok_or_else(|| format!("unknown placeholder '{{{}}}' in filename template '{}'. Expected date, tweet_id, username, media_key, index, count, ext, original or thread", name, s))?;
+                    parts.push(Part::Placeholder(placeholder));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    parts.push(Part::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        let has = |p: Placeholder| parts.contains(&Part::Placeholder(p));
+        if !(has(Placeholder::MediaKey) || has(Placeholder::Original) || (has(Placeholder::TweetId) && has(Placeholder::Index))) {
+            return Err(format!("filename template '{}' does not name files uniquely. It needs {{media_key}}, {{original}} or both {{tweet_id}} and {{index}}", s));
+        }
+
+        Ok(FilenameTemplate { template: s.to_string(), parts })
+    }
+}
+
+/// Values of the placeholders of a [FilenameTemplate](FilenameTemplate) for one media file.
+#[derive(Debug)]
+pub struct FilenameValues<'a> {
+    pub username: &'a str,
+    pub tweet_id: &'a str,
+    pub media_key: &'a str,
+    /// position of the media in the attachments of the Tweet, starting at 0
+    pub index: usize,
+    /// number of media attached to the Tweet
+    pub count: usize,
+    /// date of the Tweet in the time zone of the run
+    pub date: Option<OffsetDateTime>,
+    /// the remote file name
+    pub original: &'a str,
+    /// id of the conversation of the Tweet, its own id if unknown
+    pub thread: &'a str,
+}
+
+impl FilenameTemplate {
+    /// Returns the [sanitized](sanitize) file name for `values`.
+    pub fn render(&self, values: &FilenameValues) -> String {
+        let mut filename = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => filename.push_str(literal),
+                Part::Placeholder(placeholder) => match placeholder {
+                    Placeholder::Date => filename.push_str(&values.date
+                        .and_then(|d| d.format(format_description!("[year]-[month]-[day]")).ok())
+                        .unwrap_or_else(|| "unknown".into())),
+                    Placeholder::TweetId => filename.push_str(values.tweet_id),
+                    Placeholder::Username => filename.push_str(values.username),
+                    Placeholder::MediaKey => filename.push_str(values.media_key),
+                    Placeholder::Index => filename.push_str(&(values.index + 1).to_string()),
+                    Placeholder::Count => filename.push_str(&values.count.to_string()),
+                    Placeholder::Ext => filename.push_str(values.original.rsplit_once('.').map_or("", |(_, ext)| ext)),
+                    Placeholder::Original => filename.push_str(values.original),
+                    Placeholder::Thread => filename.push_str(values.thread),
+                },
+            }
+        }
+        sanitize(&filename)
+    }
+}
+
+/// Returns `filename` made valid on Linux, macOS and Windows.
+///
+/// Invalid and control characters become `_`, trailing dots and spaces are dropped, reserved device names like `CON`
+/// get a leading `_` and names longer than [MAX_FILENAME_LEN](MAX_FILENAME_LEN) bytes are shortened, keeping the extension.
+pub fn sanitize(filename: &str) -> String {
+    let mut name: String = filename.chars()
+        .map(|c| if c.is_control() || INVALID_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    name.truncate(name.trim_end_matches(['.', ' ']).len());
+    if name.is_empty() {
+        name.push('_');
+    }
+
+    let stem = name.split('.').next().unwrap_or("");
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        name.insert(0, '_');
+    }
+
+    if name.len() > MAX_FILENAME_LEN {
+        let ext = match name.rsplit_once('.') {
+            Some((_, ext)) if ext.len() < 16 => format!(".{}", ext),
+            _ => String::new(),
+        };
+        let mut end = MAX_FILENAME_LEN - ext.len();
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = format!("{}{}", &name[..end], ext);
+    }
+    name
+}
+
+/// Returns `filename` with `_<n>` added before the extension, e.g. `photo_2.jpg`, to tell apart different media
+/// that would get the same name.
+pub fn with_suffix(filename: &str, n: u32) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}_{}.{}", stem, n, ext),
+        _ => format!("{}_{}", filename, n),
+    }
+}
+
+/// Layout of the media files under a user directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// all files in the user directory
+    Flat,
+    /// `YYYY/MM/` of the Tweet date
+    Date,
+    /// `photos/`, `videos/` by media type
+    Type,
+    /// `YYYY/MM/photos/`, `YYYY/MM/videos/`
+    DateType,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Layout::Flat),
+            "date" => Ok(Layout::Date),
+            "type" => Ok(Layout::Type),
+            "date-type" => Ok(Layout::DateType),
+            _ => Err(format!("unknown layout '{}'. Expected flat, date, type or date-type", s)),
+        }
+    }
+}
+
+impl Layout {
+    /// Returns the directory of a media file of `kind` from a Tweet of `date` relative to the user directory.
+    pub fn subdir(&self, date: Option<OffsetDateTime>, kind: &MediaType) -> PathBuf {
+        let date_dir = || match date {
+            Some(date) => PathBuf::from(format!("{:04}", date.year())).join(format!("{:02}", u8::from(date.month()))),
+            None => PathBuf::from("unknown"),
+        };
+        let type_dir = || match kind {
+            MediaType::Photo => "photos",
+            _ => "videos",
+        };
+        match self {
+            Layout::Flat => PathBuf::new(),
+            Layout::Date => date_dir(),
+            Layout::Type => PathBuf::from(type_dir()),
+            Layout::DateType => date_dir().join(type_dir()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn values(original: &str) -> FilenameValues<'_> {
+        FilenameValues { username: "alice", tweet_id: "20", media_key: "3_1", index: 1, count: 2, date: Some(datetime!(2021-03-04 5:06 UTC)), original, thread: "10" }
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let error = |template: &str| template.parse::<FilenameTemplate>().unwrap_err();
+        assert!(error("{media_key}}").contains("unmatched '}'"));
+        assert!(error("}{media_key}").contains("unmatched '}'"));
+        assert!(error("{media_key}_{date").contains("unclosed '{'"));
+        assert!(error("{media_key}_{year}").contains("unknown placeholder '{year}'"));
+        assert!(error("{}{media_key}").contains("unknown placeholder '{}'"));
+        assert!(error("{date}/{media_key}").contains("path separators"));
+        assert!(error("{date}\\{media_key}").contains("path separators"));
+    }
+
+    #[test]
+    fn requires_unique_file_names() {
+        let unique = |template: &str| template.parse::<FilenameTemplate>().is_ok();
+        assert!(unique("{media_key}"));
+        assert!(unique("{original}"));
+        assert!(unique("{tweet_id}_{index}.{ext}"));
+        assert!(!unique("{tweet_id}.{ext}"));
+        assert!(!unique("{index}.{ext}"));
+        assert!(!unique("{date}_{username}_{thread}_{count}.{ext}"));
+        assert!(!unique("photo.jpg"));
+    }
+
+    #[test]
+    fn renders_the_placeholders() {
+        let template: FilenameTemplate = "{date}_{tweet_id}_{index}of{count}_{thread}_{username}_{media_key}.{ext}".parse().unwrap();
+        assert_eq!(template.render(&values("FqX1.jpg")), "2021-03-04_20_2of2_10_alice_3_1.jpg");
+        assert_eq!(template.to_string(), "{date}_{tweet_id}_{index}of{count}_{thread}_{username}_{media_key}.{ext}");
+
+        let undated = FilenameValues { date: None, ..values("FqX1") };
+        assert_eq!("{date}_{media_key}.{ext}".parse::<FilenameTemplate>().unwrap().render(&undated), "unknown_3_1");
+        assert_eq!(FilenameTemplate::default().render(&values("FqX1.jpg")), "3_1_alice_FqX1.jpg");
+    }
+
+    #[test]
+    fn sanitizes_for_every_platform() {
+        assert_eq!(sanitize("a:b?c*<d>|\"e\u{7}.jpg"), "a_b_c__d___e_.jpg");
+        assert_eq!(sanitize("name. ."), "name");
+        assert_eq!(sanitize("..."), "_");
+        assert_eq!(sanitize("CON.jpg"), "_CON.jpg");
+        assert_eq!(sanitize("lpt1"), "_lpt1");
+        assert_eq!(sanitize("CONSOLE.jpg"), "CONSOLE.jpg");
+    }
+
+    #[test]
+    fn shortens_long_names_at_a_char_boundary() {
+        // 3 bytes each, the cut at 196 bytes falls within a char
+        let name = sanitize(&format!("{}.jpg", "€".repeat(100)));
+        assert_eq!(name, format!("{}.jpg", "€".repeat(65)));
+        assert!(name.len() <= MAX_FILENAME_LEN);
+
+        // an extension that long is no extension
+        let name = sanitize(&format!("{}.{}", "a".repeat(200), "b".repeat(20)));
+        assert_eq!(name, "a".repeat(MAX_FILENAME_LEN));
+    }
+
+    #[test]
+    fn adds_the_suffix_before_the_extension() {
+        assert_eq!(with_suffix("photo.jpg", 2), "photo_2.jpg");
+        assert_eq!(with_suffix("a.b.jpg", 3), "a.b_3.jpg");
+        assert_eq!(with_suffix("photo", 2), "photo_2");
+        assert_eq!(with_suffix(".hidden", 2), ".hidden_2");
+    }
+
+    #[test]
+    fn lays_out_by_date_and_type() {
+        let date = Some(datetime!(2021-03-04 5:06 UTC));
+        assert_eq!(Layout::Flat.subdir(date, &MediaType::Photo), PathBuf::new());
+        assert_eq!(Layout::Date.subdir(date, &MediaType::Photo), PathBuf::from("2021").join("03"));
+        assert_eq!(Layout::Date.subdir(None, &MediaType::Photo), PathBuf::from("unknown"));
+        assert_eq!(Layout::Type.subdir(date, &MediaType::Photo), PathBuf::from("photos"));
+        assert_eq!(Layout::Type.subdir(date, &MediaType::AnimatedGif), PathBuf::from("videos"));
+        assert_eq!(Layout::DateType.subdir(date, &MediaType::Video), PathBuf::from("2021").join("03").join("videos"));
+        assert_eq!("date-type".parse(), Ok(Layout::DateType));
+        assert!("by-date".parse::<Layout>().is_err());
+    }
+}
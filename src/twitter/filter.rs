@@ -0,0 +1,91 @@
+//! Selection of the Tweets and media to download.
+use std::str::FromStr;
+
+use regex::Regex;
+use twitter_v2::{Media, Tweet};
+
+/// Tweets to download the media of. Accepts every Tweet by default.
+#[derive(Debug, Clone, Default)]
+pub struct TweetFilter {
+    /// the text has to match one of these, if any
+    pub matches: Vec<Regex>,
+    /// the text must not match any of these
+    pub excludes: Vec<Regex>,
+    /// least number of likes
+    pub min_likes: u64,
+    /// least number of retweets
+    pub min_retweets: u64,
+    /// handling of Tweets flagged as possibly sensitive
+    pub sensitive: Sensitive,
+    /// least width of a media in pixels
+    pub min_width: usize,
+    /// least height of a media in pixels
+    pub min_height: usize,
+}
+
+/// Handling of the media of Tweets Twitter flagged as possibly sensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sensitive {
+    /// download them like any other
+    #[default]
+    Include,
+    /// do not download them
+    Skip,
+    /// download only them
+    Only,
+    /// download them into a `sensitive/` directory of the user
+    SeparateDir,
+}
+
+impl FromStr for Sensitive {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "include" => Ok(Sensitive::Include),
+            "skip" => Ok(Sensitive::Skip),
+            "only" => Ok(Sensitive::Only),
+            "separate-dir" => Ok(Sensitive::SeparateDir),
+            _ => Err(format!("unknown sensitive handling '{}'. Expected include, skip, only or separate-dir", s)),
+        }
+    }
+}
+
+/// Returns true if Twitter flagged `tweet` as possibly sensitive.
+pub fn is_sensitive(tweet: &Tweet) -> bool {
+    tweet.possibly_sensitive.unwrap_or(false)
+}
+
+impl TweetFilter {
+    /// Returns true if the media of `tweet` are to be downloaded.
+    ///
+    /// A Tweet without public metrics counts as having none, so it fails any minimum.
+    pub fn accepts(&self, tweet: &Tweet) -> bool {
+        match self.sensitive {
+            Sensitive::Skip if is_sensitive(tweet) => return false,
+            Sensitive::Only if !is_sensitive(tweet) => return false,
+            _ => {}
+        }
+
+        if self.min_likes > 0 || self.min_retweets > 0 {
+            let (likes, retweets) = tweet.public_metrics.as_ref()
+                .map_or((0, 0), |m| (m.like_count as u64, m.retweet_count as u64));
+            if likes < self.min_likes || retweets < self.min_retweets {
+                return false;
+            }
+        }
+
+        let text = tweet.text.as_str();
+        if !self.matches.is_empty() && !self.matches.iter().any(|r| r.is_match(text)) {
+            return false;
+        }
+        !self.excludes.iter().any(|r| r.is_match(text))
+    }
+
+    /// Returns true if `media` of an accepted Tweet is to be downloaded.
+    ///
+    /// A media without dimensions passes, the API reports them for every photo and video.
+    pub fn accepts_media(&self, media: &Media) -> bool {
+        media.width.is_none_or(|w| w >= self.min_width) && media.height.is_none_or(|h| h >= self.min_height)
+    }
+}
@@ -0,0 +1,293 @@
+//! Videos offered as HLS (m3u8) playlist only.
+//!
+//! The video variants of a media are MP4 files of several bit rates and usually an HLS playlist of the same video.
+//! Some videos only have the playlist. It is downloaded according to [HlsMode](HlsMode): its segments stitched into
+//! a single MP4, remuxed by ffmpeg, or skipped and reported in the user's [skipped](crate::skipped) file.
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::{Client, Url};
+use tokio::process::Command;
+use tracing::{info, warn};
+use twitter_v2::Media;
+
+use crate::magic;
+use crate::twitter::error::DownloadError;
+use crate::twitter::retry::RetryPolicy;
+use crate::twitter::{fetch_with_retry, get_part_file_path};
+
+/// Content type of the playlist variant.
+const PLAYLIST_CONTENT_TYPE: &str = "application/x-mpegURL";
+
+/// Content type of the MP4 variants.
+const MP4_CONTENT_TYPE: &str = "video/mp4";
+
+/// What to do with a video that is only offered as HLS playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HlsMode {
+    /// download the segments and stitch them into an MP4, through ffmpeg if they are MPEG-TS
+    Native,
+    /// let ffmpeg download the playlist and remux it into an MP4
+    Ffmpeg,
+    /// do not download it, report it as skipped
+    Skip,
+}
+
+impl FromStr for HlsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(HlsMode::Native),
+            "ffmpeg" => Ok(HlsMode::Ffmpeg),
+            "skip" => Ok(HlsMode::Skip),
+            _ => Err(format!("unknown hls mode '{}'. Expected native, ffmpeg or skip", s)),
+        }
+    }
+}
+
+/// Where the file of a video or animated GIF comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoSource {
+    /// the MP4 variant of the highest bit rate
+    File(Url),
+    /// the HLS playlist, there is no MP4 variant
+    Playlist(Url),
+}
+
+/// Returns the source of the file of `media`, None if it has no variants.
+pub fn video_source(media: &Media) -> Option<VideoSource> {
+    let variants = media.variants.as_ref()?;
+    let mp4 = variants.iter()
+        .filter(|v| v.content_type.as_deref() == Some(MP4_CONTENT_TYPE))
+        .filter_map(|v| v.url.clone().map(|url| (v.bit_rate.unwrap_or(0), url)))
+        .max_by_key(|(bit_rate, _)| *bit_rate);
+    if let Some((_, url)) = mp4 {
+        return Some(VideoSource::File(url));
+    }
+    variants.iter()
+        .filter(|v| v.content_type.as_deref().is_some_and(|t| t.eq_ignore_ascii_case(PLAYLIST_CONTENT_TYPE)))
+        .find_map(|v| v.url.clone())
+        .map(VideoSource::Playlist)
+}
+
+/// A parsed HLS playlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playlist {
+    /// a master playlist, the playlists of its streams by bandwidth
+    Master(Vec<(u64, Url)>),
+    /// a media playlist, its segments in order and the initialization segment of fragmented MP4 segments
+    Media { init: Option<Url>, segments: Vec<Url> },
+}
+
+impl Playlist {
+    /// Parses the playlist `text` downloaded from `base`, which relative urls are resolved against.
+    pub fn parse(base: &Url, text: &str) -> Result<Playlist, String> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err("not an m3u8 playlist".to_string());
+        }
+        let resolve = |uri: &str| base.join(uri).map_err(|e| format!("invalid uri '{}': {}", uri, e));
+        let mut streams = Vec::new();
+        let mut init = None;
+        let mut segments = Vec::new();
+        let mut bandwidth = None;
+        for line in lines {
+            if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+                bandwidth = Some(attribute(attributes, "BANDWIDTH").and_then(|b| b.parse().ok()).unwrap_or(0));
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+                let uri = attribute(attributes, "URI").ok_or("EXT-X-MAP without URI")?;
+                init = Some(resolve(uri)?);
+            } else if !line.starts_with('#') {
+                match bandwidth.take() {
+                    Some(bandwidth) => streams.push((bandwidth, resolve(line)?)),
+                    None => segments.push(resolve(line)?),
+                }
+            }
+        }
+        if !streams.is_empty() {
+            Ok(Playlist::Master(streams))
+        } else if !segments.is_empty() {
+            Ok(Playlist::Media { init, segments })
+        } else {
+            Err("empty playlist".to_string())
+        }
+    }
+}
+
+/// Returns the value of `name` in the attribute list of a tag, without quotes.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], quoted[end + 1..].trim_start_matches(','))
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        if key.trim() == name {
+            return Some(value);
+        }
+        rest = next;
+    }
+    None
+}
+
+/// Downloads the video of `playlist` into `output_file` according to `mode`, like [download_url](super::download_url)
+/// does for a file. [HlsMode::Skip](HlsMode::Skip) is handled by the caller.
+///
+/// If the file exists, return false
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn download_playlist(client: &Client, retry: &RetryPolicy, stall_timeout: Duration, max_size: Option<u64>, mode: HlsMode, username: &str, media_key: &str, playlist: &Url, output_file: &Path) -> Result<bool, DownloadError> {
+    if output_file.exists() {
+        warn!("username: {}, media_key: {}, remote: {}, local: {}. File exists, skipping.", username, media_key, playlist, output_file.display());
+        return Ok(false);
+    }
+    info!("username: {}, media_key: {}, remote: {}. Downloading the HLS playlist, {:?}", username, media_key, playlist, mode);
+    let part_file = get_part_file_path(output_file);
+    match mode {
+        HlsMode::Ffmpeg => remux(playlist, &part_file).await?,
+        _ => stitch(client, retry, stall_timeout, max_size, username, media_key, playlist, &part_file).await?,
+    }
+    if let Err(mismatch) = magic::check(&part_file, output_file)? {
+        let path = magic::quarantine(&part_file, output_file)?;
+        warn!("username: {}, media_key: {}, local: {}. {}, quarantined", username, media_key, path.display(), mismatch);
+        return Err(DownloadError::Corrupt { mismatch, path });
+    }
+    fs::rename(&part_file, output_file)?;
+    info!("username: {}, media_key: {}, remote: {}, local: {}. Downloaded", username, media_key, playlist, output_file.display());
+    Ok(true)
+}
+
+/// Downloads the segments of `playlist`, of its stream of the highest bandwidth for a master playlist, and appends them
+/// to `part_file`. MPEG-TS segments do not make an MP4 by appending them, they are [remuxed](remux) if ffmpeg is found.
+#[allow(clippy::too_many_arguments)]
+async fn stitch(client: &Client, retry: &RetryPolicy, stall_timeout: Duration, max_size: Option<u64>, username: &str, media_key: &str, playlist: &Url, part_file: &Path) -> Result<(), DownloadError> {
+    let (init, segments) = match fetch_playlist(client, stall_timeout, playlist).await? {
+        Playlist::Master(streams) => {
+            let (_, stream) = streams.into_iter().max_by_key(|(bandwidth, _)| *bandwidth).expect("master playlists have streams");
+            match fetch_playlist(client, stall_timeout, &stream).await? {
+                Playlist::Media { init, segments } => (init, segments),
+                Playlist::Master(_) => return Err(DownloadError::Other(format!("HLS playlist {} nests master playlists", stream))),
+            }
+        }
+        Playlist::Media { init, segments } => (init, segments),
+    };
+    let init = match init {
+        Some(init) => init,
+        None if ffmpeg_available().await => {
+            info!("username: {}, media_key: {}, remote: {}. MPEG-TS segments, remuxing with ffmpeg", username, media_key, playlist);
+            return remux(playlist, part_file).await;
+        }
+        None => return Err(DownloadError::Other(format!("HLS playlist {} has MPEG-TS segments, which need ffmpeg to make an MP4", playlist))),
+    };
+
+    let segment_file = part_file.with_extension("segment.part");
+    let mut out = File::create(part_file)?;
+    let mut size: u64 = 0;
+    for url in std::iter::once(init).chain(segments) {
+        let _ = fs::remove_file(&segment_file);
+        let remaining = max_size.map(|limit| limit.saturating_sub(size));
+        match fetch_with_retry(client, retry, stall_timeout, remaining, username, media_key, url, &segment_file).await {
+            Ok(segment_size) => size += segment_size,
+            Err(DownloadError::TooLarge { size: segment_size, .. }) => {
+                drop(out);
+                let _ = fs::remove_file(part_file);
+                return Err(DownloadError::TooLarge { size: size + segment_size, limit: max_size.unwrap_or_default() });
+            }
+            Err(e) => return Err(e),
+        }
+        io::copy(&mut File::open(&segment_file)?, &mut out)?;
+    }
+    let _ = fs::remove_file(&segment_file);
+    out.sync_all()?;
+    Ok(())
+}
+
+/// GETs and parses the HLS playlist at `url`.
+async fn fetch_playlist(client: &Client, stall_timeout: Duration, url: &Url) -> Result<Playlist, DownloadError> {
+    let resp = tokio::time::timeout(stall_timeout, client.get(url.clone()).send()).await
+        .map_err(|_| DownloadError::Stalled(stall_timeout))??
+        .error_for_status()?;
+    let text = tokio::time::timeout(stall_timeout, resp.text()).await
+        .map_err(|_| DownloadError::Stalled(stall_timeout))??;
+    Playlist::parse(url, &text).map_err(|e| DownloadError::Other(format!("HLS playlist {}: {}", url, e)))
+}
+
+/// Lets ffmpeg download `playlist` and copy its streams into the MP4 `part_file`.
+async fn remux(playlist: &Url, part_file: &Path) -> Result<(), DownloadError> {
+    let output = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-y", "-i", playlist.as_str(), "-c", "copy", "-f", "mp4"])
+        .arg(part_file)
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => DownloadError::Other("ffmpeg not found on the PATH, needed for --hls ffmpeg".to_string()),
+            _ => DownloadError::Io(e),
+        })?;
+    if !output.status.success() {
+        let _ = fs::remove_file(part_file);
+        return Err(DownloadError::Other(format!("ffmpeg failed, {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// Whether an ffmpeg binary is found on the PATH.
+async fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg").arg("-version").output().await.is_ok_and(|output| output.status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_master_and_media_playlists() {
+        let base = Url::parse("https://video.twimg.com/ext_tw_video/1/pu/pl/abc.m3u8?tag=12").unwrap();
+        let master = "#EXTM3U\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+            #EXT-X-STREAM-INF:AVERAGE-BANDWIDTH=300000,BANDWIDTH=400000,RESOLUTION=480x270,CODECS=\"mp4a.40.2,avc1.4d001e\"\n\
+            /ext_tw_video/1/pu/pl/480x270/a.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720\n\
+            1280x720/b.m3u8\n";
+        assert_eq!(Playlist::parse(&base, master), Ok(Playlist::Master(vec![
+            (400000, Url::parse("https://video.twimg.com/ext_tw_video/1/pu/pl/480x270/a.m3u8").unwrap()),
+            (2000000, Url::parse("https://video.twimg.com/ext_tw_video/1/pu/pl/1280x720/b.m3u8").unwrap()),
+        ])));
+
+        let media = "#EXTM3U\n#EXT-X-VERSION:6\n#EXT-X-MAP:URI=\"/ext_tw_video/1/pu/vid/0/0/init.mp4\"\n\
+            #EXTINF:3.000,\n/ext_tw_video/1/pu/vid/0/3000/1.m4s\n#EXTINF:1.500,\n/ext_tw_video/1/pu/vid/3000/4500/2.m4s\n#EXT-X-ENDLIST\n";
+        assert_eq!(Playlist::parse(&base, media), Ok(Playlist::Media {
+            init: Some(Url::parse("https://video.twimg.com/ext_tw_video/1/pu/vid/0/0/init.mp4").unwrap()),
+            segments: vec![
+                Url::parse("https://video.twimg.com/ext_tw_video/1/pu/vid/0/3000/1.m4s").unwrap(),
+                Url::parse("https://video.twimg.com/ext_tw_video/1/pu/vid/3000/4500/2.m4s").unwrap(),
+            ],
+        }));
+
+        assert!(Playlist::parse(&base, "<html></html>").is_err());
+    }
+
+    #[test]
+    fn prefers_the_best_mp4_over_the_playlist() {
+        let media: Media = serde_json::from_value(serde_json::json!({
+            "media_key": "7_1", "type": "video",
+            "variants": [
+                { "content_type": "application/x-mpegURL", "url": "https://video.twimg.com/pl/a.m3u8" },
+                { "bit_rate": 256000, "content_type": "video/mp4", "url": "https://video.twimg.com/vid/480x270/a.mp4" },
+                { "bit_rate": 2176000, "content_type": "video/mp4", "url": "https://video.twimg.com/vid/1280x720/a.mp4" },
+            ],
+        })).unwrap();
+        assert_eq!(video_source(&media), Some(VideoSource::File(Url::parse("https://video.twimg.com/vid/1280x720/a.mp4").unwrap())));
+
+        let media: Media = serde_json::from_value(serde_json::json!({
+            "media_key": "7_2", "type": "video",
+            "variants": [{ "content_type": "application/x-mpegURL", "url": "https://video.twimg.com/pl/b.m3u8" }],
+        })).unwrap();
+        assert_eq!(video_source(&media), Some(VideoSource::Playlist(Url::parse("https://video.twimg.com/pl/b.m3u8").unwrap())));
+    }
+}
@@ -1,79 +1,374 @@
 //! module to handle downloading media files for the Twitter user.
-use std::{io, thread};
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::{self, DirBuilder, File};
+use std::io;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, DirBuilder, File, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use log::{error, info, warn};
-use twitter_v2::{Media, TwitterApi};
-use twitter_v2::authorization::BearerToken;
-use twitter_v2::data::{Expansions, MediaType};
-use twitter_v2::query::{Exclude, MediaField, TweetExpansion, TweetField};
+use reqwest::{Client, StatusCode, Url};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use time::OffsetDateTime;
+use tracing::{error, info, warn, Instrument};
+use twitter_v2::{Media, Tweet};
+use twitter_v2::data::{Expansions, MediaType, ReferencedTweetKind};
+use twitter_v2::query::{MediaField, TweetExpansion};
 
 use crate::Config;
+use crate::archive::RunArchive;
+use crate::capture;
+use crate::clock;
+use crate::common::sha256_file;
+use crate::compat::{self, Compat};
+use crate::convert;
+use crate::dates;
+use crate::dedup::{self, DedupStats, Duplicate};
+use crate::embed::{self, Provenance};
+use crate::exec::ExecFile;
+use crate::http;
+use crate::links;
+use crate::lock::{self, DirLock};
+use crate::magic;
+use crate::progress;
+use crate::tui::{self, UserState};
+use crate::shutdown;
+use crate::sidecar;
+use crate::similar::{self, NearDupes};
+use crate::skipped::{self, Skipped};
+use crate::source;
+use crate::stats::format_bytes;
+use crate::tweets;
+use crate::state::{MediaRecord, ResumePosition, StateStore};
+use crate::twitter::api::{PageMeta, PageRequest, TweetSource, TweetsPage};
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::filename::FilenameValues;
+use crate::twitter::filter::Sensitive;
+use crate::twitter::hls::{HlsMode, VideoSource};
+use crate::twitter::nitter::{FallbackBackend, NitterSource, WithFallback};
+use crate::twitter::order::Order;
+use crate::twitter::outcome::{Cutoff, RunStats};
+use crate::twitter::retry::RetryPolicy;
+use crate::twitter::search::{FullArchiveSearch, SourceKind};
+use crate::volumes::Volumes;
 
+pub mod api;
+pub mod auth;
+pub mod error;
+pub mod filename;
+pub mod filter;
+pub mod hls;
+pub mod nitter;
+pub mod order;
+pub mod outcome;
+pub mod ratelimit;
+pub mod retry;
+pub mod search;
+pub mod storage;
+pub mod throttle;
 
-/// Name of the checkpoint file. Checkpoint file stores the tweet id of the oldest tweet processed the application
-const CHECKPOINT_FILENAME: &str = "checkpoint";
 
 /// Give it some time during iterations of get_user_tweets
 const SLEEP_TIME: Duration = Duration::from_millis(250);
 
+/// Directory of the media of possibly sensitive Tweets with [Sensitive::SeparateDir](Sensitive::SeparateDir)
+const SENSITIVE_DIR: &str = "sensitive";
+
 /// Gets this show on the road.
 ///
-/// If `Config::download_all` is true keeps looping with the call [download_media](download_media) until there are no more Tweets. `marker` is read from [get_checkpoint](get_checkpoint).
-/// The checkpoint file [update_checkpoint](update_checkpoint) is updated during iterations.
+/// Starts a [UserRun](UserRun) for `config` and walks it page by page until it is done.
+///
+/// Returns Ok with the number of downloaded files or the [DownloadError](DownloadError).
+pub async fn start_download(config: Config) -> Result<u32, DownloadError> {
+    let mut run = UserRun::start(config).await?;
+    while !run.is_done() {
+        run.next_page().await?;
+    }
+    Ok(run.count())
+}
+
+/// The download run of one user, walked one page at a time with [next_page](UserRun::next_page), so the pages of
+/// several users can be interleaved.
+///
+/// If `Config::download_all` is true keeps calling [download_media](download_media) page by page until there are no more Tweets. `marker` is read once from [get_checkpoint](get_checkpoint).
+/// Within the run the pages are walked with the API's `next_token`, so pages never overlap.
+/// The checkpoint in the [state database](crate::state) is updated after every page with [update_checkpoint](update_checkpoint), it is
+/// the resume point of the next run.
 ///
-/// If `Config::download_all` is false, breaks after first call.
+/// If `Config::download_all` is false, is done after the first page.
 ///
-/// Returns Ok with count info or Error.
-pub async fn start_download(config: Config) -> Result<String, Box<dyn Error>> {
-    let api = TwitterApi::new(BearerToken::new(&config.bearer_token));
+/// With `Config::sync_new` only the Tweets newer than the newest Tweet seen by earlier runs are walked (`since_id`),
+/// page by page, leaving the checkpoint untouched. Every run records the newest Tweet seen.
+///
+/// If the API rate limit is exhausted, sleeps until the rate limit window resets and continues from the checkpoint.
+///
+/// On a [shutdown](crate::shutdown) request, is done after the in-flight page with its checkpoint written.
+pub struct UserRun {
+    ctx: RunContext,
+    cutoff: Option<Cutoff>,
+    marker: u64,
+    since_id: Option<u64>,
+    resume: Option<ResumePosition>,
+    /// pinned Tweet of the user, processed with the first page
+    pinned: Option<TweetsPage>,
+    pagination_token: Option<String>,
+    pages: u32,
+    count: u32,
+    done: bool,
+    /// held until the run is dropped
+    _lock: DirLock,
+}
 
-    let id = get_twitter_id(&api, &config).await?;
+/// What the pages of a [UserRun](UserRun) and the downloads they spawn share. Cheap to clone into a download task.
+#[derive(Clone)]
+struct RunContext {
+    source: Arc<dyn TweetSource>,
+    client: Client,
+    config: Arc<Config>,
+    /// Twitter id of the user
+    id: u64,
+    volumes: Arc<Volumes>,
+    state: Arc<StateStore>,
+    dedup_stats: Arc<DedupStats>,
+    run_stats: Arc<RunStats>,
+    /// archive of the downloads with `Config::archive`
+    archive: Option<Arc<RunArchive>>,
+}
 
-    let mut reset_once = config.reset_marker;
+impl UserRun {
+    /// Looks up the user with the Twitter API and reads where to continue from.
+    ///
+    /// The Tweets come from the timeline of the user or, with `Config::source`, the [full-archive search](search).
+    /// With `Config::fallback_backend` they come from [Nitter](nitter) once the API is rate limited or rejects the token.
+    pub async fn start(config: Config) -> Result<UserRun, DownloadError> {
+        let api = config.credentials.api();
+        let source: Arc<dyn TweetSource> = match config.source {
+            SourceKind::Timeline => Arc::new(api),
+            SourceKind::FullArchive => Arc::new(FullArchiveSearch::new(api, config.query.clone(), config.since, config.until)),
+        };
+        let source: Arc<dyn TweetSource> = match &config.fallback_backend {
+            Some(FallbackBackend::Nitter(instance)) => Arc::new(WithFallback::new(source, NitterSource::new(http::client()?, instance.clone(), &config.username))),
+            None => source,
+        };
+        UserRun::start_with(config, source).await
+    }
 
-    let user_output_dir = get_user_output_dir(&config.output_dir, &config.username).unwrap();
-    let user_checkpoint_file_path = get_user_checkpoint_file_path(&user_output_dir).unwrap();
+    /// Like [start](UserRun::start), with the Tweets of `source` instead of the Twitter API.
+    pub async fn start_with(config: Config, source: Arc<dyn TweetSource>) -> Result<UserRun, DownloadError> {
+        let client = http::media_client()?;
 
-    info!("username: {}, output_dir: {}", &config.username, &user_output_dir.into_os_string().into_string().unwrap());
-    let mut total_count: u32 = 0;
-    loop {
-        let checkpoint = get_checkpoint(&user_checkpoint_file_path, reset_once)?;
-        reset_once = false;
+        let id = get_twitter_id(source.as_ref(), &config.username).await?;
+
+        let user_output_dir = get_user_output_dir(&config.output_dir, &config.username)?;
+        let lock = lock::acquire(&user_output_dir, &config.username, config.wait_for_lock).await?;
+        let volumes = Arc::new(Volumes::new(&config.output_dir, &config.volumes)?);
+        let state = Arc::new(StateStore::open(&config.output_dir)?);
+
+        info!("username: {}, output_dir: {}", &config.username, user_output_dir.display());
+        tui::set_state(&config.username, UserState::Running);
+        let archive = config.archive.map(|format| Arc::new(RunArchive::new(&user_output_dir, &config.username, format)));
+
+        let checkpoint = get_checkpoint(&state, &config.username, &user_output_dir, config.reset_marker)?;
+
+        // --sync-new only tops up the Tweets newer than the newest one seen by earlier runs
+        let since_id = if config.sync_new {
+            match state.get_newest_id(&config.username)? {
+                Some(newest_id) => {
+                    info!("username: {}, newest_id: {}. Syncing new tweets only", &config.username, newest_id);
+                    Some(newest_id)
+                }
+                None => {
+                    warn!("username: {}. No newest tweet recorded yet, doing a regular run instead of syncing new tweets", &config.username);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let sync_new = since_id.is_some();
+
+        // the pinned Tweet can be older than the checkpoint or the 3200 Tweets of the timeline
+        let pinned = match source.fetch_pinned_tweet(id).await {
+            Ok(page) if page.tweets.is_some() => Some(page),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("username: {}. Cannot get the pinned tweet: {}", &config.username, e);
+                None
+            }
+        };
 
-        if checkpoint == 0 {
+        let done = checkpoint == 0 && !sync_new && pinned.is_none();
+        if checkpoint == 0 && !sync_new {
             info!("username: {}, checkpoint: {}. All media files are downloaded. Consider --reset-marker if you want to start from latest.", config.username, checkpoint);
-            return Ok("Ok".into());
         }
 
-        info!("username: {}, checkpoint: {}. Will get media for tweets", &config.username, checkpoint);
+        // a run stopped within a Tweet resumes with that Tweet, skipping its processed media
+        let resume = if config.reset_marker {
+            state.clear_resume_position(&config.username)?;
+            None
+        } else if sync_new {
+            None
+        } else {
+            state.get_resume_position(&config.username)?.filter(|p| p.tweet_id <= checkpoint)
+        };
+        let marker = match resume {
+            Some(position) => {
+                info!("username: {}, tweet_id: {}, media_index: {}. Resuming within the tweet", &config.username, position.tweet_id, position.media_index);
+                position.tweet_id + 1
+            }
+            None if sync_new => u64::MAX,
+            None => checkpoint,
+        };
+
+        let ctx = RunContext { source, client, config: Arc::new(config), id, volumes, state, dedup_stats: Arc::default(), run_stats: Arc::default(), archive };
+        Ok(UserRun { ctx, cutoff: None, marker, since_id, resume, pinned, pagination_token: None, pages: 0, count: 0, done, _lock: lock })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.ctx.config.username
+    }
+
+    /// Returns the Twitter id of the user.
+    pub fn user_id(&self) -> u64 {
+        self.ctx.id
+    }
+
+    /// Downloads the media of `tweets` from outside the timeline, e.g. from the [stream](crate::stream), like the
+    /// pinned Tweet: existing files do not end the run and the checkpoint is left alone.
+    ///
+    /// Returns the number of downloaded files.
+    pub async fn download_tweets(&mut self, tweets: &TweetsPage) -> Result<u32, DownloadError> {
+        let page = download_media(&self.ctx, 0, None, None, None, Some(tweets)).await?;
+        self.count += page.count;
+        Ok(page.count)
+    }
+
+    /// Returns true once there is no further page to walk.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
 
-        match download_media(&api, &config, id, checkpoint).await {
-            Ok((mut oldest_id, count)) => {
-                total_count += count;
+    /// Returns the number of files downloaded so far.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
 
-                oldest_id = update_checkpoint(&user_checkpoint_file_path, &oldest_id).unwrap();
+    /// Returns the duplicates found so far, see [dedup](crate::dedup).
+    pub fn dedup_stats(&self) -> &DedupStats {
+        &self.ctx.dedup_stats
+    }
+
+    /// Returns the files skipped and failed so far and the bytes written.
+    pub fn run_stats(&self) -> &RunStats {
+        &self.ctx.run_stats
+    }
 
-                info!("username: {}, oldest_id: {}. Downloaded {} files for tweets", &config.username, oldest_id, count);
+    /// Returns why the run stopped early, None if it walked all its pages or was [shut down](crate::shutdown).
+    pub fn cutoff(&self) -> Option<Cutoff> {
+        self.cutoff
+    }
 
-                if !config.download_all {
-                    break;
+    /// Downloads the media of the next page and moves the checkpoint past it.
+    ///
+    /// Rests a bit before any page but the first. Errors other than rate limiting end the run, they are logged and kept as the [cutoff](UserRun::cutoff), not returned.
+    pub async fn next_page(&mut self) -> Result<(), DownloadError> {
+        if self.done {
+            return Ok(());
+        }
+        if self.pages > 0 && !shutdown::sleep(SLEEP_TIME).await {
+            self.done = true;
+            return Ok(());
+        }
+        // paused or skipped on the dashboard, between pages so the checkpoint is written
+        let config = &self.ctx.config;
+        while tui::is_paused(&config.username) {
+            if !shutdown::sleep(Duration::from_secs(1)).await {
+                self.done = true;
+                return Ok(());
+            }
+        }
+        if tui::is_skipped(&config.username) {
+            info!("username: {}. Skipped on the dashboard", &config.username);
+            self.done = true;
+            return Ok(());
+        }
+        // before the first page and between pages, so no download runs out of space halfway
+        if let Some(min_free_space) = config.min_free_space {
+            let available = self.ctx.volumes.available_space()?;
+            if available < min_free_space {
+                error!("username: {}, checkpoint: {}. Only {} free on the output filesystem, less than the minimum of {}. Stopping", &config.username, self.marker, format_bytes(available), format_bytes(min_free_space));
+                self.cutoff = Some(Cutoff::LowDiskSpace);
+                self.done = true;
+                return Ok(());
+            }
+        }
+        let sync_new = self.since_id.is_some();
+
+        info!("username: {}, checkpoint: {}, pagination_token: {}. Will get media for tweets", &config.username, self.marker, self.pagination_token.as_deref().unwrap_or("-"));
+
+        match download_media(&self.ctx, self.marker, self.since_id, self.pagination_token.as_deref(), self.resume, self.pinned.as_ref()).await {
+            Ok(page) => {
+                self.pages += 1;
+                self.count += page.count;
+                self.resume = None;
+                self.pinned = None;
+
+                if let Some(newest_id) = page.newest_id.as_ref().and_then(|n| n.parse::<u64>().ok()) {
+                    self.ctx.state.update_newest_id(&config.username, newest_id)?;
+                }
+
+                let oldest_id = match page.oldest_id {
+                    Some(oldest_id) if sync_new => oldest_id,
+                    Some(oldest_id) => update_checkpoint(&self.ctx.state, &config.username, &oldest_id)?,
+                    None => {
+                        info!("username: {}. No more tweets", &config.username);
+                        self.done = true;
+                        return Ok(());
+                    }
+                };
+
+                info!("username: {}, oldest_id: {}. Downloaded {} files for tweets", &config.username, oldest_id, page.count);
+
+                if shutdown::is_requested() {
+                    warn!("username: {}, checkpoint: {}. Interrupted. {} files downloaded before stopping", &config.username, oldest_id, self.count);
+                    self.done = true;
+                } else if config.max_pages.is_some_and(|max_pages| self.pages >= max_pages) {
+                    info!("username: {}, checkpoint: {}. Walked {} pages, the most for a run", &config.username, oldest_id, self.pages);
+                    self.done = true;
+                } else if !config.download_all && !sync_new && config.max_pages.is_none() {
+                    self.done = true;
+                } else {
+                    match page.next_token {
+                        Some(token) => {
+                            info!("username: {}, checkpoint: {}. Resting a bit. Will continue with the next page...", config.username, oldest_id);
+                            self.pagination_token = Some(token);
+                        }
+                        None => {
+                            info!("username: {}, checkpoint: {}. No more tweets", &config.username, oldest_id);
+                            self.done = true;
+                        }
+                    }
+                }
+            }
+            Err(err) if err.is_rate_limited() => {
+                let rate_limit = ratelimit::probe_user_tweets(&self.ctx.client, &config.credentials, &config.username, self.ctx.id).await;
+                if !ratelimit::wait(&config.username, &rate_limit).await {
+                    self.cutoff = Some(Cutoff::RateLimited);
+                    self.done = true;
                 }
-                info!("username: {}, checkpoint: {}. Resetting checkpoint and resting a bit. Will continue...", config.username, oldest_id);
-                thread::sleep(SLEEP_TIME);
             }
             Err(err) => {
-                warn!("{}", err);
-                break;
+                warn!("username: {}. {}", &config.username, err);
+                self.cutoff = Some(Cutoff::of(&err));
+                self.done = true;
             }
         }
+        Ok(())
     }
-    return Ok(format!("Download complete. {} files downloaded.", total_count).into());
 }
 
 /// Ensures that the user's output directory is present.
@@ -87,166 +382,706 @@ fn get_user_output_dir(output_dir: &PathBuf, username: &str) -> Result<PathBuf,
     let mut builder = DirBuilder::new();
     builder.recursive(true);
 
-    return match builder.create(&path) {
+    match builder.create(&path) {
         Ok(..) => Ok(path),
         Err(err) => Err(err)
-    };
-}
-
-/// Returns the path to the user's checkpoint file.
-/// Checkpoint file stores the tweet id of the oldest tweet processed the application
-fn get_user_checkpoint_file_path(user_output_dir: &PathBuf) -> Result<PathBuf, io::Error> {
-    let mut path = PathBuf::new();
-    path.push(user_output_dir);
-    path.push(CHECKPOINT_FILENAME);
-
-    Ok(path)
+    }
 }
 
-/// Reads the checkpoint file `user_checkpoint_file_path` and returns the value as u64. Value is a Tweet::id
+/// Reads the checkpoint of `username` from the `state` database and returns the value as u64. Value is a Tweet::id
 ///
-/// If `reset_marker` is true update the `user_checkpoint_file_path` with u64::MAX value and return u64::MAX
+/// If `reset_marker` is true update the checkpoint with u64::MAX value and return u64::MAX
 ///
-/// If `user_checkpoint_file_path` does not exists, create the `user_checkpoint_file_path` with u64::MAX value and return u64::MAX
-///
-/// If `user_checkpoint_file_path` exists, read the contents and return the value
-fn get_checkpoint(user_checkpoint_file_path: &PathBuf, reset_marker: bool) -> Result<u64, io::Error> {
-    return if reset_marker || !Path::new(&user_checkpoint_file_path).exists() {
-        let _ = update_checkpoint(user_checkpoint_file_path, u64::MAX.to_string().as_str())?;
-        Ok(u64::MAX)
-    } else {
-        let contents: String = fs::read_to_string(&user_checkpoint_file_path)?;
-        Ok(contents.parse::<u64>().unwrap_or(u64::MAX))
-    };
+/// If there is no checkpoint (nor a legacy checkpoint file in `user_output_dir`), store u64::MAX and return u64::MAX
+fn get_checkpoint(state: &StateStore, username: &str, user_output_dir: &Path, reset_marker: bool) -> Result<u64, DownloadError> {
+    let stored = if reset_marker { None } else { state.get_checkpoint(username, user_output_dir)? };
+    match stored {
+        Some(checkpoint) => Ok(checkpoint),
+        None => {
+            state.set_checkpoint(username, u64::MAX)?;
+            Ok(u64::MAX)
+        }
+    }
 }
 
-/// Updates the `user_checkpoint_file_path` file with the given `checkpoint` value. Value is a Tweet::id
+/// Updates the checkpoint of `username` in the `state` database with the given `checkpoint` value. Value is a Tweet::id
 ///
 /// Returns the `checkpoint` untouched.
-fn update_checkpoint(user_checkpoint_file_path: &PathBuf, checkpoint: &str) -> Result<String, io::Error> {
-    let mut file = File::create(user_checkpoint_file_path)?;
-    file.write(checkpoint.as_bytes())?;
-    file.sync_all()?;
+fn update_checkpoint(state: &StateStore, username: &str, checkpoint: &str) -> Result<String, DownloadError> {
+    let oldest_id = checkpoint.parse::<u64>().map_err(|e| DownloadError::Other(format!("Invalid checkpoint {}: {}", checkpoint, e)))?;
+    state.set_checkpoint(username, oldest_id)?;
     Ok(checkpoint.into())
 }
 
-/// Calls [TwitterApi::get_user_by_username](TwitterApi::get_user_by_username) to retrieve `u64` userid associated with Twitter username
+/// Calls [TweetSource::get_user_id](TweetSource::get_user_id) to retrieve `u64` userid associated with Twitter username
 ///
-/// Returns Error is any error occurs or Twitter user does not exist.
-async fn get_twitter_id(api: &TwitterApi<BearerToken>, config: &Config) -> Result<u64, Box<dyn Error>> {
-    let username: &str = &(config.username);
-
-    if username.len() == 0 {
-        return Err("username is required to lookup user id".into());
+/// Returns [DownloadError::UserNotFound](DownloadError::UserNotFound) if the Twitter user does not exist, or any other error.
+pub(crate) async fn get_twitter_id(source: &dyn TweetSource, username: &str) -> Result<u64, DownloadError> {
+    if username.is_empty() {
+        return Err(DownloadError::Other("username is required to lookup user id".into()));
     }
 
-    let user = api.get_user_by_username(username)
-        .send()
-        .await?;
-
-    if let Some(data) = user.into_data() {
-        let id = data.id.as_u64();
-        if id > 0 {
-            info!("username {}, id: {}", username, id);
-            return Ok(id);
-        }
-    }
+    let id = source.get_user_id(username).await?;
+    info!("username: {}, id: {}", username, id);
+    Ok(id)
+}
 
-    return Err(format!("Cannot find id for username {}", username).into());
+/// Outcome of [download_media](download_media) for one page of Tweets.
+#[derive(Debug)]
+struct Page {
+    /// id of the last(actually earliest) Tweet processed, None if the page had no Tweets
+    oldest_id: Option<String>,
+    /// id of the newest Tweet of the page
+    newest_id: Option<String>,
+    /// token of the next page, None if there is none or the page was left early
+    next_token: Option<String>,
+    /// number of successfully downloaded files
+    count: u32,
 }
 
-/// Retrieves Tweets for the user extracts the `Media` info and triggers the download the files locally.
+/// Retrieves Tweets for the user from the source of `ctx`, extracts the `Media` info and triggers the download the files locally.
 ///
-/// Get `Config::count` Tweets for `Config::username` until the `marker` Tweet id.
+/// Get `Config::count` Tweets for `Config::username` until the `marker` Tweet id (and since the `since_id` Tweet id), the page given
+/// by `pagination_token` or the first one.
 ///
-/// Check if there is Media associated with the Tweet. If there is a `Media::Photo` then [download_url](download_url)
+/// Check if there is Media associated with the Tweet. If there is a `Media::Photo`, with `Config::videos` a video or
+/// animated GIF, or with `Config::video_previews` the preview image of one, then [download_file](download_file) is
+/// spawned as a task. Videos offered as HLS playlist only are skipped with [HlsMode::Skip](HlsMode::Skip).
+/// At most `Config::concurrency` downloads run at the same time, all sharing the client of `ctx`.
+/// New files go to the user's directory on the first of the volumes that is not full.
+/// With `Config::organize_by_source` media of Tweets crediting another account (see [source](crate::source))
+/// go to the directory of that account instead.
 ///
-/// If the file is not downloaded because it exists, check the `Config::download_all` parameter to decide to bail iteration or not.
+/// Media on the do-not-redownload list of the state database are always skipped, see [forget](crate::forget).
+/// Media recorded as downloaded in the state database are skipped. Files found on any of the volumes without
+/// a record (e.g. downloaded by an older version) are recorded and skipped as well. For a skipped file, check the `Config::download_all` parameter to decide to bail iteration or not.
 /// If the file exists and `Config::download_all` is false, there is no need to iterate the rest because we most like got them during previous runs of the program.
-/// If [download_url](download_url) fails, log the error keep iterating the tweets, do not bail.
+/// The existence check is done in Tweet order before any download is spawned, so bailing still points the checkpoint at the right Tweet.
+/// If [download_file](download_file) fails, log the error keep iterating the tweets, do not bail.
 ///
-/// Returns a tuple for `oldest_id` for the id of the last(actually earliest) Tweet id and the a counter for the successfully downloaded files.
+/// On a [shutdown](crate::shutdown) request no further downloads are spawned and the `oldest_id` returned is the
+/// last Tweet whose media were all spawned, or `marker` if there is none.
+///
+/// When bailing or stopping within a Tweet, the position within it is stored as the user's [ResumePosition](ResumePosition),
+/// so the next run skips the media already processed. The media of the `resume` Tweet before its position are skipped.
+/// Otherwise the resume position is cleared once the page is done.
+///
+/// With `Config::save_links` the links of the Tweets are appended to the user's [links](crate::links) file, with
+/// `Config::save_tweets` the Tweets to the user's [Tweets](crate::tweets) file.
+/// Every downloaded file is appended to the [manifest](crate::manifest) of its directory and gets a [sidecar](crate::sidecar)
+/// with the metadata of its Tweet, unless `Config::dedup` found it to be a duplicate of an earlier download, counted in the dedup stats of `ctx`.
+///
+/// The `pinned` Tweet, if any, is processed before the Tweets of the page and the Tweets of the user in the self-thread
+/// of a Tweet follow it with `Config::include_thread`, then the Tweets it quotes with `Config::include_quoted`, named
+/// and attributed to their authors. Their existing files never bail and they leave the checkpoint
+/// alone. With a `marker` of 0 and no `since_id` no page is fetched, only the `pinned` Tweets are processed.
+///
+/// The Tweets of the page are processed in `Config::order`. Out of the API's order, existing files do not bail and a
+/// shutdown leaves the checkpoint at `marker`, so the page is walked again by the next run.
+///
+/// All spawned downloads are awaited before returning.
+///
+/// Returns the [Page](Page).
 ///
 /// Or returns an Error.
-async fn download_media(api: &TwitterApi<BearerToken>, config: &Config, id: u64, marker: u64) -> Result<(String, u32), Box<dyn Error>> {
-    let mut count: u32 = 0;
-    let user_output_dir = get_user_output_dir(&config.output_dir, &config.username)?;
-
-    let mut req_tweets = api.get_user_tweets(id);
-
-    req_tweets
-        .max_results(config.count.into())
-        .exclude([Exclude::Replies, Exclude::Retweets])
-        .media_fields([MediaField::Url, MediaField::Type])
-        .tweet_fields(
-            [TweetField::AuthorId,
-                TweetField::CreatedAt,
-                TweetField::Attachments,
-                TweetField::Entities,
-                TweetField::Text
-            ])
-        .expansions([TweetExpansion::AttachmentsMediaKeys, ]);
-
-    if marker != u64::MAX {
-        req_tweets.until_id(marker);
-    }
-
-    let tweets_response = req_tweets.send().await?;
-    let tweets_data = tweets_response.clone().into_data();
+async fn download_media(ctx: &RunContext, marker: u64, since_id: Option<u64>, pagination_token: Option<&str>, resume: Option<ResumePosition>, pinned: Option<&TweetsPage>) -> Result<Page, DownloadError> {
+    let RunContext { source, config, volumes, state, run_stats, id, .. } = ctx;
+    let id = *id;
+    let semaphore = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    let mut downloads: Vec<JoinHandle<Result<bool, String>>> = Vec::new();
 
+    let request = PageRequest {
+        user_id: id,
+        max_results: config.count,
+        until_id: if marker != u64::MAX { Some(marker) } else { None },
+        since_id,
+        pagination_token: pagination_token.map(String::from),
+    };
+    // every Tweet of the timeline is done, there is nothing older than Tweet 0, only `pinned` is processed
+    let fetched = marker != 0 || since_id.is_some();
+    let page = if fetched {
+        source.fetch_tweets_page(&request).await?
+    } else {
+        TweetsPage { meta: Some(PageMeta::default()), ..TweetsPage::default() }
+    };
+    let tweets_data = page.tweets;
+    let tweets_meta = page.meta;
+    let newest_id = tweets_meta.as_ref().and_then(|m| m.newest_id.clone());
+    let pinned_tweets = pinned.and_then(|p| p.tweets.as_deref()).unwrap_or_default();
 
-    match tweets_data {
-        Some(td) => {
-            let tweets_includes = tweets_response.clone().into_includes();
-            let media_map = generate_media_map(tweets_includes);
-            for tweet in td.iter() {
+    // without Tweets the return section below handles it
+    if let Some(td) = tweets_data.or_else(|| (!pinned_tweets.is_empty()).then(Vec::new)) {
+        let mut media_map = page.media;
+        if let Some(pinned) = pinned {
+            media_map.extend(pinned.media.clone());
+        }
+        // the Tweets quoted by the Tweets of the page, looked up at once
+        let quoted_tweet_ids: Vec<u64> = match config.include_quoted {
+            true => pinned_tweets.iter().chain(td.iter()).flat_map(quoted_ids).collect(),
+            false => Vec::new(),
+        };
+        let quoted_page = match quoted_tweet_ids.is_empty() {
+            true => TweetsPage::default(),
+            false => source.fetch_tweets(&quoted_tweet_ids).await.unwrap_or_else(|e| {
+                warn!("username: {}. Cannot get the quoted tweets: {}", &config.username, e);
+                TweetsPage::default()
+            }),
+        };
+        media_map.extend(quoted_page.media.clone());
+        let quoted_tweets: HashMap<u64, &Tweet> = quoted_page.tweets.iter().flatten().map(|t| (t.id.as_u64(), t)).collect();
+        if config.save_links {
+            let user_output_dir = get_user_output_dir(&config.output_dir, &config.username)?;
+            if let Err(e) = links::append_links(&user_output_dir, &config.username, td.iter()) {
+                error!("username: {}. Cannot save the links of the tweets: {}", &config.username, e);
+            }
+        }
+        if config.save_tweets {
+            let user_output_dir = get_user_output_dir(&config.output_dir, &config.username)?;
+            if let Err(e) = tweets::append_tweets(&user_output_dir, td.iter()) {
+                error!("username: {}. Cannot save the tweets: {}", &config.username, e);
+            }
+        }
+        let mut last_done: Option<String> = None;
+        // output files of the downloads spawned for this page, which may not exist yet
+        let mut claimed: HashSet<PathBuf> = HashSet::new();
+        let mut storage_noted = false;
+        // out of order the Tweets processed so far are no contiguous range, a page left early is walked again
+        let reordered = config.order != Order::Newest;
+        // the pinned Tweet goes first, unless it is in the page anyway
+        let pinned_tweets = pinned_tweets.iter()
+            .filter(|p| !td.iter().any(|t| t.id.as_u64() == p.id.as_u64()))
+            .map(|t| (t, true));
+        for (tweet, is_pinned) in pinned_tweets.chain(config.order.sort(&td).into_iter().map(|t| (t, false))) {
+            progress::tweet_scanned();
+            if shutdown::is_requested() {
+                let count = join_downloads(&config.username, run_stats, downloads).await;
+                let checkpoint = last_done.filter(|_| !reordered).unwrap_or_else(|| marker.to_string());
+                return Ok(Page { oldest_id: Some(checkpoint), newest_id, next_token: None, count });
+            }
+            if !config.filter.accepts(tweet) {
+                info!("username: {}, tweet_id: {}. Filtered out, skipping.", &config.username, tweet.id);
+                if !is_pinned {
+                    last_done = Some(tweet.id.to_string());
+                }
+                continue;
+            }
+            // the rest of a self-thread is left out of the timeline as replies
+            let thread: Vec<Tweet> = match tweet.conversation_id.as_ref().map(|c| c.as_u64()) {
+                Some(conversation_id) if config.include_thread && conversation_id == tweet.id.as_u64() => match source.fetch_thread(id, conversation_id).await {
+                    Ok(thread) => {
+                        media_map.extend(thread.media);
+                        thread.tweets.unwrap_or_default().into_iter()
+                            .filter(|t| t.id.as_u64() != tweet.id.as_u64() && config.filter.accepts(t))
+                            .collect()
+                    }
+                    Err(e) => {
+                        warn!("username: {}, tweet_id: {}. Cannot get the thread: {}", &config.username, tweet.id, e);
+                        Vec::new()
+                    }
+                },
+                _ => Vec::new(),
+            };
+            if !thread.is_empty() {
+                info!("username: {}, tweet_id: {}. {} more Tweets in its thread", &config.username, tweet.id, thread.len());
+            }
+            // with their quoted Tweets, by the authors of those
+            let mut group: Vec<(&Tweet, bool, Option<&str>)> = vec![(tweet, is_pinned, None)];
+            group.extend(thread.iter().map(|t| (t, true, None)));
+            let quoted: Vec<(&Tweet, bool, Option<&str>)> = group.iter()
+                .flat_map(|(t, _, _)| quoted_ids(t))
+                .filter_map(|quoted_id| quoted_tweets.get(&quoted_id).copied())
+                .map(|q| (q, true, q.author_id.as_ref().and_then(|a| quoted_page.authors.get(&a.as_u64())).map(String::as_str)))
+                .collect();
+            group.extend(quoted);
+            for (tweet, off_timeline, quoted_author) in group {
                 if let Some(attachments) = &tweet.attachments {
                     if let Some(media_keys) = &attachments.media_keys {
-                        for media_key in media_keys.iter() {
+                        let original_author = match quoted_author {
+                            Some(author) if author.eq_ignore_ascii_case(&config.username) => None,
+                            Some(author) => {
+                                info!("username: {}, tweet_id: {}, original_author: {}. Quoted", &config.username, tweet.id, author);
+                                Some(author.to_string())
+                            }
+                            None => source::detect_original_author(tweet, &config.username),
+                        };
+                        if let (Some(author), None) = (&original_author, quoted_author) {
+                            info!("username: {}, tweet_id: {}, original_author: {}. Probably reposted", &config.username, tweet.id, author);
+                        }
+                        let directory_user = match &original_author {
+                            Some(author) if config.organize_by_source => author.clone(),
+                            _ => config.username.clone(),
+                        };
+                        for (media_index, media_key) in media_keys.iter().enumerate() {
+                            if resume.is_some_and(|p| p.tweet_id == tweet.id.as_u64() && media_index < p.media_index) {
+                                continue;
+                            }
+                            if media_index > 0 && !reordered && shutdown::is_requested() {
+                                state.set_resume_position(&config.username, ResumePosition { tweet_id: tweet.id.as_u64(), media_index })?;
+                                let count = join_downloads(&config.username, run_stats, downloads).await;
+                                let checkpoint = last_done.unwrap_or_else(|| marker.to_string());
+                                return Ok(Page { oldest_id: Some(checkpoint), newest_id, next_token: None, count });
+                            }
                             if let Some(media) = media_map.get(&media_key.to_string()) {
-                                if media.kind == MediaType::Photo {
-                                    let downloaded = download_url(&config.username, &user_output_dir, media).await;
-                                    match downloaded {
-                                        Ok(d) => {
-                                            if d {
-                                                count = count + 1;
-                                            } else if !config.download_all {
-                                                warn!("username: {}. File exists. Bailing because we most likely downloaded the rests of the media already. Use --download_all option to go through all tweets", &config.username);
-                                                return Ok((tweet.id.to_string(), count));
-                                            }
+                                // videos are downloaded with --videos, else with --video-previews their still frame is
+                                let video = match media.kind {
+                                    MediaType::Photo => None,
+                                    _ if config.videos => hls::video_source(media),
+                                    _ => None,
+                                };
+                                let preview = match media.kind {
+                                    MediaType::Photo => None,
+                                    _ if config.video_previews && video.is_none() => media.preview_image_url.clone(),
+                                    _ => None,
+                                };
+                                let is_preview = preview.is_some();
+                                let is_video = video.is_some();
+                                let is_playlist = matches!(video, Some(VideoSource::Playlist(_)));
+                                let media = &match (video, preview) {
+                                    (Some(VideoSource::File(url) | VideoSource::Playlist(url)), _) | (None, Some(url)) => Media { url: Some(url), ..media.clone() },
+                                    (None, None) => media.clone(),
+                                };
+                                if media.kind == MediaType::Photo || is_preview || is_video {
+                                    if state.is_forgotten(media.media_key.as_str())? {
+                                        info!("username: {}, media_key: {}. Forgotten on request, skipping.", &config.username, media.media_key.as_str());
+                                        run_stats.add_skipped();
+                                        continue;
+                                    }
+
+                                    if !config.filter.accepts_media(media) {
+                                        info!("username: {}, media_key: {}, width: {:?}, height: {:?}. Too small, skipping.", &config.username, media.media_key.as_str(), media.width, media.height);
+                                        run_stats.add_skipped();
+                                        continue;
+                                    }
+
+                                    if is_playlist && config.hls == HlsMode::Skip {
+                                        let url = media.url.as_ref().map(|u| u.to_string()).unwrap_or_default();
+                                        info!("username: {}, media_key: {}, remote: {}. Only offered as HLS playlist, skipping.", &config.username, media.media_key.as_str(), url);
+                                        let skipped = Skipped { username: &config.username, media_key: media.media_key.as_str(), tweet_id: &tweet.id.to_string(), url: &url, reason: "hls", size: 0, limit: 0 };
+                                        if let Err(e) = skipped::append(&config.output_dir.join(&config.username), &skipped) {
+                                            error!("username: {}, media_key: {}. Cannot report the skipped file: {}", &config.username, media.media_key.as_str(), e);
                                         }
+                                        run_stats.add_skipped();
+                                        continue;
+                                    }
+
+                                    let local_path = match get_media_path(config, quoted_author.unwrap_or(&config.username), tweet, media_index, media) {
+                                        // the playlist is saved as the MP4 it makes
+                                        Ok(f) if is_playlist => f.with_extension("mp4"),
+                                        Ok(f) => f,
                                         Err(e) => {
-                                            error!("{}", e.to_string());
+                                            error!("username: {}, media_key: {}. {}", &config.username, media.media_key.as_str(), e);
                                             continue;
                                         }
-                                    } // end downloaded or not
-                                } // end this is a photo
+                                    };
+
+                                    if is_downloaded(state, volumes, &config.run_id, &config.username, &directory_user, &local_path, &tweet.id.to_string(), media)? {
+                                        warn!("username: {}, media_key: {}, local: {}. File exists, skipping.", &config.username, media.media_key.as_str(), local_path.display());
+                                        run_stats.add_skipped();
+                                        // media of a thread or the pinned Tweet do not tell whether the older Tweets are done
+                                        if !config.download_all && !reordered && !off_timeline {
+                                            warn!("username: {}. File exists. Bailing because we most likely downloaded the rests of the media already. Use --download_all option to go through all tweets", &config.username);
+                                            state.set_resume_position(&config.username, ResumePosition { tweet_id: tweet.id.as_u64(), media_index: media_index + 1 })?;
+                                            let count = join_downloads(&config.username, run_stats, downloads).await;
+                                            return Ok(Page { oldest_id: Some(tweet.id.to_string()), newest_id, next_token: None, count });
+                                        }
+                                        continue;
+                                    }
+
+                                    let mut output_file = volumes.user_dir_for_new_file(&directory_user)?.join(&local_path);
+                                    // the name is taken by a different media, downloaded before or earlier in this page
+                                    let filename = output_file.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+                                    let mut suffix = 1;
+                                    while output_file.exists() || claimed.contains(&output_file) {
+                                        suffix += 1;
+                                        output_file = output_file.with_file_name(filename::with_suffix(&filename, suffix));
+                                    }
+                                    if suffix > 1 {
+                                        info!("username: {}, media_key: {}, local: {}. Name taken by another media, saving as {}", &config.username, media.media_key.as_str(), &filename, output_file.display());
+                                    }
+                                    claimed.insert(output_file.clone());
+                                    if let Some(dir) = output_file.parent() {
+                                        DirBuilder::new().recursive(true).create(dir)?;
+                                    }
+
+                                    // every download slot taken: wait for one instead of queuing more work, which holds off the next page
+                                    let permit = match semaphore.clone().try_acquire_owned() {
+                                        Ok(permit) => permit,
+                                        Err(_) => {
+                                            if !storage_noted && storage::is_storage_bound() {
+                                                info!("username: {}. Storage-bound, the output directory cannot keep up with the downloads. Pausing the Tweet pagination until it catches up", &config.username);
+                                                storage_noted = true;
+                                                progress::set_note(Some("storage-bound"));
+                                            }
+                                            semaphore.clone().acquire_owned().await.map_err(|e| DownloadError::Other(e.to_string()))?
+                                        }
+                                    };
+                                    let mut metadata = match config.compat {
+                                        Some(Compat::GalleryDl) => compat::gallery_dl_metadata(&config.username, original_author.as_deref().unwrap_or(&config.username), tweet, media, media_index),
+                                        None => sidecar::metadata(&config.username, tweet, media, media_index, original_author.as_deref()),
+                                    };
+                                    if is_preview {
+                                        metadata["preview"] = json!(true);
+                                    }
+                                    let permalink = format!("https://twitter.com/{}/status/{}", config.username, tweet.id);
+                                    let text = config.save_text.then(|| (tweet.text.clone(), tweet.created_at.or_else(|| dates::tweet_id_date(tweet.id.as_u64())).map(|d| config.timezone.local(d)), permalink.clone()));
+                                    let provenance = config.embed_metadata.then(|| Provenance {
+                                        text: tweet.text.clone(),
+                                        author: original_author.clone().unwrap_or_else(|| config.username.clone()),
+                                        date: tweet.created_at,
+                                        url: permalink,
+                                    });
+                                    let file = MediaFile {
+                                        media: media.clone(),
+                                        tweet_id: tweet.id.to_string(),
+                                        tweet_date: tweet.created_at,
+                                        archive_name: local_path.with_file_name(output_file.file_name().unwrap_or_default()),
+                                        output_file,
+                                        is_playlist,
+                                        // only images have a difference hash
+                                        near_dupes: if is_video { NearDupes::Off } else { config.near_dupes },
+                                        metadata,
+                                        text,
+                                        provenance,
+                                    };
+                                    let ctx = ctx.clone();
+                                    downloads.push(tokio::spawn(async move {
+                                        let _permit = permit;
+                                        download_file(&ctx, file).await
+                                    }.in_current_span()));
+                                } // end this is a photo or video
                             } // end matched the tweet's mediakey in the media_map
                         } // end loop attachments.media_keys
                     } // end has attachments.media_keys
                 } // end has attachments
-            } // end loop tweets
-        }
-        None => () // let this be handled by the return section below
+            } // end loop thread and quoted
+            if !is_pinned {
+                last_done = Some(tweet.id.to_string());
+            }
+        } // end loop tweets
     } // end no tweets returned
 
-    let tweets_meta = tweets_response.clone().into_meta();
+    let count = join_downloads(&config.username, run_stats, downloads).await;
+    if fetched {
+        state.clear_resume_position(&config.username)?;
+    }
 
-    return match tweets_meta {
-        Some(meta) => {
-            if let Some(oldest_id) = meta.oldest_id {
-                Ok((oldest_id, count))
-            } else {
-                Err(format!("username: {}. No more tweets", &config.username).into())
+    match tweets_meta {
+        Some(meta) => Ok(Page { oldest_id: meta.oldest_id, newest_id, next_token: meta.next_token, count }),
+        _ => Err(DownloadError::Other(format!("username: {}. Cannot access Tweets Meta. Something is up!", &config.username)))
+    }
+}
+
+/// A media file spawned for download by [download_media](download_media), with what the steps after the download need.
+struct MediaFile {
+    media: Media,
+    tweet_id: String,
+    tweet_date: Option<OffsetDateTime>,
+    /// where the file is downloaded to, moved by a conversion
+    output_file: PathBuf,
+    /// path of the file in the [archive](crate::archive) of the run
+    archive_name: PathBuf,
+    /// the media url is an HLS playlist
+    is_playlist: bool,
+    near_dupes: NearDupes,
+    /// contents of the [sidecar](crate::sidecar)
+    metadata: Value,
+    /// text, local date and permalink of the Tweet with `Config::save_text`
+    text: Option<(String, Option<OffsetDateTime>, String)>,
+    /// embedded into the file with `Config::embed_metadata`
+    provenance: Option<Provenance>,
+}
+
+/// Downloads `file` with [fetch_media](fetch_media) and, if it was, runs the steps after the download in order:
+/// [convert](convert_file), [embed](embed_provenance), [dedup](deduplicate), [near-dupes](skip_near_duplicate),
+/// [record](record_download), [sidecar](write_sidecar), [text](write_text), [mtime](set_mtime), [exec](run_exec) and
+/// [archive](add_to_archive). A skipped duplicate owns no file, its steps end with the record.
+///
+/// Returns true if the file was downloaded, or the logged error.
+async fn download_file(ctx: &RunContext, mut file: MediaFile) -> Result<bool, String> {
+    if !fetch_media(ctx, &file).await? {
+        return Ok(false);
+    }
+    convert_file(ctx, &mut file);
+    embed_provenance(ctx, &file);
+    let size = fs::metadata(&file.output_file).map(|m| m.len()).unwrap_or(0);
+    progress::file_downloaded(size);
+    tui::file_downloaded(&ctx.config.username, size);
+    ctx.run_stats.add_bytes(size);
+    let sha256 = sha256_file(&file.output_file).ok();
+    let mut duplicate = deduplicate(ctx, &file, sha256.as_deref());
+    let dhash = match duplicate {
+        None => image_hash(ctx, &file),
+        Some(_) => None,
+    };
+    if let Some(dhash) = dhash {
+        duplicate = skip_near_duplicate(ctx, &file, dhash);
+    }
+    match &duplicate {
+        Some(Duplicate::Skipped(original)) | Some(Duplicate::Hardlinked(original)) => {
+            info!("username: {}, media_key: {}, local: {}, original: {}. Duplicate content, {:?}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), original.display(), ctx.config.dedup);
+            ctx.dedup_stats.add(size);
+        }
+        None => ctx.volumes.add_used(&file.output_file, size),
+    }
+    record_download(ctx, &file, size, sha256, &duplicate, dhash);
+    if matches!(duplicate, Some(Duplicate::Skipped(_))) {
+        return Ok(true);
+    }
+    write_sidecar(ctx, &file);
+    write_text(ctx, &file);
+    // a hardlink shares the modification time of the earlier download
+    if duplicate.is_none() {
+        set_mtime(ctx, &file);
+    }
+    run_exec(ctx, &file).await;
+    add_to_archive(ctx, &file);
+    Ok(true)
+}
+
+/// Downloads `file` with [download_url](download_url), or [download_playlist](hls::download_playlist) for a playlist.
+///
+/// A file larger than `Config::max_file_size` is reported as [skipped](crate::skipped), a failure is recorded in the
+/// state database.
+///
+/// Returns false if the file exists or is too large, or the error message.
+async fn fetch_media(ctx: &RunContext, file: &MediaFile) -> Result<bool, String> {
+    let config = &ctx.config;
+    let media_key = file.media.media_key.as_str();
+    let url = file.media.url.as_ref().map(|u| u.to_string()).unwrap_or_default();
+    let downloaded = match &file.media.url {
+        Some(playlist) if file.is_playlist => hls::download_playlist(&ctx.client, &config.retry, config.stall_timeout, config.max_file_size, config.hls, &config.username, media_key, playlist, &file.output_file).await,
+        _ => download_url(&ctx.client, &config.retry, config.stall_timeout, config.max_file_size, &config.credentials, &config.username, &file.tweet_id, &file.output_file, &file.media).await,
+    };
+    match downloaded {
+        Ok(downloaded) => Ok(downloaded),
+        Err(DownloadError::TooLarge { size, limit }) => {
+            info!("username: {}, media_key: {}, size: {}, limit: {}. Too large, skipping.", config.username, media_key, size, limit);
+            let skipped = Skipped { username: &config.username, media_key, tweet_id: &file.tweet_id, url: &url, reason: "too_large", size, limit };
+            if let Err(e) = skipped::append(&config.output_dir.join(&config.username), &skipped) {
+                error!("username: {}, media_key: {}. Cannot report the skipped file: {}", config.username, media_key, e);
+            }
+            Ok(false)
+        }
+        Err(e) => {
+            if let Err(db_err) = ctx.state.record_failed(&config.run_id, &config.username, media_key, &file.tweet_id, &url, Some(&file.output_file), &e.to_string()) {
+                error!("username: {}, media_key: {}. Cannot record the failure: {}", config.username, media_key, db_err);
+            }
+            Err(format!("username: {}, media_key: {}. {}", config.username, media_key, e))
+        }
+    }
+}
+
+/// Converts the file with `Config::convert`, keeping it as downloaded if that fails.
+fn convert_file(ctx: &RunContext, file: &mut MediaFile) {
+    if let Some(format) = ctx.config.convert {
+        let extension = file.output_file.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default();
+        match convert::convert(&file.output_file, format, ctx.config.quality) {
+            Ok(Some(converted)) => {
+                file.metadata["original_extension"] = json!(extension);
+                file.archive_name.set_extension(format.extension());
+                file.output_file = converted;
+            }
+            Ok(None) => (),
+            Err(e) => warn!("username: {}, media_key: {}, local: {}. Cannot convert the file, keeping it as downloaded: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e),
+        }
+    }
+}
+
+/// Embeds the provenance of the Tweet into the file with `Config::embed_metadata`.
+fn embed_provenance(ctx: &RunContext, file: &MediaFile) {
+    if let Some(provenance) = &file.provenance {
+        if let Err(e) = embed::embed(&file.output_file, provenance) {
+            warn!("username: {}, media_key: {}, local: {}. Cannot embed the metadata: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e);
+        }
+    }
+}
+
+/// Looks up an earlier download with the same content, see [dedup](crate::dedup). Returns None if there is none or
+/// the lookup fails.
+fn deduplicate(ctx: &RunContext, file: &MediaFile, sha256: Option<&str>) -> Option<Duplicate> {
+    let sha256 = sha256?;
+    dedup::deduplicate(ctx.config.dedup, &ctx.state, file.media.media_key.as_str(), &file.output_file, sha256).unwrap_or_else(|e| {
+        warn!("username: {}, media_key: {}, local: {}. Cannot deduplicate: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e);
+        None
+    })
+}
+
+/// Returns the difference hash of the image with `Config::near_dupes`, see [similar](crate::similar).
+fn image_hash(ctx: &RunContext, file: &MediaFile) -> Option<u64> {
+    match file.near_dupes {
+        NearDupes::Off => None,
+        _ => similar::dhash(&file.output_file).map_err(|e| {
+            warn!("username: {}, media_key: {}, local: {}. Cannot hash the image: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e);
+        }).ok(),
+    }
+}
+
+/// Looks up an earlier download looking like the image of `dhash`. With [NearDupes::Skip](NearDupes::Skip) the file is
+/// deleted and returned as [skipped](Duplicate::Skipped) duplicate of it, otherwise it is only logged.
+fn skip_near_duplicate(ctx: &RunContext, file: &MediaFile, dhash: u64) -> Option<Duplicate> {
+    let (username, media_key) = (&ctx.config.username, file.media.media_key.as_str());
+    match similar::find_similar(&ctx.state, media_key, dhash) {
+        Ok(Some(original)) => {
+            info!("username: {}, media_key: {}, local: {}, original: {}. Looks like an earlier download", username, media_key, file.output_file.display(), original.display());
+            if file.near_dupes != NearDupes::Skip {
+                return None;
+            }
+            match fs::remove_file(&file.output_file) {
+                Ok(()) => Some(Duplicate::Skipped(original)),
+                Err(e) => {
+                    warn!("username: {}, media_key: {}, local: {}. Cannot delete the near-duplicate: {}", username, media_key, file.output_file.display(), e);
+                    None
+                }
+            }
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("username: {}, media_key: {}, local: {}. Cannot look up near-duplicates: {}", username, media_key, file.output_file.display(), e);
+            None
+        }
+    }
+}
+
+/// Records the download in the state database, a skipped `duplicate` as [duplicate_of](MediaRecord::duplicate_of) the
+/// earlier download, with the difference hash of the image if there is one.
+fn record_download(ctx: &RunContext, file: &MediaFile, size: u64, sha256: Option<String>, duplicate: &Option<Duplicate>, dhash: Option<u64>) {
+    let (username, media_key) = (&ctx.config.username, file.media.media_key.as_str());
+    let record = MediaRecord {
+        username: username.clone(),
+        media_key: media_key.to_string(),
+        tweet_id: file.tweet_id.clone(),
+        url: file.media.url.as_ref().map(|u| u.to_string()).unwrap_or_default(),
+        local_path: file.output_file.clone(),
+        size,
+        sha256,
+        run_id: ctx.config.run_id.clone(),
+        duplicate_of: match duplicate {
+            Some(Duplicate::Skipped(original)) => Some(original.clone()),
+            _ => None,
+        },
+    };
+    if let Err(e) = ctx.state.record_downloaded(&record) {
+        error!("username: {}, media_key: {}. Cannot record the download: {}", username, media_key, e);
+    }
+    if let Some(dhash) = dhash {
+        if let Err(e) = ctx.state.set_dhash(username, media_key, dhash) {
+            error!("username: {}, media_key: {}. Cannot record the image hash: {}", username, media_key, e);
+        }
+    }
+}
+
+/// Writes the metadata [sidecar](crate::sidecar) of the file.
+fn write_sidecar(ctx: &RunContext, file: &MediaFile) {
+    if let Err(e) = sidecar::write(&file.output_file, &file.metadata) {
+        error!("username: {}, media_key: {}. Cannot write the metadata sidecar: {}", ctx.config.username, file.media.media_key.as_str(), e);
+    }
+}
+
+/// Writes the text of the Tweet next to the file with `Config::save_text`.
+fn write_text(ctx: &RunContext, file: &MediaFile) {
+    if let Some((text, date, permalink)) = &file.text {
+        if let Err(e) = sidecar::write_text(&file.output_file, text, *date, permalink) {
+            error!("username: {}, media_key: {}. Cannot write the text file: {}", ctx.config.username, file.media.media_key.as_str(), e);
+        }
+    }
+}
+
+/// Sets the modification time of the file to the date of `Config::date_policy` with `Config::set_mtime`.
+fn set_mtime(ctx: &RunContext, file: &MediaFile) {
+    if !ctx.config.set_mtime {
+        return;
+    }
+    if let Some(date) = dates::file_date(ctx.config.date_policy, file.tweet_date, &file.output_file) {
+        if let Err(e) = dates::set_mtime(&file.output_file, date) {
+            warn!("username: {}, media_key: {}, local: {}. Cannot set the modification time: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e);
+        }
+    }
+}
+
+/// Runs the `Config::exec` command on the file.
+async fn run_exec(ctx: &RunContext, file: &MediaFile) {
+    if let Some(exec) = &ctx.config.exec {
+        exec.run(&ExecFile { path: &file.output_file, username: &ctx.config.username, tweet_id: &file.tweet_id, media_key: file.media.media_key.as_str(), media_type: &file.media.kind }).await;
+    }
+}
+
+/// Adds the file to the [archive](crate::archive) of the run with `Config::archive`.
+fn add_to_archive(ctx: &RunContext, file: &MediaFile) {
+    if let Some(archive) = &ctx.archive {
+        if let Err(e) = archive.add(&file.output_file, &file.archive_name) {
+            error!("username: {}, media_key: {}, local: {}. Cannot add the file to the archive: {}", ctx.config.username, file.media.media_key.as_str(), file.output_file.display(), e);
+        }
+    }
+}
+
+/// Awaits the spawned [download_file](download_file) tasks in the order they were spawned.
+///
+/// Failed downloads are logged and counted in `run_stats`, like the skipped ones.
+///
+/// Returns the number of successfully downloaded files.
+async fn join_downloads(username: &str, run_stats: &RunStats, downloads: Vec<JoinHandle<Result<bool, String>>>) -> u32 {
+    let mut count: u32 = 0;
+    for download in downloads {
+        match download.await {
+            Ok(Ok(true)) => count += 1,
+            Ok(Ok(false)) => run_stats.add_skipped(),
+            Ok(Err(e)) => {
+                error!("{}", e);
+                run_stats.add_failed();
+            }
+            Err(e) => {
+                error!("username: {}. Download task failed: {}", username, e);
+                run_stats.add_failed();
             }
         }
-        _ => Err(format!("username: {}. Cannot access Tweets Meta. Something is up!", &config.username).into())
+    }
+    count
+}
+
+/// Returns true if the media was downloaded before.
+///
+/// Looks up the `state` database first. A file found on the `volumes` without a record is recorded as downloaded by run `run_id`.
+/// Files are looked for at `local_path` and, in case the archive was started with another [Layout](Layout), directly in the
+/// user directory. A file recorded for a different media is a name collision, not a download of `media`.
+#[allow(clippy::too_many_arguments)]
+fn is_downloaded(state: &StateStore, volumes: &Volumes, run_id: &str, username: &str, directory_user: &str, local_path: &Path, tweet_id: &str, media: &Media) -> Result<bool, DownloadError> {
+    if state.downloaded_path(username, media.media_key.as_str())?.is_some() {
+        return Ok(true);
+    }
+
+    let existing = volumes.find_existing(directory_user, local_path)
+        .or_else(|| local_path.file_name().and_then(|f| volumes.find_existing(directory_user, Path::new(f))));
+    // a file recorded for a different media only has the same name
+    let existing = match existing {
+        Some(path) => match state.media_key_by_path(&path)? {
+            Some(media_key) if media_key != media.media_key.as_str() => None,
+            _ => Some(path),
+        },
+        None => None,
     };
+    match existing {
+        Some(path) => {
+            let record = MediaRecord {
+                username: username.into(),
+                media_key: media.media_key.to_string(),
+                tweet_id: tweet_id.into(),
+                url: media.url.as_ref().map(|u| u.to_string()).unwrap_or_default(),
+                size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                sha256: None,
+                local_path: path,
+                run_id: run_id.into(),
+                duplicate_of: None,
+            };
+            state.record_downloaded(&record)?;
+            Ok(true)
+        }
+        None => Ok(false)
+    }
 }
 
 /// Create a hashmap of media_keys to Media objects in order to help locate the Media objects which are
 /// referred by media_key in the Tweet responses.
-fn generate_media_map(expansions: Option<Expansions>) -> HashMap<String, Media> {
+pub(crate) fn generate_media_map(expansions: Option<Expansions>) -> HashMap<String, Media> {
     let mut media_map: HashMap<String, Media> = HashMap::new();
 
     if let Some(e) = expansions {
@@ -260,36 +1095,586 @@ fn generate_media_map(expansions: Option<Expansions>) -> HashMap<String, Media>
     media_map
 }
 
-/// Download the Media::url into user's output directory.
+/// Returns the local path for the `media` at `media_index` of `tweet` by `username`, relative to the user directory:
+/// the [layout](Layout) directory of the run and the file name of its [filename template](filename::FilenameTemplate),
+/// by default `{media_key}_{username}_{remote filename}`. Media of possibly sensitive Tweets go under `sensitive/` with
+/// [Sensitive::SeparateDir](filter::Sensitive::SeparateDir).
+///
+/// Returns an Error if the media url is not available.
+fn get_media_path(config: &Config, username: &str, tweet: &Tweet, media_index: usize, media: &Media) -> Result<PathBuf, DownloadError> {
+    match &media.url {
+        Some(url) => {
+            let original = url.path().split("/").last().unwrap_or("");
+            let date = tweet.created_at.or_else(|| dates::tweet_id_date(tweet.id.as_u64())).map(|d| config.timezone.local(d));
+            let filename = config.filename_template.render(&FilenameValues {
+                username,
+                tweet_id: &tweet.id.to_string(),
+                media_key: media.media_key.as_str(),
+                index: media_index,
+                count: media_count(tweet),
+                date,
+                original,
+                thread: &tweet.conversation_id.as_ref().unwrap_or(&tweet.id).to_string(),
+            });
+            let subdir = config.layout.subdir(date, &media.kind);
+            if config.filter.sensitive == Sensitive::SeparateDir && filter::is_sensitive(tweet) {
+                Ok(PathBuf::from(SENSITIVE_DIR).join(subdir).join(filename))
+            } else {
+                Ok(subdir.join(filename))
+            }
+        }
+        None => Err(DownloadError::Other("Media url not available.".into()))
+    }
+}
+
+/// Returns the ids of the Tweets quoted by `tweet`.
+fn quoted_ids(tweet: &Tweet) -> Vec<u64> {
+    tweet.referenced_tweets.iter().flatten()
+        .filter(|referenced| referenced.kind == ReferencedTweetKind::Quoted)
+        .map(|referenced| referenced.id.as_u64())
+        .collect()
+}
+
+/// Returns the number of media attached to `tweet`.
+pub(crate) fn media_count(tweet: &Tweet) -> usize {
+    tweet.attachments.as_ref().and_then(|a| a.media_keys.as_ref()).map_or(0, |keys| keys.len())
+}
+
+/// Download the Media::url into `output_file` using the shared `client`.
+///
+/// The file is written as `output_file`.part first and only renamed to `output_file` once complete.
+/// An existing .part file left by an interrupted run is resumed with a Range request when the server allows it.
+///
+/// Transient failures (see [retry::is_transient](retry::is_transient)) are retried according to `retry`, resuming the .part file.
+/// A download receiving no bytes for `stall_timeout` is aborted as [DownloadError::Stalled](DownloadError::Stalled) and retried
+/// the same way, however long the whole download takes.
+///
+/// Media urls can be signed and expire. A url rejected as expired (see [DownloadError::is_expired_link](DownloadError::is_expired_link))
+/// is refreshed by fetching Tweet `tweet_id` again, and the download retried once with the fresh url.
+///
+/// A file larger than `max_file_size` is not downloaded, see [fetch_to_part_file](fetch_to_part_file).
 ///
 /// If the file exists, return false
 ///
 /// If any error occurs, return the Error.
-async fn download_url(username: &String, user_output_dir: &PathBuf, media: &Media) -> Result<bool, Box<dyn Error>> {
-    return match &media.url {
+#[allow(clippy::too_many_arguments)]
+async fn download_url(client: &Client, retry: &RetryPolicy, stall_timeout: Duration, max_file_size: Option<u64>, credentials: &Credentials, username: &str, tweet_id: &str, output_file: &PathBuf, media: &Media) -> Result<bool, DownloadError> {
+    match &media.url {
         Some(u) => {
-            let url = u.clone();
+            let mut url = u.clone();
 
-            let filename = url.path().split("/").last().unwrap_or("");
-            let local_filename = format!("{}_{}_{}", media.media_key.to_string(), username, filename);
+            if !Path::new(output_file).exists() {
+                let media_key = media.media_key.as_str();
+                let started = Instant::now();
+                info!(event = "download_started", username, media_key, "username: {}, media_key: {}, remote: {}. Downloading", username, media_key, url);
+                let bytes = match fetch_file(client, retry, stall_timeout, max_file_size, username, media_key, url.clone(), output_file).await {
+                    Ok(bytes) => bytes,
+                    Err(e) if e.is_expired_link() => {
+                        warn!("username: {}, media_key: {}, remote: {}. Link expired: {}. Fetching the tweet again for a fresh url", username, media_key, url, e);
+                        url = refresh_media_url(credentials, tweet_id, media_key).await?;
+                        fetch_file(client, retry, stall_timeout, max_file_size, username, media_key, url.clone(), output_file).await?
+                    }
+                    Err(e) => return Err(e),
+                };
 
-            let mut output_file = PathBuf::new();
-            output_file.push(user_output_dir);
-            output_file.push(&local_filename);
-
-            if !Path::new(&output_file).exists() {
-                let resp = reqwest::get(url.clone()).await?.bytes().await?;
-                let mut out = File::create(output_file)?;
-                out.write_all(&*resp)?;
-
-                info!("username: {}, media_key: {}, remote: {}, local: {}. Downloaded", username, media.media_key.as_str(), url, &local_filename);
+                let duration_ms = started.elapsed().as_millis() as u64;
+                info!(event = "download_completed", username, media_key, bytes, duration_ms,
+                    "username: {}, media_key: {}, remote: {}, local: {}. Downloaded", username, media_key, url, output_file.display());
                 Ok(true)
             } else {
-                warn!("username: {}, media_key: {}, remote: {}, local: {}. File exists, skipping.", username, media.media_key.as_str(), url, &local_filename);
+                warn!("username: {}, media_key: {}, remote: {}, local: {}. File exists, skipping.", username, media.media_key.as_str(), url, output_file.display());
                 Ok(false)
             }
         }
-        None => Err("Media url not available.".into())
+        None => Err(DownloadError::Other("Media url not available.".into()))
+    }
+}
+
+/// Fetches Tweet `tweet_id` again and returns the current url of its media `media_key`.
+pub(crate) async fn refresh_media_url(credentials: &Credentials, tweet_id: &str, media_key: &str) -> Result<Url, DownloadError> {
+    let id = tweet_id.parse::<u64>().map_err(|e| DownloadError::Other(format!("Invalid tweet id {}: {}", tweet_id, e)))?;
+    let api = credentials.api();
+    let response = api.get_tweet(id)
+        .media_fields([MediaField::Url, MediaField::Type, MediaField::Variants])
+        .expansions([TweetExpansion::AttachmentsMediaKeys])
+        .send()
+        .await?;
+
+    response.into_includes()
+        .and_then(|includes| includes.media)
+        .and_then(|media| media.into_iter().find(|m| m.media_key.as_str() == media_key))
+        .and_then(|m| match hls::video_source(&m) {
+            Some(VideoSource::File(url)) => Some(url),
+            _ => m.url,
+        })
+        .ok_or_else(|| DownloadError::Other(format!("tweet_id: {}, media_key: {}. No fresh url, the media is gone", tweet_id, media_key)))
+}
+
+/// Downloads `url` into `output_file` through a .part file, see [download_url](download_url). An existing `output_file` is replaced.
+///
+/// A file that is no media file of the type of `output_file` is [quarantined](magic::quarantine) instead and fails
+/// with [DownloadError::Corrupt](DownloadError::Corrupt).
+///
+/// Returns the size of the file.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn fetch_file(client: &Client, retry: &RetryPolicy, stall_timeout: Duration, max_size: Option<u64>, username: &str, media_key: &str, url: Url, output_file: &Path) -> Result<u64, DownloadError> {
+    let part_file = get_part_file_path(output_file);
+    let size = fetch_with_retry(client, retry, stall_timeout, max_size, username, media_key, url, &part_file).await?;
+    if let Err(mismatch) = magic::check(&part_file, output_file)? {
+        let path = magic::quarantine(&part_file, output_file)?;
+        warn!("username: {}, media_key: {}, local: {}. {}, quarantined", username, media_key, path.display(), mismatch);
+        return Err(DownloadError::Corrupt { mismatch, path });
+    }
+    fs::rename(&part_file, output_file)?;
+    Ok(size)
+}
+
+/// Returns `output_file` with `.part` appended.
+fn get_part_file_path(output_file: &Path) -> PathBuf {
+    let mut part_file = output_file.as_os_str().to_owned();
+    part_file.push(".part");
+    PathBuf::from(part_file)
+}
+
+/// Calls [fetch_to_part_file](fetch_to_part_file) and retries transient failures with the backoff of `retry`.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_with_retry(client: &Client, retry: &RetryPolicy, stall_timeout: Duration, max_size: Option<u64>, username: &str, media_key: &str, url: Url, part_file: &Path) -> Result<u64, DownloadError> {
+    let mut attempt: u32 = 0;
+    loop {
+        match fetch_to_part_file(client, url.clone(), part_file, stall_timeout, max_size).await {
+            Ok(size) => return Ok(size),
+            Err(e) if attempt < retry.retries && e.is_transient() => {
+                let delay = retry.delay(attempt);
+                attempt += 1;
+                warn!("username: {}, media_key: {}, remote: {}. Download failed: {}. Retry {}/{} in {} ms", username, media_key, url, e, attempt, retry.retries, delay.as_millis());
+                clock::sleep(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// GETs `url` into `part_file`, streaming the body. Error responses are errors.
+///
+/// If `part_file` has content, only the rest is requested with a Range header. If the server ignores the
+/// Range header the `part_file` is started over. A `416 Range Not Satisfiable` completes the `part_file` only if the
+/// size in its Content-Range is the size of the `part_file`, else the `part_file` is started over too.
+///
+/// Waiting longer than `stall_timeout` for the response or for the next chunk of the body fails with
+/// [DownloadError::Stalled](DownloadError::Stalled). The bytes received so far stay in `part_file`.
+///
+/// A body larger than `max_size`, by its Content-Length or once more bytes arrive, fails with
+/// [DownloadError::TooLarge](DownloadError::TooLarge) and `part_file` is removed.
+///
+/// The time spent writing is reported to [storage](storage) to tell a slow output device.
+///
+/// Returns the size of the complete `part_file`.
+async fn fetch_to_part_file(client: &Client, url: Url, part_file: &Path, stall_timeout: Duration, max_size: Option<u64>) -> Result<u64, DownloadError> {
+    let started = Instant::now();
+    let mut offset = fs::metadata(part_file).map(|m| m.len()).unwrap_or(0);
+
+    let mut req = client.get(url.clone());
+    if offset > 0 {
+        req = req.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut resp = tokio::time::timeout(stall_timeout, req.send()).await
+        .map_err(|_| DownloadError::Stalled(stall_timeout))??;
+    if capture::is_enabled() {
+        capture::record(
+            "media",
+            json!({ "method": "GET", "url": url.as_str(), "offset": offset }),
+            json!({ "status": resp.status().as_u16(), "headers": capture::headers_json(resp.headers()) }),
+        );
+    }
+
+    if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        if unsatisfied_range_size(&resp) == Some(offset) {
+            // the part file already holds the whole body
+            return Ok(offset);
+        }
+        // the part file is longer than the body, or of another version of it
+        fs::remove_file(part_file)?;
+        offset = 0;
+        resp = tokio::time::timeout(stall_timeout, client.get(url.clone()).send()).await
+            .map_err(|_| DownloadError::Stalled(stall_timeout))??;
+    }
+    if let Err(e) = resp.error_for_status_ref() {
+        return Err(e.into());
+    }
+    if let (Some(limit), Some(length)) = (max_size, resp.content_length()) {
+        // a partial response only announces the rest
+        let size = if resp.status() == StatusCode::PARTIAL_CONTENT { offset + length } else { length };
+        if size > limit {
+            let _ = fs::remove_file(part_file);
+            return Err(DownloadError::TooLarge { size, limit });
+        }
+    }
+
+    let (mut out, mut size) = if offset > 0 && resp.status() == StatusCode::PARTIAL_CONTENT {
+        (OpenOptions::new().append(true).open(part_file)?, offset)
+    } else {
+        (File::create(part_file)?, 0)
     };
+    let mut writing = Duration::ZERO;
+    let length = resp.content_length().map(|length| size + length);
+    let name = part_file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let bar = progress::file_bar(&name, length, size);
+    let download = tui::download(&name, length, size);
+
+    while let Some(chunk) = tokio::time::timeout(stall_timeout, resp.chunk()).await.map_err(|_| DownloadError::Stalled(stall_timeout))?? {
+        let write_started = Instant::now();
+        out.write_all(&chunk)?;
+        writing += write_started.elapsed();
+        size += chunk.len() as u64;
+        if let Some(bar) = &bar {
+            bar.inc(chunk.len() as u64);
+        }
+        if let Some(download) = &download {
+            download.inc(chunk.len() as u64);
+        }
+        if let Some(limit) = max_size.filter(|limit| size > *limit) {
+            drop(out);
+            let _ = fs::remove_file(part_file);
+            return Err(DownloadError::TooLarge { size, limit });
+        }
+        throttle::consume(chunk.len() as u64).await;
+    }
+    let write_started = Instant::now();
+    out.sync_all()?;
+    writing += write_started.elapsed();
+    storage::record(writing, started.elapsed());
+
+    Ok(size)
 }
 
+/// Returns the size of the whole body from the Content-Range `bytes */<size>` of a `416 Range Not Satisfiable`.
+fn unsatisfied_range_size(resp: &reqwest::Response) -> Option<u64> {
+    resp.headers().get(CONTENT_RANGE)?.to_str().ok()?
+        .strip_prefix("bytes */")?
+        .trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use twitter_v2::{Media, Tweet};
+
+    use crate::Config;
+    use crate::dedup::Dedup;
+    use crate::state::StateStore;
+    use crate::twitter::UserRun;
+    use crate::twitter::api::{MemorySource, PageRequest};
+    use crate::twitter::auth::Credentials;
+    use crate::twitter::retry::DEFAULT_STALL_TIMEOUT;
+
+    const USERNAME: &str = "testuser";
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0 a jpeg, by its first bytes";
+    const USER_ID: u64 = 42;
+
+    /// Returns an empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(output_dir: &Path) -> Config {
+        Config::builder()
+            .credentials(Credentials::Bearer("test".into()))
+            .username(USERNAME)
+            .output_dir(output_dir)
+            .count(5)
+            .build()
+            .unwrap()
+    }
+
+    fn tweet(id: u64, media_keys: &[String]) -> Tweet {
+        let mut tweet = json!({ "id": id.to_string(), "text": format!("tweet {}", id), "created_at": "2023-01-01T00:00:00Z" });
+        if !media_keys.is_empty() {
+            tweet["attachments"] = json!({ "media_keys": media_keys });
+        }
+        serde_json::from_value(tweet).unwrap()
+    }
+
+    fn photo(media_key: &str, url: &str) -> Media {
+        serde_json::from_value(json!({ "media_key": media_key, "type": "photo", "url": url })).unwrap()
+    }
+
+    /// Tweets `1..=count` without media.
+    fn text_tweets(count: u64) -> Vec<Tweet> {
+        (1..=count).map(|id| tweet(id, &[])).collect()
+    }
+
+    /// Serves `body` for every GET until the test ends, answering a Range past its end with 416. Returns the base url.
+    async fn serve_files(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                    let range_start = request.lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                    if range_start.is_some_and(|start| start >= body.len()) {
+                        let head = format!("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", body.len());
+                        let _ = stream.write_all(head.as_bytes()).await;
+                        return;
+                    }
+                    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(body).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn walk(config: Config, source: Arc<MemorySource>) -> UserRun {
+        let mut run = UserRun::start_with(config, source).await.unwrap();
+        while !run.is_done() {
+            run.next_page().await.unwrap();
+        }
+        run
+    }
+
+    fn checkpoint(output_dir: &Path) -> Option<u64> {
+        StateStore::open(output_dir).unwrap().get_checkpoint(USERNAME, &output_dir.join(USERNAME)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn download_all_walks_every_page() {
+        let dir = test_dir("download-all");
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, text_tweets(12), vec![]));
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).download_all(true).build().unwrap();
+
+        walk(config, source.clone()).await;
+
+        let tokens: Vec<Option<String>> = source.requests().into_iter().map(|r| r.pagination_token).collect();
+        assert_eq!(tokens, vec![None, Some("5".into()), Some("10".into())]);
+        assert_eq!(checkpoint(&dir), Some(1));
+    }
+
+    #[tokio::test]
+    async fn max_pages_stops_the_walk() {
+        let dir = test_dir("max-pages");
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, text_tweets(12), vec![]));
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).max_pages(Some(2)).build().unwrap();
+
+        walk(config, source.clone()).await;
+
+        let tokens: Vec<Option<String>> = source.requests().into_iter().map(|r| r.pagination_token).collect();
+        assert_eq!(tokens, vec![None, Some("5".into())]);
+        assert_eq!(checkpoint(&dir), Some(3));
+    }
+
+    #[tokio::test]
+    async fn next_run_continues_below_the_checkpoint() {
+        let dir = test_dir("checkpoint");
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, text_tweets(12), vec![]));
+
+        walk(config(&dir), source.clone()).await;
+        assert_eq!(checkpoint(&dir), Some(8));
+
+        walk(config(&dir), source.clone()).await;
+        let requests = source.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], PageRequest { user_id: USER_ID, max_results: 5, ..PageRequest::default() });
+        assert_eq!(requests[1].until_id, Some(8));
+        assert_eq!(checkpoint(&dir), Some(3));
+    }
+
+    #[tokio::test]
+    async fn downloads_photos_once() {
+        let dir = test_dir("photos");
+        let base = serve_files(JPEG).await;
+        let keys = ["3_2".to_string(), "3_3".to_string()];
+        let tweets = vec![tweet(1, &[]), tweet(2, &keys[..1]), tweet(3, &keys[1..])];
+        let media = keys.iter().map(|key| photo(key, &format!("{}/{}.jpg", base, key))).collect();
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, tweets, media));
+
+        let count = walk(config(&dir), source.clone()).await.count();
+        assert_eq!(count, 2);
+        let state = StateStore::open(&dir).unwrap();
+        for key in keys.iter() {
+            let path = state.downloaded_path(USERNAME, key).unwrap().expect("recorded");
+            assert_eq!(fs::read(path).unwrap(), JPEG);
+        }
+
+        // walked again from the newest Tweet, the first existing file ends the run
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).reset_marker(true).build().unwrap();
+        let run = walk(config, source.clone()).await;
+        assert_eq!(run.count(), 0);
+        assert_eq!(run.run_stats().skipped(), 1);
+    }
+
+    #[tokio::test]
+    async fn skipped_duplicates_own_no_file() {
+        let dir = test_dir("skipped-duplicates");
+        let base = serve_files(JPEG).await;
+        let keys = ["3_2".to_string(), "3_3".to_string()];
+        let tweets = vec![tweet(2, &keys[..1]), tweet(3, &keys[1..])];
+        let media = keys.iter().map(|key| photo(key, &format!("{}/{}.jpg", base, key))).collect();
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, tweets, media));
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).dedup(Dedup::Skip).build().unwrap();
+        walk(config, source).await;
+
+        // newest first, 3_2 has the content of 3_3
+        let state = StateStore::open(&dir).unwrap();
+        let original = state.downloaded_path(USERNAME, &keys[1]).unwrap().expect("recorded");
+        assert_eq!(state.downloaded_path(USERNAME, &keys[0]).unwrap(), Some(original.clone()));
+        assert_eq!(state.downloaded_media(Some(USERNAME)).unwrap().len(), 1);
+
+        // forgetting the duplicate keeps the file of the original
+        crate::forget::forget(&dir, &keys[0], None).unwrap();
+        assert!(original.is_file());
+
+        // forgetting the original downloads its duplicates again
+        crate::state::StateStore::open(&dir).unwrap().unforget(&keys[0]).unwrap();
+        crate::forget::forget(&dir, &keys[1], None).unwrap();
+        assert!(!original.exists());
+        let failed = StateStore::open(&dir).unwrap().failed_media(Some(USERNAME)).unwrap();
+        assert_eq!(failed.iter().map(|f| f.media_key.as_str()).collect::<Vec<_>>(), vec![keys[0].as_str()]);
+    }
+
+    #[tokio::test]
+    async fn include_thread_downloads_the_replies_of_a_thread() {
+        let dir = test_dir("thread");
+        let base = serve_files(JPEG).await;
+        let key = "3_11".to_string();
+        let in_thread = |id: u64, media_keys: &[String]| {
+            let mut tweet = serde_json::to_value(tweet(id, media_keys)).unwrap();
+            tweet["conversation_id"] = json!("10");
+            serde_json::from_value::<Tweet>(tweet).unwrap()
+        };
+        let tweets = vec![in_thread(10, &[]), in_thread(11, std::slice::from_ref(&key))];
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, tweets, vec![photo(&key, &format!("{}/{}.jpg", base, key))]));
+
+        // the reply is not in the timeline
+        assert_eq!(walk(config(&dir), source.clone()).await.count(), 0);
+
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).reset_marker(true)
+            .include_thread(true).filename_template("{thread}_{media_key}.jpg".parse().unwrap()).build().unwrap();
+        assert_eq!(walk(config, source).await.count(), 1);
+        let path = StateStore::open(&dir).unwrap().downloaded_path(USERNAME, &key).unwrap().expect("recorded");
+        assert_eq!(path.file_name().unwrap(), "10_3_11.jpg");
+    }
+
+    #[tokio::test]
+    async fn downloads_the_pinned_tweet_once() {
+        let dir = test_dir("pinned");
+        let base = serve_files(JPEG).await;
+        let key = "3_1".to_string();
+        let mut tweets = text_tweets(12);
+        tweets[0] = tweet(1, std::slice::from_ref(&key));
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, tweets, vec![photo(&key, &format!("{}/{}.jpg", base, key))]).pinned(USERNAME, 1));
+
+        // pinned far below the first page, which sets the checkpoint
+        let run = walk(config(&dir), source.clone()).await;
+        assert_eq!(run.count(), 1);
+        assert_eq!(checkpoint(&dir), Some(8));
+        // a run holds the lock of the user directory until it is dropped
+        drop(run);
+
+        let run = walk(config(&dir), source).await;
+        assert_eq!((run.count(), run.run_stats().skipped()), (0, 1));
+        assert_eq!(checkpoint(&dir), Some(3));
+    }
+
+    #[tokio::test]
+    async fn include_quoted_names_the_quoted_author() {
+        let dir = test_dir("quoted");
+        let base = serve_files(JPEG).await;
+        let key = "3_5".to_string();
+        let mut quoted = serde_json::to_value(tweet(5, std::slice::from_ref(&key))).unwrap();
+        quoted["author_id"] = json!("7");
+        let mut quoting = serde_json::to_value(tweet(20, &[])).unwrap();
+        quoting["referenced_tweets"] = json!([{ "type": "quoted", "id": "5" }]);
+        let source = Arc::new(MemorySource::new()
+            .user(USERNAME, USER_ID, vec![serde_json::from_value(quoting).unwrap()], vec![])
+            .user("other", 7, vec![serde_json::from_value(quoted).unwrap()], vec![photo(&key, &format!("{}/{}.jpg", base, key))]));
+
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).include_quoted(true).build().unwrap();
+        assert_eq!(walk(config, source).await.count(), 1);
+        let path = StateStore::open(&dir).unwrap().downloaded_path(USERNAME, &key).unwrap().expect("recorded");
+        assert_eq!(path.file_name().unwrap(), "3_5_other_3_5.jpg");
+        let sidecar: serde_json::Value = serde_json::from_str(&fs::read_to_string(crate::sidecar::sidecar_path(&path)).unwrap()).unwrap();
+        assert_eq!(sidecar["original_author"], "other");
+    }
+
+    #[tokio::test]
+    async fn quarantines_error_pages() {
+        let dir = test_dir("error-page");
+        let base = serve_files(b"<html>502 Bad Gateway</html>").await;
+        let key = "3_1".to_string();
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, vec![tweet(1, std::slice::from_ref(&key))], vec![photo(&key, &format!("{}/{}.jpg", base, key))]));
+
+        let run = walk(config(&dir), source).await;
+        assert_eq!((run.count(), run.run_stats().failed()), (0, 1));
+        let failed = StateStore::open(&dir).unwrap().failed_media(Some(USERNAME)).unwrap();
+        assert_eq!(failed.len(), 1);
+        let local_path = failed[0].local_path.clone().expect("recorded");
+        assert!(!local_path.exists());
+        assert!(local_path.parent().unwrap().join(crate::magic::QUARANTINE_DIR).join(local_path.file_name().unwrap()).is_file());
+    }
+
+    #[tokio::test]
+    async fn range_not_satisfiable_checks_the_part_file_size() {
+        let dir = test_dir("range-not-satisfiable");
+        let base = serve_files(JPEG).await;
+        let url: reqwest::Url = format!("{}/3_1.jpg", base).parse().unwrap();
+        let client = crate::http::client().unwrap();
+        let part_file = dir.join("3_1.jpg.part");
+
+        // complete: kept as it is
+        fs::write(&part_file, JPEG).unwrap();
+        let size = super::fetch_to_part_file(&client, url.clone(), &part_file, DEFAULT_STALL_TIMEOUT, None).await.unwrap();
+        assert_eq!((size, fs::read(&part_file).unwrap()), (JPEG.len() as u64, JPEG.to_vec()));
+
+        // longer than the body: started over
+        fs::write(&part_file, [JPEG, b"stale bytes of another file"].concat()).unwrap();
+        let size = super::fetch_to_part_file(&client, url, &part_file, DEFAULT_STALL_TIMEOUT, None).await.unwrap();
+        assert_eq!((size, fs::read(&part_file).unwrap()), (JPEG.len() as u64, JPEG.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn sync_new_only_walks_newer_tweets() {
+        let dir = test_dir("sync-new");
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, text_tweets(4), vec![]));
+        walk(config(&dir), source).await;
+        assert_eq!(StateStore::open(&dir).unwrap().get_newest_id(USERNAME).unwrap(), Some(4));
+
+        let source = Arc::new(MemorySource::new().user(USERNAME, USER_ID, text_tweets(7), vec![]));
+        let config = Config::builder().credentials(Credentials::Bearer("test".into())).username(USERNAME).output_dir(&dir).count(5).sync_new(true).build().unwrap();
+        walk(config, source.clone()).await;
+
+        let requests = source.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].since_id, requests[0].until_id), (Some(4), None));
+        assert_eq!(StateStore::open(&dir).unwrap().get_newest_id(USERNAME).unwrap(), Some(7));
+        // the checkpoint of the regular runs is left untouched
+        assert_eq!(checkpoint(&dir), Some(1));
+    }
+
+    #[tokio::test]
+    async fn unknown_user_fails() {
+        let dir = test_dir("unknown-user");
+        let source = Arc::new(MemorySource::new());
+        assert!(UserRun::start_with(config(&dir), source).await.is_err());
+    }
+}
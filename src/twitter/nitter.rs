@@ -0,0 +1,340 @@
+//! Tweets of a user from a Nitter instance, the fallback when the API is rate limited or the token lacks access, see
+//! `--fallback-backend`.
+//!
+//! The media tab of the user, `<instance>/<user>/media`, is scraped page by page. Its photos are downloaded from
+//! `pbs.twimg.com` like those of the API, with the same state, sidecars and deduplication. Nitter does not tell the
+//! media keys, so the media are keyed by their remote file name, `pbs_<name>`. `--dedup` catches a photo downloaded
+//! through the API and through Nitter. Threads, pinned and quoted Tweets are left out while the fallback is used.
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::{Client, StatusCode, Url};
+use serde_json::json;
+use tracing::warn;
+use twitter_v2::{Media, Tweet};
+
+use crate::shutdown;
+use crate::twitter::api::{PageMeta, PageRequest, TweetSource, TweetsPage};
+use crate::twitter::error::DownloadError;
+
+/// Source the Tweets are scraped from when the API cannot be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackBackend {
+    /// a Nitter instance, written `nitter=<instance-url>`
+    Nitter(Url),
+}
+
+impl FromStr for FallbackBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("nitter", url)) => Url::parse(url)
+                .map(FallbackBackend::Nitter)
+                .map_err(|e| format!("invalid Nitter instance url '{}': {}", url, e)),
+            _ => Err(format!("unknown fallback backend '{}'. Expected nitter=<instance-url>", s)),
+        }
+    }
+}
+
+/// Prefix of the media keys of the media found on Nitter.
+pub const MEDIA_KEY_PREFIX: &str = "pbs_";
+
+/// Host of the photos of Twitter.
+const PHOTO_BASE_URL: &str = "https://pbs.twimg.com/media/";
+
+/// The media tab of a user on a Nitter instance.
+pub struct NitterSource {
+    client: Client,
+    instance: Url,
+    username: String,
+}
+
+impl NitterSource {
+    pub fn new(client: Client, instance: Url, username: &str) -> Self {
+        NitterSource { client, instance, username: username.into() }
+    }
+
+    /// Returns the HTML of the media tab at `cursor`, the first page if None.
+    async fn fetch_media_tab(&self, cursor: Option<&str>) -> Result<String, DownloadError> {
+        let mut url = self.instance.join(&format!("{}/media", self.username))
+            .map_err(|e| DownloadError::Other(format!("Invalid Nitter url: {}", e)))?;
+        if let Some(cursor) = cursor {
+            url.query_pairs_mut().append_pair("cursor", cursor);
+        }
+        let response = self.client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(DownloadError::UserNotFound(self.username.clone()));
+        }
+        Ok(response.error_for_status()?.text().await?)
+    }
+}
+
+/// A Tweet of the media tab with the file names of its photos.
+#[derive(Debug, PartialEq, Eq)]
+struct Item {
+    id: u64,
+    text: String,
+    photos: Vec<String>,
+}
+
+/// Returns the Tweets of `username` on the media tab `html`, Retweets left out, and the cursor of the next page.
+fn parse_media_tab(html: &str, username: &str) -> (Vec<Item>, Option<String>) {
+    let link = Regex::new(r#"class="tweet-link" href="/([^/"]+)/status/(\d+)"#).expect("valid regex");
+    let photo = Regex::new(r#"/pic/(?:orig/)?media%2F([A-Za-z0-9_-]+\.[a-z]+)"#).expect("valid regex");
+    let content = Regex::new(r#"(?s)class="tweet-content[^"]*"[^>]*>(.*?)</div>"#).expect("valid regex");
+    let tag = Regex::new(r"<[^>]+>").expect("valid regex");
+    let cursor = Regex::new(r#"class="show-more"[^>]*>\s*<a href="\?cursor=([^"]+)""#).expect("valid regex");
+
+    let mut items = Vec::new();
+    for chunk in html.split(r#"class="timeline-item"#).skip(1) {
+        let (author, id) = match link.captures(chunk) {
+            Some(captures) => (captures[1].to_string(), captures[2].parse().unwrap_or(0)),
+            None => continue,
+        };
+        if !author.eq_ignore_ascii_case(username) || id == 0 {
+            continue;
+        }
+        let mut photos: Vec<String> = Vec::new();
+        for captures in photo.captures_iter(chunk) {
+            if !photos.contains(&captures[1].to_string()) {
+                photos.push(captures[1].to_string());
+            }
+        }
+        let text = content.captures(chunk)
+            .map(|captures| unescape(tag.replace_all(&captures[1], "").trim()))
+            .unwrap_or_default();
+        items.push(Item { id, text, photos });
+    }
+    // the last one, the first is "Load newest" on later pages
+    let next = cursor.captures_iter(html).last().map(|captures| unescape(&captures[1]));
+    (items, next)
+}
+
+/// Returns `s` with the entities Nitter escapes replaced.
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the Tweets and media of `items`.
+fn to_page(items: &[Item], next_token: Option<String>) -> TweetsPage {
+    let mut tweets = Vec::new();
+    let mut media = HashMap::new();
+    for item in items {
+        let media_keys: Vec<String> = item.photos.iter()
+            .map(|photo| format!("{}{}", MEDIA_KEY_PREFIX, photo.split('.').next().unwrap_or(photo)))
+            .collect();
+        for (media_key, photo) in media_keys.iter().zip(item.photos.iter()) {
+            let photo: Option<Media> = serde_json::from_value(json!({ "media_key": media_key, "type": "photo", "url": format!("{}{}", PHOTO_BASE_URL, photo) })).ok();
+            if let Some(photo) = photo {
+                media.insert(media_key.clone(), photo);
+            }
+        }
+        let tweet: Option<Tweet> = serde_json::from_value(json!({ "id": item.id.to_string(), "text": item.text, "attachments": { "media_keys": media_keys } })).ok();
+        tweets.extend(tweet);
+    }
+    let meta = match (items.iter().map(|i| i.id).min(), items.iter().map(|i| i.id).max()) {
+        (Some(oldest_id), Some(newest_id)) => Some(PageMeta { oldest_id: Some(oldest_id.to_string()), newest_id: Some(newest_id.to_string()), next_token }),
+        _ => None,
+    };
+    TweetsPage { tweets: (!tweets.is_empty()).then_some(tweets), media, meta, authors: HashMap::new() }
+}
+
+fn empty_page() -> TweetsPage {
+    TweetsPage { tweets: None, media: HashMap::new(), meta: None, authors: HashMap::new() }
+}
+
+#[async_trait]
+impl TweetSource for NitterSource {
+    /// Nitter does not tell the ids of users, the user is only checked to exist.
+    async fn get_user_id(&self, _username: &str) -> Result<u64, DownloadError> {
+        self.fetch_media_tab(None).await?;
+        Ok(0)
+    }
+
+    /// Walks the media tab from the cursor of `request` until a page has Tweets in the range of `request`.
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError> {
+        let mut cursor = request.pagination_token.clone();
+        loop {
+            let html = self.fetch_media_tab(cursor.as_deref()).await?;
+            let (items, next) = parse_media_tab(&html, &self.username);
+            // newest first, the Tweets at or below since_id are known
+            let reached_since = request.since_id.is_some_and(|since_id| items.iter().any(|i| i.id <= since_id));
+            let items: Vec<Item> = items.into_iter()
+                .filter(|i| request.until_id.is_none_or(|until_id| i.id < until_id))
+                .filter(|i| request.since_id.is_none_or(|since_id| i.id > since_id))
+                .collect();
+            let next = if reached_since { None } else { next };
+            if !items.is_empty() || next.is_none() || shutdown::is_requested() {
+                return Ok(to_page(&items, next));
+            }
+            cursor = next;
+        }
+    }
+
+    async fn fetch_thread(&self, _user_id: u64, _conversation_id: u64) -> Result<TweetsPage, DownloadError> {
+        Ok(empty_page())
+    }
+
+    async fn fetch_pinned_tweet(&self, _user_id: u64) -> Result<TweetsPage, DownloadError> {
+        Ok(empty_page())
+    }
+
+    async fn fetch_tweets(&self, _ids: &[u64]) -> Result<TweetsPage, DownloadError> {
+        Ok(empty_page())
+    }
+}
+
+/// The Tweets of `primary`, or of `fallback` from the first time `primary` is rate limited or rejects the token on.
+pub struct WithFallback {
+    primary: Arc<dyn TweetSource>,
+    fallback: NitterSource,
+    failed_over: AtomicBool,
+}
+
+impl WithFallback {
+    pub fn new(primary: Arc<dyn TweetSource>, fallback: NitterSource) -> Self {
+        WithFallback { primary, fallback, failed_over: AtomicBool::new(false) }
+    }
+
+    /// Returns true if `result` of `primary` calls for the fallback, switching over for the rest of the run.
+    fn fails_over<T>(&self, result: &Result<T, DownloadError>) -> bool {
+        match result {
+            Err(e) if e.is_rate_limited() || matches!(e, DownloadError::Auth(_)) => {
+                if !self.failed_over.swap(true, Ordering::Relaxed) {
+                    warn!("username: {}, instance: {}. {}. Falling back to Nitter for the rest of the run", self.fallback.username, self.fallback.instance, e);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_failed_over(&self) -> bool {
+        self.failed_over.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl TweetSource for WithFallback {
+    async fn get_user_id(&self, username: &str) -> Result<u64, DownloadError> {
+        let result = self.primary.get_user_id(username).await;
+        if self.fails_over(&result) {
+            return self.fallback.get_user_id(username).await;
+        }
+        result
+    }
+
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError> {
+        if !self.is_failed_over() {
+            let result = self.primary.fetch_tweets_page(request).await;
+            if !self.fails_over(&result) {
+                return result;
+            }
+            // the cursors of the API mean nothing to Nitter
+            let request = PageRequest { pagination_token: None, ..request.clone() };
+            return self.fallback.fetch_tweets_page(&request).await;
+        }
+        self.fallback.fetch_tweets_page(request).await
+    }
+
+    async fn fetch_thread(&self, user_id: u64, conversation_id: u64) -> Result<TweetsPage, DownloadError> {
+        if self.is_failed_over() {
+            return self.fallback.fetch_thread(user_id, conversation_id).await;
+        }
+        let result = self.primary.fetch_thread(user_id, conversation_id).await;
+        if self.fails_over(&result) {
+            return self.fallback.fetch_thread(user_id, conversation_id).await;
+        }
+        result
+    }
+
+    async fn fetch_pinned_tweet(&self, user_id: u64) -> Result<TweetsPage, DownloadError> {
+        if self.is_failed_over() {
+            return self.fallback.fetch_pinned_tweet(user_id).await;
+        }
+        let result = self.primary.fetch_pinned_tweet(user_id).await;
+        if self.fails_over(&result) {
+            return self.fallback.fetch_pinned_tweet(user_id).await;
+        }
+        result
+    }
+
+    async fn fetch_tweets(&self, ids: &[u64]) -> Result<TweetsPage, DownloadError> {
+        if self.is_failed_over() {
+            return self.fallback.fetch_tweets(ids).await;
+        }
+        let result = self.primary.fetch_tweets(ids).await;
+        if self.fails_over(&result) {
+            return self.fallback.fetch_tweets(ids).await;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEDIA_TAB: &str = r#"<div class="timeline">
+<div class="timeline-item " data-username="alice">
+  <a class="tweet-link" href="/alice/status/30#m"></a>
+  <div class="tweet-content media-body" dir="auto">Sunset &amp; <a href="/search?q=%23sea">#sea</a></div>
+  <a class="still-image" href="/pic/orig/media%2FFqX1.jpg"><img src="/pic/media%2FFqX1.jpg%3Fname%3Dsmall"></a>
+  <a class="still-image" href="/pic/orig/media%2FFqX2.png"></a>
+</div>
+<div class="timeline-item ">
+  <a class="tweet-link" href="/bob/status/25#m"></a>
+  <div class="tweet-content media-body">retweeted</div>
+  <a class="still-image" href="/pic/orig/media%2FBob1.jpg"></a>
+</div>
+<div class="timeline-item ">
+  <a class="tweet-link" href="/Alice/status/20#m"></a>
+  <div class="tweet-content media-body">no photos</div>
+</div>
+<div class="show-more"><a href="?cursor=newest">Load newest</a></div>
+<div class="show-more"><a href="?cursor=abc%3D&amp;x">Load more</a></div>
+</div>"#;
+
+    #[test]
+    fn parses_the_fallback_backend() {
+        assert_eq!("nitter=https://nitter.example/".parse(), Ok(FallbackBackend::Nitter(Url::parse("https://nitter.example/").unwrap())));
+        assert!("nitter=not a url".parse::<FallbackBackend>().unwrap_err().contains("invalid Nitter instance url"));
+        assert!("bird=https://nitter.example/".parse::<FallbackBackend>().unwrap_err().contains("unknown fallback backend"));
+    }
+
+    #[test]
+    fn parses_the_media_tab() {
+        let (items, next) = parse_media_tab(MEDIA_TAB, "alice");
+        assert_eq!(items, vec![
+            Item { id: 30, text: "Sunset & #sea".into(), photos: vec!["FqX1.jpg".into(), "FqX2.png".into()] },
+            Item { id: 20, text: "no photos".into(), photos: vec![] },
+        ]);
+        assert_eq!(next.as_deref(), Some("abc%3D&x"));
+
+        assert_eq!(parse_media_tab("<html>no tweets</html>", "alice"), (vec![], None));
+    }
+
+    #[test]
+    fn keys_the_photos_by_their_name() {
+        let (items, next) = parse_media_tab(MEDIA_TAB, "alice");
+        let page = to_page(&items, next);
+        let tweets = page.tweets.expect("tweets");
+        assert_eq!(tweets.iter().map(|t| (t.id.as_u64(), crate::twitter::media_count(t))).collect::<Vec<_>>(), vec![(30, 2), (20, 0)]);
+        assert_eq!(page.media["pbs_FqX1"].url.as_ref().map(Url::as_str), Some("https://pbs.twimg.com/media/FqX1.jpg"));
+        assert_eq!(page.media["pbs_FqX2"].url.as_ref().map(Url::as_str), Some("https://pbs.twimg.com/media/FqX2.png"));
+        let meta = page.meta.expect("meta");
+        assert_eq!((meta.oldest_id.as_deref(), meta.newest_id.as_deref(), meta.next_token.as_deref()), (Some("20"), Some("30"), Some("abc%3D&x")));
+
+        let empty = to_page(&[], None);
+        assert!(empty.tweets.is_none() && empty.meta.is_none());
+    }
+}
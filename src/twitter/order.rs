@@ -0,0 +1,49 @@
+//! Processing order of the Tweets of a page.
+use std::str::FromStr;
+
+use twitter_v2::Tweet;
+
+/// Order the Tweets of a page are processed in. The pages themselves are always walked newest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Newest first, the order of the API.
+    Newest,
+    /// Oldest first.
+    Oldest,
+    /// Most popular first by the public metrics: likes, retweets, quotes and replies.
+    Top,
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest" => Ok(Order::Newest),
+            "oldest" => Ok(Order::Oldest),
+            "top" => Ok(Order::Top),
+            _ => Err(format!("unknown order '{}'. Expected top, newest or oldest", s)),
+        }
+    }
+}
+
+impl Order {
+    /// Returns `tweets`, given newest first, in this order.
+    pub fn sort<'a>(&self, tweets: &'a [Tweet]) -> Vec<&'a Tweet> {
+        let mut sorted: Vec<&Tweet> = tweets.iter().collect();
+        match self {
+            Order::Newest => (),
+            Order::Oldest => sorted.reverse(),
+            Order::Top => sorted.sort_by_key(|t| std::cmp::Reverse(popularity(t))),
+        }
+        sorted
+    }
+}
+
+/// Sum of the public metrics of `tweet`, 0 without metrics.
+fn popularity(tweet: &Tweet) -> u64 {
+    match &tweet.public_metrics {
+        Some(m) => m.like_count as u64 + m.retweet_count as u64 + m.quote_count.unwrap_or(0) as u64 + m.reply_count as u64,
+        None => 0,
+    }
+}
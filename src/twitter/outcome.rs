@@ -0,0 +1,68 @@
+//! Tally of a [UserRun](super::UserRun) beyond the downloaded files, for the summary of the run.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::twitter::error::DownloadError;
+
+/// Files skipped or failed by a run and the bytes it wrote, shared with the download tasks.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl RunStats {
+    /// Counts a media that was not downloaded on purpose, e.g. already there, too small or forgotten.
+    pub fn add_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a media whose download failed.
+    pub fn add_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` written by a download.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a run stopped before walking all the pages it meant to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cutoff {
+    /// the token was rejected
+    Auth,
+    /// the rate limit was exhausted and the wait for its reset was cut short
+    RateLimited,
+    /// the output filesystem fell below the minimum of free space
+    LowDiskSpace,
+    /// any other error
+    Error,
+}
+
+impl Cutoff {
+    /// Returns the cutoff for a run ended by `e`.
+    pub fn of(e: &DownloadError) -> Self {
+        match e {
+            DownloadError::Auth(_) => Cutoff::Auth,
+            e if e.is_rate_limited() => Cutoff::RateLimited,
+            _ => Cutoff::Error,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Rate limit handling for the Twitter API.
+//!
+//! `twitter_v2` does not expose response headers, so once a request fails with `429 Too Many Requests`
+//! ([DownloadError::RateLimited](crate::twitter::error::DownloadError::RateLimited)) the rate limit headers are read
+//! from a probe of the same endpoint.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Method, Url};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::capture;
+use crate::clock;
+use crate::shutdown;
+use crate::tui;
+use crate::twitter::auth::Credentials;
+
+/// Length of the Twitter API rate limit window, used when the reset time is not known.
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Extra time to wait after the reset time to be on the safe side.
+const RESET_MARGIN: Duration = Duration::from_secs(2);
+
+/// Time to wait when the reset time is already in the past by the local clock.
+const SKEWED_RESET_WAIT: Duration = Duration::from_secs(60);
+
+/// Rate limit state read from the `x-rate-limit-*` and `Retry-After` response headers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// `x-rate-limit-remaining`: requests left in the current window
+    pub remaining: Option<u32>,
+    /// `x-rate-limit-reset`: unix time in seconds when the window resets
+    pub reset: Option<u64>,
+    /// `Retry-After`: seconds to wait
+    pub retry_after: Option<u64>,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let number = |name: &str| headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+
+        RateLimit {
+            remaining: number("x-rate-limit-remaining").map(|r| r as u32),
+            reset: number("x-rate-limit-reset"),
+            retry_after: number(RETRY_AFTER.as_str()),
+        }
+    }
+
+    /// How long to wait before the next request.
+    ///
+    /// `Retry-After` wins over `x-rate-limit-reset`. Without either a full [RATE_LIMIT_WINDOW](RATE_LIMIT_WINDOW) is assumed.
+    ///
+    /// `x-rate-limit-reset` is compared with the local clock, so a skewed clock is guarded against: a reset in the past
+    /// waits [SKEWED_RESET_WAIT](SKEWED_RESET_WAIT), a reset further away than a [RATE_LIMIT_WINDOW](RATE_LIMIT_WINDOW)
+    /// is clamped to one.
+    pub fn wait_duration(&self, username: &str) -> Duration {
+        if let Some(retry_after) = self.retry_after {
+            return Duration::from_secs(retry_after) + RESET_MARGIN;
+        }
+        if let Some(reset) = self.reset {
+            let now = clock::unix_now();
+            if reset <= now {
+                warn!("username: {}. Rate limit reset {} seconds ago by the local clock, which is probably ahead. Waiting {} seconds", username, now - reset, SKEWED_RESET_WAIT.as_secs());
+                return SKEWED_RESET_WAIT;
+            }
+            let until_reset = Duration::from_secs(reset - now);
+            if until_reset > RATE_LIMIT_WINDOW {
+                warn!("username: {}. Rate limit resets in {} seconds by the local clock, which is probably behind. Waiting {} seconds instead", username, until_reset.as_secs(), RATE_LIMIT_WINDOW.as_secs());
+                return RATE_LIMIT_WINDOW + RESET_MARGIN;
+            }
+            return until_reset + RESET_MARGIN;
+        }
+        RATE_LIMIT_WINDOW
+    }
+}
+
+/// Reads the rate limit of the user Tweets endpoint for user `id` by probing it.
+pub async fn probe_user_tweets(client: &Client, credentials: &Credentials, username: &str, id: u64) -> RateLimit {
+    let url = format!("https://api.twitter.com/2/users/{}/tweets?max_results=5", id);
+    let request = match Url::parse(&url) {
+        Ok(parsed) => credentials.authorize(client.get(parsed.clone()), &Method::GET, &parsed).await,
+        Err(e) => {
+            warn!("username: {}. Cannot probe the rate limit of {}: {}", username, url, e);
+            return RateLimit::default();
+        }
+    };
+    let response = match request {
+        Ok(request) => request.send().await,
+        Err(e) => {
+            warn!("username: {}. Cannot authorize the rate limit probe: {}", username, e);
+            return RateLimit::default();
+        }
+    };
+    match response {
+        Ok(resp) => {
+            if capture::is_enabled() {
+                capture::record(
+                    "rate-limit-probe",
+                    json!({ "method": "GET", "url": url, "headers": { "authorization": capture::REDACTED } }),
+                    json!({ "status": resp.status().as_u16(), "headers": capture::headers_json(resp.headers()) }),
+                );
+            }
+            RateLimit::from_headers(resp.headers())
+        }
+        Err(e) => {
+            warn!("username: {}. Cannot read the rate limit headers: {}", username, e);
+            RateLimit::default()
+        }
+    }
+}
+
+/// Number of [wait](wait)s so far.
+static WAITS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of times a run waited for the rate limit to reset so far.
+pub fn waits() -> u64 {
+    WAITS.load(Ordering::Relaxed)
+}
+
+/// Sleeps until the rate limit window resets.
+///
+/// Returns false if the sleep was cut short by a shutdown.
+pub async fn wait(username: &str, rate_limit: &RateLimit) -> bool {
+    WAITS.fetch_add(1, Ordering::Relaxed);
+    let duration = rate_limit.wait_duration(username);
+    let wait_secs = duration.as_secs();
+    tui::rate_limited(username, duration);
+    info!(event = "rate_limited", username, wait_secs, "username: {}. Rate limited. Sleeping {} seconds until the rate limit resets. Will continue...", username, wait_secs);
+    shutdown::sleep(duration).await
+}
@@ -0,0 +1,53 @@
+//! Retry policy for transient download failures.
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::StatusCode;
+
+/// Default time a media download may go without receiving any bytes before it is aborted and retried.
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Jittered exponential backoff.
+///
+/// The delay before retry `n` (starting at 0) is picked at random between half and all of
+/// `base_delay * 2^n`, capped at `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt. 0 disables retrying.
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry`, starting at 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base_delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let millis = delay.as_millis() as u64;
+        if millis < 2 {
+            return delay;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+}
+
+/// Returns true for errors worth retrying: timeouts, connection and body errors, `429` and `5xx` responses.
+pub fn is_transient(err: &reqwest::Error) -> bool {
+    if let Some(status) = err.status() {
+        return status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+    }
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+}
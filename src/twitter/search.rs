@@ -0,0 +1,158 @@
+//! Tweets of a user from the full-archive search, for tokens with access to it (Academic Research, Pro).
+//!
+//! The timeline of a user only reaches back 3200 Tweets. The full-archive search goes back to the first Tweet and can be
+//! narrowed down by a query and a time window. Its pages are newest first like the timeline, so checkpoints carry over:
+//! a run of the timeline that reached its limit is continued by a search below its checkpoint.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use twitter_v2::TwitterApi;
+use twitter_v2::query::{MediaField, TweetExpansion, TweetField};
+
+use crate::capture;
+use crate::twitter::api::{PageMeta, PageRequest, TweetSource, TweetsPage};
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::generate_media_map;
+
+/// Where a run gets the Tweets of a user from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// The timeline of the user, its latest 3200 Tweets.
+    Timeline,
+    /// The full-archive search, see [FullArchiveSearch](FullArchiveSearch).
+    FullArchive,
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timeline" => Ok(SourceKind::Timeline),
+            "full-archive" => Ok(SourceKind::FullArchive),
+            _ => Err(format!("unknown source '{}'. Expected timeline or full-archive", s)),
+        }
+    }
+}
+
+/// Smallest and largest number of Tweets per page of the full-archive search.
+const SEARCH_RESULTS_RANGE: (u8, u8) = (10, 100);
+
+/// The Tweets of a user from the full-archive search, replies and Retweets left out like in the timeline.
+pub struct FullArchiveSearch {
+    api: TwitterApi<Credentials>,
+    /// search query the Tweets have to match as well, e.g. `#astrophotography`
+    query: Option<String>,
+    /// only Tweets created at or after
+    since: Option<OffsetDateTime>,
+    /// only Tweets created before
+    until: Option<OffsetDateTime>,
+}
+
+impl FullArchiveSearch {
+    pub fn new(api: TwitterApi<Credentials>, query: Option<String>, since: Option<OffsetDateTime>, until: Option<OffsetDateTime>) -> Self {
+        FullArchiveSearch { api, query, since, until }
+    }
+
+    /// Returns the search query for the Tweets of the user `user_id`.
+    fn query(&self, user_id: u64) -> String {
+        match &self.query {
+            Some(query) => format!("from:{} -is:retweet -is:reply ({})", user_id, query),
+            None => format!("from:{} -is:retweet -is:reply", user_id),
+        }
+    }
+}
+
+#[async_trait]
+impl TweetSource for FullArchiveSearch {
+    async fn get_user_id(&self, username: &str) -> Result<u64, DownloadError> {
+        TweetSource::get_user_id(&self.api, username).await
+    }
+
+    async fn fetch_tweets_page(&self, request: &PageRequest) -> Result<TweetsPage, DownloadError> {
+        let query = self.query(request.user_id);
+        let max_results = request.max_results.clamp(SEARCH_RESULTS_RANGE.0, SEARCH_RESULTS_RANGE.1);
+        let mut req_tweets = self.api.get_tweets_search_all(&query);
+
+        req_tweets
+            .max_results(max_results.into())
+            .media_fields([MediaField::Url, MediaField::Type, MediaField::AltText, MediaField::Width, MediaField::Height, MediaField::PreviewImageUrl, MediaField::Variants])
+            .tweet_fields(
+                [TweetField::AuthorId,
+                    TweetField::CreatedAt,
+                    TweetField::Attachments,
+                    TweetField::ConversationId,
+                    TweetField::Entities,
+                    TweetField::PublicMetrics,
+                    TweetField::PossiblySensitive,
+                    TweetField::ReferencedTweets,
+                    TweetField::Text
+                ])
+            .expansions([TweetExpansion::AttachmentsMediaKeys, ]);
+
+        if let Some(since) = self.since {
+            req_tweets.start_time(since);
+        }
+        if let Some(until) = self.until {
+            req_tweets.end_time(until);
+        }
+        if let Some(until_id) = request.until_id {
+            req_tweets.until_id(until_id);
+        }
+        if let Some(since_id) = request.since_id {
+            req_tweets.since_id(since_id);
+        }
+        // the search endpoints take the next_token of a response as pagination_token
+        if let Some(token) = &request.pagination_token {
+            req_tweets.pagination_token(token);
+        }
+
+        let response = req_tweets.send().await;
+        if capture::is_enabled() {
+            let captured_request = json!({
+                "endpoint": "GET /2/tweets/search/all",
+                "query": query,
+                "max_results": max_results,
+                "start_time": self.since.and_then(|d| d.format(&Rfc3339).ok()),
+                "end_time": self.until.and_then(|d| d.format(&Rfc3339).ok()),
+                "until_id": request.until_id,
+                "since_id": request.since_id,
+                "next_token": request.pagination_token,
+            });
+            let captured = match &response {
+                Ok(tweets) => json!({ "data": tweets.data(), "includes": tweets.includes(), "meta": tweets.meta() }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            capture::record("full-archive-search", captured_request, captured);
+        }
+        let tweets_response = response?;
+        let meta = tweets_response.clone().into_meta().map(|meta| PageMeta {
+            oldest_id: meta.oldest_id,
+            newest_id: meta.newest_id,
+            next_token: meta.next_token,
+        });
+        Ok(TweetsPage {
+            tweets: tweets_response.clone().into_data(),
+            media: generate_media_map(tweets_response.into_includes()),
+            meta,
+            authors: HashMap::new(),
+        })
+    }
+
+    async fn fetch_thread(&self, user_id: u64, conversation_id: u64) -> Result<TweetsPage, DownloadError> {
+        TweetSource::fetch_thread(&self.api, user_id, conversation_id).await
+    }
+
+    async fn fetch_pinned_tweet(&self, user_id: u64) -> Result<TweetsPage, DownloadError> {
+        TweetSource::fetch_pinned_tweet(&self.api, user_id).await
+    }
+
+    async fn fetch_tweets(&self, ids: &[u64]) -> Result<TweetsPage, DownloadError> {
+        TweetSource::fetch_tweets(&self.api, ids).await
+    }
+}
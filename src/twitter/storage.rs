@@ -0,0 +1,30 @@
+//! Detection of downloads held up by a slow output device, e.g. a NAS over Wi-Fi or an SD card.
+//!
+//! Every finished download reports how much of its time went into writing to disk. Once writing takes most of the
+//! time, the run is storage-bound: more bandwidth would not make it faster.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Share of the download time spent writing, in percent, from which the run counts as storage-bound.
+const STORAGE_BOUND_SHARE: u32 = 50;
+
+/// Downloads shorter than this say little about the device and are not counted.
+const MIN_SAMPLE: Duration = Duration::from_millis(200);
+
+/// Moving average of the share of the download time spent writing, in percent.
+static WRITE_SHARE: AtomicU32 = AtomicU32::new(0);
+
+/// Records a download that took `total`, `write` of it writing to disk.
+pub fn record(write: Duration, total: Duration) {
+    if total < MIN_SAMPLE {
+        return;
+    }
+    let share = (write.as_secs_f64() / total.as_secs_f64() * 100.0).min(100.0) as u32;
+    // the last downloads weigh the most, the device may have just filled its cache
+    let _ = WRITE_SHARE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| Some((average * 3 + share) / 4));
+}
+
+/// Returns true if writing takes most of the time of the recent downloads.
+pub fn is_storage_bound() -> bool {
+    WRITE_SHARE.load(Ordering::Relaxed) >= STORAGE_BOUND_SHARE
+}
@@ -0,0 +1,88 @@
+//! Bandwidth limit of the media downloads, shared by all of them.
+//!
+//! A token bucket holding up to a second worth of bytes. Every received chunk takes its size from the bucket; a
+//! download that takes more than there is waits until the bucket refilled the difference, so all downloads together
+//! stay at the rate. The bucket refills and waits on the [clock](crate::clock).
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{self, Clock};
+
+struct Bucket {
+    /// bytes per second
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    /// bytes that may be received right away, negative while downloads wait for the refill
+    tokens: f64,
+    refilled: SystemTime,
+}
+
+impl Bucket {
+    /// A full bucket of `bytes_per_sec`, refilled from `now` on.
+    fn new(bytes_per_sec: u64, now: SystemTime) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Bucket { rate, state: Mutex::new(BucketState { tokens: rate, refilled: now }) }
+    }
+
+    /// Takes `bytes` from the bucket and waits on `clock` as long as the rate requires.
+    async fn consume(&self, clock: &dyn Clock, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let now = clock.now();
+            // a clock set back refills nothing
+            let elapsed = now.duration_since(state.refilled).unwrap_or(Duration::ZERO);
+            state.tokens = (state.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+            state.refilled = now;
+            state.tokens -= bytes as f64;
+            if state.tokens < 0.0 { Duration::from_secs_f64(-state.tokens / self.rate) } else { Duration::ZERO }
+        };
+        if !wait.is_zero() {
+            clock.sleep(wait).await;
+        }
+    }
+}
+
+static BUCKET: OnceLock<Bucket> = OnceLock::new();
+
+/// Limits the media downloads to `bytes_per_sec` in total.
+pub fn install(bytes_per_sec: u64) {
+    let _ = BUCKET.set(Bucket::new(bytes_per_sec, clock::now()));
+}
+
+/// Takes `bytes` received from the bucket and waits as long as the limit requires. Returns right away without a limit.
+pub async fn consume(bytes: u64) {
+    if let Some(bucket) = BUCKET.get() {
+        bucket.consume(clock::get().as_ref(), bytes).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::{Clock, MockClock};
+
+    use super::Bucket;
+
+    #[tokio::test]
+    async fn waits_for_the_refill_past_the_rate() {
+        let clock = MockClock::at_unix(1_000_000);
+        let bucket = Bucket::new(1000, clock.now());
+
+        // a second worth of bytes is there right away
+        bucket.consume(&clock, 1000).await;
+        assert!(clock.sleeps().is_empty());
+
+        // the next half second of bytes waits for their refill
+        bucket.consume(&clock, 500).await;
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(500)]);
+
+        // the time slept refilled them, a pause refills the rest
+        clock.advance(Duration::from_secs(1));
+        bucket.consume(&clock, 1000).await;
+        assert_eq!(clock.sleeps().len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+//! module to update the running binary to the latest GitHub release.
+//!
+//! A release carries one binary per platform, named `twitter-media-downloader-<arch>-<os>` (with `.exe` on Windows),
+//! a `SHA256SUMS` file in `sha256sum` format and its [minisign](https://jedisct1.github.io/minisign/) signature
+//! `SHA256SUMS.minisig`. The signature is checked against the public key embedded at build time from the
+//! `TMD_UPDATE_PUBLIC_KEY` environment variable, the second line of a `minisign.pub` file. The downloaded binary is
+//! only installed if the signature is valid and its checksum matches, a build without a key cannot update itself.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openssl::base64;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{Id, PKey};
+use openssl::sign::Verifier;
+use reqwest::Client;
+use reqwest::header::USER_AGENT;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::common::write_atomic;
+use crate::http;
+
+/// Latest release of the GitHub repository.
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/codingthings-com/twitter-media-downloader/releases/latest";
+
+/// Name of the checksum file of a release.
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+/// Name of the signature of the checksum file of a release.
+const SIGNATURE_ASSET: &str = "SHA256SUMS.minisig";
+
+/// minisign public key the releases are signed with, None for a build without one.
+const PUBLIC_KEY: Option<&str> = option_env!("TMD_UPDATE_PUBLIC_KEY");
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Outcome of a [self_update](self_update).
+#[derive(Debug, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// the running version is the latest
+    UpToDate(String),
+    /// a newer version is available, only checked
+    Available(String),
+    /// the binary at the path was replaced by the version
+    Updated(String, PathBuf),
+}
+
+/// Checks the latest release and, unless `check_only`, replaces the running binary with it if it is newer.
+pub async fn self_update(check_only: bool) -> Result<UpdateStatus, Box<dyn Error + Send + Sync>> {
+    let client = http::client()?;
+    let current = env!("CARGO_PKG_VERSION");
+
+    let release: Release = get(&client, LATEST_RELEASE_URL).await?.json().await?;
+    let latest = release.tag_name.trim_start_matches('v').to_string();
+    if !is_newer(&latest, current) {
+        return Ok(UpdateStatus::UpToDate(current.to_string()));
+    }
+    if check_only {
+        return Ok(UpdateStatus::Available(latest));
+    }
+
+    let public_key = PUBLIC_KEY.ok_or("this build has no update key, download the release by hand")?;
+    let asset_name = asset_name();
+    let asset = find_asset(&release, &asset_name)?;
+    let checksums = get(&client, &find_asset(&release, CHECKSUMS_ASSET)?.browser_download_url).await?.text().await?;
+    let signature = get(&client, &find_asset(&release, SIGNATURE_ASSET)?.browser_download_url).await?.text().await?;
+    verify_signature(public_key, checksums.as_bytes(), &signature)
+        .map_err(|e| format!("release {}: invalid signature of {}: {}", release.tag_name, CHECKSUMS_ASSET, e))?;
+    let expected = checksums.lines()
+        .filter_map(|l| l.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim().trim_start_matches('*') == asset_name)
+        .map(|(sum, _)| sum.to_ascii_lowercase())
+        .ok_or_else(|| format!("release {} has no checksum for {}", release.tag_name, asset_name))?;
+
+    info!("version: {}. Downloading {}", latest, asset.browser_download_url);
+    let binary = get(&client, &asset.browser_download_url).await?.bytes().await?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(format!("checksum mismatch for {}: expected {}, got {}", asset_name, expected, actual).into());
+    }
+
+    let exe = std::env::current_exe()?;
+    replace_binary(&exe, &binary)?;
+    Ok(UpdateStatus::Updated(latest, exe))
+}
+
+async fn get(client: &Client, url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    client.get(url)
+        .header(USER_AGENT, concat!("twitter-media-downloader/", env!("CARGO_PKG_VERSION")))
+        .send()
+        .await?
+        .error_for_status()
+}
+
+fn find_asset<'a>(release: &'a Release, name: &str) -> Result<&'a Asset, String> {
+    release.assets.iter()
+        .find(|a| a.name == name)
+        .ok_or_else(|| format!("release {} has no asset {}", release.tag_name, name))
+}
+
+/// Verifies the minisign `signature` of `data` against `public_key`, both in the base64 form of their files.
+///
+/// Both the signature of `data`, over its BLAKE2b-512 hash for the default prehashed signatures, and the global
+/// signature of the trusted comment must be valid.
+fn verify_signature(public_key: &str, data: &[u8], signature: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let public_key = base64::decode_block(public_key.trim())?;
+    if public_key.len() != 42 || &public_key[..2] != b"Ed" {
+        return Err("not a minisign Ed25519 public key".into());
+    }
+    let (key_id, key) = public_key[2..].split_at(8);
+
+    let mut lines = signature.lines().filter(|l| !l.starts_with("untrusted comment:"));
+    let signature = base64::decode_block(lines.next().ok_or("no signature")?.trim())?;
+    let trusted_comment = lines.next().and_then(|l| l.strip_prefix("trusted comment: ")).ok_or("no trusted comment")?;
+    let global_signature = base64::decode_block(lines.next().ok_or("no global signature")?.trim())?;
+    if signature.len() != 74 {
+        return Err("not a minisign signature".into());
+    }
+    let (algorithm, rest) = signature.split_at(2);
+    let (signature_key_id, signature) = rest.split_at(8);
+    if signature_key_id != key_id {
+        return Err("signed with another key".into());
+    }
+    let message = match algorithm {
+        b"ED" => hash(MessageDigest::from_name("BLAKE2b512").ok_or("BLAKE2b-512 not available")?, data)?.to_vec(),
+        b"Ed" => data.to_vec(),
+        _ => return Err("unknown signature algorithm".into()),
+    };
+
+    let key = PKey::public_key_from_raw_bytes(key, Id::ED25519)?;
+    if !Verifier::new_without_digest(&key)?.verify_oneshot(signature, &message)? {
+        return Err("signature does not match".into());
+    }
+    let global_message = [signature, trusted_comment.as_bytes()].concat();
+    if !Verifier::new_without_digest(&key)?.verify_oneshot(&global_signature, &global_message)? {
+        return Err("trusted comment signature does not match".into());
+    }
+    Ok(())
+}
+
+/// Name of the release asset for this platform, e.g. `twitter-media-downloader-x86_64-linux`.
+fn asset_name() -> String {
+    format!("twitter-media-downloader-{}-{}{}", std::env::consts::ARCH, std::env::consts::OS, std::env::consts::EXE_SUFFIX)
+}
+
+/// Whether version `a` is newer than version `b`, comparing the numeric `major.minor.patch` parts.
+fn is_newer(a: &str, b: &str) -> bool {
+    let parts = |v: &str| -> Vec<u64> { v.split(['.', '-']).map_while(|p| p.parse().ok()).collect() };
+    parts(a) > parts(b)
+}
+
+/// Replaces the binary at `exe` with `binary`.
+///
+/// The running binary is moved aside first, which also works on Windows where a running binary cannot be overwritten.
+fn replace_binary(exe: &Path, binary: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let new = exe.with_extension("new");
+    let old = exe.with_extension("old");
+    write_atomic(&new, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+
+    fs::rename(exe, &old)?;
+    if let Err(e) = fs::rename(&new, exe) {
+        // put the old binary back
+        let _ = fs::rename(&old, exe);
+        return Err(e.into());
+    }
+    // fails on Windows while the old binary is still running, it is then replaced by the next update
+    let _ = fs::remove_file(&old);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::sign::Signer;
+
+    #[test]
+    fn checks_minisign_signatures() {
+        let key = PKey::generate_ed25519().unwrap();
+        let key_id = [7u8; 8];
+        let public_key = base64::encode_block(&[&b"Ed"[..], &key_id, &key.raw_public_key().unwrap()].concat());
+        let data = b"0123abcd  twitter-media-downloader-x86_64-linux\n";
+        let prehashed = hash(MessageDigest::from_name("BLAKE2b512").unwrap(), data).unwrap();
+        let signature = Signer::new_without_digest(&key).unwrap().sign_oneshot_to_vec(&prehashed).unwrap();
+        let trusted_comment = "timestamp:1700000000\tfile:SHA256SUMS";
+        let global_signature = Signer::new_without_digest(&key).unwrap().sign_oneshot_to_vec(&[&signature[..], trusted_comment.as_bytes()].concat()).unwrap();
+        let minisig = format!("untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {}\n{}\n",
+            base64::encode_block(&[&b"ED"[..], &key_id, &signature].concat()), trusted_comment, base64::encode_block(&global_signature));
+
+        assert!(verify_signature(&public_key, data, &minisig).is_ok());
+        assert!(verify_signature(&public_key, b"0123abce  twitter-media-downloader-x86_64-linux\n", &minisig).is_err());
+        assert!(verify_signature(&public_key, data, &minisig.replace("file:SHA256SUMS", "file:other")).is_err());
+        let other_key = PKey::generate_ed25519().unwrap();
+        let other_public_key = base64::encode_block(&[&b"Ed"[..], &key_id, &other_key.raw_public_key().unwrap()].concat());
+        assert!(verify_signature(&other_public_key, data, &minisig).is_err());
+    }
+}
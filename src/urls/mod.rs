@@ -0,0 +1,151 @@
+//! module to list the media urls of a user instead of downloading them.
+//!
+//! The whole timeline is walked and the url of every photo is written in original quality, either one per line or
+//! as an [aria2c](https://aria2.github.io/) input file that saves the files under the names a download would give
+//! them. The transfer can then be handed off to an external download manager.
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use reqwest::Url;
+use tracing::info;
+use twitter_v2::data::MediaType;
+use twitter_v2::query::{Exclude, MediaField, TweetExpansion, TweetField};
+use twitter_v2::{Media, Tweet};
+
+use crate::dates;
+use crate::http;
+use crate::shutdown;
+use crate::twitter::auth::Credentials;
+use crate::twitter::error::DownloadError;
+use crate::twitter::filename::{FilenameTemplate, FilenameValues};
+use crate::twitter::{generate_media_map, get_twitter_id, media_count, ratelimit};
+
+/// Host of the Twitter photo urls that accept a `name` size parameter.
+const PHOTO_HOST: &str = "pbs.twimg.com";
+
+/// Format of the exported urls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlFormat {
+    /// one url per line
+    Plain,
+    /// aria2c input file: the url followed by indented `dir=` and `out=` options
+    Aria2c,
+}
+
+impl FromStr for UrlFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(UrlFormat::Plain),
+            "aria2c" => Ok(UrlFormat::Aria2c),
+            _ => Err(format!("unknown url format '{}'. Expected plain or aria2c", s)),
+        }
+    }
+}
+
+/// Returns `url` rewritten to the original quality of a Twitter photo, e.g.
+/// `https://pbs.twimg.com/media/FqX1a2b3.jpg?name=orig`. Other urls are returned as they are.
+pub fn orig_url(url: &Url) -> Url {
+    let mut orig = url.clone();
+    if url.host_str() == Some(PHOTO_HOST) && url.path().starts_with("/media/") {
+        let others: Vec<(String, String)> = url.query_pairs()
+            .filter(|(k, _)| k != "name")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        orig.query_pairs_mut().clear().extend_pairs(others).append_pair("name", "orig");
+    }
+    orig
+}
+
+/// Writes the media urls of all Tweets of `username` to `out` in `format`.
+///
+/// Only photos are listed, the media the download covers. Rate limits are waited out like by a download.
+///
+/// Returns the number of urls written.
+pub async fn export_urls(credentials: &Credentials, username: &str, format: UrlFormat, out: &mut impl Write) -> Result<usize, DownloadError> {
+    let api = credentials.api();
+    let client = http::client()?;
+    let id = get_twitter_id(&api, username).await?;
+    let template = FilenameTemplate::default();
+
+    let mut count = 0;
+    let mut pagination_token: Option<String> = None;
+    loop {
+        if shutdown::is_requested() {
+            break;
+        }
+        let mut req_tweets = api.get_user_tweets(id);
+        req_tweets
+            .max_results(100)
+            .exclude([Exclude::Replies, Exclude::Retweets])
+            .media_fields([MediaField::Url, MediaField::Type])
+            .tweet_fields([TweetField::CreatedAt, TweetField::Attachments])
+            .expansions([TweetExpansion::AttachmentsMediaKeys]);
+        if let Some(token) = &pagination_token {
+            req_tweets.pagination_token(token);
+        }
+
+        let response = match req_tweets.send().await.map_err(DownloadError::from) {
+            Ok(response) => response,
+            Err(e) if e.is_rate_limited() => {
+                let rate_limit = ratelimit::probe_user_tweets(&client, credentials, username, id).await;
+                if !ratelimit::wait(username, &rate_limit).await {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let media_map = generate_media_map(response.clone().into_includes());
+        for tweet in response.data().into_iter().flatten() {
+            let media_keys = tweet.attachments.as_ref().and_then(|a| a.media_keys.as_ref());
+            for (media_index, media_key) in media_keys.into_iter().flatten().enumerate() {
+                if let Some(media) = media_map.get(&media_key.to_string()).filter(|m| m.kind == MediaType::Photo) {
+                    if write_url(out, format, &template, username, tweet, media_index, media)? {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        out.flush()?;
+
+        pagination_token = response.meta().and_then(|m| m.next_token.clone());
+        if pagination_token.is_none() {
+            break;
+        }
+        info!("username: {}. {} urls so far. Will continue with the next page...", username, count);
+    }
+
+    info!("username: {}. Exported {} urls", username, count);
+    Ok(count)
+}
+
+/// Writes the url of `media` at `media_index` of `tweet`.
+///
+/// Returns false if the media has no url.
+fn write_url(out: &mut impl Write, format: UrlFormat, template: &FilenameTemplate, username: &str, tweet: &Tweet, media_index: usize, media: &Media) -> Result<bool, io::Error> {
+    let url = match &media.url {
+        Some(url) => url,
+        None => return Ok(false),
+    };
+    let orig = orig_url(url);
+    match format {
+        UrlFormat::Plain => writeln!(out, "{}", orig)?,
+        UrlFormat::Aria2c => {
+            let filename = template.render(&FilenameValues {
+                username,
+                tweet_id: &tweet.id.to_string(),
+                media_key: media.media_key.as_str(),
+                index: media_index,
+                count: media_count(tweet),
+                date: tweet.created_at.or_else(|| dates::tweet_id_date(tweet.id.as_u64())),
+                original: url.path().split('/').next_back().unwrap_or(""),
+                thread: &tweet.conversation_id.as_ref().unwrap_or(&tweet.id).to_string(),
+            });
+            writeln!(out, "{}\n  dir={}\n  out={}", orig, username, filename)?;
+        }
+    }
+    Ok(true)
+}
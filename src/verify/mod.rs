@@ -0,0 +1,149 @@
+//! module to check the downloaded media files against the [state database](crate::state).
+//!
+//! Every file recorded as downloaded must exist with its recorded size and SHA-256. Files that do not are
+//! re-downloaded from their recorded url, or marked as failed in the state database. Duplicates skipped by `--dedup`
+//! or `--near-dupes` own no file, they are checked through the record of the earlier download only, so a repair never
+//! writes over it.
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use reqwest::{Client, Url};
+use tracing::{error, info, warn};
+
+use crate::common::sha256_file;
+use crate::http;
+use crate::manifest;
+use crate::messages::Message;
+use crate::state::{MediaRecord, StateStore};
+use crate::twitter;
+use crate::twitter::retry::{RetryPolicy, DEFAULT_STALL_TIMEOUT};
+
+/// What is wrong with a recorded file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    SizeMismatch { expected: u64, actual: u64 },
+    ChecksumMismatch,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing => f.write_str("missing"),
+            Problem::SizeMismatch { expected, actual } => write!(f, "size {} instead of {}", actual, expected),
+            Problem::ChecksumMismatch => f.write_str("checksum mismatch"),
+        }
+    }
+}
+
+/// A recorded file with a [Problem](Problem).
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub record: MediaRecord,
+    pub problem: Problem,
+}
+
+/// Outcome of [verify](verify).
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+    /// number of findings re-downloaded successfully
+    pub repaired: usize,
+}
+
+/// Checks the recorded files of `usernames` (every user if empty) under `output_dir`.
+///
+/// With `repair` the files with a problem are re-downloaded, otherwise they are marked as failed under `run_id`.
+pub async fn verify(output_dir: &Path, usernames: &[String], repair: bool, retry: &RetryPolicy, run_id: &str) -> Result<VerifyReport, Box<dyn Error + Send + Sync>> {
+    let state = StateStore::open(output_dir)?;
+
+    let mut records = Vec::new();
+    if usernames.is_empty() {
+        records = state.downloaded_media(None)?;
+    } else {
+        for username in usernames {
+            records.extend(state.downloaded_media(Some(username))?);
+        }
+    }
+    info!("Verifying {} recorded files", records.len());
+
+    let mut report = VerifyReport { checked: records.len(), ..Default::default() };
+    for record in records {
+        if let Some(problem) = check(&record) {
+            warn!("username: {}, media_key: {}, local: {}. {}", record.username, record.media_key, record.local_path.display(), problem);
+            report.findings.push(Finding { record, problem });
+        }
+    }
+
+    let client = http::media_client()?;
+    for finding in report.findings.iter() {
+        let record = &finding.record;
+        if repair {
+            match redownload(&client, retry, &state, record, run_id).await {
+                Ok(()) => {
+                    info!("username: {}, media_key: {}, local: {}. Re-downloaded", record.username, record.media_key, record.local_path.display());
+                    report.repaired += 1;
+                    continue;
+                }
+                Err(e) => error!("username: {}, media_key: {}. Cannot re-download: {}", record.username, record.media_key, e),
+            }
+        }
+        state.requeue(run_id, &record.username, &record.media_key, &finding.problem.to_string())?;
+    }
+    let usernames: BTreeSet<&str> = report.findings.iter().map(|finding| finding.record.username.as_str()).collect();
+    for username in usernames {
+        manifest::refresh(&state, output_dir, username)?;
+    }
+
+    Ok(report)
+}
+
+/// Returns the problem of the file of `record`, None if it is fine.
+///
+/// Files recorded without a checksum, e.g. adopted from an older version, are only checked for their size.
+fn check(record: &MediaRecord) -> Option<Problem> {
+    let metadata = match fs::metadata(&record.local_path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Some(Problem::Missing),
+    };
+    if record.size > 0 && metadata.len() != record.size {
+        return Some(Problem::SizeMismatch { expected: record.size, actual: metadata.len() });
+    }
+    match &record.sha256 {
+        Some(expected) => match sha256_file(&record.local_path) {
+            Ok(actual) if &actual == expected => None,
+            _ => Some(Problem::ChecksumMismatch),
+        },
+        None => None,
+    }
+}
+
+/// Downloads the file of `record` again and records it with its new size and checksum.
+async fn redownload(client: &Client, retry: &RetryPolicy, state: &StateStore, record: &MediaRecord, run_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = Url::parse(&record.url)?;
+    if let Some(parent) = record.local_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let size = twitter::fetch_file(client, retry, DEFAULT_STALL_TIMEOUT, None, &record.username, &record.media_key, url, &record.local_path).await?;
+
+    let repaired = MediaRecord {
+        size,
+        sha256: sha256_file(&record.local_path).ok(),
+        run_id: run_id.into(),
+        ..record.clone()
+    };
+    state.record_downloaded(&repaired)?;
+    Ok(())
+}
+
+/// Prints the findings and a summary line to stdout.
+pub fn print_report(report: &VerifyReport) {
+    for finding in report.findings.iter() {
+        println!("{:<18} {}", finding.problem.to_string(), finding.record.local_path.display());
+    }
+    println!("{}", Message::VerifySummary { problems: report.findings.len(), checked: report.checked, repaired: report.repaired });
+}
@@ -0,0 +1,187 @@
+//! module to spread the downloaded media over several output volumes.
+//!
+//! Volumes are filled in the order they are given. Once a volume reaches its capacity, new downloads roll over
+//! to the next one. Without configured volumes all media go to the output directory.
+//!
+//! The capacity is a soft limit: the size of a file is only known once it is downloaded, so the downloads started
+//! while a volume was below its capacity can take it past by up to `--concurrency` files.
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tracing::info;
+
+use crate::common::parse_size;
+
+/// An output volume, written as `<path>[=<capacity>]`, e.g. `/mnt/disk1=2TiB`. A path containing `=` is taken
+/// as a whole unless the part after its last `=` is a size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    pub path: PathBuf,
+    /// Bytes the archive may use on this volume, a soft limit. `None` is unlimited.
+    pub capacity: Option<u64>,
+}
+
+impl FromStr for Volume {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('=').and_then(|(path, capacity)| Some((path, parse_size(capacity).ok()?))) {
+            Some((path, capacity)) => Ok(Volume { path: path.into(), capacity: Some(capacity) }),
+            None => Ok(Volume { path: s.into(), capacity: None }),
+        }
+    }
+}
+
+/// The volumes of a run and the bytes used on each of them.
+#[derive(Debug)]
+pub struct Volumes {
+    volumes: Vec<Volume>,
+    used: Mutex<Vec<u64>>,
+}
+
+impl Volumes {
+    /// Volumes for `configured`, or just `output_dir` if none are configured.
+    ///
+    /// The used bytes of volumes with a capacity are computed by scanning them.
+    pub fn new(output_dir: &Path, configured: &[Volume]) -> Result<Self, io::Error> {
+        let volumes = if configured.is_empty() {
+            vec![Volume { path: output_dir.into(), capacity: None }]
+        } else {
+            configured.to_vec()
+        };
+
+        let mut used = Vec::with_capacity(volumes.len());
+        for volume in volumes.iter() {
+            used.push(match volume.capacity {
+                Some(_) if volume.path.is_dir() => dir_size(&volume.path)?,
+                _ => 0,
+            });
+        }
+
+        Ok(Volumes { volumes, used: Mutex::new(used) })
+    }
+
+    /// Returns the path of `relative_path` under the directory of `username` on the first volume holding it.
+    pub fn find_existing(&self, username: &str, relative_path: &Path) -> Option<PathBuf> {
+        self.volumes.iter()
+            .map(|v| v.path.join(username).join(relative_path))
+            .find(|p| p.exists())
+    }
+
+    /// Returns the user's directory on the first volume that is not full, creating it if needed.
+    ///
+    /// Nothing is reserved for the new file, its bytes count once [add_used](Volumes::add_used) is called.
+    pub fn user_dir_for_new_file(&self, username: &str) -> Result<PathBuf, io::Error> {
+        let volume = {
+            let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+            self.volumes.iter()
+                .zip(used.iter())
+                .find(|(v, used)| v.capacity.is_none_or(|c| **used < c))
+                .map(|(v, _)| v.clone())
+        };
+
+        match volume {
+            Some(volume) => {
+                let path = volume.path.join(username);
+                DirBuilder::new().recursive(true).create(&path)?;
+                Ok(path)
+            }
+            None => Err(io::Error::other("All output volumes are full")),
+        }
+    }
+
+    /// Returns the free bytes of the filesystem new files go to, that of the first volume that is not full.
+    pub fn available_space(&self) -> Result<u64, io::Error> {
+        let volume = {
+            let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+            self.volumes.iter()
+                .zip(used.iter())
+                .find(|(v, used)| v.capacity.is_none_or(|c| **used < c))
+                .map(|(v, _)| v.path.clone())
+        };
+        let volume = volume.ok_or_else(|| io::Error::other("All output volumes are full"))?;
+        // the volume may not be created yet, its closest existing parent is on the same filesystem
+        let existing = volume.ancestors().find(|p| p.exists()).unwrap_or_else(|| Path::new("."));
+        fs2::available_space(existing)
+    }
+
+    /// Adds `bytes` written to `file` to the used bytes of the volume holding it.
+    pub fn add_used(&self, file: &Path, bytes: u64) {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = self.volumes.iter().position(|v| file.starts_with(&v.path)) {
+            used[i] += bytes;
+            if let Some(capacity) = self.volumes[i].capacity {
+                if used[i] >= capacity {
+                    info!("volume: {}. Reached its capacity, rolling over to the next volume", self.volumes[i].path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Returns the total size of the files under `dir`.
+fn dir_size(dir: &Path) -> Result<u64, io::Error> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmd-test-volumes-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_the_capacity_after_the_last_equals_sign() {
+        assert_eq!("/mnt/disk1".parse(), Ok(Volume { path: "/mnt/disk1".into(), capacity: None }));
+        assert_eq!("/mnt/disk1=2TiB".parse(), Ok(Volume { path: "/mnt/disk1".into(), capacity: Some(2 << 40) }));
+        assert_eq!("/mnt/a=b=1KiB".parse(), Ok(Volume { path: "/mnt/a=b".into(), capacity: Some(1024) }));
+        // not a size, part of the path
+        assert_eq!("/mnt/a=b".parse(), Ok(Volume { path: "/mnt/a=b".into(), capacity: None }));
+        assert_eq!("/mnt/a=".parse(), Ok(Volume { path: "/mnt/a=".into(), capacity: None }));
+    }
+
+    #[test]
+    fn rolls_over_to_the_next_volume_at_capacity() {
+        let dir = test_dir("rollover");
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(first.join("alice")).unwrap();
+        fs::write(first.join("alice").join("old.jpg"), [0u8; 6]).unwrap();
+        let volumes = Volumes::new(&dir, &[Volume { path: first.clone(), capacity: Some(10) }, Volume { path: second.clone(), capacity: None }]).unwrap();
+
+        // the existing files count
+        assert_eq!(volumes.user_dir_for_new_file("alice").unwrap(), first.join("alice"));
+        volumes.add_used(&first.join("alice").join("new.jpg"), 4);
+        assert_eq!(volumes.user_dir_for_new_file("alice").unwrap(), second.join("alice"));
+        assert!(second.join("alice").is_dir());
+
+        assert_eq!(volumes.find_existing("alice", Path::new("old.jpg")), Some(first.join("alice").join("old.jpg")));
+        assert_eq!(volumes.find_existing("alice", Path::new("new.jpg")), None);
+    }
+
+    #[test]
+    fn fails_once_every_volume_is_full() {
+        let dir = test_dir("full");
+        let volumes = Volumes::new(&dir, &[Volume { path: dir.join("only"), capacity: Some(1) }]).unwrap();
+        volumes.add_used(&dir.join("only").join("alice").join("a.jpg"), 1);
+        assert!(volumes.user_dir_for_new_file("alice").is_err());
+        assert!(volumes.available_space().is_err());
+    }
+}